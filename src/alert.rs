@@ -0,0 +1,80 @@
+use clap::ValueEnum;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::{process::Command, sync::RwLock};
+
+use crate::ui::AppState;
+
+/// How long the TUI / progress display highlights the first failure.
+const FLASH_FOR: Duration = Duration::from_secs(5);
+
+/// Attention-grabbing signal raised once, when the first job of a run fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Alert {
+    /// Ring the terminal bell
+    Bell,
+    /// Highlight the failure in the TUI or progress bars (bell in plain mode)
+    Flash,
+    /// Desktop notification via `notify-send` (Linux) or `osascript` (macOS)
+    Notify,
+}
+
+/// A failure banner shown by the active display until `until`.
+#[derive(Debug, Clone)]
+pub struct FailureFlash {
+    pub message: String,
+    pub until: Instant,
+}
+
+impl FailureFlash {
+    pub fn is_active(&self) -> bool {
+        Instant::now() < self.until
+    }
+}
+
+/// Raise `alert` for `message`. `has_display` tells whether a TUI or
+/// progress display is running that can show a flash.
+pub async fn fire(alert: Alert, message: String, state: &Arc<RwLock<AppState>>, has_display: bool) {
+    match alert {
+        Alert::Flash if has_display => {
+            state.write().await.failure_flash = Some(FailureFlash {
+                message,
+                until: Instant::now() + FLASH_FOR,
+            });
+        }
+        Alert::Bell | Alert::Flash => bell(),
+        Alert::Notify => notify(&message).await,
+    }
+}
+
+fn bell() {
+    // stderr, so a piped stdout never receives the control character
+    let mut stderr = std::io::stderr();
+    let _ = stderr.write_all(b"\x07");
+    let _ = stderr.flush();
+}
+
+async fn notify(message: &str) {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut c = Command::new("osascript");
+        c.arg("-e").arg(format!(
+            "display notification {:?} with title \"gparallel\"",
+            message
+        ));
+        c
+    } else {
+        let mut c = Command::new("notify-send");
+        c.arg("--urgency=critical").arg("gparallel").arg(message);
+        c
+    };
+    command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    // No notification daemon is not worth failing the run over; ring instead
+    match command.status().await {
+        Ok(status) if status.success() => {}
+        _ => bell(),
+    }
+}
@@ -0,0 +1,58 @@
+//! The GPU of Apple Silicon Macs, which PyTorch (MPS) and MLX run on. It
+//! is one device sharing the machine's unified memory; with no NVML there,
+//! it is found and sampled through `sysctl`. `--jobs-per-gpu` splits it
+//! into slots.
+
+use tokio::process::Command;
+
+/// The chip's name ("Apple M2 Max") on an Apple Silicon Mac, else `None`.
+pub async fn detect() -> Option<String> {
+    if !cfg!(target_os = "macos") {
+        return None;
+    }
+    let brand = sysctl(&["machdep.cpu.brand_string"]).await?;
+    brand.starts_with("Apple").then_some(brand)
+}
+
+/// Free and total unified memory in MiB, free going by the kernel's
+/// memory pressure (the share of memory it considers free).
+pub async fn memory_mb() -> Option<(u64, u64)> {
+    if !cfg!(target_os = "macos") {
+        return None;
+    }
+    parse_memory(&sysctl(&["hw.memsize", "kern.memorystatus_level"]).await?)
+}
+
+/// `sysctl -n hw.memsize kern.memorystatus_level` output: bytes, then the
+/// percentage free.
+fn parse_memory(out: &str) -> Option<(u64, u64)> {
+    let mut lines = out.lines().map(str::trim);
+    let total: u64 = lines.next()?.parse().ok()?;
+    let free_percent: u64 = lines.next()?.parse().ok()?;
+    let total_mb = total / (1024 * 1024);
+    Some((total_mb * free_percent.min(100) / 100, total_mb))
+}
+
+async fn sysctl(names: &[&str]) -> Option<String> {
+    let out = Command::new("sysctl")
+        .arg("-n")
+        .args(names)
+        .output()
+        .await
+        .ok()?;
+    out.status
+        .success()
+        .then(|| String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_memory_follows_the_pressure_level() {
+        assert_eq!(parse_memory("34359738368\n62\n"), Some((20316, 32768)));
+        assert_eq!(parse_memory("34359738368\n"), None);
+        assert_eq!(parse_memory(""), None);
+    }
+}
@@ -0,0 +1,77 @@
+//! Token authentication for the daemon: a shared secret kept in a file, so
+//! whoever can read the file can use the daemon and nobody else can.
+
+use anyhow::Result;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use uuid::Uuid;
+
+/// The daemon's token from `path`, generating one readable only by us when
+/// the file does not exist yet.
+pub fn load_or_create(path: &Path) -> Result<String> {
+    if path.exists() {
+        return read(path);
+    }
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to create token file '{}': {}", path.display(), e))?;
+    writeln!(file, "{}", token)?;
+    Ok(token)
+}
+
+/// The token a client sends, from the file the daemon reads it from.
+pub fn read(path: &Path) -> Result<String> {
+    let token = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read token file '{}': {}", path.display(), e))?;
+    let token = token.trim();
+    if token.is_empty() {
+        anyhow::bail!("Token file '{}' is empty", path.display());
+    }
+    Ok(token.to_string())
+}
+
+/// Compare tokens in time that does not depend on where they differ.
+pub fn matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_token_is_private_and_read_back() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("gparallel-auth-{}", Uuid::new_v4()));
+        let path = dir.join("token");
+
+        let token = load_or_create(&path).unwrap();
+        assert_eq!(token.len(), 64);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(load_or_create(&path).unwrap(), token);
+        assert_eq!(read(&path).unwrap(), token);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(matches("secret", "secret"));
+        assert!(!matches("secret", "secreT"));
+        assert!(!matches("secret", "secret2"));
+        assert!(!matches("secret", ""));
+    }
+}
@@ -0,0 +1,522 @@
+//! Client side of the daemon protocol: the `submit` subcommand and friends.
+
+use anyhow::Result;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, BufReader},
+    net::{TcpStream, UnixStream},
+    sync::{mpsc::unbounded_channel, RwLock},
+};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::auth;
+use crate::duration::humanize;
+use crate::jobfile::{self, InputFormat, JobRequest, Priority};
+use crate::protocol::{self, CancelTarget, QueueEnd, Request, Response};
+use crate::report::{self, JobRecord};
+use crate::ui::{pause_notice, Action, AppState, JobInfo, Notice, UI};
+
+/// How often `wait` asks the daemon about its jobs.
+const WAIT_POLL: Duration = Duration::from_millis(500);
+
+/// Where the daemon listens, and the file with its token if it wants one.
+pub struct Endpoint {
+    pub socket: PathBuf,
+    /// The daemon's TCP address (`serve --listen`), used instead of `socket`
+    pub connect: Option<String>,
+    pub token_file: Option<PathBuf>,
+}
+
+/// A connection to `gparallel serve`.
+pub struct Client {
+    reader: BufReader<Box<dyn AsyncRead + Unpin + Send>>,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+}
+
+impl Client {
+    pub async fn connect(endpoint: &Endpoint) -> Result<Self> {
+        let token = endpoint.token_file.as_deref().map(auth::read).transpose()?;
+        let (reader, writer): (
+            Box<dyn AsyncRead + Unpin + Send>,
+            Box<dyn AsyncWrite + Unpin + Send>,
+        ) = match &endpoint.connect {
+            Some(addr) => {
+                let stream = TcpStream::connect(addr).await.map_err(|e| {
+                    anyhow::anyhow!("Cannot connect to the daemon at {}: {}", addr, e)
+                })?;
+                let (reader, writer) = stream.into_split();
+                (Box::new(reader), Box::new(writer))
+            }
+            None => {
+                let stream = UnixStream::connect(&endpoint.socket).await.map_err(|e| {
+                    anyhow::anyhow!(
+                        "Cannot connect to the daemon at '{}': {} (is `gparallel serve` running?)",
+                        endpoint.socket.display(),
+                        e
+                    )
+                })?;
+                let (reader, writer) = stream.into_split();
+                (Box::new(reader), Box::new(writer))
+            }
+        };
+        let mut client = Self {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        if let Some(token) = token {
+            // Only a daemon on another machine needs to be told who we are
+            let user = endpoint.connect.as_ref().and_then(|_| local_user());
+            client.request(&Request::Auth { token, user }).await?;
+        }
+        Ok(client)
+    }
+
+    /// Send one request and wait for its response; daemon-side errors
+    /// become `Err`.
+    pub async fn request(&mut self, request: &Request) -> Result<Response> {
+        protocol::send(&mut self.writer, request).await?;
+        self.next().await
+    }
+
+    /// Every job the daemon knows about.
+    pub async fn jobs(&mut self) -> Result<Vec<JobRecord>> {
+        match self.request(&Request::Status).await? {
+            Response::Status { jobs } => Ok(jobs),
+            other => anyhow::bail!("unexpected response: {:?}", other),
+        }
+    }
+
+    /// The next message of a streamed response (`logs`).
+    pub async fn next(&mut self) -> Result<Response> {
+        match protocol::recv(&mut self.reader).await? {
+            Some(Response::Error { message }) => anyhow::bail!("daemon: {}", message),
+            Some(response) => Ok(response),
+            None => anyhow::bail!("the daemon closed the connection"),
+        }
+    }
+}
+
+/// The name of the user we run as.
+fn local_user() -> Option<String> {
+    let user = nix::unistd::User::from_uid(nix::unistd::getuid()).ok()??;
+    Some(user.name)
+}
+
+/// Submit `template` with `cmd`, or one job per line of stdin when `cmd` is
+/// `None`, printing each new job id.
+pub async fn submit(endpoint: &Endpoint, cmd: Option<String>, template: JobRequest) -> Result<()> {
+    let commands = match cmd {
+        Some(cmd) => vec![cmd],
+        None => {
+            let mut content = String::new();
+            tokio::io::stdin().read_to_string(&mut content).await?;
+            jobfile::parse(&content, InputFormat::Text)?
+                .into_iter()
+                .map(|job| job.cmd)
+                .collect()
+        }
+    };
+
+    let mut client = Client::connect(endpoint).await?;
+    for cmd in commands {
+        let job = JobRequest {
+            cmd,
+            ..template.clone()
+        };
+        match client.request(&Request::Submit { job }).await? {
+            Response::Submitted { id } => println!("{}", id),
+            other => anyhow::bail!("unexpected response: {:?}", other),
+        }
+    }
+    Ok(())
+}
+
+/// Cancel `target`, printing the id of every job that was cancelled.
+pub async fn cancel(endpoint: &Endpoint, target: CancelTarget) -> Result<()> {
+    let mut client = Client::connect(endpoint).await?;
+    match client.request(&Request::Cancel { target }).await? {
+        Response::Cancelled { ids } => {
+            for id in &ids {
+                println!("{}", id);
+            }
+            if ids.is_empty() {
+                eprintln!("[gparallel] No jobs to cancel");
+            }
+        }
+        other => anyhow::bail!("unexpected response: {:?}", other),
+    }
+    Ok(())
+}
+
+/// Print a job's captured output; with `follow`, keep printing new lines
+/// until the job finishes.
+pub async fn logs(endpoint: &Endpoint, job: String, follow: bool) -> Result<()> {
+    let mut client = Client::connect(endpoint).await?;
+    let mut response = client.request(&Request::Logs { job, follow }).await?;
+    loop {
+        match response {
+            Response::Log { lines } => {
+                for line in lines {
+                    println!("{}", line);
+                }
+            }
+            Response::LogEnd => return Ok(()),
+            other => anyhow::bail!("unexpected response: {:?}", other),
+        }
+        response = client.next().await?;
+    }
+}
+
+/// Show the daemon's GPUs and jobs in the TUI until the user detaches
+/// with `q`; the daemon and its jobs carry on. The TUI looks for changes
+/// every `refresh`.
+pub async fn attach(endpoint: &Endpoint, refresh: Duration) -> Result<()> {
+    let mut client = Client::connect(endpoint).await?;
+    let state = Arc::new(RwLock::new(AppState {
+        attached: true,
+        ..AppState::new()
+    }));
+    let first = client.request(&Request::Watch).await?;
+    apply_watched(&state, first).await?;
+
+    let (actions, mut asked) = unbounded_channel();
+    let ui = UI::new(state.clone(), false)
+        .await?
+        .with_actions(actions)
+        .with_refresh(refresh);
+    let cancel = CancellationToken::new();
+    let mut ui_task = tokio::spawn(ui.run(cancel.clone()));
+    loop {
+        tokio::select! {
+            detached = &mut ui_task => return detached?,
+            Some(action) = asked.recv() => {
+                let message = perform(endpoint, action).await;
+                state.write().await.notice = Some(Notice::new(message));
+            }
+            response = client.next() => {
+                let applied = match response {
+                    Ok(response) => apply_watched(&state, response).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = applied {
+                    // Restore the terminal before the error is printed
+                    cancel.cancel();
+                    ui_task.await??;
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Carry out an action of the attached TUI on its own connection, as the
+/// watch one is busy streaming, and say how it went.
+async fn perform(endpoint: &Endpoint, action: Action) -> String {
+    let request = match action {
+        Action::Submit(job) => Request::Submit { job },
+        Action::SetPaused(paused) => Request::Pause { paused },
+        Action::Shift { job, up } => Request::Shift {
+            job: job.to_string(),
+            up,
+        },
+    };
+    let response = match Client::connect(endpoint).await {
+        Ok(mut client) => client.request(&request).await,
+        Err(e) => Err(e),
+    };
+    match response {
+        Ok(Response::Submitted { id }) => format!("Queued as {}", &id[..8.min(id.len())]),
+        Ok(Response::Paused { paused }) => pause_notice(paused),
+        Ok(Response::Requeued { id }) => format!("Moved {} in the queue", &id[..8.min(id.len())]),
+        Ok(other) => format!("Unexpected response: {:?}", other),
+        Err(e) => format!("Failed: {}", e),
+    }
+}
+
+/// Mirror one message of a `watch` stream into the local app state.
+async fn apply_watched(state: &RwLock<AppState>, response: Response) -> Result<()> {
+    let mut state = state.write().await;
+    match response {
+        Response::State {
+            gpus,
+            jobs,
+            paused,
+            host,
+        } => {
+            state.paused = paused;
+            state.host = host;
+            // Output only arrives once, so carry it over to the new records
+            let mut seen: HashMap<Uuid, JobInfo> =
+                state.jobs.drain(..).map(|job| (job.id, job)).collect();
+            state.gpus = gpus;
+            state.jobs = jobs
+                .iter()
+                .map(|record| {
+                    let mut job = record.to_job_info()?;
+                    if let Some(seen) = seen.remove(&job.id) {
+                        job.log_lines = seen.log_lines;
+                        job.log_dropped = seen.log_dropped;
+                        job.progress = seen.progress;
+                        job.last_output = seen.last_output;
+                    }
+                    Ok(job)
+                })
+                .collect::<Result<_>>()?;
+        }
+        Response::JobLog { id, lines } => {
+            if let Ok(id) = Uuid::parse_str(&id) {
+                for line in lines {
+                    state.push_log_line(id, line);
+                }
+            }
+        }
+        other => anyhow::bail!("unexpected response: {:?}", other),
+    }
+    Ok(())
+}
+
+/// Have the daemon apply its config file again.
+pub async fn reload(endpoint: &Endpoint) -> Result<()> {
+    let mut client = Client::connect(endpoint).await?;
+    match client.request(&Request::Reload).await? {
+        Response::Reloaded => eprintln!("[gparallel] Settings reloaded"),
+        other => anyhow::bail!("unexpected response: {:?}", other),
+    }
+    Ok(())
+}
+
+/// Have the daemon detect its GPUs again.
+pub async fn rescan(endpoint: &Endpoint) -> Result<()> {
+    let mut client = Client::connect(endpoint).await?;
+    match client.request(&Request::Rescan).await? {
+        Response::Rescanned { added, removed } if added.is_empty() && removed.is_empty() => {
+            eprintln!("[gparallel] No GPUs added or removed")
+        }
+        Response::Rescanned { added, removed } => {
+            for name in added {
+                eprintln!("[gparallel] Added {}", name);
+            }
+            for name in removed {
+                eprintln!("[gparallel] Removed {} (draining its running jobs)", name);
+            }
+        }
+        other => anyhow::bail!("unexpected response: {:?}", other),
+    }
+    Ok(())
+}
+
+/// Which jobs `wait` waits for.
+pub enum WaitFor {
+    /// Those that had not finished when it started, and any submitted since
+    All,
+    /// Every job with this tag
+    Tag(String),
+    /// These jobs (unique prefixes of their ids)
+    Jobs(Vec<String>),
+}
+
+/// Block until the jobs of `target` have finished, or `timeout` has passed.
+/// Fails if any of them failed, so scripts can submit, then wait.
+pub async fn wait(endpoint: &Endpoint, target: WaitFor, timeout: Option<Duration>) -> Result<()> {
+    let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+    let mut client = Client::connect(endpoint).await?;
+    let first = client.jobs().await?;
+    let finished = |job: &JobRecord| !matches!(job.state.as_str(), "queued" | "running");
+    let waits_for: Box<dyn Fn(&JobRecord) -> bool> = match target {
+        WaitFor::All => {
+            let done: HashSet<String> = first
+                .iter()
+                .filter(|job| finished(job))
+                .map(|job| job.id.clone())
+                .collect();
+            Box::new(move |job| !done.contains(&job.id))
+        }
+        WaitFor::Tag(tag) => {
+            if !first.iter().any(|job| job.tag.as_ref() == Some(&tag)) {
+                anyhow::bail!("no job has the tag '{}'", tag);
+            }
+            Box::new(move |job| job.tag.as_ref() == Some(&tag))
+        }
+        WaitFor::Jobs(prefixes) => {
+            let ids = prefixes
+                .iter()
+                .map(|prefix| find_record(&first, prefix).map(|job| job.id.clone()))
+                .collect::<Result<HashSet<String>>>()?;
+            Box::new(move |job| ids.contains(&job.id))
+        }
+    };
+
+    let mut jobs = first;
+    loop {
+        let waited: Vec<&JobRecord> = jobs.iter().filter(|job| waits_for(job)).collect();
+        let pending = waited.iter().filter(|job| !finished(job)).count();
+        if pending == 0 {
+            let failed: Vec<String> = waited
+                .iter()
+                .filter(|job| matches!(job.state.as_str(), "failed" | "timed_out" | "setup_failed"))
+                .map(|job| job.short_id())
+                .collect();
+            if !failed.is_empty() {
+                anyhow::bail!(
+                    "{} of {} jobs failed: {}",
+                    failed.len(),
+                    waited.len(),
+                    failed.join(", ")
+                );
+            }
+            eprintln!("[gparallel] {} jobs finished, none failed", waited.len());
+            return Ok(());
+        }
+        if deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline) {
+            anyhow::bail!(
+                "timed out with {} of {} jobs not finished",
+                pending,
+                waited.len()
+            );
+        }
+        tokio::time::sleep(WAIT_POLL).await;
+        jobs = client.jobs().await?;
+    }
+}
+
+/// The record whose id starts with `prefix`.
+fn find_record<'a>(jobs: &'a [JobRecord], prefix: &str) -> Result<&'a JobRecord> {
+    let mut matches = jobs
+        .iter()
+        .filter(|job| !prefix.is_empty() && job.id.starts_with(prefix));
+    match (matches.next(), matches.next()) {
+        (Some(job), None) => Ok(job),
+        (None, _) => anyhow::bail!("no job with id '{}'", prefix),
+        (Some(_), Some(_)) => anyhow::bail!("job id '{}' is ambiguous", prefix),
+    }
+}
+
+/// Give a queued job another priority.
+pub async fn requeue(endpoint: &Endpoint, job: String, priority: Priority) -> Result<()> {
+    let done = format!("now waits with {} priority", priority);
+    reorder(endpoint, Request::Requeue { job, priority }, &done).await
+}
+
+/// Put a queued job at the front or the back of the queue.
+pub async fn move_job(endpoint: &Endpoint, job: String, to: QueueEnd) -> Result<()> {
+    let done = match to {
+        QueueEnd::Front => "moved to the front of the queue",
+        QueueEnd::Back => "moved to the back of the queue",
+    };
+    reorder(endpoint, Request::Move { job, to }, done).await
+}
+
+async fn reorder(endpoint: &Endpoint, request: Request, done: &str) -> Result<()> {
+    let mut client = Client::connect(endpoint).await?;
+    match client.request(&request).await? {
+        Response::Requeued { id } => eprintln!("[gparallel] Job {} {}", &id[..8], done),
+        other => anyhow::bail!("unexpected response: {:?}", other),
+    }
+    Ok(())
+}
+
+/// Drain the daemon and wait for it to exit.
+pub async fn drain(endpoint: &Endpoint, timeout: Option<Duration>) -> Result<()> {
+    let mut client = Client::connect(endpoint).await?;
+    let timeout_secs = timeout.map(|t| t.as_secs_f64());
+    match client.request(&Request::Drain { timeout_secs }).await? {
+        Response::Draining { running, queued } => {
+            let limit = timeout.map_or(String::new(), |t| format!(" (at most {})", humanize(t)));
+            eprintln!(
+                "[gparallel] Draining: waiting for {} running jobs{}, {} queued jobs left",
+                running, limit, queued
+            );
+        }
+        other => anyhow::bail!("unexpected response: {:?}", other),
+    }
+    // The daemon says nothing more; it hangs up as it exits
+    while protocol::recv::<Response>(&mut client.reader)
+        .await?
+        .is_some()
+    {}
+    eprintln!("[gparallel] Daemon stopped");
+    Ok(())
+}
+
+/// Print the daemon's jobs as a table, or as JSON records for scripts.
+pub async fn status(endpoint: &Endpoint, json: bool) -> Result<()> {
+    let jobs = Client::connect(endpoint).await?.jobs().await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&jobs)?);
+    } else {
+        for line in status_table(&jobs) {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+fn status_table(jobs: &[JobRecord]) -> Vec<String> {
+    let rows: Vec<[String; 7]> = jobs
+        .iter()
+        .map(|job| {
+            [
+                job.short_id(),
+                job.owner.clone().unwrap_or_default(),
+                job.state.clone(),
+                job.gpu.map(|g| g.to_string()).unwrap_or_default(),
+                job.runtime(),
+                job.exit(),
+                job.cmd.clone(),
+            ]
+        })
+        .collect();
+
+    let header = ["ID", "OWNER", "STATE", "GPU", "RUNTIME", "EXIT", "COMMAND"];
+    let mut lines = report::table(header, &rows);
+
+    let count = |state: &str| jobs.iter().filter(|j| j.state == state).count();
+    let failed = count("failed") + count("timed_out") + count("setup_failed");
+    lines.push(format!(
+        "{} queued, {} running, {} completed, {} failed",
+        count("queued"),
+        count("running"),
+        count("completed"),
+        failed
+    ));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::{JobExit, JobInfo, JobState};
+    use uuid::Uuid;
+
+    #[test]
+    fn status_table_aligns_columns() {
+        let done = JobInfo {
+            state: JobState::Failed,
+            exit: Some(JobExit::Signal(9)),
+            gpu: Some(1),
+            owner: Some("alice".to_string()),
+            ..JobInfo::new(Uuid::nil(), "python train.py --lr 1")
+        };
+        let queued = JobInfo {
+            owner: Some("bob".to_string()),
+            ..JobInfo::new(Uuid::nil(), "sleep 1")
+        };
+        let jobs = [JobRecord::new(&done), JobRecord::new(&queued)];
+
+        assert_eq!(
+            status_table(&jobs),
+            vec![
+                "ID        OWNER  STATE   GPU  RUNTIME  EXIT   COMMAND",
+                "00000000  alice  failed  1             sig 9  python train.py --lr 1",
+                "00000000  bob    queued                       sleep 1",
+                "1 queued, 0 running, 0 completed, 1 failed",
+            ]
+        );
+    }
+}
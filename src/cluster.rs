@@ -0,0 +1,465 @@
+//! Cluster mode: a `gparallel agent` on each GPU machine offers its GPUs to
+//! a coordinator (`gparallel serve --agents ADDR`) over TCP. The coordinator
+//! schedules its one queue across the GPUs of every agent, and an agent only
+//! starts the processes it is told to and streams back their output and how
+//! they ended. Messages are JSON lines, as on the daemon's socket.
+
+use anyhow::Result;
+use nix::sys::signal::Signal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, DuplexStream},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot, Mutex,
+    },
+    task::JoinSet,
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth;
+use crate::protocol;
+use crate::scheduler::{self, Scheduler};
+use crate::shell::Shell;
+use crate::ssh::ListedGpu;
+use crate::ui::JobExit;
+
+/// How long an agent waits before connecting again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// What an agent sends.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentMessage {
+    /// The first message: the GPUs on offer, and the coordinator's token if
+    /// it has one
+    Hello {
+        host: String,
+        #[serde(default)]
+        token: Option<String>,
+        gpus: Vec<ListedGpu>,
+    },
+    /// A line a job printed
+    Output {
+        job: Uuid,
+        line: String,
+        #[serde(default)]
+        stderr: bool,
+    },
+    /// A job ended; with neither a code nor a signal it never started
+    Exited {
+        job: Uuid,
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+}
+
+/// What the coordinator sends.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CoordinatorMessage {
+    /// The agent's GPUs are in the pool now
+    Welcome,
+    /// Start a job on the GPUs `env` names
+    Run {
+        job: Uuid,
+        cmd: String,
+        shell: Shell,
+        env: Vec<(String, String)>,
+    },
+    /// Send `signal` to a job
+    Kill { job: Uuid, signal: i32 },
+    /// The agent was turned away; it should not connect again
+    Error { message: String },
+}
+
+/// A job started through an agent: its output, and how it ended once it has.
+pub struct AgentRun {
+    pub stdout: DuplexStream,
+    pub stderr: DuplexStream,
+    /// Dropped without a value if the agent goes away first
+    pub exit: oneshot::Receiver<JobExit>,
+}
+
+/// Where the output and exit of a job on an agent are delivered. The pipes
+/// have locks of their own, so a job slow to read its output does not hold
+/// up starting other jobs on the agent.
+struct RunSink {
+    stdout: Arc<Mutex<DuplexStream>>,
+    stderr: Arc<Mutex<DuplexStream>>,
+    exit: oneshot::Sender<JobExit>,
+}
+
+/// The coordinator's end of an agent's connection.
+#[derive(Clone)]
+pub struct AgentLink {
+    host: Arc<str>,
+    tx: UnboundedSender<CoordinatorMessage>,
+    /// Jobs running there; `None` once the agent has gone
+    runs: Arc<Mutex<Option<HashMap<Uuid, RunSink>>>>,
+}
+
+impl AgentLink {
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Have the agent start `cmd` for `job`.
+    pub async fn start(
+        &self,
+        job: Uuid,
+        cmd: &str,
+        shell: Shell,
+        env: Vec<(String, String)>,
+    ) -> Result<AgentRun> {
+        let mut runs = self.runs.lock().await;
+        let Some(runs) = runs.as_mut() else {
+            anyhow::bail!("agent disconnected");
+        };
+        let (stdout, stdout_sink) = tokio::io::duplex(64 * 1024);
+        let (stderr, stderr_sink) = tokio::io::duplex(64 * 1024);
+        let (exit_tx, exit) = oneshot::channel();
+        self.tx
+            .send(CoordinatorMessage::Run {
+                job,
+                cmd: cmd.to_string(),
+                shell,
+                env,
+            })
+            .map_err(|_| anyhow::anyhow!("agent disconnected"))?;
+        runs.insert(
+            job,
+            RunSink {
+                stdout: Arc::new(Mutex::new(stdout_sink)),
+                stderr: Arc::new(Mutex::new(stderr_sink)),
+                exit: exit_tx,
+            },
+        );
+        Ok(AgentRun {
+            stdout,
+            stderr,
+            exit,
+        })
+    }
+
+    /// Have the agent send `signal` to a job it runs.
+    pub fn kill(&self, job: Uuid, signal: Signal) {
+        self.tx
+            .send(CoordinatorMessage::Kill {
+                job,
+                signal: signal as i32,
+            })
+            .ok();
+    }
+
+    /// Deliver what the agent reports until it disconnects.
+    async fn route(&self, reader: &mut (impl AsyncBufRead + Unpin)) -> Result<()> {
+        while let Some(message) = protocol::recv(reader).await? {
+            match message {
+                AgentMessage::Output { job, line, stderr } => {
+                    let pipe = {
+                        let runs = self.runs.lock().await;
+                        let Some(run) = runs.as_ref().and_then(|runs| runs.get(&job)) else {
+                            continue;
+                        };
+                        if stderr {
+                            run.stderr.clone()
+                        } else {
+                            run.stdout.clone()
+                        }
+                    };
+                    let mut pipe = pipe.lock().await;
+                    pipe.write_all(format!("{}\n", line).as_bytes()).await.ok();
+                }
+                AgentMessage::Exited { job, code, signal } => {
+                    let run = self.runs.lock().await.as_mut().and_then(|r| r.remove(&job));
+                    if let Some(run) = run {
+                        let exit = match (code, signal) {
+                            (Some(code), _) => JobExit::Code(code),
+                            (None, Some(signal)) => JobExit::Signal(signal),
+                            (None, None) => JobExit::SpawnFailed,
+                        };
+                        run.exit.send(exit).ok();
+                    }
+                }
+                AgentMessage::Hello { .. } => anyhow::bail!("unexpected hello"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Take agents on `listener` and put their GPUs to work for `sched`. With a
+/// `token`, agents must present it.
+pub async fn serve(listener: TcpListener, sched: Scheduler, token: Option<Arc<str>>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let (sched, token) = (sched.clone(), token.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = serve_agent(stream, addr, sched, token).await {
+                        warn!("Agent {}: {}", addr, e);
+                    }
+                });
+            }
+            Err(e) => warn!("Failed to accept agent: {}", e),
+        }
+    }
+}
+
+async fn serve_agent(
+    stream: TcpStream,
+    addr: SocketAddr,
+    sched: Scheduler,
+    token: Option<Arc<str>>,
+) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    let Some(AgentMessage::Hello {
+        host,
+        token: given,
+        gpus,
+    }) = protocol::recv(&mut reader).await?
+    else {
+        anyhow::bail!("expected hello");
+    };
+    let refusal = match &token {
+        Some(expected) if !given.is_some_and(|given| auth::matches(expected, &given)) => {
+            Some("invalid token")
+        }
+        _ if gpus.is_empty() => Some("no GPUs offered"),
+        _ => None,
+    };
+    if let Some(message) = refusal {
+        let error = CoordinatorMessage::Error {
+            message: message.to_string(),
+        };
+        protocol::send(&mut write, &error).await?;
+        anyhow::bail!("refused {}: {}", host, message);
+    }
+    protocol::send(&mut write, &CoordinatorMessage::Welcome).await?;
+
+    let (tx, mut rx) = unbounded_channel();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if protocol::send(&mut write, &message).await.is_err() {
+                break;
+            }
+        }
+    });
+    let link = AgentLink {
+        host: Arc::from(host.as_str()),
+        tx,
+        runs: Arc::new(Mutex::new(Some(HashMap::new()))),
+    };
+    info!("Agent {} ({}) joined with {} GPUs", host, addr, gpus.len());
+    let ids = sched.add_agent(link.clone(), gpus).await;
+
+    let routed = link.route(&mut reader).await;
+    // Jobs still running there end as lost
+    link.runs.lock().await.take();
+    sched.remove_agent(&ids).await;
+    writer.abort();
+    info!("Agent {} left", host);
+    routed
+}
+
+/// How a connection to the coordinator ended.
+enum Ended {
+    Disconnected,
+    Refused(String),
+}
+
+/// `gparallel agent`: offer this machine's GPUs to the coordinator at
+/// `coordinator` and run the jobs it sends, connecting again whenever the
+/// connection drops.
+pub async fn run_agent(
+    coordinator: &str,
+    token_file: Option<&Path>,
+    name: Option<String>,
+) -> Result<()> {
+    let token = token_file.map(auth::read).transpose()?;
+    let host = match name {
+        Some(name) => name,
+        None => nix::unistd::gethostname()?.to_string_lossy().into_owned(),
+    };
+    let nvml = scheduler::init_nvml();
+    let (ids, names) = scheduler::detect_gpus_with_info(nvml.as_deref()).await?;
+    let mut uuids = scheduler::lookup_gpu_uuids(&ids, nvml.as_deref()).await;
+    let gpus: Vec<ListedGpu> = ids
+        .iter()
+        .zip(names)
+        .map(|(index, name)| ListedGpu {
+            index: *index,
+            name,
+            uuid: uuids.remove(index),
+        })
+        .collect();
+
+    loop {
+        let session = session(coordinator, &host, token.as_deref(), &gpus);
+        let ended = tokio::select! {
+            ended = session => ended,
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        };
+        match ended {
+            Ok(Ended::Refused(message)) => {
+                anyhow::bail!("The coordinator refused this agent: {}", message)
+            }
+            Ok(Ended::Disconnected) => warn!("Lost the connection to {}", coordinator),
+            Err(e) => warn!("Cannot reach coordinator {}: {}", coordinator, e),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+/// One connection to the coordinator. Jobs started over it are killed when
+/// it ends, since nobody is left to report them to.
+async fn session(
+    coordinator: &str,
+    host: &str,
+    token: Option<&str>,
+    gpus: &[ListedGpu],
+) -> Result<Ended> {
+    let stream = TcpStream::connect(coordinator).await?;
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    let hello = AgentMessage::Hello {
+        host: host.to_string(),
+        token: token.map(str::to_string),
+        gpus: gpus.to_vec(),
+    };
+    protocol::send(&mut write, &hello).await?;
+    match protocol::recv(&mut reader).await? {
+        Some(CoordinatorMessage::Welcome) => {}
+        Some(CoordinatorMessage::Error { message }) => return Ok(Ended::Refused(message)),
+        _ => anyhow::bail!("unexpected reply"),
+    }
+    info!("Offering {} GPUs to {}", gpus.len(), coordinator);
+
+    let (tx, mut rx) = unbounded_channel();
+    let mut writer = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if protocol::send(&mut write, &message).await.is_err() {
+                break;
+            }
+        }
+    });
+    let pids = Arc::new(std::sync::Mutex::new(HashMap::new()));
+    let mut jobs = JoinSet::new();
+    loop {
+        let message = tokio::select! {
+            message = protocol::recv(&mut reader) => message?,
+            _ = &mut writer => None,
+        };
+        match message {
+            Some(CoordinatorMessage::Run {
+                job,
+                cmd,
+                shell,
+                env,
+            }) => {
+                info!(job = %job, "Running job: {}", cmd);
+                jobs.spawn(run_job(job, cmd, shell, env, tx.clone(), pids.clone()));
+            }
+            Some(CoordinatorMessage::Kill { job, signal }) => {
+                let pid = pids.lock().unwrap().get(&job).copied();
+                if let (Some(pid), Ok(signal)) = (pid, Signal::try_from(signal)) {
+                    info!(job = %job, pid, "Killing job");
+                    nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid as i32), signal).ok();
+                }
+            }
+            Some(CoordinatorMessage::Welcome | CoordinatorMessage::Error { .. }) => {}
+            None => break,
+        }
+    }
+    writer.abort();
+    Ok(Ended::Disconnected)
+}
+
+/// Run a job the coordinator sent and report its output and exit.
+async fn run_job(
+    job: Uuid,
+    cmd: String,
+    shell: Shell,
+    env: Vec<(String, String)>,
+    tx: UnboundedSender<AgentMessage>,
+    pids: Arc<std::sync::Mutex<HashMap<Uuid, u32>>>,
+) {
+    let spawned = shell.command(&cmd).and_then(|mut command| {
+        command
+            .envs(env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        Ok(command.spawn()?)
+    });
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            warn!(job = %job, "Failed to spawn job: {}", e);
+            let exited = AgentMessage::Exited {
+                job,
+                code: None,
+                signal: None,
+            };
+            tx.send(exited).ok();
+            return;
+        }
+    };
+    if let Some(pid) = child.id() {
+        pids.lock().unwrap().insert(job, pid);
+    }
+    let mut readers = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(tokio::spawn(forward(job, stdout, false, tx.clone())));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(tokio::spawn(forward(job, stderr, true, tx.clone())));
+    }
+    let status = child.wait().await;
+    pids.lock().unwrap().remove(&job);
+    // Bounded, as a backgrounded grandchild may keep the pipes open
+    for reader in readers {
+        tokio::time::timeout(Duration::from_secs(1), reader)
+            .await
+            .ok();
+    }
+    let exit = match status {
+        Ok(status) => JobExit::from_status(status),
+        Err(_) => JobExit::SpawnFailed,
+    };
+    let (code, signal) = match exit {
+        JobExit::Code(code) => (Some(code), None),
+        JobExit::Signal(signal) => (None, Some(signal)),
+        _ => (None, None),
+    };
+    tx.send(AgentMessage::Exited { job, code, signal }).ok();
+}
+
+/// Send every line of a job's pipe to the coordinator.
+async fn forward(
+    job: Uuid,
+    pipe: impl AsyncRead + Unpin,
+    stderr: bool,
+    tx: UnboundedSender<AgentMessage>,
+) {
+    let mut lines = BufReader::new(pipe).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if tx.send(AgentMessage::Output { job, line, stderr }).is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::Result;
+use clap::CommandFactory;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Default options loaded from `~/.config/gparallel/config.toml` and
+/// `./gparallel.toml`, the latter taking precedence.
+///
+/// Top-level keys are long option names without the leading dashes; a
+/// `[profile.<name>]` table holds overrides selected with `--profile <name>`:
+///
+/// ```toml
+/// progress = "bars"
+///
+/// [profile.big-sweep]
+/// interleave = true
+/// ```
+pub struct Config {
+    defaults: Table,
+    profiles: Table,
+    sources: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let mut config = Config {
+            defaults: Table::new(),
+            profiles: Table::new(),
+            sources: Vec::new(),
+        };
+
+        let mut paths = Vec::new();
+        if let Some(home) = std::env::var_os("HOME") {
+            paths.push(Path::new(&home).join(".config/gparallel/config.toml"));
+        }
+        paths.push(PathBuf::from("gparallel.toml"));
+
+        for path in paths {
+            if path.exists() {
+                config.merge_file(&path)?;
+            }
+        }
+        Ok(config)
+    }
+
+    fn merge_file(&mut self, path: &Path) -> Result<()> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read config '{}': {}", path.display(), e))?;
+        let mut table: Table = content
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Failed to parse config '{}': {}", path.display(), e))?;
+
+        if let Some(profiles) = table.remove("profile") {
+            let Value::Table(profiles) = profiles else {
+                anyhow::bail!("{}: 'profile' must be a table", path.display());
+            };
+            for (name, overrides) in profiles {
+                let Value::Table(overrides) = overrides else {
+                    anyhow::bail!("{}: profile '{}' must be a table", path.display(), name);
+                };
+                match self.profiles.get_mut(&name) {
+                    Some(Value::Table(existing)) => existing.extend(overrides),
+                    _ => {
+                        self.profiles.insert(name, Value::Table(overrides));
+                    }
+                }
+            }
+        }
+        self.defaults.extend(table);
+        self.sources.push(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Turn the defaults (plus the selected profile) into command-line
+    /// arguments. They are placed before the user's own arguments, so
+    /// anything given on the command line wins.
+    pub fn to_args<C: CommandFactory>(&self, profile: Option<&str>) -> Result<Vec<String>> {
+        let mut options = self.defaults.clone();
+        if let Some(name) = profile {
+            match self.profiles.get(name) {
+                Some(Value::Table(overrides)) => options.extend(overrides.clone()),
+                _ => anyhow::bail!(
+                    "Unknown profile '{}' (looked in: {})",
+                    name,
+                    self.source_list()
+                ),
+            }
+        }
+
+        let command = C::command();
+        let known: Vec<&str> = command
+            .get_arguments()
+            .filter_map(|a| a.get_long())
+            .collect();
+
+        let mut args = Vec::new();
+        for (key, value) in options {
+            if key == "profile" || !known.contains(&key.as_str()) {
+                anyhow::bail!(
+                    "Unknown option '{}' in config (looked in: {})",
+                    key,
+                    self.source_list()
+                );
+            }
+            let flag = format!("--{}", key);
+            match value {
+                Value::Boolean(true) => args.push(flag),
+                // Flags have no negated form, so there is nothing to pass
+                Value::Boolean(false) => {}
+                Value::Array(items) => {
+                    for item in items {
+                        args.push(flag.clone());
+                        args.push(scalar(&key, item)?);
+                    }
+                }
+                other => {
+                    args.push(flag);
+                    args.push(scalar(&key, other)?);
+                }
+            }
+        }
+        Ok(args)
+    }
+
+    fn source_list(&self) -> String {
+        if self.sources.is_empty() {
+            return "no config files found".to_string();
+        }
+        self.sources
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn scalar(key: &str, value: Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        _ => anyhow::bail!(
+            "Config option '{}' must be a string, number or boolean",
+            key
+        ),
+    }
+}
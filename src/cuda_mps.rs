@@ -0,0 +1,177 @@
+//! CUDA MPS (Multi-Process Service) for GPUs that run several jobs at once.
+//! Without it the jobs sharing a GPU take turns on it; through an MPS
+//! server their kernels run side by side on the SMs. Each GPU gets its own
+//! `nvidia-cuda-mps-control` daemon, found by its jobs through the pipe
+//! directory in their environment.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+/// The MPS control daemon of one GPU.
+#[derive(Debug, Clone)]
+pub struct MpsDaemon {
+    /// The GPU as the daemon's `CUDA_VISIBLE_DEVICES` names it: its index,
+    /// or a MIG instance's UUID
+    device: String,
+    /// Holds the daemon's `pipe` and `log` directories
+    dir: PathBuf,
+}
+
+impl MpsDaemon {
+    fn new(base: &Path, gpu: u32, device: String) -> Self {
+        Self {
+            device,
+            dir: base.join(format!("gpu{}", gpu)),
+        }
+    }
+
+    /// What a job needs in its environment to run through this daemon. The
+    /// daemon only sees its own GPU, which is device 0 to its clients.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("CUDA_VISIBLE_DEVICES", "0".to_string()),
+            (
+                "CUDA_MPS_PIPE_DIRECTORY",
+                self.pipe_dir().display().to_string(),
+            ),
+            (
+                "CUDA_MPS_LOG_DIRECTORY",
+                self.log_dir().display().to_string(),
+            ),
+        ]
+    }
+
+    fn pipe_dir(&self) -> PathBuf {
+        self.dir.join("pipe")
+    }
+
+    fn log_dir(&self) -> PathBuf {
+        self.dir.join("log")
+    }
+
+    fn control(&self) -> Command {
+        let mut cmd = Command::new("nvidia-cuda-mps-control");
+        cmd.env("CUDA_VISIBLE_DEVICES", &self.device)
+            .env("CUDA_MPS_PIPE_DIRECTORY", self.pipe_dir())
+            .env("CUDA_MPS_LOG_DIRECTORY", self.log_dir());
+        cmd
+    }
+
+    async fn start(&self) -> Result<()> {
+        tokio::fs::create_dir_all(self.pipe_dir()).await?;
+        tokio::fs::create_dir_all(self.log_dir()).await?;
+        let status = self.control().arg("-d").status().await?;
+        if !status.success() {
+            anyhow::bail!("nvidia-cuda-mps-control -d exited with {}", status);
+        }
+        Ok(())
+    }
+
+    /// Ask the daemon to quit, which it does once its clients are gone.
+    async fn stop(&self) -> Result<()> {
+        let mut child = self
+            .control()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(b"quit\n").await?;
+        }
+        child.wait().await?;
+        Ok(())
+    }
+}
+
+/// The MPS daemons gparallel started, by GPU id. GPUs without one run their
+/// jobs the usual way.
+#[derive(Debug, Default)]
+pub struct CudaMps {
+    base: Option<PathBuf>,
+    daemons: HashMap<u32, MpsDaemon>,
+}
+
+impl CudaMps {
+    /// Start a daemon for each GPU in `devices` (GPU id and the device the
+    /// daemon is to see). A GPU whose daemon fails to start goes without.
+    pub async fn start(devices: Vec<(u32, String)>) -> Self {
+        let base = std::env::temp_dir().join(format!("gparallel-mps-{}", std::process::id()));
+        let mut daemons = HashMap::new();
+        for (gpu, device) in devices {
+            let daemon = MpsDaemon::new(&base, gpu, device);
+            match daemon.start().await {
+                Ok(()) => {
+                    info!(gpu, "Started CUDA MPS daemon in {}", daemon.dir.display());
+                    daemons.insert(gpu, daemon);
+                }
+                Err(e) => warn!(gpu, "No CUDA MPS for this GPU: {}", e),
+            }
+        }
+        Self {
+            base: Some(base),
+            daemons,
+        }
+    }
+
+    /// The daemon single-GPU jobs on `gpu` go through.
+    pub fn daemon(&self, gpu: u32) -> Option<&MpsDaemon> {
+        self.daemons.get(&gpu)
+    }
+
+    /// Stop every daemon and remove their directories.
+    pub async fn stop(&self) {
+        self.quit().await;
+        if let Some(base) = &self.base {
+            tokio::fs::remove_dir_all(base).await.ok();
+        }
+    }
+
+    /// Leave the daemons to jobs that outlive us: each is asked to quit,
+    /// which it does after the last of its clients, and their directories
+    /// stay for those jobs to reach it through.
+    pub async fn release(&self) {
+        if self.daemons.is_empty() {
+            return;
+        }
+        self.quit().await;
+        if let Some(base) = &self.base {
+            info!(
+                "CUDA MPS daemons quit once the jobs still running on them end; remove {} after that",
+                base.display()
+            );
+        }
+    }
+
+    async fn quit(&self) {
+        for (gpu, daemon) in &self.daemons {
+            // Not waiting for a daemon that holds on to its clients
+            match tokio::time::timeout(Duration::from_secs(5), daemon.stop()).await {
+                Ok(Err(e)) => debug!(gpu, "Failed to stop CUDA MPS daemon: {}", e),
+                Err(_) => debug!(gpu, "CUDA MPS daemon still waiting for its clients"),
+                Ok(Ok(())) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_find_the_daemon_of_their_gpu() {
+        let daemon = MpsDaemon::new(Path::new("/tmp/mps"), 3, "MIG-1".to_string());
+        let env: HashMap<_, _> = daemon.env().into_iter().collect();
+
+        assert_eq!(env["CUDA_MPS_PIPE_DIRECTORY"], "/tmp/mps/gpu3/pipe");
+        assert_eq!(env["CUDA_MPS_LOG_DIRECTORY"], "/tmp/mps/gpu3/log");
+        assert_eq!(env["CUDA_VISIBLE_DEVICES"], "0");
+        assert!(CudaMps::default().daemon(3).is_none());
+    }
+}
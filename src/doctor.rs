@@ -0,0 +1,211 @@
+//! `gparallel doctor`: report what the GPU detection sees, so silent
+//! fallbacks (e.g. to "GPU0 only") can be diagnosed.
+
+use anyhow::Result;
+use nvml_wrapper::Nvml;
+use std::env;
+use tokio::process::Command;
+
+use crate::apple;
+use crate::health::{self, XidLog};
+use crate::mig;
+use crate::scheduler::detect_gpus_with_info;
+
+pub async fn run() -> Result<()> {
+    let mut degraded = Vec::new();
+
+    println!("Environment");
+    let visible = env::var("CUDA_VISIBLE_DEVICES").ok();
+    row(
+        "CUDA_VISIBLE_DEVICES",
+        visible.as_deref().unwrap_or("(unset)"),
+    );
+    let mock = env::var("GPARALLEL_MOCK_GPUS").ok();
+    row("GPARALLEL_MOCK_GPUS", mock.as_deref().unwrap_or("(unset)"));
+    if let Some(list) = &visible {
+        let ignored: Vec<&str> = list
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty() && s.parse::<u32>().is_err())
+            .collect();
+        if !ignored.is_empty() {
+            degraded.push(format!(
+                "CUDA_VISIBLE_DEVICES entries {:?} are not indices and are ignored",
+                ignored
+            ));
+        }
+    }
+
+    // A Mac's GPU needs neither NVML nor nvidia-smi
+    let apple_gpu = apple::detect().await;
+    if let Some(chip) = &apple_gpu {
+        println!();
+        println!("Apple Silicon");
+        row("GPU", chip);
+        let memory = match apple::memory_mb().await {
+            Some((free, total)) => format!("{} / {} MiB free (unified)", free, total),
+            None => {
+                degraded.push("memory pressure of the Apple GPU is unknown".to_string());
+                "?".to_string()
+            }
+        };
+        row("memory", &memory);
+    }
+
+    println!();
+    println!("NVML");
+    let nvml = Nvml::init();
+    let nvml_ok = match &nvml {
+        Ok(nvml) => {
+            report_nvml(nvml, &mut degraded);
+            true
+        }
+        Err(e) => {
+            row("status", &format!("unavailable ({})", e));
+            if apple_gpu.is_none() {
+                degraded.push("no NVML: GPU memory monitoring is disabled".to_string());
+            }
+            false
+        }
+    };
+
+    println!();
+    println!("nvidia-smi");
+    let smi_ok = match Command::new("nvidia-smi").arg("-L").output().await {
+        Ok(out) if out.status.success() => {
+            let listed = String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .filter(|l| l.contains("GPU"))
+                .count();
+            row("status", &format!("ok, {} GPU(s) listed", listed));
+            listed > 0
+        }
+        Ok(out) => {
+            row("status", &format!("failed ({})", out.status));
+            false
+        }
+        Err(e) => {
+            row("status", &format!("not runnable ({})", e));
+            false
+        }
+    };
+    let migs = nvml.as_ref().map(mig::detect).unwrap_or_default();
+    for mig in migs {
+        row(
+            &format!("GPU {} MIG {}", mig.parent, mig.index),
+            &format!(
+                "{}, {} (scheduled in place of GPU {})",
+                mig.profile, mig.uuid, mig.parent
+            ),
+        );
+    }
+    if !nvml_ok && !smi_ok && apple_gpu.is_none() {
+        degraded.push("no NVML and no nvidia-smi: GPU UUIDs and names are unknown".to_string());
+    }
+
+    println!();
+    println!("Scheduling");
+    let (ids, names) = detect_gpus_with_info(nvml.as_ref().ok()).await?;
+    let source = if mock.is_some() {
+        "GPARALLEL_MOCK_GPUS"
+    } else if apple_gpu.is_some() {
+        "sysctl (Apple Silicon)"
+    } else if visible
+        .as_deref()
+        .is_some_and(|v| v.split(',').any(|s| s.trim().parse::<u32>().is_ok()))
+    {
+        "CUDA_VISIBLE_DEVICES"
+    } else if nvml_ok {
+        "NVML"
+    } else if smi_ok {
+        "nvidia-smi"
+    } else {
+        degraded.push("no GPU could be detected: every job runs on GPU 0".to_string());
+        "fallback"
+    };
+    let gpus: Vec<String> = ids
+        .iter()
+        .zip(&names)
+        .map(|(id, name)| format!("{} ({})", id, name))
+        .collect();
+    row("GPUs", &format!("{} [from {}]", gpus.join(", "), source));
+
+    println!();
+    if degraded.is_empty() {
+        println!("All checks passed.");
+    } else {
+        println!("Degraded:");
+        for item in &degraded {
+            println!("  - {}", item);
+        }
+    }
+    Ok(())
+}
+
+fn report_nvml(nvml: &Nvml, degraded: &mut Vec<String>) {
+    row(
+        "status",
+        &format!(
+            "ok (NVML {})",
+            nvml.sys_nvml_version().unwrap_or_else(|_| "?".into())
+        ),
+    );
+    row(
+        "driver",
+        &nvml.sys_driver_version().unwrap_or_else(|_| "?".into()),
+    );
+    match nvml.sys_cuda_driver_version() {
+        Ok(v) => row(
+            "CUDA (driver)",
+            &format!(
+                "{}.{}",
+                nvml_wrapper::cuda_driver_version_major(v),
+                nvml_wrapper::cuda_driver_version_minor(v)
+            ),
+        ),
+        Err(e) => row("CUDA (driver)", &format!("unknown ({})", e)),
+    }
+
+    let count = nvml.device_count().unwrap_or(0);
+    if count == 0 {
+        degraded.push("NVML reports no devices".to_string());
+    }
+    for i in 0..count {
+        let device = match nvml.device_by_index(i) {
+            Ok(device) => device,
+            Err(e) => {
+                row(&format!("GPU {}", i), &format!("unreadable ({})", e));
+                degraded.push(format!("GPU {} cannot be queried through NVML", i));
+                continue;
+            }
+        };
+        let name = device.name().unwrap_or_else(|_| "?".into());
+        let memory = match device.memory_info() {
+            Ok(m) => format!(
+                "{} / {} MiB free",
+                m.free / 1024 / 1024,
+                m.total / 1024 / 1024
+            ),
+            Err(_) => {
+                degraded.push(format!("GPU {}: memory info unavailable", i));
+                "memory ?".to_string()
+            }
+        };
+        let compute = device
+            .cuda_compute_capability()
+            .map(|c| format!("sm_{}{}", c.major, c.minor))
+            .unwrap_or_else(|_| "sm_?".into());
+        let uuid = device.uuid().unwrap_or_else(|_| "?".into());
+        row(
+            &format!("GPU {}", i),
+            &format!("{}, {}, {}, {}", name, memory, compute, uuid),
+        );
+        if let Some(fault) = health::check(nvml, i, &XidLog::default()) {
+            degraded.push(format!("GPU {} is quarantined: {}", i, fault));
+        }
+    }
+}
+
+fn row(label: &str, value: &str) {
+    println!("  {:<22} {}", label, value);
+}
@@ -0,0 +1,112 @@
+//! Duration parsing for command-line options and the one formatting routine
+//! used everywhere a duration is shown to the user.
+
+use anyhow::Result;
+use std::time::Duration;
+
+/// Parse durations like `90m`, `1h30m`, `1h 30m`, `2d`, `1.5h`, `45 seconds`
+/// or a bare number of seconds.
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let s = input.trim().to_ascii_lowercase();
+    if s.is_empty() {
+        anyhow::bail!("empty duration");
+    }
+    if let Ok(secs) = s.parse::<f64>() {
+        return from_secs(secs, input);
+    }
+
+    let mut total = 0.0;
+    let mut rest = s.as_str();
+    while !rest.is_empty() {
+        rest = rest.trim_start();
+        let num_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        if num_len == 0 {
+            anyhow::bail!("invalid duration '{}': expected a number", input);
+        }
+        let value: f64 = rest[..num_len]
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid duration '{}'", input))?;
+        rest = rest[num_len..].trim_start();
+
+        let unit_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let unit = &rest[..unit_len];
+        rest = rest[unit_len..].trim_start_matches([' ', ',']);
+
+        let scale = match unit {
+            "ms" | "msec" | "millis" | "millisecond" | "milliseconds" => 0.001,
+            "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+            "d" | "day" | "days" => 86400.0,
+            "w" | "wk" | "week" | "weeks" => 604800.0,
+            "" => anyhow::bail!("invalid duration '{}': missing unit after {}", input, value),
+            other => anyhow::bail!("invalid duration '{}': unknown unit '{}'", input, other),
+        };
+        total += value * scale;
+    }
+    from_secs(total, input)
+}
+
+fn from_secs(secs: f64, input: &str) -> Result<Duration> {
+    if !secs.is_finite() || secs < 0.0 {
+        anyhow::bail!("invalid duration '{}'", input);
+    }
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// Human-readable form used in the TUI, summaries and reports:
+/// `45s`, `3m 07s`, `1h 02m 13s`, `2d 04h 00m`.
+pub fn humanize(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (days, hours, mins, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {:02}h {:02}m", days, hours, mins)
+    } else if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, mins, secs)
+    } else if mins > 0 {
+        format!("{}m {:02}s", mins, secs)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: &str) -> u64 {
+        parse_duration(s).unwrap().as_secs()
+    }
+
+    #[test]
+    fn parses_compound_forms() {
+        assert_eq!(secs("90m"), 5400);
+        assert_eq!(secs("1h30m"), 5400);
+        assert_eq!(secs("1h 30m"), 5400);
+        assert_eq!(secs("2d"), 172800);
+        assert_eq!(secs("1.5h"), 5400);
+        assert_eq!(secs("45 seconds"), 45);
+        assert_eq!(secs("2 hours, 5 mins"), 7500);
+        assert_eq!(secs("30"), 30);
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for bad in ["", "h", "10x", "1h-5m", "-3", "abc"] {
+            assert!(parse_duration(bad).is_err(), "{:?} should not parse", bad);
+        }
+    }
+
+    #[test]
+    fn humanizes_consistently() {
+        assert_eq!(humanize(Duration::from_secs(45)), "45s");
+        assert_eq!(humanize(Duration::from_secs(187)), "3m 07s");
+        assert_eq!(humanize(Duration::from_secs(3733)), "1h 02m 13s");
+        assert_eq!(humanize(Duration::from_secs(187200)), "2d 04h 00m");
+    }
+}
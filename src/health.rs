@@ -0,0 +1,132 @@
+//! GPU health as NVML sees it. A GPU that has fallen off the bus, hit an
+//! uncorrectable ECC error, has memory pages waiting to be retired or
+//! raised a hardware Xid only kills the jobs sent to it, so the scheduler
+//! quarantines it until it has been reset and gparallel restarted.
+
+use nvml_wrapper::enum_wrappers::device::{EccCounter, MemoryError};
+use nvml_wrapper::error::NvmlError;
+use nvml_wrapper::Nvml;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// Why a GPU is quarantined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Fallen off the bus, or otherwise gone from NVML
+    Lost,
+    /// Uncorrectable (double-bit) ECC errors since the driver loaded
+    UncorrectedEcc(u64),
+    /// Pages with errors that are only retired at the next reset
+    RetiredPagesPending,
+    Xid(u64),
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::Lost => f.write_str("fallen off the bus"),
+            Fault::UncorrectedEcc(count) => write!(f, "{} uncorrectable ECC errors", count),
+            Fault::RetiredPagesPending => f.write_str("memory pages pending retirement"),
+            Fault::Xid(xid) => write!(f, "Xid {}", xid),
+        }
+    }
+}
+
+/// The latest hardware Xid of each GPU (by NVML index) since we started.
+pub type XidLog = Arc<Mutex<HashMap<u32, u64>>>;
+
+/// Xids that point at the GPU rather than at the job that ran on it:
+/// ECC and page retirement (48, 63, 64, 92, 94, 95), NVLink (74), fallen
+/// off the bus (79) and GSP failures (119, 120). Others, such as 13 or 31,
+/// are a program's own faults.
+const HARDWARE_XIDS: &[u64] = &[48, 63, 64, 74, 79, 92, 94, 95, 119, 120];
+
+/// What is wrong with the GPU at NVML index `index`, if anything.
+pub fn check(nvml: &Nvml, index: u32, xids: &XidLog) -> Option<Fault> {
+    let device = match nvml.device_by_index(index) {
+        Ok(device) => device,
+        Err(NvmlError::GpuLost) => return Some(Fault::Lost),
+        Err(_) => return None,
+    };
+    // Without ECC (most consumer GPUs) the counter is not supported
+    match device.total_ecc_errors(MemoryError::Uncorrected, EccCounter::Volatile) {
+        Ok(count) if count > 0 => return Some(Fault::UncorrectedEcc(count)),
+        Err(NvmlError::GpuLost) => return Some(Fault::Lost),
+        _ => {}
+    }
+    if device.are_pages_pending_retired().unwrap_or(false) {
+        return Some(Fault::RetiredPagesPending);
+    }
+    xids.lock().unwrap().get(&index).map(|xid| Fault::Xid(*xid))
+}
+
+/// Record the hardware Xids NVML reports in `log`, on a thread of its own
+/// as NVML only waits for events blocking, until `cancel` fires.
+#[cfg(target_os = "linux")]
+pub fn watch_xids(nvml: Arc<Nvml>, log: XidLog, cancel: CancellationToken) {
+    use nvml_wrapper::bitmasks::event::EventTypes;
+    use nvml_wrapper::enums::event::XidError;
+
+    std::thread::spawn(move || {
+        let Ok(mut set) = nvml.create_event_set() else {
+            return;
+        };
+        for index in 0..nvml.device_count().unwrap_or(0) {
+            let Ok(device) = nvml.device_by_index(index) else {
+                continue;
+            };
+            let supported = device
+                .supported_event_types()
+                .is_ok_and(|types| types.contains(EventTypes::CRITICAL_XID_ERROR));
+            if !supported {
+                continue;
+            }
+            set = match device.register_events(EventTypes::CRITICAL_XID_ERROR, set) {
+                Ok(set) => set,
+                Err(e) => {
+                    tracing::debug!("Cannot watch for Xid errors: {}", e);
+                    return;
+                }
+            };
+        }
+        while !cancel.is_cancelled() {
+            match set.wait(1000) {
+                Ok(event) => {
+                    let Some(XidError::Value(xid)) = event.event_data else {
+                        continue;
+                    };
+                    let Ok(index) = event.device.index() else {
+                        continue;
+                    };
+                    tracing::warn!(gpu = index, "GPU reported Xid {}", xid);
+                    if HARDWARE_XIDS.contains(&xid) {
+                        log.lock().unwrap().insert(index, xid);
+                    }
+                }
+                Err(NvmlError::Timeout) => {}
+                // A lost GPU ends the wait for good; `check` notices it
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch_xids(_nvml: Arc<Nvml>, _log: XidLog, _cancel: CancellationToken) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faults_read_as_reasons() {
+        assert_eq!(Fault::Xid(79).to_string(), "Xid 79");
+        assert_eq!(
+            Fault::UncorrectedEcc(2).to_string(),
+            "2 uncorrectable ECC errors"
+        );
+        assert!(HARDWARE_XIDS.contains(&48));
+        assert!(!HARDWARE_XIDS.contains(&13));
+    }
+}
@@ -0,0 +1,219 @@
+//! The job history: every job `gparallel run` or `serve` has finished, one
+//! `JobRecord` per line of `~/.local/share/gparallel/history.jsonl`, for
+//! `gparallel history` to search.
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use tracing::warn;
+
+use crate::report::{self, JobRecord};
+use crate::store;
+
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    /// The history in gparallel's data directory.
+    pub fn open_default() -> Self {
+        Self::at(store::data_dir().join("history.jsonl"))
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Add a finished job. Each record is a single write to a file opened
+    /// for appending, so any number of runs and daemons can share it.
+    pub fn append(&self, job: &JobRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(job)?;
+        line.push(b'\n');
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&line))
+            .map_err(|e| {
+                anyhow::anyhow!("Failed to write history '{}': {}", self.path.display(), e)
+            })
+    }
+
+    /// Every job in the history, oldest first. Lines that cannot be read,
+    /// e.g. cut short by a crash or not even UTF-8, are skipped.
+    pub fn load(&self) -> Result<Vec<JobRecord>> {
+        let content = match std::fs::read(&self.path) {
+            Ok(content) => String::from_utf8_lossy(&content).into_owned(),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                anyhow::bail!("Failed to read history '{}': {}", self.path.display(), e)
+            }
+        };
+        let mut jobs = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(job) => jobs.push(job),
+                Err(e) => warn!(
+                    "{}:{}: skipping entry: {}",
+                    self.path.display(),
+                    number + 1,
+                    e
+                ),
+            }
+        }
+        Ok(jobs)
+    }
+}
+
+/// Which jobs `gparallel history` shows.
+#[derive(Debug, Default)]
+pub struct Query {
+    /// Finished at or after this time
+    pub since: Option<DateTime<Local>>,
+    /// Only failed ones, including setup failures
+    pub failed: bool,
+    /// Whose command contains this text
+    pub grep: Option<String>,
+}
+
+impl Query {
+    fn matches(&self, job: &JobRecord) -> bool {
+        if self.failed && !matches!(job.state.as_str(), "failed" | "timed_out" | "setup_failed") {
+            return false;
+        }
+        if let Some(text) = &self.grep {
+            if !job.cmd.contains(text.as_str()) {
+                return false;
+            }
+        }
+        match self.since {
+            Some(since) => finished_at(job).is_some_and(|at| at >= since),
+            None => true,
+        }
+    }
+}
+
+fn finished_at(job: &JobRecord) -> Option<DateTime<Local>> {
+    let at = DateTime::parse_from_rfc3339(job.finished_at.as_deref()?).ok()?;
+    Some(at.with_timezone(&Local))
+}
+
+/// `gparallel history`: print the jobs matching `query`, oldest first, as a
+/// table or as JSON records for scripts.
+pub fn show(history: &History, query: &Query, json: bool) -> Result<()> {
+    let jobs: Vec<JobRecord> = history
+        .load()?
+        .into_iter()
+        .filter(|job| query.matches(job))
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&jobs)?);
+    } else {
+        for line in history_table(&jobs) {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+/// `jobs` as rows under a header, with a count of them and of the failed
+/// ones below.
+pub fn history_table(jobs: &[JobRecord]) -> Vec<String> {
+    let rows: Vec<[String; 7]> = jobs
+        .iter()
+        .map(|job| {
+            [
+                job.short_id(),
+                finished_at(job)
+                    .map(|at| at.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default(),
+                job.state.clone(),
+                job.gpu.map(|g| g.to_string()).unwrap_or_default(),
+                job.runtime(),
+                job.exit(),
+                job.cmd.clone(),
+            ]
+        })
+        .collect();
+    let header = [
+        "ID", "FINISHED", "STATE", "GPU", "RUNTIME", "EXIT", "COMMAND",
+    ];
+    let mut lines = report::table(header, &rows);
+    let failed = jobs
+        .iter()
+        .filter(|job| matches!(job.state.as_str(), "failed" | "timed_out" | "setup_failed"))
+        .count();
+    lines.push(format!("{} jobs, {} failed", jobs.len(), failed));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::{JobInfo, JobState};
+    use uuid::Uuid;
+
+    fn finished(cmd: &str, state: JobState, at: &str) -> JobRecord {
+        let mut job = JobRecord::new(&JobInfo {
+            state,
+            ..JobInfo::new(Uuid::new_v4(), cmd)
+        });
+        job.finished_at = Some(at.to_string());
+        job
+    }
+
+    #[test]
+    fn history_keeps_jobs_across_appends_and_skips_bad_lines() {
+        let path = std::env::temp_dir().join(format!("gparallel-history-{}", Uuid::new_v4()));
+        let history = History::at(path.join("history.jsonl"));
+        assert!(history.load().unwrap().is_empty());
+
+        let first = finished("a", JobState::Completed, "2026-01-01T00:00:00Z");
+        history.append(&first).unwrap();
+        let mut content = std::fs::read(path.join("history.jsonl")).unwrap();
+        content.extend_from_slice(b"{\"id\": \xff\n");
+        std::fs::write(path.join("history.jsonl"), content).unwrap();
+        history
+            .append(&finished("b", JobState::Failed, "2026-01-02T00:00:00Z"))
+            .unwrap();
+
+        let jobs = history.load().unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(
+            (jobs[0].id.as_str(), jobs[1].cmd.as_str()),
+            (first.id.as_str(), "b")
+        );
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn query_filters_by_time_state_and_command() {
+        let old = finished("python resnet.py", JobState::Failed, "2026-01-01T00:00:00Z");
+        let new = finished(
+            "python resnet.py",
+            JobState::Completed,
+            "2026-03-01T00:00:00Z",
+        );
+        let other = finished("python vit.py", JobState::Failed, "2026-03-01T00:00:00Z");
+        let query = Query {
+            since: Some("2026-02-01T00:00:00Z".parse::<DateTime<Local>>().unwrap()),
+            failed: false,
+            grep: Some("resnet".to_string()),
+        };
+
+        assert!(!query.matches(&old));
+        assert!(query.matches(&new));
+        assert!(!query.matches(&other));
+        let failed = Query {
+            failed: true,
+            ..Default::default()
+        };
+        assert!(failed.matches(&old) && failed.matches(&other) && !failed.matches(&new));
+    }
+}
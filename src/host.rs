@@ -0,0 +1,78 @@
+//! Load, CPU and memory of the machine the jobs run on, for the status bar.
+//! GPU jobs often wait on data loading or run out of host memory, which
+//! nothing on the GPU side shows.
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HostUsage {
+    /// One-minute load average
+    pub load: f64,
+    /// Busy share of all CPUs since the previous sample, once there is one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_percent: Option<f64>,
+    pub mem_available: u64,
+    pub mem_total: u64,
+}
+
+/// Samples `HostUsage`, keeping the CPU counters between calls as CPU
+/// usage is the change in them.
+#[derive(Debug, Default)]
+pub struct HostSampler {
+    /// Busy and total jiffies at the last sample
+    last_cpu: Option<(u64, u64)>,
+}
+
+impl HostSampler {
+    /// The host as it is now, or `None` where `/proc` cannot be read.
+    pub fn sample(&mut self) -> Option<HostUsage> {
+        let loadavg = std::fs::read_to_string("/proc/loadavg").ok()?;
+        let stat = std::fs::read_to_string("/proc/stat").ok()?;
+        let cpu = parse_cpu_times(&stat)?;
+        let cpu_percent = self.last_cpu.and_then(|(busy, total)| {
+            let elapsed = cpu.1.checked_sub(total).filter(|&t| t > 0)?;
+            Some(cpu.0.saturating_sub(busy) as f64 * 100.0 / elapsed as f64)
+        });
+        self.last_cpu = Some(cpu);
+        Some(HostUsage {
+            load: parse_load(&loadavg)?,
+            cpu_percent,
+            mem_available: memory::available_bytes()?,
+            mem_total: memory::total_bytes()?,
+        })
+    }
+}
+
+fn parse_load(loadavg: &str) -> Option<f64> {
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+/// Busy and total jiffies from the aggregate `cpu` line of `/proc/stat`;
+/// idle and iowait count as not busy.
+fn parse_cpu_times(stat: &str) -> Option<(u64, u64)> {
+    let line = stat.lines().find(|l| l.starts_with("cpu "))?;
+    let times: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|t| t.parse().ok())
+        .collect::<Option<_>>()?;
+    // guest time is already counted in user time
+    let total: u64 = times.iter().take(8).sum();
+    let idle = times.get(3)? + times.get(4).unwrap_or(&0);
+    Some((total - idle, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_load_and_cpu_counters() {
+        assert_eq!(parse_load("3.27 2.10 1.05 4/1203 98211\n"), Some(3.27));
+        let stat = "cpu  100 5 45 800 50 0 0 0 0 0\ncpu0 50 2 20 400 25 0 0 0 0 0\n";
+        assert_eq!(parse_cpu_times(stat), Some((150, 1000)));
+        assert_eq!(parse_cpu_times("intr 1 2 3\n"), None);
+    }
+}
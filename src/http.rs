@@ -0,0 +1,154 @@
+//! HTTP side of `gparallel serve --http`: a browser dashboard, the job list
+//! as JSON and a WebSocket that pushes job events as they happen.
+//! A daemon with a token wants it on every request, as a bearer token or a
+//! `token` query parameter (browsers cannot set headers on a WebSocket).
+
+use anyhow::Result;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Request, State,
+    },
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse},
+    routing::get,
+    Json, Router,
+};
+use std::net::SocketAddr;
+use tokio::{net::TcpListener, sync::broadcast::error::RecvError};
+use tracing::{debug, info, warn};
+
+use crate::protocol::Response;
+use crate::report::JobRecord;
+use crate::scheduler::JobEvent;
+use crate::server::{self, Daemon};
+use crate::ui::GpuInfo;
+
+pub async fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", addr, e))
+}
+
+/// Serve the API until the daemon shuts down.
+pub async fn serve(listener: TcpListener, daemon: Daemon) {
+    if let Ok(addr) = listener.local_addr() {
+        info!("HTTP API on http://{}", addr);
+    }
+    let shutdown = daemon.sched.cancel_token();
+    let app = Router::new()
+        .route("/", get(dashboard))
+        .route("/status", get(status))
+        .route("/gpus", get(gpus))
+        .route("/jobs/:id/logs", get(job_logs))
+        .route("/events", get(events))
+        .layer(middleware::from_fn_with_state(
+            daemon.clone(),
+            require_token,
+        ))
+        .with_state(daemon);
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+    {
+        warn!("HTTP API stopped: {}", e);
+    }
+}
+
+async fn require_token(
+    State(daemon): State<Daemon>,
+    request: Request,
+    next: Next,
+) -> Result<axum::response::Response, StatusCode> {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let query = request
+        .uri()
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("token="));
+    let allowed = match bearer.or(query) {
+        Some(token) => daemon.authenticate(token),
+        None => daemon.token.is_none(),
+    };
+    if !allowed {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(next.run(request).await)
+}
+
+/// Single-page dashboard: GPU panel, job queue and live logs, kept up to
+/// date through `/events`.
+async fn dashboard() -> Html<&'static str> {
+    Html(include_str!("dashboard.html"))
+}
+
+/// The same records as `gparallel status --json`.
+async fn status(State(daemon): State<Daemon>) -> Json<Vec<JobRecord>> {
+    Json(server::job_records(&*daemon.state.read().await))
+}
+
+/// GPUs with their current memory use, which `/events` does not push.
+async fn gpus(State(daemon): State<Daemon>) -> Json<Vec<GpuInfo>> {
+    Json(daemon.state.read().await.gpus.clone())
+}
+
+/// A job's captured output (its log buffer), by id or unique id prefix.
+async fn job_logs(
+    Path(id): Path<String>,
+    State(daemon): State<Daemon>,
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let state = daemon.state.read().await;
+    let job = server::find_job(&state, &id).map_err(|e| (StatusCode::NOT_FOUND, e.to_string()))?;
+    Ok(Json(job.log_lines.iter().cloned().collect()))
+}
+
+async fn events(ws: WebSocketUpgrade, State(daemon): State<Daemon>) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = push_events(daemon, socket).await {
+            debug!("Event stream client went away: {}", e);
+        }
+    })
+}
+
+/// A `state` snapshot, then a `job` message whenever a job is submitted or
+/// changes state and a `job_log` message for every output line. A client
+/// that falls behind gets a fresh snapshot instead of the events it missed.
+async fn push_events(daemon: Daemon, mut socket: WebSocket) -> Result<()> {
+    let (snapshot, mut events) = {
+        let state = daemon.state.read().await;
+        (server::state_response(&state), daemon.sched.subscribe())
+    };
+    send(&mut socket, &snapshot).await?;
+
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                Some(Ok(_)) => continue,
+            },
+        };
+        let message = match event {
+            Ok(JobEvent::State { job, .. }) => Response::Job { job },
+            Ok(JobEvent::Log { job_id, line }) => Response::JobLog {
+                id: job_id.to_string(),
+                lines: vec![line],
+            },
+            Err(RecvError::Lagged(_)) => server::state_response(&*daemon.state.read().await),
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        send(&mut socket, &message).await?;
+    }
+}
+
+async fn send(socket: &mut WebSocket, message: &Response) -> Result<()> {
+    let text = serde_json::to_string(message)?;
+    socket.send(Message::Text(text)).await?;
+    Ok(())
+}
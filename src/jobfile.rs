@@ -0,0 +1,297 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A single job as written in a `.jsonl` command file. The same object is
+/// used as the payload of the daemon `submit` message.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobRequest {
+    /// Shell command to run
+    pub cmd: String,
+
+    /// Extra environment variables for this job only
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+
+    /// GPUs the job needs at once (default 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpus: Option<u32>,
+
+    /// Free memory each of its GPUs must have before it starts, e.g. "12G"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem: Option<String>,
+
+    /// Power cap for its GPUs while it runs, e.g. "250W"
+    #[serde(
+        default,
+        alias = "power-limit",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub power_limit: Option<String>,
+
+    /// Queued jobs of a higher priority start first (default normal)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+
+    /// Name shared by related jobs, e.g. to `gparallel wait` for all of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+
+    /// Command file the job was read from (not part of the wire format)
+    #[serde(skip)]
+    pub source: Option<String>,
+
+    /// User who submitted the job, as the daemon sees them (not part of the
+    /// wire format)
+    #[serde(skip)]
+    pub owner: Option<String>,
+}
+
+impl JobRequest {
+    pub fn new(cmd: impl Into<String>) -> Self {
+        Self {
+            cmd: cmd.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Where a job goes in the queue: ahead of every queued job of a lower
+/// priority, behind those of its own.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// Pick by file extension (`.jsonl` → JSON Lines, anything else → text)
+    Auto,
+    /// One shell command per line
+    Text,
+    /// One JSON job object per line
+    Jsonl,
+}
+
+impl InputFormat {
+    pub fn resolve(self, filename: &str) -> InputFormat {
+        match self {
+            InputFormat::Auto if filename.ends_with(".jsonl") => InputFormat::Jsonl,
+            InputFormat::Auto => InputFormat::Text,
+            other => other,
+        }
+    }
+}
+
+/// Parse the contents of a command file into job requests.
+pub fn parse(content: &str, format: InputFormat) -> Result<Vec<JobRequest>> {
+    match format {
+        InputFormat::Jsonl => parse_jsonl(content),
+        _ => Ok(parse_text(content)),
+    }
+}
+
+/// Merge per-file job lists round-robin: first job of each file, then the
+/// second of each, and so on.
+pub fn interleave(per_file: Vec<Vec<JobRequest>>) -> Vec<JobRequest> {
+    let total = per_file.iter().map(Vec::len).sum();
+    let mut iters: Vec<_> = per_file.into_iter().map(Vec::into_iter).collect();
+    let mut jobs = Vec::with_capacity(total);
+    while jobs.len() < total {
+        for it in iters.iter_mut() {
+            if let Some(job) = it.next() {
+                jobs.push(job);
+            }
+        }
+    }
+    jobs
+}
+
+/// One command per line. Lines starting with `#` are comments and a trailing
+/// backslash joins the next line onto the current command.
+fn parse_text(content: &str) -> Vec<JobRequest> {
+    let mut jobs = Vec::new();
+    let mut pending = String::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(head) = line.strip_suffix('\\') {
+            pending.push_str(head.trim_end());
+            pending.push(' ');
+            continue;
+        }
+
+        pending.push_str(line);
+        let cmd = pending.trim();
+        if !cmd.is_empty() {
+            jobs.push(JobRequest::new(cmd));
+        }
+        pending.clear();
+    }
+
+    // A continuation on the last line still ends the command
+    let cmd = pending.trim();
+    if !cmd.is_empty() {
+        jobs.push(JobRequest::new(cmd));
+    }
+    jobs
+}
+
+/// One job per CSV row: `{column}` placeholders in `template` are replaced
+/// by that row's (shell-quoted) value. Braces that do not name a column are
+/// left alone if they follow `$` (shell parameter expansion), otherwise they
+/// are an error so typos cannot silently run the wrong command.
+pub fn from_csv(content: &str, template: &str) -> Result<Vec<JobRequest>> {
+    let mut rows = parse_csv(content)?.into_iter();
+    let header = rows
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("CSV has no header row"))?;
+
+    let mut jobs = Vec::new();
+    for (i, row) in rows.enumerate() {
+        if row.len() != header.len() {
+            anyhow::bail!(
+                "row {}: expected {} fields, found {}",
+                i + 2,
+                header.len(),
+                row.len()
+            );
+        }
+        let mut cmd = String::new();
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            let Some(close) = rest[open..].find('}').map(|c| open + c) else {
+                break;
+            };
+            let name = &rest[open + 1..close];
+            cmd.push_str(&rest[..open]);
+            match header.iter().position(|h| h == name) {
+                Some(col) => cmd.push_str(&crate::shell::quote(&row[col])),
+                None if rest[..open].ends_with('$') => cmd.push_str(&rest[open..=close]),
+                None => anyhow::bail!(
+                    "template placeholder {{{}}} is not a CSV column (columns: {})",
+                    name,
+                    header.join(", ")
+                ),
+            }
+            rest = &rest[close + 1..];
+        }
+        cmd.push_str(rest);
+        jobs.push(JobRequest::new(cmd));
+    }
+    Ok(jobs)
+}
+
+/// Minimal RFC 4180 reader: comma separated, double-quoted fields may hold
+/// commas, newlines and doubled quotes. Blank lines are skipped.
+fn parse_csv(content: &str) -> Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                if !row.is_empty() || !field.trim().is_empty() {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                field.clear();
+            }
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        anyhow::bail!("unterminated quoted field in CSV");
+    }
+    if !row.is_empty() || !field.trim().is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn parse_jsonl(content: &str) -> Result<Vec<JobRequest>> {
+    let mut jobs = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let job: JobRequest = serde_json::from_str(line)
+            .map_err(|e| anyhow::anyhow!("line {}: invalid job object: {}", i + 1, e))?;
+        if job.cmd.trim().is_empty() {
+            anyhow::bail!("line {}: job has an empty \"cmd\"", i + 1);
+        }
+        jobs.push(job);
+    }
+    Ok(jobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows_fill_the_template() {
+        let csv = "lr,bs,tag\n0.1,32,base\r\n0.01,64,\"it's, \"\"quoted\"\"\"\n\n";
+        let jobs = from_csv(csv, "train --lr {lr} --bs {bs} --tag {tag} --home ${HOME}").unwrap();
+        let cmds: Vec<&str> = jobs.iter().map(|j| j.cmd.as_str()).collect();
+        assert_eq!(
+            cmds,
+            vec![
+                "train --lr 0.1 --bs 32 --tag base --home ${HOME}",
+                "train --lr 0.01 --bs 64 --tag 'it'\\''s, \"quoted\"' --home ${HOME}",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_and_ragged_rows_are_errors() {
+        assert!(from_csv("lr\n0.1\n", "train --lr {rl}").is_err());
+        assert!(from_csv("lr,bs\n0.1\n", "train --lr {lr}").is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! Scheduler diagnostics (retries, kills, spawn failures, ...) on stderr,
+//! as `[gparallel] ...` lines or JSON objects for log aggregators.
+
+use clap::{ArgAction, Args, ValueEnum};
+use std::fmt;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::{format::Writer, FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// `[gparallel] message` lines
+    Text,
+    /// One JSON object per line, with timestamp, level and job fields
+    Json,
+}
+
+#[derive(Debug, Args)]
+pub struct LogArgs {
+    /// Log more scheduler detail (-v: dispatch decisions, -vv: everything)
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Only log warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Format of scheduler log lines on stderr
+    #[arg(long, value_enum, value_name = "FORMAT", global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+impl LogArgs {
+    fn level(&self) -> Level {
+        match (self.quiet, self.verbose) {
+            (true, _) => Level::WARN,
+            (false, 0) => Level::INFO,
+            (false, 1) => Level::DEBUG,
+            (false, _) => Level::TRACE,
+        }
+    }
+}
+
+/// Install the global subscriber. Must run before the first log event.
+pub fn init(args: &LogArgs) {
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(args.level())
+        .with_ansi(false)
+        .with_writer(std::io::stderr);
+    match args.log_format {
+        LogFormat::Text => builder.event_format(Text).init(),
+        LogFormat::Json => builder.json().flatten_event(true).init(),
+    }
+}
+
+/// The historical plain format: `[gparallel] message`, with the level
+/// spelled out for anything but info.
+struct Text;
+
+impl<S, N> FormatEvent<S, N> for Text
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        write!(writer, "[gparallel] ")?;
+        let level = *event.metadata().level();
+        if level != Level::INFO {
+            write!(writer, "{}: ", level)?;
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
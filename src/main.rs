@@ -1,96 +1,1017 @@
 /************************  src/main.rs ********************************/
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{ArgGroup, Args, Parser, Subcommand};
 use std::sync::Arc;
 use tokio::{
     signal,
-    sync::RwLock,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver},
+        RwLock,
+    },
+    task::JoinHandle,
     time::{sleep, Duration},
 };
 
+mod alert;
+mod apple;
+mod auth;
+mod client;
+mod cluster;
+mod config;
+mod cuda_mps;
+mod doctor;
+mod duration;
+mod health;
+mod history;
+mod host;
+mod http;
+mod jobfile;
+mod logging;
+mod memory;
+mod mig;
+mod power;
+mod progress;
+mod protocol;
+mod report;
+mod retry;
 mod scheduler;
+mod server;
+mod settings;
+mod shell;
+mod ssh;
+mod store;
+mod summary;
+mod systemd;
+mod tabs;
+mod topology;
 mod ui;
-use scheduler::Scheduler;
-use ui::{AppState, UI};
+use alert::Alert;
+use jobfile::InputFormat;
+use progress::{ProgressBars, ProgressStyle};
+use scheduler::{ForwardedLine, OutputMode, Scheduler, SchedulerOptions};
+use shell::Shell;
+use tokio_util::sync::CancellationToken;
+use ui::{AppState, KeyMap, UI};
 
 /// gparallel — 1GPU x multi‑process scheduler
 #[derive(Parser)]
 #[command(author, version, about = "simple gpu‑wise parallel executor")]
+#[command(args_override_self = true, args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// File containing commands to execute (one per line)
-    filename: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Without a subcommand, gparallel behaves like `gparallel run`
+    #[command(flatten)]
+    run: RunArgs,
+
+    #[command(flatten)]
+    log: logging::LogArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the jobs in one or more command files (the default)
+    #[command(args_override_self = true)]
+    Run(Box<RunArgs>),
+    /// Start a long-lived daemon that owns the GPUs and accepts jobs over a socket
+    Serve(ServeArgs),
+    /// Send jobs to a running daemon
+    Submit(SubmitArgs),
+    /// Show the jobs of a running daemon
+    Status(StatusArgs),
+    /// Cancel queued jobs or stop running ones on a running daemon
+    Cancel(CancelArgs),
+    /// Wait for a daemon's jobs to finish; fails if any of them failed
+    Wait(WaitArgs),
+    /// Give a job waiting in a daemon's queue another priority
+    Requeue(RequeueArgs),
+    /// Move a job to the front or the back of a daemon's queue
+    Move(MoveArgs),
+    /// Print a daemon job's output
+    Logs(LogsArgs),
+    /// Search the jobs that runs and daemons have finished
+    History(HistoryArgs),
+    /// Open the TUI on a running daemon
+    Attach(AttachArgs),
+    /// Make a running daemon read its --config file again
+    Reload(SocketArgs),
+    /// Make a running daemon detect its GPUs again, adding new ones and
+    /// draining vanished ones
+    Rescan(SocketArgs),
+    /// Stop a daemon for maintenance: take no more jobs, let the running
+    /// ones finish, save the queue and exit
+    Drain(DrainArgs),
+    /// Offer this machine's GPUs to a daemon started with --agents
+    Agent(AgentArgs),
+    /// Report NVML, driver and GPU details and any degraded features
+    Doctor,
+}
+
+#[derive(Args)]
+struct SocketArgs {
+    /// Path of the daemon's Unix socket
+    #[arg(long, default_value = "/tmp/gparallel.sock")]
+    socket: std::path::PathBuf,
+
+    /// Reach the daemon over TCP instead, at the address of its --listen
+    #[arg(long, value_name = "ADDR", requires = "token_file")]
+    connect: Option<String>,
+
+    /// File with the daemon's token: `serve` creates it (mode 600) if
+    /// missing and then requires the token from every client
+    #[arg(long, value_name = "PATH")]
+    token_file: Option<std::path::PathBuf>,
+}
+
+impl SocketArgs {
+    fn endpoint(&self) -> client::Endpoint {
+        client::Endpoint {
+            socket: self.socket.clone(),
+            connect: self.connect.clone(),
+            token_file: self.token_file.clone(),
+        }
+    }
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    /// Also take clients on this TCP address, e.g. 0.0.0.0:7000; needs
+    /// --token-file
+    #[arg(long, value_name = "ADDR", requires = "token_file")]
+    listen: Option<std::net::SocketAddr>,
+
+    /// Also serve the job list (`/status`) and a WebSocket event stream
+    /// (`/events`) over HTTP on this address, e.g. 127.0.0.1:8080
+    #[arg(long, value_name = "ADDR")]
+    http: Option<std::net::SocketAddr>,
+
+    /// Permissions of the socket in octal, e.g. 660 to let --socket-group in
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    socket_mode: Option<u32>,
+
+    /// Group to own the socket
+    #[arg(long, value_name = "GROUP")]
+    socket_group: Option<String>,
+
+    /// Keep the queue and the job history in this database across restarts
+    /// [default: ~/.local/share/gparallel/daemon.db]
+    #[arg(long, value_name = "PATH")]
+    db: Option<std::path::PathBuf>,
+
+    /// Keep nothing across restarts
+    #[arg(long, conflicts_with = "db")]
+    no_db: bool,
+
+    /// Leave the daemon's jobs out of the history `gparallel history` searches
+    #[arg(long)]
+    no_history: bool,
+
+    /// GPUs one user's jobs may hold at once; their other jobs wait
+    #[arg(long, value_name = "N")]
+    max_gpus_per_user: Option<u32>,
+
+    /// Jobs one user may have waiting in the queue; more are refused
+    #[arg(long, value_name = "N")]
+    max_queued_per_user: Option<usize>,
+
+    /// TOML file with settings to apply over these options, read again on
+    /// SIGHUP or `gparallel reload`
+    #[arg(long, value_name = "PATH")]
+    config: Option<std::path::PathBuf>,
+
+    /// Take `gparallel agent`s on this TCP address and run jobs on their
+    /// GPUs too, e.g. 0.0.0.0:7070. Requires --token-file, whose token
+    /// every agent has to present
+    #[arg(long, value_name = "ADDR", requires = "token_file")]
+    agents: Option<std::net::SocketAddr>,
+
+    /// Leave this machine's GPUs alone and only run jobs on agents
+    #[arg(long, requires = "agents")]
+    no_local_gpus: bool,
+
+    /// Only use these GPUs of this machine, by index or by UUID
+    #[arg(
+        long,
+        value_name = "GPUS",
+        value_delimiter = ',',
+        value_parser = scheduler::GpuSelector::parse,
+        conflicts_with = "no_local_gpus"
+    )]
+    gpus: Vec<scheduler::GpuSelector>,
+
+    /// Run the jobs sharing a GPU through a CUDA MPS daemon started for it
+    /// (set `jobs-per-gpu` in the --config file)
+    #[arg(long)]
+    cuda_mps: bool,
+
+    /// Keep starting jobs on GPUs that processes from outside gparallel are
+    /// using, instead of waiting for those to end
+    #[arg(long)]
+    ignore_other_processes: bool,
+
+    /// Detect this machine's GPUs again this often (e.g. "1m"), adding ones
+    /// that appear after a driver reload and draining vanished ones
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    rescan_every: Option<std::time::Duration>,
+}
+
+#[derive(Args)]
+struct AgentArgs {
+    /// Address the coordinator takes agents on (its `serve --agents`)
+    #[arg(long, value_name = "ADDR")]
+    coordinator: String,
+
+    /// File with the coordinator's token, if it has one
+    #[arg(long, value_name = "PATH")]
+    token_file: Option<std::path::PathBuf>,
+
+    /// Name this machine's GPUs go by on the coordinator [default: hostname]
+    #[arg(long)]
+    name: Option<String>,
+}
+
+#[derive(Args)]
+struct StatusArgs {
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    /// Print the jobs as JSON (the same records as --results)
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("target").required(true).args(["job", "all", "queued"])))]
+struct CancelArgs {
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    /// Job to cancel (any unique prefix of its id, as shown by `status`)
+    job: Option<String>,
+
+    /// Cancel every job that has not finished, stopping running ones
+    #[arg(long)]
+    all: bool,
+
+    /// Cancel only the jobs that have not started
+    #[arg(long)]
+    queued: bool,
+}
+
+#[derive(Args)]
+struct WaitArgs {
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    /// Jobs to wait for (any unique prefix of their ids); without any, every
+    /// job that has not finished yet, and those submitted meanwhile
+    #[arg(conflicts_with = "tag")]
+    jobs: Vec<String>,
+
+    /// Wait for every job submitted with this --tag
+    #[arg(long)]
+    tag: Option<String>,
+
+    /// Give up after this long (e.g. "2h")
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    timeout: Option<std::time::Duration>,
+}
+
+#[derive(Args)]
+struct HistoryArgs {
+    /// Only jobs that finished within this long (e.g. "2d")
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    since: Option<std::time::Duration>,
+
+    /// Only jobs that failed
+    #[arg(long)]
+    failed: bool,
+
+    /// Only jobs whose command contains TEXT
+    #[arg(long, value_name = "TEXT")]
+    grep: Option<String>,
+
+    /// Print the jobs as JSON (the same records as --results)
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct RequeueArgs {
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    /// Queued job (any unique prefix of its id)
+    job: String,
+
+    /// Its new priority; it goes behind the queued jobs that already have it
+    #[arg(long, value_enum)]
+    priority: jobfile::Priority,
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("end").required(true).args(["front", "back"])))]
+struct MoveArgs {
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    /// Queued job (any unique prefix of its id)
+    job: String,
+
+    /// Start it before every other queued job
+    #[arg(long)]
+    front: bool,
+
+    /// Start it after every other queued job
+    #[arg(long)]
+    back: bool,
+}
+
+#[derive(Args)]
+struct AttachArgs {
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    /// How often the TUI looks for changes to redraw, in milliseconds
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u64).range(10..)
+    )]
+    refresh_ms: u64,
+}
+
+#[derive(Args)]
+struct DrainArgs {
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    /// Stop jobs still running after this long (e.g. "30m") instead of
+    /// waiting for them
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    timeout: Option<std::time::Duration>,
+}
+
+#[derive(Args)]
+struct LogsArgs {
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    /// Job whose output to print (any unique prefix of its id)
+    job: String,
+
+    /// Keep printing new output until the job finishes
+    #[arg(short, long)]
+    follow: bool,
+}
+
+#[derive(Args)]
+struct SubmitArgs {
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    /// Command to submit; without one, each line of stdin is a job
+    cmd: Option<String>,
+
+    /// GPUs each job runs on at once
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    gpus: u32,
+
+    /// Free memory each of the job's GPUs must have before it starts (e.g. "12G")
+    #[arg(long, value_name = "SIZE", value_parser = parse_mem)]
+    mem: Option<String>,
+
+    /// Power cap for the job's GPUs while it runs (e.g. "250W"; needs the
+    /// daemon to run as root)
+    #[arg(long, value_name = "WATTS", value_parser = parse_power_limit)]
+    power_limit: Option<String>,
+
+    /// Queued jobs of a higher priority start first
+    #[arg(long, value_enum, default_value_t = jobfile::Priority::Normal)]
+    priority: jobfile::Priority,
+
+    /// Name to find these jobs by later, e.g. with `gparallel wait --tag`
+    #[arg(long)]
+    tag: Option<String>,
+
+    /// Set KEY=VAL in the job, or pass KEY through from our environment (repeatable)
+    #[arg(long, value_name = "KEY[=VAL]", value_parser = parse_env_var)]
+    env: Vec<(String, Option<String>)>,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Files containing commands to execute (one per line); `-` or none reads stdin
+    filenames: Vec<String>,
+
+    /// Take jobs round-robin from each file instead of file by file
+    #[arg(long)]
+    interleave: bool,
+
+    /// Command file format
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    format: InputFormat,
 
     /// Disable TUI and use plain text output
     #[arg(long)]
     no_tui: bool,
 
-    /// Maximum runtime for each job (e.g., "4h", "30m")
+    /// Render the TUI on stderr so stdout can be piped (job stdout is forwarded)
+    #[arg(long)]
+    tui_on_stderr: bool,
+
+    /// Extra TUI keys for moving around: "vim" adds j/k, gg/G and
+    /// Ctrl-d/Ctrl-u to the arrows
+    #[arg(long, value_enum, default_value_t = KeyMap::Arrows)]
+    keys: KeyMap,
+
+    /// Ring the terminal bell in the TUI whenever a job fails
+    #[arg(long)]
+    bell_on_failure: bool,
+
+    /// Keep the TUI open on the run's statistics once all jobs have
+    /// finished, until q
+    #[arg(long)]
+    keep_open: bool,
+
+    /// How often the TUI looks for changes to redraw, in milliseconds; it
+    /// only redraws when something on screen changed
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u64).range(10..)
+    )]
+    refresh_ms: u64,
+
+    /// Turn a running job's idle badge in the TUI red once it has printed
+    /// nothing for this long (e.g. "30m")
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "10m",
+        value_parser = duration::parse_duration
+    )]
+    hung_after: std::time::Duration,
+
+    /// Use a lightweight progress display instead of the TUI ("bars", "bar"
+    /// or "off"; non-TUI runs default to "bar" when stderr is a terminal)
+    #[arg(long, value_enum)]
+    progress: Option<ProgressStyle>,
+
+    /// Maximum runtime for each job (e.g., "4h", "90m", "1h30m", "2d")
+    #[arg(long, value_parser = duration::parse_duration)]
+    max_runtime: Option<std::time::Duration>,
+
+    /// Only start a job while the host has at least this much free RAM
+    /// (e.g. "8G"); jobs wait until memory frees up, and start at least a
+    /// second apart so each one's memory use shows before the next check
+    #[arg(long, value_name = "SIZE", value_parser = memory::parse_size)]
+    memfree: Option<u64>,
+
+    /// Only use these GPUs of this machine, by index or by UUID (which
+    /// stays the same when the driver renumbers them), e.g. 0,2 or
+    /// GPU-5d5ba0d6-...
+    #[arg(
+        long,
+        value_name = "GPUS",
+        value_delimiter = ',',
+        value_parser = scheduler::GpuSelector::parse
+    )]
+    gpus: Vec<scheduler::GpuSelector>,
+
+    /// Jobs that run on each GPU at once
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    jobs_per_gpu: u64,
+
+    /// Run the jobs sharing a GPU through a CUDA MPS daemon started for it,
+    /// so their kernels run side by side instead of taking turns
+    #[arg(long)]
+    cuda_mps: bool,
+
+    /// Keep starting jobs on GPUs that processes from outside gparallel are
+    /// using, instead of waiting for those to end
+    #[arg(long)]
+    ignore_other_processes: bool,
+
+    /// Detect this machine's GPUs again this often (e.g. "1m"), adding ones
+    /// that appear after a driver reload and draining vanished ones
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    rescan_every: Option<std::time::Duration>,
+
+    /// Start no job on a GPU at this temperature (°C) or hotter until it has
+    /// cooled down to --resume-temp
+    #[arg(long, value_name = "CELSIUS")]
+    max_temp: Option<u32>,
+
+    /// Temperature a GPU that reached --max-temp has to cool down to before
+    /// it takes jobs again [default: 10 below --max-temp]
+    #[arg(long, value_name = "CELSIUS", requires = "max_temp")]
+    resume_temp: Option<u32>,
+
+    /// Signal sent to jobs on timeout or cancellation, before SIGKILL
+    /// (e.g. SIGINT for frameworks that checkpoint on it)
+    #[arg(long, value_name = "SIGNAL", default_value = "SIGTERM", value_parser = parse_signal)]
+    stop_signal: nix::sys::signal::Signal,
+
+    /// Shell used to run each command; `none` splits the line and execs it directly
+    #[arg(long, value_enum, default_value_t = Shell::Bash)]
+    shell: Shell,
+
+    /// Set KEY=VAL in every job, or pass KEY through from our environment (repeatable)
+    #[arg(long = "env", value_name = "KEY[=VAL]", value_parser = parse_env_var)]
+    env: Vec<(String, Option<String>)>,
+
+    /// Start jobs from a minimal environment (PATH, HOME, locale, CUDA/NVIDIA vars)
+    #[arg(long)]
+    clean_env: bool,
+
+    /// Retry each failed job up to this many times
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// Only retry jobs exiting with one of these codes (signals count as 128+N)
+    #[arg(
+        long,
+        value_name = "CODES",
+        value_delimiter = ',',
+        requires = "retries"
+    )]
+    retry_on: Vec<i32>,
+
+    /// Never retry jobs exiting with one of these codes
+    #[arg(
+        long,
+        value_name = "CODES",
+        value_delimiter = ',',
+        requires = "retries"
+    )]
+    no_retry_on: Vec<i32>,
+
+    /// Wait this long before a failed job is retried (e.g. 30s, 5m)
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration, requires = "retries")]
+    retry_delay: Option<std::time::Duration>,
+
+    /// Add a random extra wait of up to this much to each retry delay
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration, requires = "retries")]
+    retry_jitter: Option<std::time::Duration>,
+
+    /// Setup command run before each job (same GPU and environment); if it
+    /// fails, the job is marked as a setup failure and not retried
+    #[arg(long, value_name = "CMD")]
+    pre_cmd: Option<String>,
+
+    /// Give every job a subdirectory of DIR with its stdout.log, stderr.log
+    /// and job.json metadata
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<std::path::PathBuf>,
+
+    /// Run each job with its output subdirectory as working directory
+    #[arg(long, requires = "output_dir")]
+    run_in_output_dir: bool,
+
+    /// Also run jobs on the GPUs of these hosts over ssh (`:` is this
+    /// machine, whose GPUs are otherwise left out)
+    #[arg(long, value_name = "HOSTS", value_delimiter = ',')]
+    sshlogin: Vec<String>,
+
+    /// Command used to reach a --sshlogin host
+    #[arg(long, value_name = "CMD", default_value = "ssh", requires = "sshlogin")]
+    ssh: String,
+
+    /// Instead of command files, make one job per row of this CSV file from
+    /// --template (column names are the placeholders)
+    #[arg(long, value_name = "FILE", requires = "template", conflicts_with_all = ["filenames", "interleave"])]
+    from_csv: Option<String>,
+
+    /// Command with {column} placeholders, e.g. 'python train.py --lr {lr}'
+    #[arg(long, value_name = "CMD", requires = "from_csv")]
+    template: Option<String>,
+
+    /// Instead of command files, run the jobs that failed in an earlier
+    /// run's --results JSON (with their original per-job environment)
+    #[arg(long, value_name = "RESULTS", conflicts_with_all = ["filenames", "interleave", "from_csv"])]
+    retry_failed: Option<std::path::PathBuf>,
+
+    /// Write a JUnit XML report with one test case per job
+    #[arg(long, value_name = "FILE")]
+    junit: Option<std::path::PathBuf>,
+
+    /// Write per-job results (command, GPU, timestamps, duration, exit code)
+    /// as JSON, or CSV when FILE ends in .csv
+    #[arg(long, value_name = "FILE")]
+    results: Option<std::path::PathBuf>,
+
+    /// Leave this run's jobs out of the history `gparallel history` searches
     #[arg(long)]
-    max_runtime: Option<String>,
+    no_history: bool,
+
+    /// Ring the bell, flash the display or send a desktop notification as
+    /// soon as the first job fails
+    #[arg(long, value_enum, value_name = "ALERT")]
+    alert_on_first_failure: Option<Alert>,
+
+    /// Apply a named profile from the config file
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Ignore ~/.config/gparallel/config.toml and ./gparallel.toml
+    #[arg(long)]
+    no_config: bool,
+}
+
+impl Cli {
+    /// Parse the command line, filling in `run` defaults from the config files.
+    fn load() -> Result<Self> {
+        let cli = Cli::parse();
+        // Defaults go right after the program name, or after `run` when the
+        // subcommand is spelled out (options cannot precede a subcommand)
+        let (run, insert_at) = match &cli.command {
+            None => (&cli.run, 1),
+            Some(Command::Run(run)) => (&**run, 2),
+            Some(_) => return Ok(cli),
+        };
+        if run.no_config {
+            return Ok(cli);
+        }
+
+        let defaults = config::Config::load()?.to_args::<Cli>(run.profile.as_deref())?;
+        if defaults.is_empty() {
+            return Ok(cli);
+        }
+
+        let mut args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+        args.splice(insert_at..insert_at, defaults.into_iter().map(Into::into));
+        Ok(Cli::parse_from(args))
+    }
+}
+
+/// Accepts `SIGINT`, `INT` or `2`.
+fn parse_signal(s: &str) -> Result<nix::sys::signal::Signal> {
+    use nix::sys::signal::Signal;
+    if let Ok(n) = s.parse::<i32>() {
+        return Signal::try_from(n).map_err(|_| anyhow::anyhow!("unknown signal number {}", n));
+    }
+    let name = s.to_ascii_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    name.parse()
+        .map_err(|_| anyhow::anyhow!("unknown signal '{}'", s))
+}
+
+/// Parses an octal socket mode like `660`.
+fn parse_mode(s: &str) -> Result<u32> {
+    u32::from_str_radix(s, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| anyhow::anyhow!("invalid mode '{}': expected octal like 660", s))
+}
+
+/// Validates a size like `12G` but keeps it as written for the daemon.
+fn parse_mem(s: &str) -> Result<String> {
+    memory::parse_size(s)?;
+    Ok(s.to_string())
+}
+
+fn parse_power_limit(s: &str) -> Result<String> {
+    power::parse_watts(s)?;
+    Ok(s.to_string())
+}
+
+/// Fill in `--env KEY` pass-throughs from our environment; unset variables
+/// are skipped.
+fn resolve_env(env: &[(String, Option<String>)]) -> Vec<(String, String)> {
+    env.iter()
+        .filter_map(|(key, value)| {
+            let value = value.clone().or_else(|| std::env::var(key).ok())?;
+            Some((key.clone(), value))
+        })
+        .collect()
+}
+
+fn parse_env_var(s: &str) -> Result<(String, Option<String>)> {
+    let (key, value) = match s.split_once('=') {
+        Some((key, value)) => (key, Some(value.to_string())),
+        None => (s, None),
+    };
+    if key.is_empty() {
+        anyhow::bail!("missing variable name in '{}'", s);
+    }
+    Ok((key.to_string(), value))
+}
+
+/// Read jobs from every command file (or stdin), remembering where each
+/// came from.
+async fn read_jobs(
+    filenames: &[String],
+    format: InputFormat,
+    interleave: bool,
+) -> Result<Vec<jobfile::JobRequest>> {
+    let filenames = if filenames.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        filenames.to_vec()
+    };
+    if filenames.iter().filter(|f| *f == "-").count() > 1 {
+        anyhow::bail!("stdin ('-') can only be read once");
+    }
+    let mut per_file = Vec::new();
+    for filename in &filenames {
+        let file_content = read_commands(filename).await?;
+        let format = format.resolve(filename);
+        let mut jobs = jobfile::parse(&file_content, format)
+            .map_err(|e| anyhow::anyhow!("Failed to parse '{}': {}", filename, e))?;
+        for job in jobs.iter_mut() {
+            job.source = Some(filename.clone());
+        }
+        per_file.push(jobs);
+    }
+
+    Ok(if interleave {
+        jobfile::interleave(per_file)
+    } else {
+        per_file.into_iter().flatten().collect()
+    })
+}
+
+async fn read_commands(filename: &str) -> Result<String> {
+    if filename != "-" {
+        return tokio::fs::read_to_string(filename)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read file '{}': {}", filename, e));
+    }
+
+    let mut content = String::new();
+    tokio::io::AsyncReadExt::read_to_string(&mut tokio::io::stdin(), &mut content)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to read commands from stdin: {}", e))?;
+    Ok(content)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let cli = Cli::load()?;
+    logging::init(&cli.log);
+    match cli.command {
+        None => run(cli.run).await,
+        Some(Command::Run(args)) => run(*args).await,
+        Some(Command::Serve(args)) => {
+            if args.socket.connect.is_some() {
+                anyhow::bail!("--connect is for clients; use --listen to take them over TCP");
+            }
+            let db = (!args.no_db).then(|| args.db.unwrap_or_else(store::Store::default_path));
+            server::run(server::ServeOptions {
+                socket: args.socket.socket,
+                socket_mode: args.socket_mode,
+                socket_group: args.socket_group,
+                token_file: args.socket.token_file,
+                listen: args.listen,
+                http: args.http,
+                db,
+                max_gpus_per_user: args.max_gpus_per_user,
+                max_queued_per_user: args.max_queued_per_user,
+                config: args.config,
+                history: (!args.no_history).then(history::History::open_default),
+                agents: args.agents,
+                local_gpus: !args.no_local_gpus,
+                gpus: Some(args.gpus).filter(|gpus| !gpus.is_empty()),
+                cuda_mps: args.cuda_mps,
+                yield_gpus: !args.ignore_other_processes,
+                rescan_every: args.rescan_every,
+            })
+            .await
+        }
+        Some(Command::Submit(args)) => {
+            let template = jobfile::JobRequest {
+                env: resolve_env(&args.env).into_iter().collect(),
+                gpus: Some(args.gpus).filter(|&n| n != 1),
+                mem: args.mem,
+                power_limit: args.power_limit,
+                priority: Some(args.priority).filter(|&p| p != jobfile::Priority::Normal),
+                tag: args.tag,
+                ..Default::default()
+            };
+            client::submit(&args.socket.endpoint(), args.cmd, template).await
+        }
+        Some(Command::Status(args)) => client::status(&args.socket.endpoint(), args.json).await,
+        Some(Command::Cancel(args)) => {
+            let target = match args.job {
+                Some(id) => protocol::CancelTarget::Job(id),
+                None if args.all => protocol::CancelTarget::All,
+                None => protocol::CancelTarget::Queued,
+            };
+            client::cancel(&args.socket.endpoint(), target).await
+        }
+        Some(Command::Wait(args)) => {
+            let target = match args.tag {
+                Some(tag) => client::WaitFor::Tag(tag),
+                None if args.jobs.is_empty() => client::WaitFor::All,
+                None => client::WaitFor::Jobs(args.jobs),
+            };
+            client::wait(&args.socket.endpoint(), target, args.timeout).await
+        }
+        Some(Command::History(args)) => {
+            let since = args
+                .since
+                .and_then(|since| chrono::Duration::from_std(since).ok())
+                .and_then(|since| chrono::Local::now().checked_sub_signed(since));
+            let query = history::Query {
+                since,
+                failed: args.failed,
+                grep: args.grep,
+            };
+            history::show(&history::History::open_default(), &query, args.json)
+        }
+        Some(Command::Requeue(args)) => {
+            client::requeue(&args.socket.endpoint(), args.job, args.priority).await
+        }
+        Some(Command::Move(args)) => {
+            let to = match args.front {
+                true => protocol::QueueEnd::Front,
+                false => protocol::QueueEnd::Back,
+            };
+            client::move_job(&args.socket.endpoint(), args.job, to).await
+        }
+        Some(Command::Logs(args)) => {
+            client::logs(&args.socket.endpoint(), args.job, args.follow).await
+        }
+        Some(Command::Attach(args)) => {
+            let refresh = Duration::from_millis(args.refresh_ms);
+            client::attach(&args.socket.endpoint(), refresh).await
+        }
+        Some(Command::Reload(args)) => client::reload(&args.endpoint()).await,
+        Some(Command::Rescan(args)) => client::rescan(&args.endpoint()).await,
+        Some(Command::Drain(args)) => client::drain(&args.socket.endpoint(), args.timeout).await,
+        Some(Command::Agent(args)) => {
+            cluster::run_agent(&args.coordinator, args.token_file.as_deref(), args.name).await
+        }
+        Some(Command::Doctor) => doctor::run().await,
+    }
+}
+
+/// Run every job from the command files and wait for them to finish.
+async fn run(args: RunArgs) -> Result<()> {
+    let no_job_source =
+        args.filenames.is_empty() && args.retry_failed.is_none() && args.from_csv.is_none();
+    if no_job_source && atty::is(atty::Stream::Stdin) {
+        anyhow::bail!("No command file given (pass a filename, or '-' to read from stdin)");
+    }
 
     // Determine if we should use TUI
     let stdout_is_tty = atty::is(atty::Stream::Stdout);
-    let use_tui = !cli.no_tui && stdout_is_tty;
+    let stderr_is_tty = atty::is(atty::Stream::Stderr);
+    let tui_on_stderr = args.tui_on_stderr && stderr_is_tty;
+    let use_tui = !args.no_tui && args.progress.is_none() && (stdout_is_tty || tui_on_stderr);
+    let progress = match args.progress {
+        Some(ProgressStyle::Off) => None,
+        None if !use_tui && stderr_is_tty => Some(ProgressStyle::Bar),
+        style => style,
+    };
 
     // Create shared app state
     let app_state = Arc::new(RwLock::new(AppState::new()));
 
-    // Create scheduler with app state
-    let sched = Scheduler::new(app_state.clone(), use_tui).await?;
+    // Decide where job output goes: the TUI captures it, the progress bars
+    // print it above themselves, and plain mode lets children inherit our
+    // stdio unless a report or log files need their output
+    let (forward_tx, forward_rx) = unbounded_channel();
+    let needs_capture = args.junit.is_some() || args.output_dir.is_some();
+    let output = if use_tui && !tui_on_stderr {
+        OutputMode::Capture
+    } else if use_tui || progress.is_some() || needs_capture {
+        OutputMode::Forward(forward_tx)
+    } else {
+        OutputMode::Inherit
+    };
 
-    // Read commands from file
-    let file_content = tokio::fs::read_to_string(&cli.filename)
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to read file '{}': {}", cli.filename, e))?;
+    // Create scheduler with app state
+    let options = SchedulerOptions {
+        output,
+        max_runtime: args.max_runtime,
+        stop_signal: args.stop_signal,
+        shell: args.shell,
+        env: resolve_env(&args.env),
+        clean_env: args.clean_env,
+        retry: retry::RetryPolicy {
+            max: args.retries,
+            on: args.retry_on,
+            not_on: args.no_retry_on,
+            delay: args.retry_delay.unwrap_or_default(),
+            jitter: args.retry_jitter.unwrap_or_default(),
+        },
+        pre_cmd: args.pre_cmd.clone(),
+        output_dir: args.output_dir.clone(),
+        run_in_output_dir: args.run_in_output_dir,
+        tunables: scheduler::Tunables {
+            gpus: Some(args.gpus).filter(|gpus| !gpus.is_empty()),
+            jobs_per_gpu: args.jobs_per_gpu as usize,
+            memfree: args.memfree,
+            max_temp: args
+                .max_temp
+                .map(|max| scheduler::TempLimit::new(max, args.resume_temp))
+                .transpose()?,
+            alert: args.alert_on_first_failure,
+            ..Default::default()
+        },
+        sshlogin: args.sshlogin,
+        ssh: args.ssh,
+        local_gpus: true,
+        agents: false,
+        history: (!args.no_history).then(history::History::open_default),
+        cuda_mps: args.cuda_mps,
+        yield_gpus: !args.ignore_other_processes,
+        rescan_every: args.rescan_every,
+    };
+    let sched = Scheduler::new(app_state.clone(), options).await?;
 
-    for line in file_content.lines() {
-        let cmd = line.trim();
-        if !cmd.is_empty() {
-            sched.submit(cmd.to_string()).await?;
+    let jobs = match &args.retry_failed {
+        Some(path) => {
+            let jobs = report::failed_jobs(path)?;
+            if jobs.is_empty() {
+                tracing::info!("No failed jobs in '{}', nothing to retry", path.display());
+            }
+            jobs
         }
+        None => match (&args.from_csv, &args.template) {
+            (Some(csv), Some(template)) => {
+                let content = read_commands(csv).await?;
+                let mut jobs = jobfile::from_csv(&content, template)
+                    .map_err(|e| anyhow::anyhow!("Failed to parse '{}': {}", csv, e))?;
+                for job in jobs.iter_mut() {
+                    job.source = Some(csv.clone());
+                }
+                jobs
+            }
+            _ => read_jobs(&args.filenames, args.format, args.interleave).await?,
+        },
+    };
+    for job in jobs {
+        sched.submit(job).await?;
     }
 
+    let mut printer = None;
     if use_tui {
+        if tui_on_stderr {
+            // When the TUI lives on stderr, captured job stdout is forwarded to our stdout
+            printer = Some(spawn_printer(forward_rx, sched.cancel_token(), false));
+        }
+
+        {
+            let mut state = app_state.write().await;
+            state.keys = args.keys;
+            state.bell_on_failure = args.bell_on_failure;
+            state.keep_open = args.keep_open;
+            state.hung_after = args.hung_after;
+        }
         // Try to spawn UI, fall back to non-TUI mode if it fails
-        let ui_result = UI::new(app_state.clone()).await;
+        let ui_result = UI::new(app_state.clone(), tui_on_stderr).await;
         match ui_result {
             Ok(ui) => {
-                let ui_handle = tokio::spawn(async move { ui.run().await });
+                let (actions, asked) = tokio::sync::mpsc::unbounded_channel();
+                let ui = ui
+                    .with_actions(actions)
+                    .with_refresh(Duration::from_millis(args.refresh_ms));
+                let action_sched = sched.clone();
+                tokio::spawn(async move { action_sched.handle_actions(asked).await });
+                let ui_cancel = sched.cancel_token();
+                let ui_handle = tokio::spawn(async move { ui.run(ui_cancel).await });
 
-                // Set up Ctrl+C handler
+                // SIGINT from outside the TUI asks first too, as Ctrl+C does
                 let ctrlc_state = app_state.clone();
-                let ctrlc_sched = sched.clone();
-                let ctrlc_handle = tokio::spawn(async move {
-                    signal::ctrl_c()
-                        .await
-                        .expect("Failed to install Ctrl+C handler");
-                    // Set should_quit flag
-                    let mut state = ctrlc_state.write().await;
-                    state.should_quit = true;
-                    // Kill all running jobs
-                    ctrlc_sched.kill_all_jobs().await;
+                tokio::spawn(async move {
+                    loop {
+                        signal::ctrl_c()
+                            .await
+                            .expect("Failed to install Ctrl+C handler");
+                        ctrlc_state.write().await.confirm_stop = true;
+                    }
                 });
 
-                // Wait for UI to exit (user pressed 'q' or Ctrl+C)
-                tokio::select! {
-                    result = ui_handle => {
-                        result??;
-                    }
-                    _ = ctrlc_handle => {
-                        // Ctrl+C was pressed, jobs have been killed
-                        println!("\\nAll jobs terminated.");
-                    }
+                // Wait for UI to exit (user pressed 'q', all jobs finished, or Ctrl+C)
+                ui_handle.await??;
+                if app_state.read().await.stop_all {
+                    // Kill all running jobs
+                    sched.shutdown().await;
+                    tracing::info!("All jobs terminated");
                 }
             }
             Err(e) => {
-                eprintln!(
-                    "[gparallel] Failed to initialize TUI: {}, falling back to plain text mode",
+                tracing::warn!(
+                    "Failed to initialize TUI: {}, falling back to plain text mode",
                     e
                 );
 
@@ -100,8 +1021,8 @@ async fn main() -> Result<()> {
                     signal::ctrl_c()
                         .await
                         .expect("Failed to install Ctrl+C handler");
-                    println!("\\n[gparallel] Caught Ctrl+C, terminating all jobs...");
-                    ctrlc_sched.kill_all_jobs().await;
+                    tracing::info!("Caught Ctrl+C, terminating all jobs...");
+                    ctrlc_sched.shutdown().await;
                     std::process::exit(1);
                 });
 
@@ -112,6 +1033,7 @@ async fn main() -> Result<()> {
                     }
                     sleep(Duration::from_millis(100)).await;
                 }
+                print_summary(&app_state).await;
             }
         }
     } else {
@@ -123,11 +1045,19 @@ async fn main() -> Result<()> {
             signal::ctrl_c()
                 .await
                 .expect("Failed to install Ctrl+C handler");
-            println!("\n[gparallel] Caught Ctrl+C, terminating all jobs...");
-            ctrlc_sched.kill_all_jobs().await;
+            tracing::info!("Caught Ctrl+C, terminating all jobs...");
+            ctrlc_sched.shutdown().await;
             std::process::exit(1);
         });
 
+        if let Some(style) = progress {
+            ProgressBars::new(app_state.clone(), forward_rx, style)
+                .run(sched.clone())
+                .await?;
+        } else {
+            printer = Some(spawn_printer(forward_rx, sched.cancel_token(), true));
+        }
+
         // Wait for all jobs to complete
         loop {
             if sched.is_idle().await {
@@ -135,7 +1065,70 @@ async fn main() -> Result<()> {
             }
             sleep(Duration::from_millis(100)).await;
         }
+        print_summary(&app_state).await;
+    }
+
+    if let Some(path) = &args.junit {
+        report::write_junit(path, &*app_state.read().await)?;
+    }
+    if let Some(path) = &args.results {
+        report::write_results(path, &*app_state.read().await)?;
+    }
+
+    // Stop background tasks cleanly unless the user quit the TUI early
+    // (in which case running jobs are left alone, and so are the MPS
+    // daemons they may be using until they end)
+    if sched.is_idle().await {
+        sched.shutdown().await;
+        if let Some(printer) = printer {
+            printer.await.ok();
+        }
+    } else if !app_state.read().await.stop_all {
+        sched.release_cuda_mps().await;
     }
 
     Ok(())
 }
+
+/// Print captured job output that no display consumes (stdout to stdout,
+/// stderr to stderr when `with_stderr`), draining what is left on shutdown.
+fn spawn_printer(
+    mut rx: UnboundedReceiver<ForwardedLine>,
+    cancel: CancellationToken,
+    with_stderr: bool,
+) -> JoinHandle<()> {
+    let print = move |fwd: ForwardedLine| {
+        if !fwd.is_stderr {
+            println!("{}", fwd.line);
+        } else if with_stderr {
+            eprintln!("{}", fwd.line);
+        }
+    };
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                fwd = rx.recv() => match fwd {
+                    Some(fwd) => print(fwd),
+                    None => break,
+                },
+                _ = cancel.cancelled() => {
+                    while let Ok(fwd) = rx.try_recv() {
+                        print(fwd);
+                    }
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// Exit-status breakdown printed to stderr at the end of non-TUI runs.
+async fn print_summary(app_state: &Arc<RwLock<AppState>>) {
+    let state = app_state.read().await;
+    for line in summary::exit_summary(&state) {
+        eprintln!("{}", line);
+    }
+    if let Some(line) = summary::queue_wait_summary(&state) {
+        eprintln!("{}", line);
+    }
+}
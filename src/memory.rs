@@ -0,0 +1,63 @@
+//! Host (not GPU) memory checks for `--memfree` and the status bar.
+
+use anyhow::Result;
+
+/// Parse a memory size such as `8G`, `512M`, `1.5GiB` or a plain byte
+/// count. Suffixes are binary (`1G` = 1024³ bytes), as in GNU parallel.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid size '{}' (e.g. 8G, 512M)", s))?;
+    let shift = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        "t" | "tb" | "tib" => 40,
+        _ => anyhow::bail!("unknown size unit '{}' in '{}'", unit, s),
+    };
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+/// Memory the kernel considers available for new allocations without
+/// swapping (`MemAvailable`), or `None` if it cannot be read.
+pub fn available_bytes() -> Option<u64> {
+    meminfo_bytes("MemAvailable:")
+}
+
+/// Installed memory (`MemTotal`), or `None` if it cannot be read.
+pub fn total_bytes() -> Option<u64> {
+    meminfo_bytes("MemTotal:")
+}
+
+fn meminfo_bytes(field: &str) -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with(field))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// `8.0 GiB`-style rendering for log messages.
+pub fn humanize_bytes(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / (1u64 << 30) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_binary_suffixes() {
+        assert_eq!(parse_size("8G").unwrap(), 8 << 30);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("1.5GiB").unwrap(), 3 << 29);
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert!(parse_size("8X").is_err());
+        assert!(parse_size("G").is_err());
+    }
+}
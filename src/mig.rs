@@ -0,0 +1,140 @@
+//! MIG (Multi-Instance GPU) instances of A100/H100-class GPUs. Each one
+//! is a GPU of its own to CUDA, reached through its `MIG-` UUID, so the
+//! scheduler hands them out in place of their parent.
+
+use nvml_wrapper::{Device, Nvml};
+use nvml_wrapper_sys::bindings::{
+    nvmlDevice_t, nvmlReturn_enum_NVML_SUCCESS, NvmlLib, NVML_DEVICE_MIG_ENABLE,
+};
+use std::sync::OnceLock;
+
+/// A MIG instance NVML lists under its GPU.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigDevice {
+    /// NVML index of the GPU it is carved from
+    pub parent: u32,
+    /// Its index within the parent
+    pub index: u32,
+    /// Profile such as `3g.20gb`
+    pub profile: String,
+    pub uuid: String,
+}
+
+impl MigDevice {
+    /// How the GPU panel names it, e.g. "A100 MIG 3g.20gb #1".
+    pub fn name(&self, parent_name: &str) -> String {
+        format!("{} MIG {} #{}", parent_name, self.profile, self.index)
+    }
+
+    /// Its own NVML handle, for the memory and processes of the instance
+    /// rather than of the whole GPU.
+    pub fn device<'nvml>(&self, nvml: &'nvml Nvml) -> Option<Device<'nvml>> {
+        let parent = nvml.device_by_index(self.parent).ok()?;
+        mig_device_by_index(nvml, &parent, self.index)
+    }
+}
+
+/// MIG instances of this machine's GPUs, none where MIG is off.
+pub fn detect(nvml: &Nvml) -> Vec<MigDevice> {
+    let mut devices = Vec::new();
+    for parent in 0..nvml.device_count().unwrap_or(0) {
+        let Ok(device) = nvml.device_by_index(parent) else {
+            continue;
+        };
+        for index in 0..mig_device_count(&device) {
+            let Some(mig) = mig_device_by_index(nvml, &device, index) else {
+                continue;
+            };
+            let (Ok(name), Ok(uuid)) = (mig.name(), mig.uuid()) else {
+                continue;
+            };
+            devices.push(MigDevice {
+                parent,
+                index,
+                profile: profile(&name).to_string(),
+                uuid,
+            });
+        }
+    }
+    devices
+}
+
+/// NVML's MIG calls, which nvml-wrapper does not wrap. Loading the library
+/// again gets the one `Nvml` already has loaded and initialized, so its
+/// device handles are good here.
+fn mig_calls() -> Option<&'static NvmlLib> {
+    static LIB: OnceLock<Option<NvmlLib>> = OnceLock::new();
+    LIB.get_or_init(|| unsafe { NvmlLib::new("libnvidia-ml.so") }.ok())
+        .as_ref()
+}
+
+/// How many instances `device` has room for, 0 unless MIG is on.
+fn mig_device_count(device: &Device) -> u32 {
+    let Some(lib) = mig_calls() else {
+        return 0;
+    };
+    let (mut current, mut pending, mut count) = (0, 0, 0);
+    // SAFETY: the handle comes from a live `Nvml`, and the out-pointers
+    // are to locals
+    unsafe {
+        if lib.nvmlDeviceGetMigMode(device.handle(), &mut current, &mut pending)
+            != nvmlReturn_enum_NVML_SUCCESS
+            || current != NVML_DEVICE_MIG_ENABLE
+            || lib.nvmlDeviceGetMaxMigDeviceCount(device.handle(), &mut count)
+                != nvmlReturn_enum_NVML_SUCCESS
+        {
+            return 0;
+        }
+    }
+    count
+}
+
+/// The instance at `index` of `device`, `None` where that slot is empty.
+fn mig_device_by_index<'nvml>(
+    nvml: &'nvml Nvml,
+    device: &Device,
+    index: u32,
+) -> Option<Device<'nvml>> {
+    let lib = mig_calls()?;
+    let mut mig: nvmlDevice_t = std::ptr::null_mut();
+    // SAFETY: as above; NVML hands back a handle that lives as long as
+    // the library stays initialized, which `nvml` keeps it
+    unsafe {
+        if lib.nvmlDeviceGetMigDeviceHandleByIndex(device.handle(), index, &mut mig)
+            != nvmlReturn_enum_NVML_SUCCESS
+        {
+            return None;
+        }
+        Some(Device::new(mig, nvml))
+    }
+}
+
+/// The profile in a MIG instance's name, "NVIDIA A100-SXM4-40GB MIG
+/// 3g.20gb" → "3g.20gb".
+fn profile(name: &str) -> &str {
+    name.rsplit_once("MIG ")
+        .map_or(name, |(_, profile)| profile.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_instances_by_their_profile() {
+        assert_eq!(profile("NVIDIA A100-SXM4-40GB MIG 3g.20gb"), "3g.20gb");
+        assert_eq!(
+            profile("NVIDIA H100 80GB HBM3 MIG 1g.10gb+me"),
+            "1g.10gb+me"
+        );
+        assert_eq!(profile("3g.20gb"), "3g.20gb");
+
+        let mig = MigDevice {
+            parent: 0,
+            index: 1,
+            profile: "2g.10gb".to_string(),
+            uuid: "MIG-8f1d2c3b-0000".to_string(),
+        };
+        assert_eq!(mig.name("A100"), "A100 MIG 2g.10gb #1");
+    }
+}
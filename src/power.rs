@@ -0,0 +1,118 @@
+//! Per-job GPU power caps (`power_limit`), for batches that have to stay
+//! within an energy budget. NVML keeps a cap until the driver unloads, so
+//! each GPU gets its own limit back once the jobs that capped it are done.
+//! Setting a cap takes root.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use nvml_wrapper::Nvml;
+use tracing::warn;
+
+/// Parse a power limit such as `250W` or a plain number of watts into
+/// milliwatts, NVML's unit.
+pub fn parse_watts(s: &str) -> Result<u32> {
+    let s = s.trim();
+    let number = s.strip_suffix(['W', 'w']).unwrap_or(s).trim_end();
+    let watts: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid power limit '{}' (e.g. 250W)", s))?;
+    if !(1.0..=100_000.0).contains(&watts) {
+        anyhow::bail!("power limit '{}' is out of range", s);
+    }
+    Ok((watts * 1000.0) as u32)
+}
+
+/// Caps in force by NVML index: the GPU's own limit and how many running
+/// jobs capped it. While jobs share a GPU the cap of the latest to start
+/// holds, until the last of them finishes.
+#[derive(Debug, Default)]
+pub struct PowerCaps {
+    held: Mutex<HashMap<u32, (u32, usize)>>,
+}
+
+impl PowerCaps {
+    /// Cap the GPUs at NVML `indices` to `limit_mw` for a starting job, or
+    /// leave all of them alone. Blocks on NVML.
+    pub fn apply(&self, nvml: &Nvml, indices: &[u32], limit_mw: u32) -> Result<()> {
+        for (done, &index) in indices.iter().enumerate() {
+            if let Err(e) = self.apply_one(nvml, index, limit_mw) {
+                self.release(nvml, &indices[..done]);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_one(&self, nvml: &Nvml, index: u32, limit_mw: u32) -> Result<()> {
+        let mut device = nvml.device_by_index(index)?;
+        let range = device.power_management_limit_constraints()?;
+        if !(range.min_limit..=range.max_limit).contains(&limit_mw) {
+            anyhow::bail!(
+                "{} W is outside the {}-{} W GPU {} can be capped to",
+                limit_mw / 1000,
+                range.min_limit / 1000,
+                range.max_limit / 1000,
+                index
+            );
+        }
+        let mut held = self.held.lock().unwrap();
+        let own = match held.get(&index) {
+            Some((own, _)) => *own,
+            None => device.power_management_limit()?,
+        };
+        device.set_power_management_limit(limit_mw)?;
+        held.entry(index).or_insert((own, 0)).1 += 1;
+        Ok(())
+    }
+
+    /// Undo `apply` for a finished job: GPUs no other job capped get their
+    /// own limit back. Blocks on NVML.
+    pub fn release(&self, nvml: &Nvml, indices: &[u32]) {
+        let restore: Vec<(u32, u32)> = {
+            let mut held = self.held.lock().unwrap();
+            indices
+                .iter()
+                .filter_map(|index| {
+                    let (own, jobs) = held.get_mut(index)?;
+                    *jobs -= 1;
+                    if *jobs > 0 {
+                        return None;
+                    }
+                    let own = *own;
+                    held.remove(index);
+                    Some((*index, own))
+                })
+                .collect()
+        };
+        for (index, own) in restore {
+            let restored = nvml
+                .device_by_index(index)
+                .and_then(|mut device| device.set_power_management_limit(own));
+            if let Err(e) = restored {
+                warn!(
+                    gpu = index,
+                    "Failed to restore the {} W power limit: {}",
+                    own / 1000,
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_watts_into_milliwatts() {
+        assert_eq!(parse_watts("250W").unwrap(), 250_000);
+        assert_eq!(parse_watts("300").unwrap(), 300_000);
+        assert_eq!(parse_watts("187.5 w").unwrap(), 187_500);
+        assert!(parse_watts("250kW").is_err());
+        assert!(parse_watts("0W").is_err());
+        assert!(parse_watts("W").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use crossterm::{
+    cursor::{MoveToColumn, MoveUp},
+    queue,
+    terminal::{self, Clear, ClearType},
+};
+use std::{
+    io::{self, Write},
+    sync::Arc,
+};
+use tokio::{
+    sync::{mpsc::UnboundedReceiver, RwLock},
+    time::{sleep, Duration},
+};
+
+use crate::duration::humanize;
+use crate::scheduler::{ForwardedLine, Scheduler};
+use crate::ui::{AppState, JobState};
+
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// Lightweight renderers for non-TUI runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressStyle {
+    /// One line per running job plus an overall bar, redrawn in place on stderr
+    Bars,
+    /// A single overall bar on stderr (default when stderr is a terminal)
+    Bar,
+    /// No progress display, only job output and the final summary
+    Off,
+}
+
+/// Progress display drawn with plain cursor movements (no alternate
+/// screen). Job output received on `rx` is printed above the bars.
+pub struct ProgressBars {
+    state: Arc<RwLock<AppState>>,
+    rx: UnboundedReceiver<ForwardedLine>,
+    /// Only the overall bar, kept on the cursor's line
+    single_line: bool,
+    drawn_lines: u16,
+    tick: usize,
+}
+
+impl ProgressBars {
+    pub fn new(
+        state: Arc<RwLock<AppState>>,
+        rx: UnboundedReceiver<ForwardedLine>,
+        style: ProgressStyle,
+    ) -> Self {
+        Self {
+            state,
+            rx,
+            single_line: style == ProgressStyle::Bar,
+            drawn_lines: 0,
+            tick: 0,
+        }
+    }
+
+    /// Redraw until the scheduler has no queued or running jobs left, or
+    /// shuts down.
+    pub async fn run(mut self, sched: Scheduler) -> Result<()> {
+        let cancel = sched.cancel_token();
+        loop {
+            let idle = sched.is_idle().await;
+            self.redraw().await?;
+            if idle {
+                break;
+            }
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = sleep(Duration::from_millis(200)) => {}
+            }
+        }
+        if self.single_line {
+            // Leave the final bar in place for the summary below it
+            eprintln!();
+        }
+        Ok(())
+    }
+
+    async fn redraw(&mut self) -> Result<()> {
+        let width = match terminal::size() {
+            Ok((w, _)) if w > 0 => w as usize,
+            _ => 80,
+        };
+        let lines = {
+            let state = self.state.read().await;
+            self.render_lines(&state, width)
+        };
+
+        let mut stderr = io::stderr();
+        self.clear(&mut stderr)?;
+
+        // Job output goes above the bars, each line to its original stream
+        let mut stdout = io::stdout();
+        while let Ok(fwd) = self.rx.try_recv() {
+            if fwd.is_stderr {
+                writeln!(stderr, "{}", fwd.line)?;
+            } else {
+                writeln!(stdout, "{}", fwd.line)?;
+            }
+        }
+        stdout.flush()?;
+
+        if self.single_line {
+            write!(stderr, "{}", lines.concat())?;
+        } else {
+            for line in &lines {
+                writeln!(stderr, "{}", line)?;
+            }
+            self.drawn_lines = lines.len() as u16;
+        }
+        stderr.flush()?;
+        self.tick += 1;
+        Ok(())
+    }
+
+    fn clear(&mut self, out: &mut impl Write) -> Result<()> {
+        if self.drawn_lines > 0 {
+            queue!(out, MoveUp(self.drawn_lines), MoveToColumn(0))?;
+        }
+        queue!(out, Clear(ClearType::FromCursorDown))?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn render_lines(&self, state: &AppState, width: usize) -> Vec<String> {
+        let total = state.jobs.len();
+        let mut done = 0;
+        let mut failed = 0;
+        let mut running = 0;
+        let mut lines = Vec::new();
+
+        for job in &state.jobs {
+            match job.state {
+                JobState::Completed | JobState::Cancelled => done += 1,
+                JobState::Failed | JobState::TimedOut | JobState::SetupFailed { .. } => {
+                    done += 1;
+                    failed += 1;
+                }
+                JobState::Running { .. } if self.single_line => running += 1,
+                JobState::Running { gpu_id } => {
+                    running += 1;
+                    let spinner = SPINNER[self.tick % SPINNER.len()];
+                    let id_str = job.id.to_string();
+                    let last = job.log_lines.back().map(String::as_str).unwrap_or("");
+                    let elapsed = job.timing.elapsed().map(humanize).unwrap_or_default();
+                    let line = format!(
+                        "  G{:<2} {} {} {:>10} {}  {}",
+                        gpu_id,
+                        spinner,
+                        &id_str[..8],
+                        elapsed,
+                        job.cmd,
+                        last
+                    );
+                    lines.push(truncate(&line, width));
+                }
+                JobState::Queued => {}
+            }
+        }
+
+        let bar_width: usize = 30;
+        let filled = (done * bar_width).checked_div(total).unwrap_or(0);
+        let elapsed = state.run_started.elapsed();
+        let eta = eta(elapsed, done, total)
+            .map(|d| format!(", ETA {}", humanize(d)))
+            .unwrap_or_default();
+        let running = match state.gpus.len() {
+            0 => format!("{} running", running),
+            gpus => format!("{}/{} GPUs busy", running, gpus),
+        };
+        let overall = format!(
+            "[{}{}] {}/{} done, {} failed, {}, {} elapsed{}",
+            "#".repeat(filled),
+            ".".repeat(bar_width - filled),
+            done,
+            total,
+            failed,
+            running,
+            humanize(elapsed),
+            eta
+        );
+        lines.insert(0, truncate(&overall, width));
+        if let Some(flash) = state.failure_flash.as_ref().filter(|f| f.is_active()) {
+            let banner = truncate(&format!("✗ {}", flash.message), width);
+            let banner = format!("\x1b[1;37;41m{}\x1b[0m", banner);
+            if self.single_line {
+                // One row only: the banner takes the bar's place while active
+                lines[0] = banner;
+            } else {
+                lines.insert(0, banner);
+            }
+        }
+        lines
+    }
+}
+
+/// Remaining time, extrapolated from the average throughput so far.
+fn eta(elapsed: Duration, done: usize, total: usize) -> Option<Duration> {
+    if done == 0 || done >= total {
+        return None;
+    }
+    Some(elapsed.mul_f64((total - done) as f64 / done as f64))
+}
+
+fn truncate(s: &str, width: usize) -> String {
+    // Keep each entry on one terminal row so the cursor-up count stays right
+    s.chars().take(width.saturating_sub(1)).collect()
+}
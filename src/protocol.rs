@@ -0,0 +1,173 @@
+//! Messages exchanged with `gparallel serve` over its Unix socket: one JSON
+//! object per line in each direction, every request answered by exactly one
+//! response (except `logs`, which is answered by `log` messages up to a
+//! `log_end`, `watch`, which streams until the client disconnects, and
+//! `drain`, whose `draining` is followed by the daemon closing the
+//! connection as it exits).
+//! A daemon started with a token file wants `auth` before anything else.
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::host::HostUsage;
+use crate::jobfile::{JobRequest, Priority};
+use crate::report::JobRecord;
+use crate::ui::GpuInfo;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Present the daemon's token; required first when it has one. Clients
+    /// over TCP also say which user they run as
+    Auth {
+        token: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+    },
+    /// Queue a job
+    Submit { job: JobRequest },
+    /// List every job the daemon knows about
+    Status,
+    /// Cancel queued jobs and stop running ones
+    Cancel { target: CancelTarget },
+    /// Send a job's captured output, then with `follow` keep sending new
+    /// lines until it finishes
+    Logs {
+        job: String,
+        #[serde(default)]
+        follow: bool,
+    },
+    /// Stream `state` snapshots and every job's output (`gparallel attach`)
+    Watch,
+    /// Give a queued job another priority
+    Requeue { job: String, priority: Priority },
+    /// Put a queued job at the front or the back of the queue
+    Move { job: String, to: QueueEnd },
+    /// Swap a queued job with its neighbour ahead of it (`up`) or behind it
+    /// in the queue
+    Shift { job: String, up: bool },
+    /// Hold queued jobs back (`paused`), or let them start again
+    Pause { paused: bool },
+    /// Read the `--config` file again and apply it
+    Reload,
+    /// Detect the daemon's GPUs again, adding new ones and draining
+    /// vanished ones
+    Rescan,
+    /// Take no more jobs, let the running ones finish (stopping them after
+    /// `timeout_secs`), save the queue and exit
+    Drain {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_secs: Option<f64>,
+    },
+}
+
+/// Which jobs a `cancel` request applies to.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelTarget {
+    /// The job whose id starts with this prefix
+    Job(String),
+    /// Every job that has not finished
+    All,
+    /// Every job that has not started
+    Queued,
+}
+
+/// Where `move` puts a job.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueEnd {
+    Front,
+    Back,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    /// The token was accepted
+    Authenticated,
+    /// The config file was read again and applied
+    Reloaded,
+    /// GPUs were detected again: these joined and these left, by name
+    Rescanned {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    /// Queued jobs are now held back, or not
+    Paused {
+        paused: bool,
+    },
+    /// The daemon is draining; it closes the connection when it exits
+    Draining {
+        running: usize,
+        queued: usize,
+    },
+    /// The job was queued under this id
+    Submitted {
+        id: String,
+    },
+    /// Jobs in submission order
+    Status {
+        jobs: Vec<JobRecord>,
+    },
+    /// The job now waits with its new priority, or in its new place
+    Requeued {
+        id: String,
+    },
+    /// These jobs were cancelled; running ones are being stopped
+    Cancelled {
+        ids: Vec<String>,
+    },
+    /// Output lines of the job a `logs` request is for
+    Log {
+        lines: Vec<String>,
+    },
+    /// The last message for a `logs` request
+    LogEnd,
+    /// A job was submitted or changed state (HTTP `/events`)
+    Job {
+        job: Box<JobRecord>,
+    },
+    /// GPUs and jobs as they are now, sent periodically to watchers
+    State {
+        gpus: Vec<GpuInfo>,
+        jobs: Vec<JobRecord>,
+        /// Queued jobs are held back
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        paused: bool,
+        /// Load and memory of the daemon's machine
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        host: Option<HostUsage>,
+    },
+    /// New output lines of a job, for watchers
+    JobLog {
+        id: String,
+        lines: Vec<String>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Write `msg` as one line.
+pub async fn send<T: Serialize>(writer: &mut (impl AsyncWrite + Unpin), msg: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(msg)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read the next message, or `None` once the peer has closed the connection.
+pub async fn recv<T: DeserializeOwned>(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Result<Option<T>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    serde_json::from_str(&line)
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("invalid message: {}", e))
+}
@@ -0,0 +1,488 @@
+//! Files written at the end of a run for CI systems and later analysis.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::duration::humanize;
+use crate::jobfile::{JobRequest, Priority};
+use crate::ui::{AppState, JobExit, JobInfo, JobProcess, JobState, JobTiming};
+
+/// Lines of stderr quoted in a JUnit failure.
+const STDERR_TAIL: usize = 20;
+
+/// JUnit XML with one test case per job, named after its command.
+pub fn write_junit(path: &Path, state: &AppState) -> Result<()> {
+    std::fs::write(path, junit_xml(state))
+        .map_err(|e| anyhow::anyhow!("Failed to write JUnit report '{}': {}", path.display(), e))
+}
+
+fn junit_xml(state: &AppState) -> String {
+    let failures = state.jobs.iter().filter(|j| j.state.is_failure()).count();
+    let skipped = state.jobs.iter().filter(|j| j.exit.is_none()).count();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"gparallel\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+        state.jobs.len(),
+        failures,
+        skipped,
+        state.run_started.elapsed().as_secs_f64()
+    );
+    for job in &state.jobs {
+        let time = job.timing.elapsed().map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        let _ = write!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape(&job.cmd),
+            escape(job.source.as_deref().unwrap_or("gparallel")),
+            time
+        );
+        match (&job.state, job.exit) {
+            (state, Some(exit)) if state.is_failure() => {
+                let message = match state {
+                    JobState::SetupFailed { phase } => format!("{} failed: {}", phase, exit),
+                    JobState::TimedOut => format!("timed out: {}", exit),
+                    _ => exit.to_string(),
+                };
+                let _ = writeln!(
+                    xml,
+                    ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>",
+                    escape(&message),
+                    escape(&stderr_tail(job))
+                );
+            }
+            (_, None) => xml.push_str(">\n      <skipped/>\n    </testcase>\n"),
+            _ => xml.push_str("/>\n"),
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// One row of `--results`, also the job entry of the daemon's `status`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub cmd: String,
+    pub source: Option<String>,
+    /// User who submitted the job to the daemon (JSON only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Per-job options (JSON only), so failed jobs can be re-run as-is
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    pub requested_gpus: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_limit: Option<String>,
+    #[serde(default, skip_serializing_if = "is_normal")]
+    pub priority: Priority,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub state: String,
+    pub gpu: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu_uuid: Option<String>,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub duration_secs: Option<f64>,
+    pub queue_wait_secs: Option<f64>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub retries: u32,
+    pub peak_gpu_memory_mb: Option<u64>,
+    pub output_dir: Option<String>,
+    /// The job's process while it runs (JSON only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process: Option<JobProcess>,
+}
+
+fn is_normal(priority: &Priority) -> bool {
+    *priority == Priority::Normal
+}
+
+impl JobRecord {
+    pub fn new(job: &JobInfo) -> Self {
+        let (exit_code, signal) = match job.exit {
+            Some(JobExit::Code(code)) => (Some(code), None),
+            Some(JobExit::Signal(sig)) => (None, Some(sig)),
+            _ => (None, None),
+        };
+        Self {
+            id: job.id.to_string(),
+            cmd: job.cmd.clone(),
+            source: job.source.clone(),
+            owner: job.owner.clone(),
+            env: job.env.clone(),
+            requested_gpus: job.requested_gpus,
+            mem: job.mem.clone(),
+            power_limit: job.power_limit.clone(),
+            priority: job.priority,
+            tag: job.tag.clone(),
+            state: match job.state {
+                JobState::Queued => "queued",
+                JobState::Running { .. } => "running",
+                JobState::Completed => "completed",
+                JobState::Failed => "failed",
+                JobState::TimedOut => "timed_out",
+                JobState::SetupFailed { .. } => "setup_failed",
+                JobState::Cancelled => "cancelled",
+            }
+            .to_string(),
+            gpu: job.gpu,
+            gpu_uuid: job.gpu_uuid.clone(),
+            started_at: job.timing.started_at.map(|t| t.to_rfc3339()),
+            finished_at: job.timing.finished_at.map(|t| t.to_rfc3339()),
+            duration_secs: job.timing.elapsed().map(|d| d.as_secs_f64()),
+            queue_wait_secs: job.timing.started.map(|_| job.timing.waited.as_secs_f64()),
+            exit_code,
+            signal,
+            retries: job.retries,
+            peak_gpu_memory_mb: job.peak_gpu_memory_mb,
+            output_dir: job.output_dir.as_ref().map(|d| d.display().to_string()),
+            process: job.process.clone(),
+        }
+    }
+
+    /// The first eight characters of the id, as tables show it.
+    pub fn short_id(&self) -> String {
+        self.id.chars().take(8).collect()
+    }
+
+    /// How long the job ran, for tables.
+    pub fn runtime(&self) -> String {
+        self.duration_secs
+            .map(|s| humanize(Duration::from_secs_f64(s)))
+            .unwrap_or_default()
+    }
+
+    /// Its exit code or signal, for tables.
+    pub fn exit(&self) -> String {
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => code.to_string(),
+            (None, Some(sig)) => format!("sig {}", sig),
+            _ => String::new(),
+        }
+    }
+
+    /// The job as the TUI of `gparallel attach` shows it. Times are
+    /// re-anchored on now, and log lines are left for the caller to fill.
+    pub fn to_job_info(&self) -> Result<JobInfo> {
+        let state = match self.state.as_str() {
+            "queued" => JobState::Queued,
+            "running" => JobState::Running {
+                gpu_id: self.gpu.unwrap_or_default(),
+            },
+            "completed" => JobState::Completed,
+            "failed" => JobState::Failed,
+            "timed_out" => JobState::TimedOut,
+            "setup_failed" => JobState::SetupFailed { phase: "setup" },
+            "cancelled" => JobState::Cancelled,
+            other => anyhow::bail!("unknown job state '{}'", other),
+        };
+        let exit = match (self.exit_code, self.signal) {
+            (Some(code), _) => Some(JobExit::Code(code)),
+            (None, Some(sig)) => Some(JobExit::Signal(sig)),
+            _ if state.is_failure() => Some(JobExit::SpawnFailed),
+            _ if matches!(state, JobState::Completed) => Some(JobExit::Unknown),
+            _ => None,
+        };
+        let now = Instant::now();
+        let local = |t: &Option<String>| {
+            t.as_deref()
+                .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.with_timezone(&chrono::Local))
+        };
+        let started_at = local(&self.started_at);
+        // A running job's duration is only as recent as the record
+        let running_for = match (&state, started_at) {
+            (JobState::Running { .. }, Some(at)) => (chrono::Local::now() - at).to_std().ok(),
+            _ => self.duration_secs.map(Duration::from_secs_f64),
+        };
+        let timing = JobTiming {
+            queued: None,
+            waited: Duration::from_secs_f64(self.queue_wait_secs.unwrap_or_default()),
+            started: running_for.and_then(|d| now.checked_sub(d)),
+            finished: state.is_finished().then_some(now),
+            started_at,
+            finished_at: local(&self.finished_at),
+        };
+        Ok(JobInfo {
+            source: self.source.clone(),
+            owner: self.owner.clone(),
+            env: self.env.clone(),
+            requested_gpus: self.requested_gpus,
+            mem: self.mem.clone(),
+            power_limit: self.power_limit.clone(),
+            priority: self.priority,
+            tag: self.tag.clone(),
+            state,
+            timing,
+            exit,
+            retries: self.retries,
+            gpu: self.gpu,
+            gpu_uuid: self.gpu_uuid.clone(),
+            peak_gpu_memory_mb: self.peak_gpu_memory_mb,
+            output_dir: self.output_dir.as_ref().map(Into::into),
+            process: self.process.clone(),
+            ..JobInfo::new(Uuid::parse_str(&self.id)?, self.cmd.clone())
+        })
+    }
+}
+
+/// `rows` under `header`, in columns two spaces apart; the last column is
+/// left ragged, as it holds the commands.
+pub fn table<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> Vec<String> {
+    let mut widths = header.map(|h| h.chars().count());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let format_row = |row: &[&str]| {
+        let cells: Vec<String> = row[..N - 1]
+            .iter()
+            .zip(widths)
+            .map(|(cell, w)| format!("{:<w$}", cell, w = w))
+            .collect();
+        format!("{}  {}", cells.join("  "), row[N - 1])
+    };
+    let mut lines = vec![format_row(&header)];
+    lines.extend(
+        rows.iter()
+            .map(|row| format_row(&row.each_ref().map(String::as_str))),
+    );
+    lines
+}
+
+/// Per-job records for downstream analysis: CSV if `path` ends in `.csv`,
+/// otherwise a JSON array.
+pub fn write_results(path: &Path, state: &AppState) -> Result<()> {
+    let records: Vec<JobRecord> = state.jobs.iter().map(JobRecord::new).collect();
+    let content = if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("csv"))
+    {
+        results_csv(&records)
+    } else {
+        serde_json::to_string_pretty(&records)? + "\n"
+    };
+    std::fs::write(path, content)
+        .map_err(|e| anyhow::anyhow!("Failed to write results '{}': {}", path.display(), e))
+}
+
+/// The parts of a `--results` JSON record needed to run the job again.
+#[derive(Debug, Deserialize)]
+struct PreviousJob {
+    cmd: String,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    requested_gpus: Option<u32>,
+    #[serde(default)]
+    mem: Option<String>,
+    #[serde(default)]
+    power_limit: Option<String>,
+    #[serde(default)]
+    priority: Priority,
+    state: String,
+}
+
+/// The jobs that failed (including setup failures and timeouts) in an
+/// earlier run's `--results` JSON, in their original order.
+pub fn failed_jobs(path: &Path) -> Result<Vec<JobRequest>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read results '{}': {}", path.display(), e))?;
+    let previous: Vec<PreviousJob> = serde_json::from_str(&content).map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to parse results '{}' (a --results JSON file is needed): {}",
+            path.display(),
+            e
+        )
+    })?;
+    Ok(previous
+        .into_iter()
+        .filter(|job| matches!(job.state.as_str(), "failed" | "timed_out" | "setup_failed"))
+        .map(|job| JobRequest {
+            cmd: job.cmd,
+            env: job.env,
+            gpus: job.requested_gpus,
+            mem: job.mem,
+            power_limit: job.power_limit,
+            priority: Some(job.priority).filter(|p| *p != Priority::Normal),
+            tag: None,
+            source: job.source,
+            owner: None,
+        })
+        .collect())
+}
+
+/// The `job.json` metadata file of a job's output directory.
+pub fn write_job_json(path: &Path, job: &JobInfo) -> Result<()> {
+    let json = serde_json::to_string_pretty(&JobRecord::new(job))? + "\n";
+    std::fs::write(path, json)
+        .map_err(|e| anyhow::anyhow!("Failed to write '{}': {}", path.display(), e))
+}
+
+fn results_csv(records: &[JobRecord]) -> String {
+    fn opt<T: ToString>(v: &Option<T>) -> String {
+        v.as_ref().map(ToString::to_string).unwrap_or_default()
+    }
+
+    let mut csv = String::from(
+        "id,cmd,source,state,gpu,gpu_uuid,started_at,finished_at,duration_secs,queue_wait_secs,exit_code,signal,retries,peak_gpu_memory_mb,output_dir\n",
+    );
+    for r in records {
+        let fields = [
+            r.id.clone(),
+            r.cmd.clone(),
+            opt(&r.source),
+            r.state.to_string(),
+            opt(&r.gpu),
+            opt(&r.gpu_uuid),
+            opt(&r.started_at),
+            opt(&r.finished_at),
+            r.duration_secs
+                .map(|d| format!("{:.3}", d))
+                .unwrap_or_default(),
+            r.queue_wait_secs
+                .map(|d| format!("{:.3}", d))
+                .unwrap_or_default(),
+            opt(&r.exit_code),
+            opt(&r.signal),
+            r.retries.to_string(),
+            opt(&r.peak_gpu_memory_mb),
+            opt(&r.output_dir),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn stderr_tail(job: &JobInfo) -> String {
+    let lines: Vec<&str> = job
+        .log_lines
+        .iter()
+        .filter_map(|l| l.strip_prefix("[stderr] "))
+        .collect();
+    lines[lines.len().saturating_sub(STDERR_TAIL)..].join("\n")
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Control characters other than tab/newline are not allowed in XML 1.0
+            c if c.is_control() && c != '\n' && c != '\t' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::JobExit;
+    use uuid::Uuid;
+
+    fn job(cmd: &str, state: JobState, exit: Option<JobExit>, logs: &[&str]) -> JobInfo {
+        JobInfo {
+            state,
+            source: Some("jobs.txt".to_string()),
+            exit,
+            log_lines: logs.iter().map(|l| l.to_string()).collect(),
+            ..JobInfo::new(Uuid::new_v4(), cmd)
+        }
+    }
+
+    #[test]
+    fn junit_has_one_case_per_job() {
+        let mut state = AppState::new();
+        state.jobs = vec![
+            job("echo ok", JobState::Completed, Some(JobExit::Code(0)), &[]),
+            job(
+                "python train.py --tag \"a<b\"",
+                JobState::Failed,
+                Some(JobExit::Code(1)),
+                &["epoch 1", "[stderr] Traceback", "[stderr] Error: x & y"],
+            ),
+            job("never", JobState::Queued, None, &[]),
+        ];
+
+        let xml = junit_xml(&state);
+
+        assert!(xml.contains("tests=\"3\" failures=\"1\" skipped=\"1\""));
+        assert!(xml.contains("<testcase name=\"echo ok\" classname=\"jobs.txt\" time=\"0.000\"/>"));
+        assert!(xml.contains("name=\"python train.py --tag &quot;a&lt;b&quot;\""));
+        assert!(xml.contains("<failure message=\"exit 1\">Traceback\nError: x &amp; y</failure>"));
+        assert!(xml.contains("<skipped/>"));
+    }
+
+    #[test]
+    fn csv_quotes_commands() {
+        let mut failed = job(
+            "python a.py --tags \"x,y\"",
+            JobState::Failed,
+            Some(JobExit::Signal(9)),
+            &[],
+        );
+        failed.gpu = Some(1);
+        failed.gpu_uuid = Some("GPU-5d5ba0d6".to_string());
+        let records = vec![JobRecord::new(&failed)];
+
+        let csv = results_csv(&records);
+
+        let row = csv.lines().nth(1).unwrap();
+        assert!(row.contains(",\"python a.py --tags \"\"x,y\"\"\",jobs.txt,failed,1,GPU-5d5ba0d6,"));
+        assert!(row.ends_with(",,9,0,,"), "{}", row);
+    }
+
+    #[test]
+    fn records_round_trip_to_job_info() {
+        let mut running = job("sleep 9", JobState::Running { gpu_id: 2 }, None, &[]);
+        running.gpu = Some(2);
+        running.gpu_uuid = Some("GPU-5d5ba0d6".to_string());
+        running.timing.mark_started();
+        let failed = job("false", JobState::Failed, Some(JobExit::Code(1)), &[]);
+
+        let running_again = JobRecord::new(&running).to_job_info().unwrap();
+        let failed_again = JobRecord::new(&failed).to_job_info().unwrap();
+
+        assert_eq!(running_again.id, running.id);
+        assert!(matches!(
+            running_again.state,
+            JobState::Running { gpu_id: 2 }
+        ));
+        assert_eq!(running_again.gpu_uuid, running.gpu_uuid);
+        assert!(running_again.timing.elapsed().is_some());
+        assert_eq!(running_again.timing.started_at, running.timing.started_at);
+        assert!(matches!(failed_again.state, JobState::Failed));
+        assert_eq!(failed_again.exit, Some(JobExit::Code(1)));
+        assert_eq!(failed_again.source.as_deref(), Some("jobs.txt"));
+    }
+}
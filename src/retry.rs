@@ -0,0 +1,91 @@
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::ui::JobExit;
+
+/// When a failed job is put back in the queue.
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicy {
+    /// Retries per job (0 disables retrying)
+    pub max: u32,
+    /// Only retry these exit codes (empty: any failure)
+    pub on: Vec<i32>,
+    /// Never retry these exit codes
+    pub not_on: Vec<i32>,
+    /// Wait at least this long before a retried job may run again
+    pub delay: Duration,
+    /// Plus a random extra wait of up to this much
+    pub jitter: Duration,
+}
+
+impl RetryPolicy {
+    /// Whether a job that ended with `exit` after `retries` earlier retries
+    /// should run again. Signals match as their shell exit code (128 + n),
+    /// so `137` covers OOM kills; jobs that could not be spawned are never
+    /// retried.
+    pub fn should_retry(&self, exit: JobExit, retries: u32) -> bool {
+        if exit.success() || retries >= self.max {
+            return false;
+        }
+        let code = match exit {
+            JobExit::Code(code) => code,
+            JobExit::Signal(sig) => 128 + sig,
+            JobExit::SpawnFailed | JobExit::Unknown | JobExit::Lost => return false,
+        };
+        (self.on.is_empty() || self.on.contains(&code)) && !self.not_on.contains(&code)
+    }
+
+    /// How long the next retry waits: `delay` plus a uniformly random share
+    /// of `jitter`, so retries of jobs that failed together spread out.
+    pub fn delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.delay;
+        }
+        let (random, _) = Uuid::new_v4().as_u64_pair();
+        self.delay + self.jitter.mul_f64(random as f64 / u64::MAX as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_only_listed_failures() {
+        let policy = RetryPolicy {
+            max: 2,
+            on: vec![137, 139, 1],
+            ..Default::default()
+        };
+        assert!(policy.should_retry(JobExit::Code(1), 0));
+        assert!(policy.should_retry(JobExit::Signal(9), 1));
+        assert!(!policy.should_retry(JobExit::Signal(9), 2));
+        assert!(!policy.should_retry(JobExit::Code(2), 0));
+        assert!(!policy.should_retry(JobExit::Code(0), 0));
+        assert!(!policy.should_retry(JobExit::SpawnFailed, 0));
+    }
+
+    #[test]
+    fn excluded_codes_fail_fast() {
+        let policy = RetryPolicy {
+            max: 3,
+            not_on: vec![2],
+            ..Default::default()
+        };
+        assert!(policy.should_retry(JobExit::Code(1), 0));
+        assert!(!policy.should_retry(JobExit::Code(2), 0));
+    }
+
+    #[test]
+    fn delay_stays_within_jitter() {
+        let policy = RetryPolicy {
+            delay: Duration::from_secs(300),
+            jitter: Duration::from_secs(120),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let d = policy.delay();
+            assert!(d >= Duration::from_secs(300) && d <= Duration::from_secs(420));
+        }
+    }
+}
@@ -1,56 +1,457 @@
 /************************  src/schduler.rs ********************************/
 
 use anyhow::Result;
+use nix::sys::signal::Signal;
+use nvml_wrapper::enum_wrappers::device::TemperatureSensor;
+use nvml_wrapper::enums::device::UsedGpuMemory;
+use nvml_wrapper::Nvml;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     env,
-    process::Stdio,
+    os::fd::OwnedFd,
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
         Arc,
     },
+    time::Duration,
 };
 use tokio::{
-    io::{AsyncBufReadExt, BufReader as AsyncBufReader},
-    process::Command,
+    fs::OpenOptions,
+    io::{unix::AsyncFd, AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader as AsyncBufReader},
+    process::{Child, Command},
     sync::{
+        broadcast,
         mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
         Mutex, RwLock,
     },
+    task::JoinHandle,
 };
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{debug, error, info, trace, warn};
 use uuid::Uuid;
 
-use crate::ui::{AppState, GpuInfo, JobInfo, JobState};
+use crate::alert::{self, Alert};
+use crate::apple;
+use crate::cluster::AgentLink;
+use crate::cuda_mps::CudaMps;
+use crate::duration::humanize;
+use crate::health::{self, Fault, XidLog};
+use crate::history::History;
+use crate::host::HostSampler;
+use crate::jobfile::{JobRequest, Priority};
+use crate::memory;
+use crate::mig::{self, MigDevice};
+use crate::power::{self, PowerCaps};
+use crate::report::{self, JobRecord};
+use crate::retry::RetryPolicy;
+use crate::shell::Shell;
+use crate::ssh::{self, ListedGpu};
+use crate::topology::{self, Topology};
+use crate::ui::{
+    pause_notice, Action, AppState, GpuInfo, GpuProcess, JobExit, JobInfo, JobProcess, JobState,
+    Notice,
+};
 
 #[derive(Debug, Clone)]
 pub struct JobSpec {
     pub id: Uuid,
     pub cmd: String,
+    pub env: BTreeMap<String, String>,
+    /// Times this job has already been retried
+    pub retries: u32,
+    /// Where this job's logs and metadata go (`--output-dir`)
+    pub output_dir: Option<PathBuf>,
+    /// GPUs the job runs on at once
+    pub gpus: u32,
+    /// Free memory (MiB) each of its GPUs must report before it starts
+    pub mem_mb: Option<u64>,
+    /// Power cap (mW) of its GPUs while it runs
+    pub power_limit_mw: Option<u32>,
+    /// User who submitted it to the daemon, held to `user_gpu_limit`
+    pub owner: Option<String>,
+    pub priority: Priority,
+    /// Cancelled by `Scheduler::cancel` or on shutdown
+    pub cancel: CancellationToken,
+}
+
+/// How job stdout/stderr is handled.
+#[derive(Clone)]
+pub enum OutputMode {
+    /// Children write straight to our stdout/stderr
+    Inherit,
+    /// Output is captured into each job's log buffer (TUI)
+    Capture,
+    /// Captured, and every line is also sent to a printer task
+    Forward(UnboundedSender<ForwardedLine>),
+}
+
+#[derive(Debug, Clone)]
+pub struct ForwardedLine {
+    pub is_stderr: bool,
+    pub line: String,
+}
+
+/// What happened to a job, for clients following the scheduler live.
+/// Sent while the app state is still locked, after it has been updated.
+#[derive(Debug, Clone)]
+pub enum JobEvent {
+    /// The job was submitted or changed state; `job` is how it is now
+    State {
+        job_id: Uuid,
+        job: Box<report::JobRecord>,
+    },
+    /// A captured output line, as stored in the job's log buffer
+    Log { job_id: Uuid, line: String },
+}
+
+impl JobEvent {
+    fn state(job: &JobInfo) -> Self {
+        JobEvent::State {
+            job_id: job.id,
+            job: Box::new(report::JobRecord::new(job)),
+        }
+    }
+}
+
+/// Settings that apply to every job the scheduler runs.
+#[derive(Clone)]
+pub struct SchedulerOptions {
+    pub output: OutputMode,
+    /// Terminate jobs that run longer than this
+    pub max_runtime: Option<Duration>,
+    /// Sent to jobs on timeout and cancellation, before the SIGKILL fallback
+    pub stop_signal: Signal,
+    pub shell: Shell,
+    /// Variables injected into every job (from `--env`)
+    pub env: Vec<(String, String)>,
+    /// Start jobs from a minimal environment instead of inheriting ours
+    pub clean_env: bool,
+    pub retry: RetryPolicy,
+    /// Setup command run before each job; if it fails the job is marked
+    /// `SetupFailed` and its command never runs
+    pub pre_cmd: Option<String>,
+    /// Give every job a subdirectory here with its stdout/stderr and `job.json`
+    pub output_dir: Option<PathBuf>,
+    /// Run each job inside its own output directory
+    pub run_in_output_dir: bool,
+    /// Settings that can also change while the scheduler runs
+    pub tunables: Tunables,
+    /// Hosts whose GPUs jobs also run on, through ssh (`ssh::LOCAL` for
+    /// this machine, whose GPUs are only used alone or when listed)
+    pub sshlogin: Vec<String>,
+    /// Command line that reaches a host in `sshlogin`
+    pub ssh: String,
+    /// Use this machine's GPUs; a coordinator may leave them to its agents
+    pub local_gpus: bool,
+    /// Agents may bring GPUs later (`serve --agents`), so start without any
+    /// and let jobs too big for what is there wait instead of refusing them
+    pub agents: bool,
+    /// Add every finished job here
+    pub history: Option<History>,
+    /// Run the jobs of each local GPU through a CUDA MPS daemon of its own
+    pub cuda_mps: bool,
+    /// Start no jobs on a local GPU while processes gparallel did not start
+    /// run on it
+    pub yield_gpus: bool,
+    /// Detect this machine's GPUs again this often (`Scheduler::rescan`)
+    pub rescan_every: Option<Duration>,
+}
+
+/// Settings a daemon can change without a restart (`Scheduler::reconfigure`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tunables {
+    /// This machine's GPUs that jobs may use; all of them if `None`
+    pub gpus: Option<Vec<GpuSelector>>,
+    /// Jobs that run on each GPU at once
+    pub jobs_per_gpu: usize,
+    /// Only start a job while the host has at least this many bytes available
+    pub memfree: Option<u64>,
+    /// Hold jobs back from GPUs that run too hot
+    pub max_temp: Option<TempLimit>,
+    /// Raised once, when the first job fails
+    pub alert: Option<Alert>,
+    /// GPUs the jobs of one owner may hold at once; their other jobs wait
+    /// while later ones of other owners start
+    pub user_gpu_limit: Option<u32>,
+}
+
+impl Default for Tunables {
+    fn default() -> Self {
+        Self {
+            gpus: None,
+            jobs_per_gpu: 1,
+            memfree: None,
+            max_temp: None,
+            alert: None,
+            user_gpu_limit: None,
+        }
+    }
+}
+
+/// `--max-temp`: a GPU at `max` °C or hotter gets no new job until it has
+/// cooled down to `resume` °C.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TempLimit {
+    pub max: u32,
+    pub resume: u32,
+}
+
+impl TempLimit {
+    /// `resume` defaults to 10 °C below `max`.
+    pub fn new(max: u32, resume: Option<u32>) -> Result<Self> {
+        let resume = resume.unwrap_or(max.saturating_sub(10));
+        if resume >= max {
+            anyhow::bail!(
+                "the resume temperature ({}°C) must be below the maximum ({}°C)",
+                resume,
+                max
+            );
+        }
+        Ok(Self { max, resume })
+    }
+
+    /// Track whether `gpu`, now at `temp`, is cooling down: it starts at
+    /// `max` and ends at `resume`, so a GPU hovering around `max` does not
+    /// take a job every time it dips below.
+    fn update(&self, cooling: &mut HashSet<u32>, gpu: u32, temp: u32) {
+        if temp >= self.max {
+            cooling.insert(gpu);
+        } else if temp <= self.resume {
+            cooling.remove(&gpu);
+        }
+    }
+}
+
+/// Update `cooling` from the temperatures in `gpus`; nothing cools down
+/// without `--max-temp`.
+fn note_temperatures(cooling: &mut HashSet<u32>, limit: Option<TempLimit>, gpus: &[GpuInfo]) {
+    let Some(limit) = limit else {
+        cooling.clear();
+        return;
+    };
+    for gpu in gpus {
+        if let Some(temp) = gpu.temperature {
+            limit.update(cooling, gpu.id, temp);
+        }
+    }
+}
+
+/// The first of `ids` in `gpus` that could never have `needed` MiB free,
+/// as its memory all told is less. GPUs whose memory is unknown fit.
+fn too_small<'a>(gpus: &'a [GpuInfo], ids: &[u32], needed: u64) -> Option<&'a GpuInfo> {
+    gpus.iter()
+        .find(|g| ids.contains(&g.id) && g.total_memory_mb > 0 && g.total_memory_mb < needed)
+}
+
+/// The first of `ids` in `gpus` that is still cooling down, with its
+/// temperature.
+fn cooling_gpu(cooling: &HashSet<u32>, gpus: &[GpuInfo], ids: &[u32]) -> Option<(u32, u32)> {
+    gpus.iter()
+        .filter(|g| ids.contains(&g.id) && cooling.contains(&g.id))
+        .find_map(|g| Some((g.id, g.temperature?)))
+}
+
+/// A GPU of this machine as `--gpus` or the config file names it: by
+/// index, which the driver may hand out differently after a reboot, or by
+/// UUID (`GPU-…`, or `MIG-…` for a MIG instance), which stays with the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpuSelector {
+    Index(u32),
+    Uuid(String),
+}
+
+impl GpuSelector {
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Ok(index) = s.parse() {
+            return Ok(Self::Index(index));
+        }
+        if s.starts_with("GPU-") || s.starts_with("MIG-") {
+            return Ok(Self::Uuid(s.to_string()));
+        }
+        anyhow::bail!(
+            "invalid GPU '{}': expected an index or a UUID like GPU-5d5ba0d6-…",
+            s
+        )
+    }
+
+    fn matches(&self, id: u32, uuid: Option<&str>) -> bool {
+        match self {
+            Self::Index(index) => *index == id,
+            Self::Uuid(wanted) => uuid.is_some_and(|uuid| uuid.eq_ignore_ascii_case(wanted)),
+        }
+    }
 }
 
+impl std::fmt::Display for GpuSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "{}", index),
+            Self::Uuid(uuid) => f.write_str(uuid),
+        }
+    }
+}
+
+/// A GPU's places in the free pool: one per job it may run at once.
+#[derive(Debug, Clone, Copy)]
+struct Slots {
+    /// How many it should have
+    wanted: usize,
+    /// How many are in the pool or held by jobs; the extra ones are dropped
+    /// as they come back
+    issued: usize,
+}
+
+/// A GPU on another machine, under the index it has there.
+#[derive(Clone)]
+struct RemoteGpu {
+    host: String,
+    index: u32,
+    /// Connection of the agent that runs its jobs; jobs on `--sshlogin`
+    /// hosts have none and run through ssh
+    agent: Option<AgentLink>,
+}
+
+/// How long a job that passed the `--memfree` check holds the next one
+/// back, for its memory use to show.
+const MEMORY_SETTLE: Duration = Duration::from_secs(1);
+
 #[derive(Clone)]
 pub struct Scheduler {
     queue: Arc<Mutex<VecDeque<JobSpec>>>,
     gpu_tx: UnboundedSender<u32>,
     gpu_rx: Arc<Mutex<UnboundedReceiver<u32>>>,
     busy: Arc<AtomicUsize>,
+    /// Retried jobs waiting out their delay before being queued again
+    delayed: Arc<AtomicUsize>,
+    /// Multi-GPU jobs collecting their GPUs; while any are, workers hand
+    /// their GPU back instead of taking the next queued job
+    reserving: Arc<AtomicUsize>,
+    /// Lets one multi-GPU job at a time collect GPUs, so two can never each
+    /// hold part of what the other needs
+    gather_lock: Arc<Mutex<()>>,
+    /// When the last job passed the `--memfree` check, locked while a job
+    /// is checked so that jobs pass one at a time
+    memory_gate: Arc<Mutex<Option<tokio::time::Instant>>>,
+    /// Set by `drain`: queued jobs stay queued
+    draining: Arc<AtomicBool>,
+    /// Set by `set_paused`: queued jobs wait until it is cleared again
+    paused: Arc<AtomicBool>,
     app_state: Arc<RwLock<AppState>>,
     _gpu_names: Vec<String>,
+    gpu_uuids: Arc<std::sync::RwLock<HashMap<u32, String>>>, // gpu id -> NVML UUID
+    /// MIG instances, scheduled in place of the GPU they are carved from
+    mig_devices: Arc<HashMap<u32, MigDevice>>,
+    /// MPS daemons of the local GPUs, with `--cuda-mps`
+    cuda_mps: Arc<CudaMps>,
+    /// GPUs found unhealthy, which get no more jobs
+    faults: Arc<std::sync::RwLock<HashMap<u32, Fault>>>,
+    /// Hardware Xids NVML reported, by NVML index
+    xids: XidLog,
+    /// GPUs kept out of the pool while someone else's processes run on them
+    yielded: Arc<std::sync::RwLock<HashSet<u32>>>,
+    /// GPUs that reached `--max-temp` and have not cooled down to
+    /// `--resume-temp` yet, kept by the monitor for every job that waits
+    cooling: Arc<std::sync::Mutex<HashSet<u32>>>,
+    /// Shared by everything that reads or sets a local GPU, none without
+    /// NVML; a re-scan initializes it again if it has stopped answering
+    nvml: Arc<std::sync::RwLock<Option<Arc<Nvml>>>>,
+    /// Power caps jobs put on local GPUs, to lift when they finish
+    power_caps: Arc<PowerCaps>,
+    /// How the local GPUs are connected, for placing multi-GPU jobs
+    topology: Arc<Topology>,
+    /// GPUs of `--sshlogin` hosts and agents, which have ids after the
+    /// local ones
+    remote_gpus: Arc<std::sync::RwLock<HashMap<u32, RemoteGpu>>>,
+    /// Id the next GPU to join gets
+    next_gpu_id: Arc<AtomicU32>,
+    /// Places of each GPU in the free pool, which holds a GPU's id once per
+    /// job it may take
+    slots: Arc<std::sync::Mutex<HashMap<u32, Slots>>>,
+    tunables: Arc<std::sync::RwLock<Tunables>>,
     running_jobs: Arc<Mutex<HashMap<Uuid, u32>>>, // job_id -> PID
-    use_tui: bool,
+    /// Tokens of jobs that have not finished yet, for `cancel`
+    job_tokens: Arc<Mutex<HashMap<Uuid, CancellationToken>>>,
+    /// Jobs stopped for running longer than `max_runtime`
+    timed_out: Arc<std::sync::Mutex<HashSet<Uuid>>>,
+    /// GPUs held by each owner's started jobs, for `user_gpu_limit`
+    owner_gpus: Arc<std::sync::Mutex<HashMap<String, u32>>>,
+    events: broadcast::Sender<JobEvent>,
+    options: Arc<SchedulerOptions>,
+    failure_alerted: Arc<AtomicBool>,
+    /// Root of the cancellation hierarchy; every task we spawn watches it
+    cancel: CancellationToken,
+    tasks: TaskTracker,
 }
 
 impl Scheduler {
-    pub async fn new(app_state: Arc<RwLock<AppState>>, use_tui: bool) -> Result<Self> {
-        let (gpus, gpu_names) = detect_gpus_with_info().await?;
-        if gpus.is_empty() {
+    pub async fn new(app_state: Arc<RwLock<AppState>>, options: SchedulerOptions) -> Result<Self> {
+        let use_local =
+            options.sshlogin.is_empty() || options.sshlogin.iter().any(|h| h == ssh::LOCAL);
+        let nvml = match use_local && options.local_gpus {
+            true => init_nvml(),
+            false => None,
+        };
+        let (mut gpus, mut gpu_names) = match use_local && options.local_gpus {
+            true => detect_gpus_with_info(nvml.as_deref()).await?,
+            false => (Vec::new(), Vec::new()),
+        };
+        let mut gpu_uuids = lookup_gpu_uuids(&gpus, nvml.as_deref()).await;
+        let mut mig_devices = HashMap::new();
+        if let Some(nvml) = nvml.as_deref() {
+            let instances = mig::detect(nvml);
+            mig_devices = replace_mig_parents(&mut gpus, &mut gpu_names, &mut gpu_uuids, instances);
+        }
+        let mig_devices = Arc::new(mig_devices);
+        let mut remote_gpus = HashMap::new();
+        for host in options.sshlogin.iter().filter(|h| *h != ssh::LOCAL) {
+            for listed in ssh::discover(&options.ssh, host).await? {
+                let id = gpus.iter().max().map_or(0, |max| max + 1);
+                gpus.push(id);
+                gpu_names.push(format!("{}:{} {}", host, listed.index, listed.name));
+                if let Some(uuid) = listed.uuid {
+                    gpu_uuids.insert(id, uuid);
+                }
+                remote_gpus.insert(
+                    id,
+                    RemoteGpu {
+                        host: host.clone(),
+                        index: listed.index,
+                        agent: None,
+                    },
+                );
+            }
+        }
+        if gpus.is_empty() && !options.agents {
             anyhow::bail!("No GPUs detected");
         }
+        let mut cuda_mps = CudaMps::default();
+        if options.cuda_mps && use_local && options.local_gpus && mock_gpu_count().is_none() {
+            let devices = gpus
+                .iter()
+                .filter(|gpu| !remote_gpus.contains_key(gpu))
+                .map(|gpu| match mig_devices.get(gpu) {
+                    Some(mig) => (*gpu, mig.uuid.clone()),
+                    None => (*gpu, gpu.to_string()),
+                })
+                .collect();
+            cuda_mps = CudaMps::start(devices).await;
+        }
+        let mut topology = Topology::default();
+        if let Some(nvml) = nvml.clone() {
+            let local: Vec<u32> = gpus
+                .iter()
+                .copied()
+                .filter(|gpu| !remote_gpus.contains_key(gpu) && !mig_devices.contains_key(gpu))
+                .collect();
+            topology = tokio::task::spawn_blocking(move || topology::detect(&nvml, &local)).await?;
+        }
+        let next_gpu_id = gpus.iter().max().map_or(0, |max| max + 1);
+        let remote_gpus = Arc::new(std::sync::RwLock::new(remote_gpus));
 
         let (tx, rx) = unbounded_channel();
-        for id in &gpus {
-            tx.send(*id)?;
-        }
 
         // Initialize GPU info in app state
         {
@@ -58,349 +459,1971 @@ impl Scheduler {
             state.gpus = gpus
                 .iter()
                 .zip(gpu_names.iter())
-                .map(|(id, name)| GpuInfo {
-                    id: *id,
-                    name: name.clone(),
-                    free_memory_mb: 0,
-                    total_memory_mb: 0,
-                })
+                .map(|(id, name)| GpuInfo::new(*id, name.clone()))
                 .collect();
         }
 
+        let cancel = CancellationToken::new();
+        let tasks = TaskTracker::new();
+
+        let faults = Arc::new(std::sync::RwLock::new(HashMap::new()));
+        let xids = XidLog::default();
+        if let Some(nvml) = &nvml {
+            health::watch_xids(nvml.clone(), xids.clone(), cancel.child_token());
+        }
+
         // Start GPU memory monitoring
         let state_clone = app_state.clone();
-        tokio::spawn(async move {
+        let running_jobs = Arc::new(Mutex::new(HashMap::new()));
+        let monitor_jobs = running_jobs.clone();
+        let nvml = Arc::new(std::sync::RwLock::new(nvml));
+        let monitor_nvml = nvml.clone();
+        let monitor_remote = remote_gpus.clone();
+        let monitor_mig = mig_devices.clone();
+        let monitor_faults = faults.clone();
+        let monitor_xids = xids.clone();
+        let monitor_cancel = cancel.child_token();
+        let tunables = Arc::new(std::sync::RwLock::new(options.tunables.clone()));
+        let monitor_tunables = tunables.clone();
+        let cooling = Arc::new(std::sync::Mutex::new(HashSet::new()));
+        let monitor_cooling = cooling.clone();
+        tasks.spawn(async move {
+            let mut host = HostSampler::default();
             loop {
-                update_gpu_memory_info(&state_clone).await;
-                tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+                update_gpu_memory_info(
+                    &state_clone,
+                    &monitor_jobs,
+                    &monitor_nvml,
+                    &monitor_remote,
+                    &monitor_mig,
+                    &monitor_faults,
+                    &monitor_xids,
+                )
+                .await;
+                let usage = host.sample();
+                let mut state = state_clone.write().await;
+                state.host = usage;
+                let limit = monitor_tunables.read().unwrap().max_temp;
+                note_temperatures(&mut monitor_cooling.lock().unwrap(), limit, &state.gpus);
+                drop(state);
+                tokio::select! {
+                    _ = monitor_cancel.cancelled() => break,
+                    _ = tokio::time::sleep(Duration::from_secs(2)) => {}
+                }
             }
         });
 
-        Ok(Self {
+        let sched = Self {
             queue: Arc::new(Mutex::new(VecDeque::new())),
             gpu_tx: tx,
             gpu_rx: Arc::new(Mutex::new(rx)),
             busy: Arc::new(AtomicUsize::new(0)),
+            delayed: Arc::new(AtomicUsize::new(0)),
+            reserving: Arc::new(AtomicUsize::new(0)),
+            gather_lock: Arc::new(Mutex::new(())),
+            memory_gate: Arc::new(Mutex::new(None)),
+            draining: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
             app_state,
             _gpu_names: gpu_names,
-            running_jobs: Arc::new(Mutex::new(HashMap::new())),
-            use_tui,
-        })
+            gpu_uuids: Arc::new(std::sync::RwLock::new(gpu_uuids)),
+            mig_devices,
+            cuda_mps: Arc::new(cuda_mps),
+            faults,
+            xids,
+            yielded: Arc::new(std::sync::RwLock::new(HashSet::new())),
+            cooling,
+            nvml,
+            power_caps: Arc::new(PowerCaps::default()),
+            topology: Arc::new(topology),
+            remote_gpus,
+            next_gpu_id: Arc::new(AtomicU32::new(next_gpu_id)),
+            slots: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            tunables,
+            running_jobs,
+            job_tokens: Arc::new(Mutex::new(HashMap::new())),
+            timed_out: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            owner_gpus: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            events: broadcast::channel(1024).0,
+            options: Arc::new(options),
+            failure_alerted: Arc::new(AtomicBool::new(false)),
+            cancel,
+            tasks,
+        };
+        sched.warn_unmatched(&gpus);
+        for gpu in gpus {
+            sched.set_slots(gpu, sched.wanted_slots(gpu));
+        }
+        if sched.options.yield_gpus {
+            let watcher = sched.clone();
+            sched
+                .tasks
+                .spawn(async move { watcher.yield_to_others().await });
+        }
+        if let Some(every) = sched.options.rescan_every {
+            let scanner = sched.clone();
+            sched.tasks.spawn(async move {
+                loop {
+                    tokio::select! {
+                        _ = scanner.cancel.cancelled() => break,
+                        _ = tokio::time::sleep(every) => {}
+                    }
+                    if let Err(e) = scanner.rescan().await {
+                        debug!("GPU re-scan failed: {}", e);
+                    }
+                }
+            });
+        }
+        Ok(sched)
     }
 
-    pub async fn submit(&self, cmd: String) -> Result<()> {
-        let job = JobSpec {
-            id: Uuid::new_v4(),
+    /// Token cancelled when the scheduler shuts down; the UI and renderers
+    /// derive theirs from it.
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Receive every job event from now on. Subscribing while holding the
+    /// app state lock gives exactly the changes that are not in it yet.
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.events.subscribe()
+    }
+
+    /// Queue a job and return its id.
+    pub async fn submit(&self, req: JobRequest) -> Result<Uuid> {
+        self.submit_as(Uuid::new_v4(), req).await
+    }
+
+    /// Queue a job under a known id, e.g. one restored from the daemon's
+    /// database.
+    pub async fn submit_as(&self, id: Uuid, req: JobRequest) -> Result<Uuid> {
+        if self.cancel.is_cancelled() {
+            anyhow::bail!("scheduler is shutting down");
+        }
+        let gpus = req.gpus.unwrap_or(1);
+        let gpu_count = self.gpu_count();
+        if gpus == 0 || (gpus as usize > gpu_count && !self.options.agents) {
+            anyhow::bail!(
+                "job asks for {} GPUs, but {} are available: {}",
+                gpus,
+                gpu_count,
+                req.cmd
+            );
+        }
+        let mem_mb = match &req.mem {
+            Some(mem) => Some(memory::parse_size(mem)? >> 20),
+            None => None,
+        };
+        let power_limit_mw = req
+            .power_limit
+            .as_deref()
+            .map(power::parse_watts)
+            .transpose()?;
+        let cmd = req.cmd;
+        let mut job = JobSpec {
+            id,
             cmd: cmd.clone(),
+            env: req.env.clone(),
+            retries: 0,
+            output_dir: None,
+            gpus,
+            mem_mb,
+            power_limit_mw,
+            owner: req.owner.clone(),
+            priority: req.priority.unwrap_or_default(),
+            cancel: self.cancel.child_token(),
         };
 
         // Add job to UI state
         {
             let mut state = self.app_state.write().await;
+            // Numbered in submission order so directory listings sort naturally
+            job.output_dir = self.options.output_dir.as_ref().map(|root| {
+                root.join(format!(
+                    "{:04}-{}",
+                    state.jobs.len() + 1,
+                    &job.id.to_string()[..8]
+                ))
+            });
             state.jobs.push(JobInfo {
-                id: job.id,
-                cmd: cmd.clone(),
-                state: JobState::Queued,
-                log_lines: VecDeque::new(),
+                source: req.source,
+                owner: req.owner,
+                env: req.env,
+                requested_gpus: gpus,
+                mem: req.mem,
+                power_limit: req.power_limit,
+                priority: job.priority,
+                tag: req.tag,
+                output_dir: job.output_dir.clone(),
+                ..JobInfo::new(job.id, cmd.clone())
+            });
+            if let Some(job_info) = state.jobs.last() {
+                self.events.send(JobEvent::state(job_info)).ok();
+            }
+        }
+
+        self.job_tokens.lock().await.insert(id, job.cancel.clone());
+        self.enqueue(job).await?;
+        Ok(id)
+    }
+
+    /// Carry out what the TUI asks for until it closes `actions`, telling
+    /// the outcome in a notice.
+    pub async fn handle_actions(&self, mut actions: UnboundedReceiver<Action>) {
+        while let Some(action) = actions.recv().await {
+            let message = match action {
+                Action::Submit(req) => match self.submit(req).await {
+                    Ok(id) => format!("Queued as {}", &id.to_string()[..8]),
+                    Err(e) => format!("Could not submit: {}", e),
+                },
+                Action::SetPaused(paused) => {
+                    self.set_paused(paused).await;
+                    pause_notice(paused)
+                }
+                Action::Shift { job, up } => match self.shift_job(job, up).await {
+                    Ok(()) => format!("Moved {} in the queue", &job.to_string()[..8]),
+                    Err(e) => format!("Could not move: {}", e),
+                },
+            };
+            self.app_state.write().await.notice = Some(Notice::new(message));
+        }
+    }
+
+    /// Take `gpus` out of the free pool for jobs that are brought back with
+    /// `adopt`. Must run before anything is submitted.
+    pub async fn reserve_gpus(&self, gpus: &[u32]) {
+        let mut rx = self.gpu_rx.lock().await;
+        let mut free = Vec::new();
+        while let Some(gpu) = self.take_free(&mut rx) {
+            free.push(gpu);
+        }
+        // One place per GPU, as a GPU may run several jobs
+        let mut needed = gpus.to_vec();
+        for gpu in free {
+            match needed.iter().position(|g| *g == gpu) {
+                Some(i) => {
+                    needed.swap_remove(i);
+                }
+                None => {
+                    self.gpu_tx.send(gpu).ok();
+                }
+            }
+        }
+    }
+
+    /// Apply new tunables. Running jobs carry on: a GPU left with fewer
+    /// places than jobs on it takes no more until enough have finished.
+    pub async fn reconfigure(&self, tunables: Tunables) {
+        *self.tunables.write().unwrap() = tunables;
+        let gpus: Vec<u32> = self
+            .app_state
+            .read()
+            .await
+            .gpus
+            .iter()
+            .map(|g| g.id)
+            .collect();
+        self.warn_unmatched(&gpus);
+        {
+            let _queue = self.queue.lock().await;
+            for gpu in gpus {
+                self.set_slots(gpu, self.wanted_slots(gpu));
+            }
+        }
+        self.dispatch_free().await;
+    }
+
+    /// Most GPUs in use on one machine, and so the most one job can have.
+    /// GPUs lent to other processes for now count.
+    fn gpu_count(&self) -> usize {
+        let slots = self.slots.lock().unwrap();
+        let remote_gpus = self.remote_gpus.read().unwrap();
+        let yielded = self.yielded.read().unwrap();
+        let mut per_host: HashMap<Option<&str>, usize> = HashMap::new();
+        let in_use = slots
+            .iter()
+            .filter(|(gpu, slots)| slots.wanted > 0 || yielded.contains(gpu));
+        for (gpu, _) in in_use {
+            let host = remote_gpus.get(gpu).map(|r| r.host.as_str());
+            *per_host.entry(host).or_default() += 1;
+        }
+        per_host.into_values().max().unwrap_or_default()
+    }
+
+    /// How many jobs `gpu` may run at once under the current tunables. The
+    /// allow-list only covers this machine's GPUs.
+    fn wanted_slots(&self, gpu: u32) -> usize {
+        if self.faults.read().unwrap().contains_key(&gpu)
+            || self.yielded.read().unwrap().contains(&gpu)
+        {
+            return 0;
+        }
+        let tunables = self.tunables.read().unwrap();
+        let remote = self.remote_gpus.read().unwrap().contains_key(&gpu);
+        let uuid = self.gpu_uuids.read().unwrap().get(&gpu).cloned();
+        let allowed = remote
+            || tunables
+                .gpus
+                .as_ref()
+                .is_none_or(|selected| selected.iter().any(|s| s.matches(gpu, uuid.as_deref())));
+        if allowed {
+            tunables.jobs_per_gpu
+        } else {
+            0
+        }
+    }
+
+    /// Take local GPUs that processes gparallel did not start run on out of
+    /// the pool, going by the monitor's samples, and put them back once
+    /// those processes have ended. Jobs already running on them carry on.
+    async fn yield_to_others(&self) {
+        loop {
+            let roots: HashMap<u32, Uuid> = self
+                .running_jobs
+                .lock()
+                .await
+                .iter()
+                .map(|(job_id, pid)| (*pid, *job_id))
+                .collect();
+            let mut others = HashMap::new();
+            for gpu in &self.app_state.read().await.gpus {
+                let pids: Vec<u32> = gpu
+                    .processes
+                    .iter()
+                    .filter(|p| p.job.is_none())
+                    .map(|p| p.pid)
+                    .filter(|pid| self.is_foreign(gpu.id, *pid, &roots))
+                    .collect();
+                if !pids.is_empty() {
+                    others.insert(gpu.id, describe_processes(&pids));
+                }
+            }
+            let changed: Vec<u32> = {
+                let mut yielded = self.yielded.write().unwrap();
+                let now: HashSet<u32> = others.keys().copied().collect();
+                let changed = yielded.symmetric_difference(&now).copied().collect();
+                *yielded = now;
+                changed
+            };
+            {
+                let mut state = self.app_state.write().await;
+                for gpu in state.gpus.iter_mut() {
+                    gpu.in_use_by = others.get(&gpu.id).cloned();
+                    if !changed.contains(&gpu.id) {
+                        continue;
+                    }
+                    match &gpu.in_use_by {
+                        Some(by) => info!(
+                            gpu = gpu.id,
+                            "GPU {} is in use by {}, holding jobs off it", gpu.name, by
+                        ),
+                        None => info!(
+                            gpu = gpu.id,
+                            "GPU {} is free of other processes again", gpu.name
+                        ),
+                    }
+                }
+            }
+            if !changed.is_empty() {
+                {
+                    let _queue = self.queue.lock().await;
+                    for gpu in changed {
+                        self.set_slots(gpu, self.wanted_slots(gpu));
+                    }
+                }
+                self.dispatch_free().await;
+            }
+            tokio::select! {
+                _ = self.cancel.cancelled() => break,
+                _ = tokio::time::sleep(Duration::from_secs(2)) => {}
+            }
+        }
+    }
+
+    /// Whether `pid`, sampled on `gpu` without a job, is someone else's:
+    /// still running, not in a job started since the sample, and not the
+    /// GPU's MPS server. Processes we cannot see (from inside a container)
+    /// are not counted.
+    fn is_foreign(&self, gpu: u32, pid: u32, roots: &HashMap<u32, Uuid>) -> bool {
+        if process_start_time(pid).is_none() || owning_job(pid, roots).is_some() {
+            return false;
+        }
+        let mps_server = self.cuda_mps.daemon(gpu).is_some()
+            && std::fs::read_to_string(format!("/proc/{}/comm", pid))
+                .is_ok_and(|comm| comm.trim() == "nvidia-cuda-mps-server");
+        !mps_server
+    }
+
+    /// Warn about entries of the allow-list that name none of `gpus`, such
+    /// as the UUID of a GPU that has been taken out.
+    fn warn_unmatched(&self, gpus: &[u32]) {
+        let tunables = self.tunables.read().unwrap();
+        let uuids = self.gpu_uuids.read().unwrap();
+        for selected in tunables.gpus.iter().flatten() {
+            let found = gpus
+                .iter()
+                .any(|gpu| selected.matches(*gpu, uuids.get(gpu).map(String::as_str)));
+            if !found {
+                warn!("GPU {} is not on this machine; no jobs run on it", selected);
+            }
+        }
+    }
+
+    /// Point the process of a job started by a previous daemon at the ids
+    /// its GPUs have now, going by their UUIDs, in case the driver has
+    /// numbered them differently since.
+    pub fn relocate(&self, process: &mut JobProcess) {
+        let uuids = self.gpu_uuids.read().unwrap();
+        for (gpu, uuid) in process.gpus.iter_mut().zip(&process.gpu_uuids) {
+            if let Some((id, _)) = uuids.iter().find(|(_, u)| *u == uuid) {
+                *gpu = *id;
+            }
+        }
+    }
+
+    /// Give `gpu` `wanted` places in the pool, adding the ones it lacks.
+    /// Extra ones go once they come back.
+    fn set_slots(&self, gpu: u32, wanted: usize) {
+        let mut all = self.slots.lock().unwrap();
+        let slots = all.entry(gpu).or_insert(Slots {
+            wanted: 0,
+            issued: 0,
+        });
+        slots.wanted = wanted;
+        while slots.issued < wanted {
+            self.gpu_tx.send(gpu).ok();
+            slots.issued += 1;
+        }
+    }
+
+    /// The next GPU in the free pool, dropping places a GPU no longer has
+    /// and all places of quarantined GPUs.
+    fn take_free(&self, rx: &mut UnboundedReceiver<u32>) -> Option<u32> {
+        loop {
+            let gpu = rx.try_recv().ok()?;
+            let quarantined = self.faults.read().unwrap().contains_key(&gpu);
+            let mut all = self.slots.lock().unwrap();
+            match all.get_mut(&gpu) {
+                Some(slots) if quarantined => {
+                    slots.wanted = 0;
+                    slots.issued -= 1;
+                }
+                Some(slots) if slots.issued > slots.wanted => slots.issued -= 1,
+                _ => return Some(gpu),
+            }
+        }
+    }
+
+    /// Whether `gpu` runs more jobs than it has places for now.
+    fn over_capacity(&self, gpu: u32) -> bool {
+        if self.faults.read().unwrap().contains_key(&gpu) {
+            return true;
+        }
+        let all = self.slots.lock().unwrap();
+        all.get(&gpu)
+            .is_some_and(|slots| slots.issued > slots.wanted)
+    }
+
+    /// Put an agent's GPUs in the pool under new ids, which are returned
+    /// for `remove_agent`.
+    pub async fn add_agent(&self, agent: AgentLink, gpus: Vec<ListedGpu>) -> Vec<u32> {
+        let mut ids = Vec::new();
+        let mut infos = Vec::new();
+        for listed in gpus {
+            let id = self.next_gpu_id.fetch_add(1, Ordering::SeqCst);
+            if let Some(uuid) = listed.uuid {
+                self.gpu_uuids.write().unwrap().insert(id, uuid);
+            }
+            infos.push(GpuInfo::new(
+                id,
+                format!("{}:{} {}", agent.host(), listed.index, listed.name),
+            ));
+            self.remote_gpus.write().unwrap().insert(
+                id,
+                RemoteGpu {
+                    host: agent.host().to_string(),
+                    index: listed.index,
+                    agent: Some(agent.clone()),
+                },
+            );
+            ids.push(id);
+        }
+        self.app_state.write().await.gpus.extend(infos);
+        {
+            let _queue = self.queue.lock().await;
+            for id in &ids {
+                self.set_slots(*id, self.wanted_slots(*id));
+            }
+        }
+        self.dispatch_free().await;
+        ids
+    }
+
+    /// Take the GPUs of an agent that went away out of service. Jobs still
+    /// running on them end as lost through their agent connection.
+    pub async fn remove_agent(&self, gpus: &[u32]) {
+        for gpu in gpus {
+            self.set_slots(*gpu, 0);
+        }
+        {
+            let mut remote_gpus = self.remote_gpus.write().unwrap();
+            for gpu in gpus {
+                remote_gpus.remove(gpu);
+            }
+        }
+        let mut state = self.app_state.write().await;
+        state.gpus.retain(|g| !gpus.contains(&g.id));
+    }
+
+    /// Detect this machine's GPUs again, for ones that appear after the
+    /// driver is (re)loaded or become visible later, and ones taken out. New
+    /// GPUs join the pool under their index; vanished ones take no more jobs
+    /// and leave the list, while the jobs already on them run out. MIG
+    /// layouts and how the GPUs are linked stay as found at startup. Returns
+    /// the names of the GPUs added and removed.
+    pub async fn rescan(&self) -> Result<(Vec<String>, Vec<String>)> {
+        let options = &self.options;
+        let use_local =
+            options.sshlogin.is_empty() || options.sshlogin.iter().any(|h| h == ssh::LOCAL);
+        if !(use_local && options.local_gpus) {
+            anyhow::bail!("this scheduler does not use this machine's GPUs");
+        }
+        let nvml = self.refresh_nvml();
+        // Without NVML a failed detection would look like every GPU but one gone
+        if nvml.is_none() && mock_gpu_count().is_none() {
+            anyhow::bail!("NVML is unavailable, so GPUs cannot be re-scanned");
+        }
+        let (ids, names) = detect_gpus_with_info(nvml.as_deref()).await?;
+        let mut uuids = lookup_gpu_uuids(&ids, nvml.as_deref()).await;
+        let parents: HashSet<u32> = self.mig_devices.values().map(|m| m.parent).collect();
+        let mut found_names = HashMap::new();
+        let mut found = HashMap::new();
+        for (id, name) in ids.into_iter().zip(names) {
+            if !parents.contains(&id) {
+                found_names.insert(id, name);
+                found.insert(id, uuids.remove(&id));
+            }
+        }
+        let pooled: Vec<u32> = self
+            .app_state
+            .read()
+            .await
+            .gpus
+            .iter()
+            .map(|g| g.id)
+            .collect();
+        let pooled: HashMap<u32, Option<String>> = {
+            let remote_gpus = self.remote_gpus.read().unwrap();
+            let gpu_uuids = self.gpu_uuids.read().unwrap();
+            pooled
+                .into_iter()
+                .filter(|id| !remote_gpus.contains_key(id) && !self.mig_devices.contains_key(id))
+                .map(|id| (id, gpu_uuids.get(&id).cloned()))
+                .collect()
+        };
+        let (mut added, removed) = compare_gpus(&pooled, &found);
+        {
+            let remote_gpus = self.remote_gpus.read().unwrap();
+            added.retain(|id| {
+                let taken = remote_gpus.contains_key(id) || self.mig_devices.contains_key(id);
+                if taken {
+                    warn!(
+                        gpu = id,
+                        "GPU {} appeared under the id of another GPU; restart gparallel to use it",
+                        found_names[id]
+                    );
+                }
+                !taken
+            });
+        }
+        if added.is_empty() && removed.is_empty() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+
+        for gpu in &removed {
+            self.set_slots(*gpu, 0);
+            self.gpu_uuids.write().unwrap().remove(gpu);
+            // A GPU that comes back has been reset
+            self.faults.write().unwrap().remove(gpu);
+            self.xids.lock().unwrap().remove(gpu);
+            self.yielded.write().unwrap().remove(gpu);
+        }
+        for gpu in &added {
+            if let Some(uuid) = found[gpu].clone() {
+                self.gpu_uuids.write().unwrap().insert(*gpu, uuid);
+            }
+            self.next_gpu_id.fetch_max(gpu + 1, Ordering::SeqCst);
+        }
+        let mut removed_names = Vec::new();
+        let mut added_names = Vec::new();
+        {
+            let mut state = self.app_state.write().await;
+            state.gpus.retain(|g| {
+                if !removed.contains(&g.id) {
+                    return true;
+                }
+                info!(gpu = g.id, "GPU {} is gone, draining it", g.name);
+                removed_names.push(g.name.clone());
+                false
             });
+            for gpu in &added {
+                let name = found_names[gpu].clone();
+                info!(gpu, "GPU {} joined the pool", name);
+                let at = state.gpus.partition_point(|g| g.id < *gpu);
+                state.gpus.insert(at, GpuInfo::new(*gpu, name.clone()));
+                added_names.push(name);
+            }
+        }
+        {
+            let _queue = self.queue.lock().await;
+            for gpu in &added {
+                self.set_slots(*gpu, self.wanted_slots(*gpu));
+            }
+        }
+        self.dispatch_free().await;
+        Ok((added_names, removed_names))
+    }
+
+    /// The shared NVML, if there is one.
+    fn nvml(&self) -> Option<Arc<Nvml>> {
+        self.nvml.read().unwrap().clone()
+    }
+
+    /// The shared NVML, initialized again if there is none yet or it has
+    /// stopped answering, as after the driver was reloaded.
+    fn refresh_nvml(&self) -> Option<Arc<Nvml>> {
+        let current = self.nvml();
+        if current
+            .as_ref()
+            .is_some_and(|nvml| nvml.device_count().is_ok())
+        {
+            return current;
+        }
+        let nvml = init_nvml()?;
+        info!("Initialized NVML");
+        health::watch_xids(nvml.clone(), self.xids.clone(), self.cancel.child_token());
+        *self.nvml.write().unwrap() = Some(nvml.clone());
+        Some(nvml)
+    }
+
+    /// Take over a job whose process outlived a previous daemon, on GPUs
+    /// already taken with `reserve_gpus`. It finishes as completed with an
+    /// unknown exit status, since only its parent could see that.
+    pub async fn adopt(&self, job_info: JobInfo) -> Result<()> {
+        let Some(process) = job_info.process.clone() else {
+            anyhow::bail!("job {} has no process to adopt", job_info.id);
+        };
+        let job = JobSpec {
+            id: job_info.id,
+            cmd: job_info.cmd.clone(),
+            env: job_info.env.clone(),
+            retries: job_info.retries,
+            output_dir: job_info.output_dir.clone(),
+            gpus: process.gpus.len() as u32,
+            mem_mb: None,
+            power_limit_mw: None,
+            owner: job_info.owner.clone(),
+            priority: job_info.priority,
+            cancel: self.cancel.child_token(),
+        };
+        self.charge(&job);
+        self.busy.fetch_add(1, Ordering::SeqCst);
+        {
+            let mut state = self.app_state.write().await;
+            self.events.send(JobEvent::state(&job_info)).ok();
+            state.jobs.push(job_info);
+        }
+        self.job_tokens
+            .lock()
+            .await
+            .insert(job.id, job.cancel.clone());
+        self.running_jobs.lock().await.insert(job.id, process.pid);
+        info!(job = %job.id, pid = process.pid, "Adopted running job: {}", job.cmd);
+
+        let sched = self.clone();
+        self.tasks.spawn(async move {
+            let exit = tokio::select! {
+                _ = wait_for_exit(&process) => JobExit::Unknown,
+                _ = job.cancel.cancelled() => {
+                    info!(job = %job.id, pid = process.pid, "Killing job");
+                    let pid = nix::unistd::Pid::from_raw(process.pid as i32);
+                    let signal = sched.options.stop_signal;
+                    nix::sys::signal::kill(pid, signal).ok();
+                    let grace = Duration::from_secs(if sched.cancel.is_cancelled() { 1 } else { 5 });
+                    if tokio::time::timeout(grace, wait_for_exit(&process)).await.is_err() {
+                        nix::sys::signal::kill(pid, Signal::SIGKILL).ok();
+                    }
+                    JobExit::Signal(signal as i32)
+                }
+            };
+            sched.running_jobs.lock().await.remove(&job.id);
+            sched.finish_job(&job, exit, None).await;
+            sched.refund(&job);
+            {
+                let _queue = sched.queue.lock().await;
+                for gpu in &process.gpus {
+                    sched.gpu_tx.send(*gpu).ok();
+                }
+                sched.busy.fetch_sub(1, Ordering::SeqCst);
+            }
+            sched.dispatch_free().await;
+        });
+        Ok(())
+    }
+
+    /// Cancel a job: drop it from the queue, or send the stop signal to it
+    /// if it is already running. Returns false if the job is unknown or has
+    /// already finished.
+    pub async fn cancel(&self, job_id: Uuid) -> bool {
+        let Some(token) = self.job_tokens.lock().await.get(&job_id).cloned() else {
+            return false;
+        };
+        token.cancel();
+        let queued = {
+            let mut queue = self.queue.lock().await;
+            let position = queue.iter().position(|job| job.id == job_id);
+            position.and_then(|i| queue.remove(i))
+        };
+        match queued {
+            Some(job) => self.mark_cancelled(&job).await,
+            // Whoever holds it (a worker, a gang gathering GPUs or a retry
+            // delay) sees the token and marks it cancelled
+            None => debug!(job = %job_id, "Cancelling job"),
+        }
+        true
+    }
+
+    /// Give a queued job another priority; it goes behind the queued jobs
+    /// that already have it.
+    pub async fn set_priority(&self, job_id: Uuid, priority: Priority) -> Result<()> {
+        {
+            let mut queue = self.queue.lock().await;
+            let mut job = take_queued(&mut queue, job_id)?;
+            job.priority = priority;
+            queue_by_priority(&mut queue, job);
+        }
+        let mut state = self.app_state.write().await;
+        if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == job_id) {
+            job_info.priority = priority;
+            self.events.send(JobEvent::state(job_info)).ok();
+        }
+        Ok(())
+    }
+
+    /// Put a queued job ahead of (`front`) or behind every other queued
+    /// job, whatever their priority.
+    pub async fn move_job(&self, job_id: Uuid, front: bool) -> Result<()> {
+        let mut queue = self.queue.lock().await;
+        let job = take_queued(&mut queue, job_id)?;
+        match front {
+            true => queue.push_front(job),
+            false => queue.push_back(job),
+        }
+        Ok(())
+    }
+
+    /// Swap a queued job with the one just ahead of it (`up`) or just behind
+    /// it in the queue, whatever their priority. The job list then shows
+    /// the queued jobs in queue order, which priorities and `move_job` may
+    /// have made differ from the order they were submitted in.
+    pub async fn shift_job(&self, job_id: Uuid, up: bool) -> Result<()> {
+        let order: Vec<Uuid> = {
+            let mut queue = self.queue.lock().await;
+            let Some(at) = queue.iter().position(|job| job.id == job_id) else {
+                anyhow::bail!("job {} is not waiting in the queue", job_id);
+            };
+            let to = match up {
+                true => at.checked_sub(1),
+                false => Some(at + 1).filter(|&to| to < queue.len()),
+            };
+            let Some(to) = to else {
+                // Already first or last
+                return Ok(());
+            };
+            queue.swap(at, to);
+            queue.iter().map(|job| job.id).collect()
+        };
+
+        // The queued jobs take the rows they had between them, in queue order
+        let mut state = self.app_state.write().await;
+        let before: Vec<Uuid> = state.jobs.iter().map(|job| job.id).collect();
+        let waiting: HashSet<Uuid> = order.iter().copied().collect();
+        let mut queued = HashMap::new();
+        let mut rows = Vec::with_capacity(before.len());
+        for job in std::mem::take(&mut state.jobs) {
+            if waiting.contains(&job.id) {
+                queued.insert(job.id, job);
+                rows.push(None);
+            } else {
+                rows.push(Some(job));
+            }
+        }
+        let mut in_order = order.iter().filter_map(|id| queued.remove(id));
+        state.jobs = rows
+            .into_iter()
+            .filter_map(|row| row.or_else(|| in_order.next()))
+            .collect();
+        // Saved by position, so every job that moved is saved again
+        for (job, id) in state.jobs.iter().zip(before) {
+            if job.id != id {
+                self.events.send(JobEvent::state(job)).ok();
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a job that was cancelled before it could run to completion.
+    /// On shutdown jobs keep the state they had instead.
+    async fn mark_cancelled(&self, job: &JobSpec) {
+        if self.cancel.is_cancelled() {
+            return;
+        }
+        info!(job = %job.id, "Job cancelled");
+        self.job_tokens.lock().await.remove(&job.id);
+        self.set_job_state(job.id, JobState::Cancelled).await;
+    }
+
+    /// Start `job` on a free GPU, or queue it for the next worker.
+    async fn enqueue(&self, job: JobSpec) -> Result<()> {
+        // Workers release their GPU under the queue lock, so holding it here
+        // means a job can never be queued just after the last worker gave up
+        let mut queue = self.queue.lock().await;
+        let free = if self.reserving.load(Ordering::SeqCst) > 0
+            || self.holding()
+            || !self.within_limit(&job)
+        {
+            None
+        } else {
+            self.take_free(&mut *self.gpu_rx.lock().await)
+        };
+        if let Some(gpu) = free {
+            self.charge(&job);
+            self.spawn_job(job, gpu);
+        } else {
+            trace!(job = %job.id, queued = queue.len() + 1, "No free GPU, queueing job");
+            queue_by_priority(&mut queue, job);
+        }
+        Ok(())
+    }
+
+    /// Hand free GPUs to queued jobs, e.g. after a multi-GPU job has
+    /// collected its GPUs or given them back.
+    async fn dispatch_free(&self) {
+        let mut queue = self.queue.lock().await;
+        if self.reserving.load(Ordering::SeqCst) > 0 || self.holding() || self.cancel.is_cancelled()
+        {
+            return;
+        }
+        while queue.iter().any(|job| self.within_limit(job)) {
+            let Some(gpu) = ({ self.take_free(&mut *self.gpu_rx.lock().await) }) else {
+                break;
+            };
+            if let Some(job) = self.take_next(&mut queue) {
+                self.spawn_job(job, gpu);
+            }
+        }
+    }
+
+    /// The first queued job whose owner has room under `user_gpu_limit`,
+    /// charged to them.
+    fn take_next(&self, queue: &mut VecDeque<JobSpec>) -> Option<JobSpec> {
+        let index = queue.iter().position(|job| self.within_limit(job))?;
+        let job = queue.remove(index)?;
+        self.charge(&job);
+        Some(job)
+    }
+
+    fn within_limit(&self, job: &JobSpec) -> bool {
+        let limit = self.tunables.read().unwrap().user_gpu_limit;
+        match (&job.owner, limit) {
+            (Some(owner), Some(limit)) => {
+                let held = self.owner_gpus.lock().unwrap().get(owner).copied();
+                held.unwrap_or_default() + job.gpus <= limit
+            }
+            _ => true,
+        }
+    }
+
+    /// Count a starting job's GPUs against its owner.
+    fn charge(&self, job: &JobSpec) {
+        if let Some(owner) = &job.owner {
+            *self
+                .owner_gpus
+                .lock()
+                .unwrap()
+                .entry(owner.clone())
+                .or_default() += job.gpus;
+        }
+    }
+
+    /// Give back what `charge` took, once the job is done with its GPUs.
+    fn refund(&self, job: &JobSpec) {
+        if let Some(owner) = &job.owner {
+            let mut held = self.owner_gpus.lock().unwrap();
+            if let Some(gpus) = held.get_mut(owner) {
+                *gpus = gpus.saturating_sub(job.gpus);
+                if *gpus == 0 {
+                    held.remove(owner);
+                }
+            }
+        }
+    }
+
+    /// Start a worker that runs `job` on `gpu`, then keeps taking queued
+    /// jobs until the queue is empty.
+    fn spawn_job(&self, job: JobSpec, gpu: u32) {
+        self.busy.fetch_add(1, Ordering::SeqCst);
+
+        let sched = self.clone();
+        self.tasks.spawn(async move {
+            let mut next = Some(job);
+            while let Some(job) = next {
+                if job.gpus > 1 {
+                    sched.start_gang(job);
+                } else {
+                    let owned = job.clone();
+                    sched.run_job(job, &[gpu]).await;
+                    sched.refund(&owned);
+                }
+                // try to fetch next job for same GPU
+                let mut queue = sched.queue.lock().await;
+                next = if sched.cancel.is_cancelled()
+                    || sched.holding()
+                    || sched.reserving.load(Ordering::SeqCst) > 0
+                    || sched.over_capacity(gpu)
+                {
+                    None
+                } else {
+                    sched.take_next(&mut queue)
+                };
+                if next.is_none() {
+                    // no queued job, release GPU
+                    sched.gpu_tx.send(gpu).ok();
+                    sched.busy.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+        });
+    }
+
+    /// Run a multi-GPU job in its own task: collect `job.gpus` GPUs as they
+    /// are freed, run it, then give them all back.
+    fn start_gang(&self, job: JobSpec) {
+        self.reserving.fetch_add(1, Ordering::SeqCst);
+        self.busy.fetch_add(1, Ordering::SeqCst);
+
+        let sched = self.clone();
+        self.tasks.spawn(async move {
+            let gpus = {
+                let _turn = sched.gather_lock.lock().await;
+                sched.gather_gpus(job.gpus as usize, &job.cancel).await
+            };
+            sched.reserving.fetch_sub(1, Ordering::SeqCst);
+            let owned = job.clone();
+            if gpus.len() == job.gpus as usize {
+                sched.dispatch_free().await;
+                sched.run_job(job, &gpus).await;
+            } else if job.cancel.is_cancelled() {
+                sched.mark_cancelled(&job).await;
+            } else {
+                // Drained while collecting: it never started, so it goes
+                // back to the head of the queue
+                sched.queue.lock().await.push_front(job);
+            }
+            sched.refund(&owned);
+
+            {
+                let _queue = sched.queue.lock().await;
+                for gpu in gpus {
+                    sched.gpu_tx.send(gpu).ok();
+                }
+                sched.busy.fetch_sub(1, Ordering::SeqCst);
+            }
+            sched.dispatch_free().await;
+        });
+    }
+
+    /// Take `count` GPUs of one machine from the free pool, waiting for
+    /// workers to hand theirs back, and the best connected of them once
+    /// there are enough. Returns none if `cancel` fires or the
+    /// scheduler is drained first.
+    async fn gather_gpus(&self, count: usize, cancel: &CancellationToken) -> Vec<u32> {
+        let mut held = Vec::new();
+        loop {
+            {
+                let mut rx = self.gpu_rx.lock().await;
+                while let Some(gpu) = self.take_free(&mut rx) {
+                    held.push(gpu);
+                }
+            }
+            let hosts: HashMap<u32, String> = {
+                let remote_gpus = self.remote_gpus.read().unwrap();
+                held.iter()
+                    .filter_map(|gpu| Some((*gpu, remote_gpus.get(gpu)?.host.clone())))
+                    .collect()
+            };
+            let host_of = |gpu: &u32| hosts.get(gpu).map(String::as_str);
+            // A GPU that runs several jobs is in the pool more than once
+            let mut distinct = held.clone();
+            distinct.sort_unstable();
+            distinct.dedup();
+            let full_host = distinct
+                .iter()
+                .map(host_of)
+                .find(|host| distinct.iter().filter(|g| host_of(g) == *host).count() >= count);
+            if let Some(host) = full_host {
+                let candidates: Vec<u32> = distinct
+                    .iter()
+                    .copied()
+                    .filter(|g| host_of(g) == host)
+                    .collect();
+                let gpus = self.topology.best_set(&candidates, count);
+                for gpu in &gpus {
+                    if let Some(i) = held.iter().position(|g| g == gpu) {
+                        held.swap_remove(i);
+                    }
+                }
+                for gpu in held {
+                    self.gpu_tx.send(gpu).ok();
+                }
+                return gpus;
+            }
+            // Polled rather than awaited so the receiver lock is never held
+            // while enqueue/dispatch need it
+            if self.holding() {
+                for gpu in held {
+                    self.gpu_tx.send(gpu).ok();
+                }
+                return Vec::new();
+            }
+            tokio::select! {
+                _ = cancel.cancelled() => {
+                    for gpu in held {
+                        self.gpu_tx.send(gpu).ok();
+                    }
+                    return Vec::new();
+                }
+                _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+            }
+        }
+    }
+
+    /// Run a single job on `gpus` to completion, keeping the UI state in sync.
+    async fn run_job(&self, job: JobSpec, gpus: &[u32]) {
+        if let Some(needed) = job.mem_mb.filter(|_| !job.cancel.is_cancelled()) {
+            let small = {
+                let state = self.app_state.read().await;
+                too_small(&state.gpus, gpus, needed).map(|g| (g.id, g.total_memory_mb))
+            };
+            if let Some((gpu, total)) = small {
+                error!(
+                    job = %job.id,
+                    "GPU {} has {} MiB in all, less than the {} MiB the job asks for",
+                    gpu,
+                    total,
+                    needed
+                );
+                self.finish_job(&job, JobExit::SpawnFailed, Some("mem"))
+                    .await;
+                return;
+            }
+        }
+        if job.cancel.is_cancelled()
+            || !self.wait_for_gpu_memory(&job, gpus).await
+            || !self.wait_for_cool(&job, gpus).await
+            || !self.wait_for_memory(&job).await
+        {
+            self.mark_cancelled(&job).await;
+            return;
+        }
+        if !self.healthy(gpus).await {
+            info!(job = %job.id, "GPU quarantined, putting the job back in the queue");
+            self.queue.lock().await.push_front(job);
+            self.dispatch_free().await;
+            return;
+        }
+        let gpu = gpus[0];
+        debug!(job = %job.id, ?gpus, "Dispatching job: {}", job.cmd);
+        // Update job state to running
+        self.set_job_state(job.id, JobState::Running { gpu_id: gpu })
+            .await;
+
+        if let Some(limit_mw) = job.power_limit_mw {
+            if let Err(e) = self.cap_power(gpus, limit_mw).await {
+                error!(job = %job.id, "Failed to set the power limit: {}", e);
+                self.finish_job(&job, JobExit::SpawnFailed, Some("power-limit"))
+                    .await;
+                return;
+            }
+        }
+        let (exit, setup_phase) = self.run_steps(&job, gpus).await;
+        if job.power_limit_mw.is_some() {
+            self.uncap_power(gpus).await;
+        }
+        self.finish_job(&job, exit, setup_phase).await;
+    }
+
+    /// Run the `--pre-cmd` and, if it succeeds, the job's command. Returns
+    /// how the last to run exited, and the setup step if it was that one.
+    async fn run_steps(&self, job: &JobSpec, gpus: &[u32]) -> (JobExit, Option<&'static str>) {
+        if let Some(pre_cmd) = &self.options.pre_cmd {
+            let exit = self.run_process(job, gpus, pre_cmd).await;
+            if !exit.success() {
+                return (exit, Some("pre-cmd"));
+            }
+        }
+        (self.run_process(job, gpus, &job.cmd).await, None)
+    }
+
+    /// Cap `gpus` at a job's power limit until `uncap_power`. Only this
+    /// machine's whole GPUs can be capped: a MIG instance draws from the
+    /// power budget of its parent, which it shares.
+    async fn cap_power(&self, gpus: &[u32], limit_mw: u32) -> Result<()> {
+        {
+            let remote_gpus = self.remote_gpus.read().unwrap();
+            let shared = gpus
+                .iter()
+                .find(|gpu| remote_gpus.contains_key(gpu) || self.mig_devices.contains_key(gpu));
+            if let Some(gpu) = shared {
+                anyhow::bail!(
+                    "GPU {} is remote or a MIG instance, which cannot be capped",
+                    gpu
+                );
+            }
+        }
+        let Some(nvml) = self.nvml() else {
+            anyhow::bail!("NVML is unavailable");
+        };
+        let caps = self.power_caps.clone();
+        let gpus = gpus.to_vec();
+        tokio::task::spawn_blocking(move || caps.apply(&nvml, &gpus, limit_mw)).await?
+    }
+
+    /// Lift a finished job's power cap from `gpus`.
+    async fn uncap_power(&self, gpus: &[u32]) {
+        let Some(nvml) = self.nvml() else {
+            return;
+        };
+        let caps = self.power_caps.clone();
+        let gpus = gpus.to_vec();
+        tokio::task::spawn_blocking(move || caps.release(&nvml, &gpus))
+            .await
+            .ok();
+    }
+
+    /// Check this machine's `gpus` through NVML right before a job starts on
+    /// them, and quarantine the unhealthy ones. MIG instances go by their
+    /// parent's health.
+    async fn healthy(&self, gpus: &[u32]) -> bool {
+        let Some(nvml) = self.nvml() else {
+            return true;
+        };
+        let indices: Vec<(u32, u32)> = {
+            let remote_gpus = self.remote_gpus.read().unwrap();
+            gpus.iter()
+                .filter(|gpu| !remote_gpus.contains_key(gpu))
+                .map(|gpu| (*gpu, self.mig_devices.get(gpu).map_or(*gpu, |m| m.parent)))
+                .collect()
+        };
+        if indices.is_empty() {
+            return true;
+        }
+        let xids = self.xids.clone();
+        let found = tokio::task::spawn_blocking(move || {
+            indices
+                .into_iter()
+                .filter_map(|(gpu, index)| Some((gpu, health::check(&nvml, index, &xids)?)))
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        let healthy = found.is_empty();
+        let mut state = self.app_state.write().await;
+        for (gpu, fault) in found {
+            if let Some(info) = state.gpus.iter_mut().find(|g| g.id == gpu) {
+                quarantine(&self.faults, info, fault);
+            }
+        }
+        healthy
+    }
+
+    /// Hold `job` back until each of its GPUs reports the free memory it
+    /// asked for. GPUs whose memory is unknown (no NVML) never block.
+    async fn wait_for_gpu_memory(&self, job: &JobSpec, gpus: &[u32]) -> bool {
+        let Some(needed) = job.mem_mb else {
+            return true;
+        };
+        let mut logged = false;
+        loop {
+            let short = {
+                let state = self.app_state.read().await;
+                state
+                    .gpus
+                    .iter()
+                    .filter(|g| gpus.contains(&g.id) && g.total_memory_mb > 0)
+                    .find(|g| g.free_memory_mb < needed)
+                    .map(|g| (g.id, g.free_memory_mb))
+            };
+            let Some((gpu, free)) = short else {
+                return true;
+            };
+            if !logged {
+                info!(
+                    job = %job.id,
+                    "Waiting for {} MiB free on GPU {} ({} MiB free)",
+                    needed,
+                    gpu,
+                    free
+                );
+                logged = true;
+            }
+            tokio::select! {
+                _ = job.cancel.cancelled() => return false,
+                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            }
+        }
+    }
+
+    /// Hold `job` back while any of its GPUs is cooling down after reaching
+    /// `--max-temp`, as the monitor tracks it across jobs. GPUs whose
+    /// temperature is unknown never block. Returns false if the job was
+    /// cancelled in the meantime.
+    async fn wait_for_cool(&self, job: &JobSpec, gpus: &[u32]) -> bool {
+        let mut logged = false;
+        loop {
+            let Some(limit) = self.tunables.read().unwrap().max_temp else {
+                return true;
+            };
+            let hot = {
+                let state = self.app_state.read().await;
+                cooling_gpu(&self.cooling.lock().unwrap(), &state.gpus, gpus)
+            };
+            let Some((gpu, temp)) = hot else {
+                return true;
+            };
+            if !logged {
+                info!(
+                    job = %job.id,
+                    "Waiting for GPU {} to cool down to {}°C ({}°C now)",
+                    gpu,
+                    limit.resume,
+                    temp
+                );
+                logged = true;
+            }
+            tokio::select! {
+                _ = job.cancel.cancelled() => return false,
+                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            }
+        }
+    }
+
+    /// Hold `job` back until the host has `--memfree` bytes available.
+    /// Jobs are checked one at a time, each at least `MEMORY_SETTLE` after
+    /// the last one passed, so that one's memory shows before the next
+    /// check instead of a whole batch starting on the same free memory.
+    /// A job keeps the gate while it waits, so every other job's check
+    /// waits behind it; all of them wait for the same `--memfree`, so none
+    /// could pass before it anyway, and they pass in the order they came.
+    /// Returns false if the job was cancelled in the meantime.
+    async fn wait_for_memory(&self, job: &JobSpec) -> bool {
+        if self.tunables.read().unwrap().memfree.is_none() {
+            return true;
+        }
+        let mut last_passed = tokio::select! {
+            _ = job.cancel.cancelled() => return false,
+            gate = self.memory_gate.lock() => gate,
+        };
+        if let Some(last) = *last_passed {
+            tokio::select! {
+                _ = job.cancel.cancelled() => return false,
+                _ = tokio::time::sleep_until(last + MEMORY_SETTLE) => {}
+            }
+        }
+        let mut logged = false;
+        loop {
+            // Read again each time, as a reload may lower or drop it
+            let Some(needed) = self.tunables.read().unwrap().memfree else {
+                *last_passed = Some(tokio::time::Instant::now());
+                return true;
+            };
+            // Without /proc/meminfo there is nothing to wait for
+            let available = match memory::available_bytes() {
+                Some(available) if available < needed => available,
+                _ => {
+                    *last_passed = Some(tokio::time::Instant::now());
+                    return true;
+                }
+            };
+            if !logged {
+                info!(
+                    job = %job.id,
+                    "Waiting for {} of free memory ({} available)",
+                    memory::humanize_bytes(needed),
+                    memory::humanize_bytes(available)
+                );
+                logged = true;
+            }
+            tokio::select! {
+                _ = job.cancel.cancelled() => return false,
+                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            }
+        }
+    }
+
+    /// Run one process of `job` (its command or a setup step) on `gpu` and
+    /// wait for it, enforcing the runtime limit and cancellation.
+    async fn run_process(&self, job: &JobSpec, gpus: &[u32], cmd: &str) -> JobExit {
+        let capture = !matches!(self.options.output, OutputMode::Inherit);
+        if let Some(dir) = &job.output_dir {
+            if let Err(e) = tokio::fs::create_dir_all(dir).await {
+                error!(job = %job.id, "Failed to create output directory '{}': {}", dir.display(), e);
+                return JobExit::SpawnFailed;
+            }
+        }
+
+        // Multi-GPU jobs only ever get GPUs of one machine
+        let remote = gpus
+            .first()
+            .and_then(|gpu| self.remote_gpus.read().unwrap().get(gpu).cloned());
+        if let Some(agent) = remote.as_ref().and_then(|r| r.agent.as_ref()) {
+            return self.run_on_agent(job, gpus, cmd, agent).await;
+        }
+        let built = match &remote {
+            Some(remote) => {
+                ssh::remote_script(self.options.shell, &self.remote_env(job, gpus), cmd)
+                    .and_then(|script| ssh::command(&self.options.ssh, &remote.host, &script))
+            }
+            None => self.options.shell.command(cmd),
+        };
+        let mut child = match built {
+            Ok(child) => child,
+            Err(e) => {
+                error!(job = %job.id, "Failed to spawn job: {}", e);
+                return JobExit::SpawnFailed;
+            }
+        };
+        if remote.is_none() {
+            if self.options.clean_env {
+                child.env_clear();
+                child.envs(env::vars().filter(|(key, _)| keep_in_clean_env(key)));
+            }
+            child.envs(self.options.env.iter().cloned());
+            child.envs(self.gpu_env(gpus));
+            child.env("GPARALLEL_JOB_ID", job.id.to_string());
+            if let Some(dir) = &job.output_dir {
+                child.env("GPARALLEL_OUTPUT_DIR", dir);
+                if self.options.run_in_output_dir {
+                    child.current_dir(dir);
+                }
+            }
+            child.envs(&job.env);
         }
 
-        if let Some(gpu) = { self.gpu_rx.lock().await.try_recv().ok() } {
-            self.spawn_job(job, gpu).await?;
+        // A remote job lives until ssh's stdin closes
+        child.stdin(match remote {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        });
+        if capture {
+            child.stdout(Stdio::piped()).stderr(Stdio::piped());
         } else {
-            self.queue.lock().await.push_back(job);
+            child.stdout(Stdio::inherit()).stderr(Stdio::inherit());
         }
-        Ok(())
-    }
 
-    async fn spawn_job(&self, job: JobSpec, gpu: u32) -> Result<()> {
-        self.busy.fetch_add(1, Ordering::SeqCst);
+        let mut child_process = match child.spawn() {
+            Ok(cp) => cp,
+            Err(e) => {
+                error!(job = %job.id, "Failed to spawn job: {}", e);
+                return JobExit::SpawnFailed;
+            }
+        };
 
-        // Update job state to running
-        {
+        let _remote_stdin = child_process.stdin.take();
+
+        // Track the PID; a remote job cannot be adopted after a restart, as
+        // it dies with its ssh connection
+        if let Some(pid) = child_process.id().filter(|_| remote.is_none()) {
+            self.running_jobs.lock().await.insert(job.id, pid);
+            let process = JobProcess {
+                pid,
+                start_time: process_start_time(pid).unwrap_or_default(),
+                gpus: gpus.to_vec(),
+                gpu_uuids: self.uuids_of(gpus),
+            };
             let mut state = self.app_state.write().await;
             if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == job.id) {
-                job_info.state = JobState::Running { gpu_id: gpu };
+                job_info.process = Some(process);
+                self.events.send(JobEvent::state(job_info)).ok();
             }
         }
 
-        let queue = self.queue.clone();
-        let tx = self.gpu_tx.clone();
-        let busy = self.busy.clone();
-        let app_state = self.app_state.clone();
-        let running_jobs = self.running_jobs.clone();
-        let use_tui = self.use_tui;
-
-        let mut child = Command::new("bash");
-        child.arg("-c").arg(&job.cmd);
-        child.env("CUDA_VISIBLE_DEVICES", gpu.to_string());
-
-        if self.use_tui {
-            child
-                .stdin(Stdio::null())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped());
-        } else {
-            child
-                .stdin(Stdio::null())
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit());
-        }
-
-        tokio::spawn(async move {
-            let mut child_process = match child.spawn() {
-                Ok(cp) => cp,
-                Err(e) => {
-                    eprintln!("[gparallel] Failed to spawn job {}: {}", job.id, e);
-                    // Update job state to failed
-                    {
-                        let mut state = app_state.write().await;
-                        if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == job.id) {
-                            job_info.state = JobState::Failed;
-                        }
-                    }
-                    tx.send(gpu).ok();
-                    busy.fetch_sub(1, Ordering::SeqCst);
-                    return;
-                }
-            };
+        // Capture stdout/stderr (not in inherit mode)
+        let mut readers = Vec::new();
+        if capture {
+            if let Some(stdout) = child_process.stdout.take() {
+                readers.push(self.spawn_log_reader(job, stdout, false));
+            }
+            if let Some(stderr) = child_process.stderr.take() {
+                readers.push(self.spawn_log_reader(job, stderr, true));
+            }
+        }
 
-            // Track the PID
-            if let Some(pid) = child_process.id() {
-                running_jobs.lock().await.insert(job.id, pid);
-            }
-
-            // Capture stdout (only in TUI mode)
-            if use_tui {
-                if let Some(stdout) = child_process.stdout.take() {
-                    let job_id = job.id;
-                    let state_clone = app_state.clone();
-                    tokio::spawn(async move {
-                        let reader = AsyncBufReader::new(stdout);
-                        let mut lines = reader.lines();
-                        while let Ok(Some(line)) = lines.next_line().await {
-                            let mut state = state_clone.write().await;
-                            if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == job_id) {
-                                job_info.log_lines.push_back(line.clone());
-                                if job_info.log_lines.len() > 1000 {
-                                    job_info.log_lines.pop_front();
-                                }
-                            }
-                        }
-                    });
-                }
-
-                // Capture stderr
-                if let Some(stderr) = child_process.stderr.take() {
-                    let job_id = job.id;
-                    let state_clone = app_state.clone();
-                    tokio::spawn(async move {
-                        let reader = AsyncBufReader::new(stderr);
-                        let mut lines = reader.lines();
-                        while let Ok(Some(line)) = lines.next_line().await {
-                            let mut state = state_clone.write().await;
-                            if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == job_id) {
-                                job_info.log_lines.push_back(format!("[stderr] {}", line));
-                                if job_info.log_lines.len() > 1000 {
-                                    job_info.log_lines.pop_front();
-                                }
-                            }
-                        }
-                    });
+        let deadline = async {
+            match self.options.max_runtime {
+                Some(limit) => tokio::time::sleep(limit).await,
+                None => std::future::pending().await,
+            }
+        };
+        let status = tokio::select! {
+            status = child_process.wait() => status,
+            _ = deadline => {
+                warn!(
+                    job = %job.id,
+                    "Job exceeded max runtime of {}, terminating",
+                    humanize(self.options.max_runtime.unwrap_or_default())
+                );
+                self.timed_out.lock().unwrap().insert(job.id);
+                terminate_child(&mut child_process, self.options.stop_signal, Duration::from_secs(5)).await
+            }
+            _ = job.cancel.cancelled() => {
+                if let Some(pid) = child_process.id() {
+                    info!(job = %job.id, pid, "Killing job");
                 }
+                // A cancelled job gets the same grace period as a timed out
+                // one; on shutdown we do not wait as long
+                let grace = if self.cancel.is_cancelled() { 1 } else { 5 };
+                terminate_child(&mut child_process, self.options.stop_signal, Duration::from_secs(grace)).await
             }
+        };
 
-            let status = child_process.wait().await;
+        // Drain remaining output so the final lines are not lost (bounded, since
+        // a backgrounded grandchild may keep the pipe open indefinitely)
+        for reader in readers {
+            tokio::time::timeout(Duration::from_secs(1), reader)
+                .await
+                .ok();
+        }
 
-            // Update job state based on exit status
-            {
-                let mut state = app_state.write().await;
-                if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == job.id) {
-                    job_info.state = match status {
-                        Ok(s) if s.success() => JobState::Completed,
-                        _ => JobState::Failed,
-                    };
+        // Remove from running jobs
+        self.running_jobs.lock().await.remove(&job.id);
+
+        match status {
+            Ok(s) => JobExit::from_status(s),
+            Err(_) => JobExit::SpawnFailed,
+        }
+    }
+
+    /// Run one process of `job` through the agent that has its GPUs. It is
+    /// stopped like a local one, by asking the agent to signal it.
+    async fn run_on_agent(
+        &self,
+        job: &JobSpec,
+        gpus: &[u32],
+        cmd: &str,
+        agent: &AgentLink,
+    ) -> JobExit {
+        let env = self.remote_env(job, gpus);
+        let mut run = match agent.start(job.id, cmd, self.options.shell, env).await {
+            Ok(run) => run,
+            Err(e) => {
+                error!(job = %job.id, "Failed to start job on {}: {}", agent.host(), e);
+                return JobExit::Lost;
+            }
+        };
+        let readers = [
+            self.spawn_log_reader(job, run.stdout, false),
+            self.spawn_log_reader(job, run.stderr, true),
+        ];
+
+        let deadline = async {
+            match self.options.max_runtime {
+                Some(limit) => tokio::time::sleep(limit).await,
+                None => std::future::pending().await,
+            }
+        };
+        // Ok with how it ended, or Err with how long to give it to stop
+        let outcome = tokio::select! {
+            exit = &mut run.exit => Ok(exit),
+            _ = deadline => {
+                warn!(
+                    job = %job.id,
+                    "Job exceeded max runtime of {}, terminating",
+                    humanize(self.options.max_runtime.unwrap_or_default())
+                );
+                self.timed_out.lock().unwrap().insert(job.id);
+                Err(5)
+            }
+            _ = job.cancel.cancelled() => {
+                info!(job = %job.id, "Killing job on {}", agent.host());
+                Err(if self.cancel.is_cancelled() { 1 } else { 5 })
+            }
+        };
+        let exit = match outcome {
+            Ok(exit) => exit,
+            Err(grace) => {
+                agent.kill(job.id, self.options.stop_signal);
+                match tokio::time::timeout(Duration::from_secs(grace), &mut run.exit).await {
+                    Ok(exit) => exit,
+                    Err(_) => {
+                        agent.kill(job.id, Signal::SIGKILL);
+                        run.exit.await
+                    }
                 }
             }
+        };
+
+        for reader in readers {
+            tokio::time::timeout(Duration::from_secs(1), reader)
+                .await
+                .ok();
+        }
+        // The sender goes away with the agent's connection
+        exit.unwrap_or(JobExit::Lost)
+    }
 
-            // Remove from running jobs
-            running_jobs.lock().await.remove(&job.id);
+    /// What a job on another machine gets in its environment, since it
+    /// cannot inherit ours.
+    fn remote_env(&self, job: &JobSpec, gpus: &[u32]) -> Vec<(String, String)> {
+        let mut env = self.options.env.clone();
+        env.extend(
+            self.gpu_env(gpus)
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v)),
+        );
+        env.push(("GPARALLEL_JOB_ID".to_string(), job.id.to_string()));
+        env.extend(job.env.clone());
+        env
+    }
 
+    fn spawn_log_reader<R>(&self, job: &JobSpec, pipe: R, is_stderr: bool) -> JoinHandle<()>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let job_id = job.id;
+        let log_path = job.output_dir.as_ref().map(|dir| {
+            dir.join(if is_stderr {
+                "stderr.log"
+            } else {
+                "stdout.log"
+            })
+        });
+        let app_state = self.app_state.clone();
+        let forward = match &self.options.output {
+            OutputMode::Forward(tx) => Some(tx.clone()),
+            _ => None,
+        };
+        let events = self.events.clone();
+        let cancel = self.cancel.child_token();
+        self.tasks.spawn(async move {
+            let reader = AsyncBufReader::new(pipe);
+            let mut lines = reader.lines();
+            // Appended to, so setup output and retries end up in one file
+            let mut log_file = match &log_path {
+                Some(path) => OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| warn!("Cannot write '{}': {}", path.display(), e))
+                    .ok(),
+                None => None,
+            };
             loop {
-                // 1. try to fetch next job for same GPU
-                let maybe_job = {
-                    let mut q = queue.lock().await;
-                    q.pop_front()
+                let line = tokio::select! {
+                    line = lines.next_line() => line,
+                    _ = cancel.cancelled() => break,
+                };
+                let Ok(Some(line)) = line else {
+                    break;
+                };
+                if let Some(file) = log_file.as_mut() {
+                    let written = file.write_all(format!("{}\n", line).as_bytes()).await;
+                    if written.is_err() {
+                        log_file = None;
+                    }
+                }
+                if let Some(tx) = &forward {
+                    tx.send(ForwardedLine {
+                        is_stderr,
+                        line: line.clone(),
+                    })
+                    .ok();
+                }
+                let line = if is_stderr {
+                    format!("[stderr] {}", line)
+                } else {
+                    line
                 };
+                if app_state.write().await.push_log_line(job_id, line.clone()) {
+                    events.send(JobEvent::Log { job_id, line }).ok();
+                }
+            }
+        })
+    }
 
-                match maybe_job {
-                    Some(next) => {
-                        // Update existing job state to running
-                        {
-                            let mut state = app_state.write().await;
-                            if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == next.id)
-                            {
-                                job_info.state = JobState::Running { gpu_id: gpu };
-                            }
-                        }
+    /// The UUIDs of `gpus`, or none unless every one of them is known.
+    fn uuids_of(&self, gpus: &[u32]) -> Vec<String> {
+        let uuids = self.gpu_uuids.read().unwrap();
+        gpus.iter()
+            .map(|gpu| uuids.get(gpu).cloned())
+            .collect::<Option<_>>()
+            .unwrap_or_default()
+    }
+
+    /// Device identification exported to every job alongside
+    /// `CUDA_VISIBLE_DEVICES`, for tools that need the absolute NVML index.
+    /// Multi-GPU jobs get comma-separated lists in the same order.
+    fn gpu_env(&self, gpus: &[u32]) -> Vec<(&'static str, String)> {
+        fn list(items: impl Iterator<Item = String>) -> String {
+            items.collect::<Vec<_>>().join(",")
+        }
+        // Remote GPUs go by their index on their own host, and MIG
+        // instances by the one of their parent
+        let remote_gpus = self.remote_gpus.read().unwrap();
+        let index = |gpu: &u32| match self.mig_devices.get(gpu) {
+            Some(mig) => mig.parent,
+            None => remote_gpus.get(gpu).map_or(*gpu, |r| r.index),
+        };
+        let indices = list(gpus.iter().map(|gpu| index(gpu).to_string()));
+        // CUDA only tells MIG instances apart by UUID
+        let visible = list(gpus.iter().map(|gpu| match self.mig_devices.get(gpu) {
+            Some(mig) => mig.uuid.clone(),
+            None => index(gpu).to_string(),
+        }));
+        let mut vars = vec![
+            ("CUDA_VISIBLE_DEVICES", visible),
+            ("GPARALLEL_GPU", indices.clone()),
+            // Index as seen by the job after CUDA_VISIBLE_DEVICES remapping
+            (
+                "GPARALLEL_GPU_LOGICAL",
+                list((0..gpus.len()).map(|i| i.to_string())),
+            ),
+            // Absolute (NVML) index of the assigned device
+            ("GPARALLEL_GPU_PHYSICAL", indices.clone()),
+        ];
+        // The NVIDIA container runtime accepts either form; the UUID stays
+        // unambiguous when the container enumerates devices differently
+        let gpu_uuids = self.gpu_uuids.read().unwrap();
+        let uuids: Option<Vec<&String>> = gpus.iter().map(|g| gpu_uuids.get(g)).collect();
+        match uuids {
+            Some(uuids) => {
+                let uuids = list(uuids.into_iter().cloned());
+                vars.push(("NVIDIA_VISIBLE_DEVICES", uuids.clone()));
+                vars.push(("GPARALLEL_GPU_UUID", uuids));
+            }
+            None => vars.push(("NVIDIA_VISIBLE_DEVICES", indices)),
+        }
+        // An MPS client talks to a single daemon, so multi-GPU jobs run
+        // outside MPS
+        if let [gpu] = gpus {
+            if let Some(daemon) = self.cuda_mps.daemon(*gpu) {
+                vars.retain(|(key, _)| *key != "CUDA_VISIBLE_DEVICES");
+                vars.extend(daemon.env());
+            }
+        }
+        vars
+    }
 
-                        // launch next job (reusing same GPU)
-                        let mut next_child = Command::new("bash");
-                        next_child.arg("-c").arg(&next.cmd);
-                        next_child.env("CUDA_VISIBLE_DEVICES", gpu.to_string());
+    async fn set_job_state(&self, job_id: Uuid, new_state: JobState) {
+        let mut state = self.app_state.write().await;
+        if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == job_id) {
+            match new_state {
+                JobState::Running { gpu_id } => {
+                    job_info.gpu = Some(gpu_id);
+                    job_info.gpu_uuid = self.gpu_uuids.read().unwrap().get(&gpu_id).cloned();
+                    job_info.timing.mark_started()
+                }
+                JobState::Completed
+                | JobState::Failed
+                | JobState::TimedOut
+                | JobState::SetupFailed { .. }
+                | JobState::Cancelled => {
+                    job_info.timing.mark_finished();
+                    job_info.process = None;
+                }
+                JobState::Queued => {}
+            }
+            job_info.state = new_state;
+            self.events.send(JobEvent::state(job_info)).ok();
+        }
+    }
 
-                        if use_tui {
-                            next_child
-                                .stdin(Stdio::null())
-                                .stdout(Stdio::piped())
-                                .stderr(Stdio::piped());
-                        } else {
-                            next_child
-                                .stdin(Stdio::null())
-                                .stdout(Stdio::inherit())
-                                .stderr(Stdio::inherit());
-                        }
+    /// Record how a job ended, or put it back in the queue if the retry
+    /// policy says so. `setup_phase` names the setup step that failed, if it
+    /// was not the job's own command; those failures are never retried.
+    async fn finish_job(&self, job: &JobSpec, exit: JobExit, setup_phase: Option<&'static str>) {
+        let timed_out = self.timed_out.lock().unwrap().remove(&job.id);
+        if setup_phase.is_none()
+            && !job.cancel.is_cancelled()
+            && self.options.retry.should_retry(exit, job.retries)
+        {
+            self.requeue(job, exit).await;
+            return;
+        }
 
-                        let mut child_process = match next_child.spawn() {
-                            Ok(cp) => cp,
-                            Err(e) => {
-                                eprintln!("[gparallel] Failed to spawn job {}: {}", next.id, e);
-                                // Update job state to failed
-                                {
-                                    let mut state = app_state.write().await;
-                                    if let Some(job_info) =
-                                        state.jobs.iter_mut().find(|j| j.id == next.id)
-                                    {
-                                        job_info.state = JobState::Failed;
-                                    }
-                                }
-                                continue;
-                            }
-                        };
-
-                        // Track the PID
-                        if let Some(pid) = child_process.id() {
-                            running_jobs.lock().await.insert(next.id, pid);
-                        }
+        let job_id = job.id;
+        self.job_tokens.lock().await.remove(&job_id);
+        let new_state = match setup_phase {
+            _ if job.cancel.is_cancelled() && !self.cancel.is_cancelled() => JobState::Cancelled,
+            Some(phase) => JobState::SetupFailed { phase },
+            None if exit.success() || exit == JobExit::Unknown => JobState::Completed,
+            None if timed_out => JobState::TimedOut,
+            None => JobState::Failed,
+        };
+        debug!(job = %job_id, "Job finished: {}", exit);
+        let failed = new_state.is_failure();
+        // The exit goes in first so it is there by the time the state
+        // change is announced
+        {
+            let mut state = self.app_state.write().await;
+            if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == job_id) {
+                job_info.exit = Some(exit);
+            }
+        }
+        self.set_job_state(job_id, new_state).await;
+        let job_info = {
+            let state = self.app_state.read().await;
+            let Some(job_info) = state.jobs.iter().find(|j| j.id == job_id) else {
+                return;
+            };
+            job_info.clone()
+        };
+        let cmd = &job_info.cmd;
 
-                        // Capture stdout (only in TUI mode)
-                        if use_tui {
-                            if let Some(stdout) = child_process.stdout.take() {
-                                let job_id = next.id;
-                                let state_clone = app_state.clone();
-                                tokio::spawn(async move {
-                                    let reader = AsyncBufReader::new(stdout);
-                                    let mut lines = reader.lines();
-                                    while let Ok(Some(line)) = lines.next_line().await {
-                                        let mut state = state_clone.write().await;
-                                        if let Some(job_info) =
-                                            state.jobs.iter_mut().find(|j| j.id == job_id)
-                                        {
-                                            job_info.log_lines.push_back(line.clone());
-                                            if job_info.log_lines.len() > 1000 {
-                                                job_info.log_lines.pop_front();
-                                            }
-                                        }
-                                    }
-                                });
-                            }
-
-                            // Capture stderr
-                            if let Some(stderr) = child_process.stderr.take() {
-                                let job_id = next.id;
-                                let state_clone = app_state.clone();
-                                tokio::spawn(async move {
-                                    let reader = AsyncBufReader::new(stderr);
-                                    let mut lines = reader.lines();
-                                    while let Ok(Some(line)) = lines.next_line().await {
-                                        let mut state = state_clone.write().await;
-                                        if let Some(job_info) =
-                                            state.jobs.iter_mut().find(|j| j.id == job_id)
-                                        {
-                                            job_info
-                                                .log_lines
-                                                .push_back(format!("[stderr] {}", line));
-                                            if job_info.log_lines.len() > 1000 {
-                                                job_info.log_lines.pop_front();
-                                            }
-                                        }
-                                    }
-                                });
-                            }
-                        }
+        if let Some(dir) = &job.output_dir {
+            if let Err(e) = report::write_job_json(&dir.join("job.json"), &job_info) {
+                warn!(job = %job_id, "{}", e);
+            }
+        }
+        if let Some(history) = &self.options.history {
+            if let Err(e) = history.append(&JobRecord::new(&job_info)) {
+                warn!(job = %job_id, "{}", e);
+            }
+        }
 
-                        let status = child_process.wait().await;
-
-                        // Update job state based on exit status
-                        {
-                            let mut state = app_state.write().await;
-                            if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == next.id)
-                            {
-                                job_info.state = match status {
-                                    Ok(s) if s.success() => JobState::Completed,
-                                    _ => JobState::Failed,
-                                };
-                            }
-                        }
+        let alert = self.tunables.read().unwrap().alert;
+        if let Some(alert) = alert {
+            if failed && !self.failure_alerted.swap(true, Ordering::SeqCst) {
+                let phase = setup_phase.map(|p| format!("{}: ", p)).unwrap_or_default();
+                let message = format!(
+                    "First failure: job {} ({}{}): {}",
+                    &job_id.to_string()[..8],
+                    phase,
+                    exit,
+                    cmd
+                );
+                // Plain inherit mode has nothing on screen to flash
+                let has_display = !matches!(self.options.output, OutputMode::Inherit);
+                let app_state = self.app_state.clone();
+                self.tasks.spawn(async move {
+                    alert::fire(alert, message, &app_state, has_display).await;
+                });
+            }
+        }
+    }
+
+    async fn requeue(&self, job: &JobSpec, exit: JobExit) {
+        let mut retry = job.clone();
+        retry.retries += 1;
+        let delay = self.options.retry.delay();
+        let after = if delay.is_zero() {
+            String::new()
+        } else {
+            format!(" in {}", humanize(delay))
+        };
+        info!(
+            job = %job.id,
+            "Job failed ({}), retrying{} ({}/{})",
+            exit,
+            after,
+            retry.retries,
+            self.options.retry.max
+        );
 
-                        // Remove from running jobs
-                        running_jobs.lock().await.remove(&next.id);
+        {
+            let mut state = self.app_state.write().await;
+            if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == job.id) {
+                job_info.retries = retry.retries;
+            }
+        }
+        self.set_job_state(job.id, JobState::Queued).await;
 
-                        // continue loop to see if more jobs remain
-                        continue;
-                    }
-                    None => {
-                        // no queued job, release GPU
-                        tx.send(gpu).ok();
-                        busy.fetch_sub(1, Ordering::SeqCst);
-                        break;
-                    }
+        if delay.is_zero() {
+            self.mark_queued(job.id).await;
+            queue_by_priority(&mut *self.queue.lock().await, retry);
+            return;
+        }
+        // The job only becomes eligible for dispatch once the delay is over
+        self.delayed.fetch_add(1, Ordering::SeqCst);
+        let sched = self.clone();
+        self.tasks.spawn(async move {
+            tokio::select! {
+                _ = retry.cancel.cancelled() => sched.mark_cancelled(&retry).await,
+                _ = tokio::time::sleep(delay) => {
+                    sched.mark_queued(retry.id).await;
+                    sched.enqueue(retry).await.ok();
                 }
             }
+            sched.delayed.fetch_sub(1, Ordering::SeqCst);
         });
-        Ok(())
+    }
+
+    /// Start the queue-wait clock; a retry delay does not count as waiting.
+    async fn mark_queued(&self, job_id: Uuid) {
+        let mut state = self.app_state.write().await;
+        if let Some(job_info) = state.jobs.iter_mut().find(|j| j.id == job_id) {
+            job_info.timing.mark_queued();
+        }
     }
 
     pub async fn is_idle(&self) -> bool {
-        self.queue.lock().await.is_empty() && self.busy.load(Ordering::SeqCst) == 0
+        self.queue.lock().await.is_empty()
+            && self.busy.load(Ordering::SeqCst) == 0
+            && self.delayed.load(Ordering::SeqCst) == 0
     }
 
-    pub async fn kill_all_jobs(&self) {
-        let jobs = self.running_jobs.lock().await;
-        for (job_id, pid) in jobs.iter() {
-            println!("[gparallel] Killing job {} (PID {})", job_id, pid);
-            // Use nix to send SIGTERM to the process
-            if let Err(e) = nix::sys::signal::kill(
-                nix::unistd::Pid::from_raw(*pid as i32),
-                nix::sys::signal::Signal::SIGTERM,
-            ) {
-                eprintln!("[gparallel] Failed to kill job {}: {}", job_id, e);
-            }
+    /// Start no more jobs: queued ones stay in the queue and running ones
+    /// carry on. False if the scheduler was already draining.
+    pub fn drain(&self) -> bool {
+        !self.draining.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Hold queued jobs in the queue, or let them start again. Running jobs
+    /// carry on either way.
+    pub async fn set_paused(&self, paused: bool) {
+        if self.paused.swap(paused, Ordering::SeqCst) == paused {
+            return;
+        }
+        self.app_state.write().await.paused = paused;
+        if paused {
+            info!("Queue paused");
+        } else {
+            info!("Queue resumed");
+            self.dispatch_free().await;
+        }
+    }
+
+    /// Whether queued jobs are held back, by `drain` or `set_paused`.
+    fn holding(&self) -> bool {
+        self.draining.load(Ordering::SeqCst) || self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Wait, after `drain`, until no job runs and none is waiting out a
+    /// retry delay.
+    pub async fn drained(&self) {
+        while self.busy.load(Ordering::SeqCst) > 0 || self.delayed.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
         }
+    }
 
-        // Give processes a moment to terminate gracefully
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    /// Stop everything: cancel the token hierarchy so workers terminate their
+    /// running jobs (SIGTERM, then SIGKILL) and stop dispatching, then wait
+    /// for every spawned task to finish. MPS daemons go last, once their
+    /// clients are gone.
+    pub async fn shutdown(&self) {
+        self.cancel.cancel();
+        self.tasks.close();
+        self.tasks.wait().await;
+        self.cuda_mps.stop().await;
+    }
 
-        // Force kill any remaining processes
-        let jobs = self.running_jobs.lock().await;
-        for (job_id, pid) in jobs.iter() {
-            if let Err(e) = nix::sys::signal::kill(
-                nix::unistd::Pid::from_raw(*pid as i32),
-                nix::sys::signal::Signal::SIGKILL,
-            ) {
-                // Process might have already terminated
-                if e != nix::errno::Errno::ESRCH {
-                    eprintln!("[gparallel] Failed to force kill job {}: {}", job_id, e);
-                }
-            }
+    /// Let go of the MPS daemons when we exit with jobs still running,
+    /// which may be their clients: see `CudaMps::release`.
+    pub async fn release_cuda_mps(&self) {
+        self.cuda_mps.release().await;
+    }
+}
+
+/// Queue `job` ahead of the jobs of a lower priority, behind all others.
+fn queue_by_priority(queue: &mut VecDeque<JobSpec>, job: JobSpec) {
+    let at = queue
+        .iter()
+        .position(|queued| queued.priority < job.priority)
+        .unwrap_or(queue.len());
+    queue.insert(at, job);
+}
+
+/// Take the job out of the queue, if it is waiting there.
+fn take_queued(queue: &mut VecDeque<JobSpec>, job_id: Uuid) -> Result<JobSpec> {
+    let position = queue.iter().position(|job| job.id == job_id);
+    match position.and_then(|i| queue.remove(i)) {
+        Some(job) => Ok(job),
+        None => anyhow::bail!("job {} is not waiting in the queue", job_id),
+    }
+}
+
+/// Variables that survive `--clean-env`: enough to find executables, libraries
+/// and the GPU stack.
+fn keep_in_clean_env(key: &str) -> bool {
+    const KEEP: [&str; 8] = [
+        "PATH",
+        "HOME",
+        "USER",
+        "LOGNAME",
+        "SHELL",
+        "LANG",
+        "TMPDIR",
+        "LD_LIBRARY_PATH",
+    ];
+    KEEP.contains(&key)
+        || key.starts_with("LC_")
+        || key.starts_with("CUDA_")
+        || key.starts_with("NVIDIA_")
+}
+
+/// Send `signal` to the child, then SIGKILL it if it is still around after
+/// `grace`.
+async fn terminate_child(
+    child: &mut Child,
+    signal: Signal,
+    grace: Duration,
+) -> std::io::Result<ExitStatus> {
+    if let Some(pid) = child.id() {
+        nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid as i32), signal).ok();
+        if let Ok(status) = tokio::time::timeout(grace, child.wait()).await {
+            return status;
         }
     }
+    child.kill().await.ok();
+    child.wait().await
 }
 
 // ------------------------------------------------
 // GPU detection helpers
 // ------------------------------------------------
-async fn detect_gpus_with_info() -> Result<(Vec<u32>, Vec<String>)> {
+/// This machine's NVML, initialized once and shared by detection, the
+/// monitor and everything that checks or sets a GPU: initializing it loads
+/// the driver library and queries every device, which is slow and now and
+/// then fails on a loaded machine.
+pub fn init_nvml() -> Option<Arc<Nvml>> {
+    if mock_gpu_count().is_some() {
+        return None;
+    }
+    match Nvml::init() {
+        Ok(nvml) => Some(Arc::new(nvml)),
+        Err(e) => {
+            debug!("NVML unavailable: {}", e);
+            None
+        }
+    }
+}
+
+pub async fn detect_gpus_with_info(nvml: Option<&Nvml>) -> Result<(Vec<u32>, Vec<String>)> {
+    // Fake devices for tests and machines without a GPU
+    if let Some(count) = mock_gpu_count() {
+        let ids: Vec<u32> = (0..count).collect();
+        let names = ids.iter().map(|i| format!("Mock{}", i)).collect();
+        return Ok((ids, names));
+    }
+
+    if let Some(name) = apple::detect().await {
+        return Ok((vec![0], vec![name]));
+    }
+
     if let Ok(list) = env::var("CUDA_VISIBLE_DEVICES") {
         let ids: Vec<u32> = list
             .split(',')
@@ -413,13 +2436,13 @@ async fn detect_gpus_with_info() -> Result<(Vec<u32>, Vec<String>)> {
     }
 
     // Try NVML first for better GPU info
-    if let Ok(nvml) = nvml_wrapper::Nvml::init() {
+    if let Some(nvml) = nvml {
         if let Ok(count) = nvml.device_count() {
             if count > 0 {
                 let mut ids = Vec::new();
                 let mut names = Vec::new();
                 for i in 0..count {
-                    ids.push(i as u32);
+                    ids.push(i);
                     if let Ok(device) = nvml.device_by_index(i) {
                         if let Ok(name) = device.name() {
                             names.push(name);
@@ -442,20 +2465,20 @@ async fn detect_gpus_with_info() -> Result<(Vec<u32>, Vec<String>)> {
             let mut ids = Vec::new();
             let mut names = Vec::new();
 
-            for (i, line) in output.lines().enumerate() {
-                if line.contains("GPU") {
-                    ids.push(i as u32);
-                    // Try to parse GPU name from line like "GPU 0: NVIDIA GeForce RTX 4090 (UUID: ...)"
-                    if let Some(start) = line.find(':') {
-                        if let Some(end) = line.find('(') {
-                            let name = line[start + 1..end].trim();
-                            names.push(name.to_string());
-                        } else {
-                            names.push(format!("GPU{}", i));
-                        }
+            // MIG instances are listed under their GPU, indented
+            let gpu_lines = output.lines().filter(|line| line.starts_with("GPU "));
+            for (i, line) in gpu_lines.enumerate() {
+                ids.push(i as u32);
+                // Try to parse GPU name from line like "GPU 0: NVIDIA GeForce RTX 4090 (UUID: ...)"
+                if let Some(start) = line.find(':') {
+                    if let Some(end) = line.find('(') {
+                        let name = line[start + 1..end].trim();
+                        names.push(name.to_string());
                     } else {
                         names.push(format!("GPU{}", i));
                     }
+                } else {
+                    names.push(format!("GPU{}", i));
                 }
             }
 
@@ -465,20 +2488,513 @@ async fn detect_gpus_with_info() -> Result<(Vec<u32>, Vec<String>)> {
         }
     }
 
-    eprintln!("[gparallel] WARN: cannot detect GPUs → use GPU0 only");
+    warn!("cannot detect GPUs → use GPU0 only");
     Ok((vec![0], vec!["GPU0".to_string()]))
 }
 
-async fn update_gpu_memory_info(app_state: &Arc<RwLock<AppState>>) {
-    if let Ok(nvml) = nvml_wrapper::Nvml::init() {
-        let mut state = app_state.write().await;
-        for gpu_info in state.gpus.iter_mut() {
-            if let Ok(device) = nvml.device_by_index(gpu_info.id) {
-                if let Ok(mem_info) = device.memory_info() {
-                    gpu_info.free_memory_mb = mem_info.free / (1024 * 1024);
-                    gpu_info.total_memory_mb = mem_info.total / (1024 * 1024);
+/// Put each MIG instance in place of the GPU it is carved from, with an id
+/// after every other GPU's. Instances of GPUs not in `gpus` are left out.
+fn replace_mig_parents(
+    gpus: &mut Vec<u32>,
+    names: &mut Vec<String>,
+    uuids: &mut HashMap<u32, String>,
+    instances: Vec<MigDevice>,
+) -> HashMap<u32, MigDevice> {
+    let parent_names: HashMap<u32, String> =
+        gpus.iter().copied().zip(names.iter().cloned()).collect();
+    let mut next_id = gpus.iter().max().map_or(0, |max| max + 1);
+    let mut mig_devices = HashMap::new();
+    for mig in instances {
+        let Some(parent_name) = parent_names.get(&mig.parent) else {
+            continue;
+        };
+        if let Some(at) = gpus.iter().position(|&id| id == mig.parent) {
+            gpus.remove(at);
+            names.remove(at);
+            uuids.remove(&mig.parent);
+        }
+        gpus.push(next_id);
+        names.push(mig.name(parent_name));
+        uuids.insert(next_id, mig.uuid.clone());
+        mig_devices.insert(next_id, mig);
+        next_id += 1;
+    }
+    mig_devices
+}
+
+/// The GPUs a re-scan `found` that are new to the pool, and the `pooled`
+/// ones it no longer has, both by index with their UUIDs. A GPU whose
+/// index now holds another card, going by UUID where both are known, is
+/// both: it leaves and the new card takes its place.
+fn compare_gpus(
+    pooled: &HashMap<u32, Option<String>>,
+    found: &HashMap<u32, Option<String>>,
+) -> (Vec<u32>, Vec<u32>) {
+    let mut removed: Vec<u32> = pooled
+        .iter()
+        .filter(|(id, uuid)| match (uuid, found.get(id)) {
+            (_, None) => true,
+            (Some(pooled), Some(Some(found))) => pooled != found,
+            _ => false,
+        })
+        .map(|(id, _)| *id)
+        .collect();
+    let mut added: Vec<u32> = found
+        .keys()
+        .filter(|id| !pooled.contains_key(id) || removed.contains(id))
+        .copied()
+        .collect();
+    added.sort();
+    removed.sort();
+    (added, removed)
+}
+
+/// Number of fake GPUs requested through `GPARALLEL_MOCK_GPUS`, if any.
+fn mock_gpu_count() -> Option<u32> {
+    env::var("GPARALLEL_MOCK_GPUS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|n| *n > 0)
+}
+
+/// Resolve device UUIDs for the given indices, via NVML or `nvidia-smi -L`.
+pub async fn lookup_gpu_uuids(ids: &[u32], nvml: Option<&Nvml>) -> HashMap<u32, String> {
+    let mut uuids = HashMap::new();
+
+    if mock_gpu_count().is_some() {
+        for id in ids {
+            uuids.insert(*id, format!("GPU-mock-{}", id));
+        }
+        return uuids;
+    }
+
+    if let Some(nvml) = nvml {
+        for id in ids {
+            if let Ok(uuid) = nvml.device_by_index(*id).and_then(|d| d.uuid()) {
+                uuids.insert(*id, uuid);
+            }
+        }
+        return uuids;
+    }
+
+    // Fallback: lines look like "GPU 0: NVIDIA GeForce RTX 4090 (UUID: GPU-...)"
+    if let Ok(out) = Command::new("nvidia-smi").arg("-L").output().await {
+        if out.status.success() {
+            let output = String::from_utf8_lossy(&out.stdout);
+            let gpu_lines = output.lines().filter(|line| line.starts_with("GPU "));
+            for (i, line) in gpu_lines.enumerate() {
+                if let Some(start) = line.find("UUID: ") {
+                    let uuid = line[start + 6..].trim_end_matches(')').trim();
+                    if ids.contains(&(i as u32)) {
+                        uuids.insert(i as u32, uuid.to_string());
+                    }
                 }
             }
         }
     }
+    uuids
+}
+
+/// Refresh each GPU's memory, utilization, temperature, health and compute
+/// processes, with their history, and each running job's peak usage.
+async fn update_gpu_memory_info(
+    app_state: &Arc<RwLock<AppState>>,
+    running_jobs: &Mutex<HashMap<Uuid, u32>>,
+    nvml: &std::sync::RwLock<Option<Arc<Nvml>>>,
+    remote_gpus: &std::sync::RwLock<HashMap<u32, RemoteGpu>>,
+    mig_devices: &Arc<HashMap<u32, MigDevice>>,
+    faults: &std::sync::RwLock<HashMap<u32, Fault>>,
+    xids: &XidLog,
+) {
+    if mock_gpu_count().is_some() {
+        return;
+    }
+    // An Apple GPU has the machine's memory, and no per-process usage
+    if let Some((free_mb, total_mb)) = apple::memory_mb().await {
+        let remote: HashSet<u32> = remote_gpus.read().unwrap().keys().copied().collect();
+        let mut state = app_state.write().await;
+        for gpu_info in state.gpus.iter_mut().filter(|g| !remote.contains(&g.id)) {
+            gpu_info.free_memory_mb = free_mb;
+            gpu_info.total_memory_mb = total_mb;
+            gpu_info.record_sample();
+        }
+        return;
+    }
+    let Some(nvml) = nvml.read().unwrap().clone() else {
+        return;
+    };
+    // NVML only sees this machine's GPUs
+    let ids: Vec<u32> = app_state.read().await.gpus.iter().map(|g| g.id).collect();
+    let ids: Vec<u32> = {
+        let remote_gpus = remote_gpus.read().unwrap();
+        ids.into_iter()
+            .filter(|id| !remote_gpus.contains_key(id))
+            .collect()
+    };
+    let (mig, log) = (mig_devices.clone(), xids.clone());
+    let Ok(samples) =
+        tokio::task::spawn_blocking(move || sample_gpus(&nvml, &ids, &mig, &log)).await
+    else {
+        return;
+    };
+    let roots: HashMap<u32, Uuid> = running_jobs
+        .lock()
+        .await
+        .iter()
+        .map(|(job_id, pid)| (*pid, *job_id))
+        .collect();
+
+    let mut state = app_state.write().await;
+    // Memory per job and GPU in this sample, summed over its processes
+    let mut usage: HashMap<Uuid, BTreeMap<u32, u64>> = HashMap::new();
+    for sample in samples {
+        let Some(gpu_info) = state.gpus.iter_mut().find(|g| g.id == sample.id) else {
+            continue;
+        };
+        if let Some(fault) = sample.fault {
+            quarantine(faults, gpu_info, fault);
+        }
+        gpu_info.temperature = sample.temperature;
+        if let Some((free_mb, total_mb)) = sample.memory_mb {
+            gpu_info.free_memory_mb = free_mb;
+            gpu_info.total_memory_mb = total_mb;
+        }
+        gpu_info.utilization = sample.utilization;
+        gpu_info.record_sample();
+        gpu_info.processes = sample
+            .processes
+            .into_iter()
+            .map(|(pid, memory_mb)| GpuProcess {
+                pid,
+                memory_mb,
+                job: owning_job(pid, &roots),
+            })
+            .collect();
+        for process in &gpu_info.processes {
+            if let (Some(job_id), Some(mb)) = (process.job, process.memory_mb) {
+                *usage
+                    .entry(job_id)
+                    .or_default()
+                    .entry(gpu_info.id)
+                    .or_default() += mb;
+            }
+        }
+    }
+    for job in state.jobs.iter_mut() {
+        job.gpu_memory_mb = usage.remove(&job.id).unwrap_or_default();
+        if !job.gpu_memory_mb.is_empty() {
+            let mb = job.gpu_memory_mb.values().sum();
+            job.peak_gpu_memory_mb = Some(job.peak_gpu_memory_mb.unwrap_or(0).max(mb));
+        }
+    }
+}
+
+/// What the monitor reads through NVML for one local GPU.
+struct GpuSample {
+    id: u32,
+    fault: Option<Fault>,
+    temperature: Option<u32>,
+    /// Free and total memory
+    memory_mb: Option<(u64, u64)>,
+    utilization: Option<u32>,
+    /// Compute processes and the memory each holds, where NVML can tell
+    processes: Vec<(u32, Option<u64>)>,
+}
+
+/// Read the local GPUs `ids` through NVML, without holding any lock while
+/// it blocks. MIG instances go by their parent's health and temperature,
+/// and their own memory and processes.
+fn sample_gpus(
+    nvml: &Nvml,
+    ids: &[u32],
+    mig_devices: &HashMap<u32, MigDevice>,
+    xids: &XidLog,
+) -> Vec<GpuSample> {
+    ids.iter()
+        .map(|&id| {
+            let index = mig_devices.get(&id).map_or(id, |m| m.parent);
+            let device = nvml.device_by_index(index).ok();
+            let own = match mig_devices.get(&id) {
+                Some(mig) => mig.device(nvml),
+                None => nvml.device_by_index(id).ok(),
+            };
+            let own = own.as_ref();
+            GpuSample {
+                id,
+                fault: health::check(nvml, index, xids),
+                temperature: device
+                    .as_ref()
+                    .and_then(|d| d.temperature(TemperatureSensor::Gpu).ok()),
+                memory_mb: own
+                    .and_then(|d| d.memory_info().ok())
+                    .map(|m| (m.free / (1024 * 1024), m.total / (1024 * 1024))),
+                utilization: own.and_then(|d| d.utilization_rates().ok()).map(|u| u.gpu),
+                processes: own
+                    .and_then(|d| d.running_compute_processes().ok())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|process| match process.used_gpu_memory {
+                        UsedGpuMemory::Used(bytes) => (process.pid, Some(bytes / (1024 * 1024))),
+                        UsedGpuMemory::Unavailable => (process.pid, None),
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Give `gpu` no more jobs, showing `fault` as the reason. The places it
+/// has in the pool are dropped as they come up.
+fn quarantine(faults: &std::sync::RwLock<HashMap<u32, Fault>>, gpu: &mut GpuInfo, fault: Fault) {
+    if gpu.fault.is_none() {
+        warn!(gpu = gpu.id, "Quarantining GPU {}: {}", gpu.name, fault);
+    }
+    gpu.fault = Some(fault.to_string());
+    faults.write().unwrap().insert(gpu.id, fault);
+}
+
+/// "PID 4242 (bob), PID 4250 (carol)", with the user of each where known.
+fn describe_processes(pids: &[u32]) -> String {
+    use std::os::unix::fs::MetadataExt;
+    pids.iter()
+        .map(|pid| {
+            let user = std::fs::metadata(format!("/proc/{}", pid))
+                .ok()
+                .and_then(|meta| {
+                    nix::unistd::User::from_uid(nix::unistd::Uid::from_raw(meta.uid())).ok()?
+                })
+                .map(|user| format!(" ({})", user.name))
+                .unwrap_or_default();
+            format!("PID {}{}", pid, user)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The job whose process tree contains `pid`, found by walking up the
+/// parent chain until one of the job root PIDs is reached.
+fn owning_job(mut pid: u32, roots: &HashMap<u32, Uuid>) -> Option<Uuid> {
+    // Bounded in case of a /proc race producing a cycle
+    for _ in 0..64 {
+        if let Some(job_id) = roots.get(&pid) {
+            return Some(*job_id);
+        }
+        pid = parent_pid(pid)?;
+        if pid <= 1 {
+            return None;
+        }
+    }
+    None
+}
+
+/// When `pid` started, in clock ticks since boot (field 22 of its stat).
+pub fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Whether the process is still the one that was recorded, and not a later
+/// one that reused its PID.
+pub fn is_running(process: &JobProcess) -> bool {
+    process_start_time(process.pid) == Some(process.start_time)
+}
+
+/// Wait for a process that is not our child to exit: through a pidfd, or by
+/// polling on kernels without one (before 5.3).
+async fn wait_for_exit(process: &JobProcess) {
+    match open_pidfd(process.pid) {
+        Ok(pidfd) => {
+            // Readable once the process has exited
+            pidfd.readable().await.ok();
+        }
+        Err(_) => {
+            while is_running(process) {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_pidfd(pid: u32) -> std::io::Result<AsyncFd<OwnedFd>> {
+    use nix::libc;
+    use std::os::fd::{FromRawFd, RawFd};
+    // SAFETY: pidfd_open takes no pointers, and a non-negative result is a
+    // new file descriptor that nothing else owns
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    AsyncFd::new(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Only Linux has pidfds; elsewhere `wait_for_exit` polls.
+#[cfg(not(target_os = "linux"))]
+fn open_pidfd(_pid: u32) -> std::io::Result<AsyncFd<OwnedFd>> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces or parentheses; fields resume
+    // after the last ')': state, then ppid
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walks_process_tree_to_job_root() {
+        let me = std::process::id();
+        assert_eq!(parent_pid(me), Some(std::os::unix::process::parent_id()));
+
+        let job_id = Uuid::new_v4();
+        let roots = HashMap::from([(std::os::unix::process::parent_id(), job_id)]);
+        assert_eq!(owning_job(me, &roots), Some(job_id));
+        assert_eq!(owning_job(me, &HashMap::new()), None);
+    }
+
+    #[test]
+    fn higher_priorities_queue_ahead_of_lower_ones() {
+        let job = |cmd: &str, priority| JobSpec {
+            id: Uuid::new_v4(),
+            cmd: cmd.to_string(),
+            env: BTreeMap::new(),
+            retries: 0,
+            output_dir: None,
+            gpus: 1,
+            mem_mb: None,
+            power_limit_mw: None,
+            owner: None,
+            priority,
+            cancel: CancellationToken::new(),
+        };
+        let mut queue = VecDeque::new();
+        queue_by_priority(&mut queue, job("a", Priority::Normal));
+        queue_by_priority(&mut queue, job("b", Priority::Low));
+        queue_by_priority(&mut queue, job("c", Priority::High));
+        queue_by_priority(&mut queue, job("d", Priority::Normal));
+        queue_by_priority(&mut queue, job("e", Priority::High));
+
+        let order: Vec<&str> = queue.iter().map(|job| job.cmd.as_str()).collect();
+        assert_eq!(order, ["c", "e", "a", "d", "b"]);
+
+        let low = queue[4].id;
+        let moved = take_queued(&mut queue, low).unwrap();
+        queue.push_front(moved);
+        assert_eq!(queue[0].cmd, "b");
+        assert!(take_queued(&mut queue, Uuid::nil()).is_err());
+    }
+
+    #[test]
+    fn mig_instances_take_the_place_of_their_gpu() {
+        let mig = |parent, index| MigDevice {
+            parent,
+            index,
+            profile: "3g.20gb".to_string(),
+            uuid: format!("MIG-{}-{}", parent, index),
+        };
+        let mut gpus = vec![0, 1];
+        let mut names = vec!["A100".to_string(), "A100".to_string()];
+        let mut uuids = HashMap::from([(0, "GPU-a".to_string()), (1, "GPU-b".to_string())]);
+
+        let instances = vec![mig(1, 0), mig(1, 1), mig(7, 0)];
+        let devices = replace_mig_parents(&mut gpus, &mut names, &mut uuids, instances);
+
+        assert_eq!(gpus, [0, 2, 3]);
+        assert_eq!(names[1], "A100 MIG 3g.20gb #0");
+        assert_eq!(uuids.get(&3).map(String::as_str), Some("MIG-1-1"));
+        assert!(!uuids.contains_key(&1));
+        assert_eq!(devices[&2].parent, 1);
+        assert_eq!(devices.len(), 2);
+    }
+
+    #[test]
+    fn rescans_add_new_gpus_and_drop_vanished_ones() {
+        let uuid = |s: &str| Some(s.to_string());
+        let pooled = HashMap::from([(0, uuid("GPU-a")), (1, uuid("GPU-b")), (2, None)]);
+        // GPU 1 was swapped for another card, GPU 2 is gone and 3 is new;
+        // GPU 0 answers without its UUID, as a GPU off the bus does
+        let found = HashMap::from([(0, None), (1, uuid("GPU-c")), (3, uuid("GPU-d"))]);
+
+        assert_eq!(compare_gpus(&pooled, &found), (vec![1, 3], vec![1, 2]));
+        assert_eq!(compare_gpus(&pooled, &pooled), (vec![], vec![]));
+    }
+
+    #[test]
+    fn hot_gpus_cool_down_to_the_resume_point() {
+        let limit = TempLimit::new(85, None).unwrap();
+        assert_eq!(limit.resume, 75);
+        let mut cooling = HashSet::new();
+
+        limit.update(&mut cooling, 0, 84);
+        assert!(cooling.is_empty());
+        limit.update(&mut cooling, 0, 86);
+        limit.update(&mut cooling, 0, 80);
+        assert!(cooling.contains(&0));
+        limit.update(&mut cooling, 0, 75);
+        assert!(cooling.is_empty());
+        assert!(TempLimit::new(80, Some(80)).is_err());
+    }
+
+    #[test]
+    fn later_jobs_wait_for_a_gpu_that_is_still_cooling_down() {
+        let limit = TempLimit::new(85, Some(75)).unwrap();
+        let at = |temp| {
+            vec![GpuInfo {
+                temperature: Some(temp),
+                ..GpuInfo::new(0, "Mock0")
+            }]
+        };
+        let mut cooling = HashSet::new();
+
+        // The monitor saw GPU 0 reach the limit while a job waited ...
+        note_temperatures(&mut cooling, Some(limit), &at(86));
+        note_temperatures(&mut cooling, Some(limit), &at(80));
+        assert_eq!(cooling_gpu(&cooling, &at(80), &[0]), Some((0, 80)));
+        // ... so a job arriving at 80°C waits as well
+        assert_eq!(cooling_gpu(&cooling, &at(80), &[0, 1]), Some((0, 80)));
+        assert_eq!(cooling_gpu(&cooling, &at(80), &[1]), None);
+
+        note_temperatures(&mut cooling, Some(limit), &at(75));
+        assert_eq!(cooling_gpu(&cooling, &at(80), &[0]), None);
+        note_temperatures(&mut cooling, Some(limit), &at(86));
+        note_temperatures(&mut cooling, None, &at(86));
+        assert!(cooling.is_empty());
+    }
+
+    #[test]
+    fn jobs_asking_for_more_memory_than_a_gpu_has_do_not_fit() {
+        let gpus = vec![
+            GpuInfo {
+                free_memory_mb: 20000,
+                total_memory_mb: 24000,
+                ..GpuInfo::new(0, "Mock0")
+            },
+            GpuInfo::new(1, "Mock1"),
+        ];
+
+        assert_eq!(too_small(&gpus, &[0], 30000).map(|g| g.id), Some(0));
+        // Short of free memory only, so it waits instead
+        assert!(too_small(&gpus, &[0], 22000).is_none());
+        assert!(too_small(&gpus, &[1], 30000).is_none());
+        assert!(too_small(&gpus, &[1], u64::MAX).is_none());
+    }
+
+    #[test]
+    fn gpus_are_picked_by_index_or_uuid() {
+        let by_uuid = GpuSelector::parse("GPU-5d5ba0d6").unwrap();
+        assert!(by_uuid.matches(3, Some("gpu-5D5BA0D6")));
+        assert!(!by_uuid.matches(3, Some("GPU-7c2e9f41")));
+        assert!(!by_uuid.matches(3, None));
+        assert!(GpuSelector::parse(" 2")
+            .unwrap()
+            .matches(2, Some("GPU-5d5ba0d6")));
+        assert!(GpuSelector::parse("MIG-6e4275af").is_ok());
+        assert!(GpuSelector::parse("first").is_err());
+    }
 }
@@ -0,0 +1,954 @@
+//! `gparallel serve`: a long-lived scheduler that owns the GPU pool and
+//! takes jobs from clients over a Unix socket (see `protocol`).
+
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    io::BufReader,
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener, UnixStream},
+    signal::unix::{signal, SignalKind},
+    sync::{
+        broadcast::{self, error::RecvError},
+        RwLock,
+    },
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::auth;
+use crate::cluster;
+use crate::duration::humanize;
+use crate::history::History;
+use crate::http;
+use crate::jobfile::JobRequest;
+use crate::protocol::{self, CancelTarget, QueueEnd, Request, Response};
+use crate::report::JobRecord;
+use crate::retry::RetryPolicy;
+use crate::scheduler::{
+    self, GpuSelector, JobEvent, OutputMode, Scheduler, SchedulerOptions, Tunables,
+};
+use crate::settings::{Settings, SettingsSource};
+use crate::shell::Shell;
+use crate::store::Store;
+use crate::systemd;
+use crate::ui::{AppState, JobInfo, JobState};
+
+pub struct ServeOptions {
+    pub socket: PathBuf,
+    /// Permission bits for the socket, e.g. 0o660 to let a group in
+    pub socket_mode: Option<u32>,
+    /// Group to give the socket to
+    pub socket_group: Option<String>,
+    /// Require the token in this file (created if missing) from every client
+    pub token_file: Option<PathBuf>,
+    /// Also take clients over TCP here; they always need the token
+    pub listen: Option<SocketAddr>,
+    /// Also serve HTTP here
+    pub http: Option<SocketAddr>,
+    /// Keep jobs in this database across restarts
+    pub db: Option<PathBuf>,
+    /// GPUs one user's jobs may hold at once
+    pub max_gpus_per_user: Option<u32>,
+    /// Jobs one user may have waiting in the queue
+    pub max_queued_per_user: Option<usize>,
+    /// Settings file read at startup and again on SIGHUP or `reload`, over
+    /// the ones above
+    pub config: Option<PathBuf>,
+    /// Add every finished job here
+    pub history: Option<History>,
+    /// Take agents here and run jobs on their GPUs too
+    pub agents: Option<SocketAddr>,
+    /// Run jobs on this machine's GPUs
+    pub local_gpus: bool,
+    /// Only these of them, unless the config file says otherwise
+    pub gpus: Option<Vec<GpuSelector>>,
+    /// Start a CUDA MPS daemon for each of this machine's GPUs
+    pub cuda_mps: bool,
+    /// Keep jobs off GPUs that other processes are using
+    pub yield_gpus: bool,
+    /// Detect this machine's GPUs again this often
+    pub rescan_every: Option<Duration>,
+}
+
+pub async fn run(options: ServeOptions) -> Result<()> {
+    let socket = options.socket.as_path();
+    let token = match &options.token_file {
+        Some(path) => {
+            let token = auth::load_or_create(path)?;
+            info!("Clients need the token in {}", path.display());
+            Some(Arc::from(token))
+        }
+        None => None,
+    };
+    // Under socket activation systemd owns the socket and its permissions
+    let mut passed = systemd::listen_fds();
+    if passed.len() > 1 {
+        warn!("Only the first socket systemd passed is used");
+    }
+    let activated = !passed.is_empty();
+    let listener = match passed.drain(..).next() {
+        Some(fd) => {
+            let listener = std::os::unix::net::UnixListener::from(fd);
+            listener.set_nonblocking(true)?;
+            UnixListener::from_std(listener)?
+        }
+        None => {
+            let listener = bind(socket).await?;
+            set_socket_access(socket, options.socket_mode, options.socket_group.as_deref())?;
+            listener
+        }
+    };
+    let socket = match listener.local_addr()?.as_pathname() {
+        Some(path) if activated => path.to_path_buf(),
+        _ => socket.to_path_buf(),
+    };
+    let http_listener = match options.http {
+        Some(addr) => Some(http::bind(addr).await?),
+        None => None,
+    };
+    let tcp_listener = match options.listen {
+        Some(addr) if token.is_none() => {
+            anyhow::bail!(
+                "--listen {} needs --token-file, so strangers cannot use the daemon",
+                addr
+            )
+        }
+        Some(addr) => Some(
+            TcpListener::bind(addr)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", addr, e))?,
+        ),
+        None => None,
+    };
+    let agent_listener = match options.agents {
+        // Agents are sent every job's command and environment
+        Some(addr) if token.is_none() => {
+            anyhow::bail!(
+                "--agents {} needs --token-file, so strangers cannot join as agents",
+                addr
+            )
+        }
+        Some(addr) => Some(
+            TcpListener::bind(addr)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to listen for agents on {}: {}", addr, e))?,
+        ),
+        None => None,
+    };
+    let store = match &options.db {
+        Some(path) => Some(Store::open(path)?),
+        None => None,
+    };
+
+    let source = SettingsSource {
+        base: Settings {
+            tunables: Tunables {
+                gpus: options.gpus.clone(),
+                user_gpu_limit: options.max_gpus_per_user,
+                ..Default::default()
+            },
+            max_queued_per_user: options.max_queued_per_user,
+        },
+        path: options.config.clone(),
+    };
+    let settings = source.load()?;
+
+    let app_state = Arc::new(RwLock::new(AppState::new()));
+    // Job output is kept in the app state for clients, never on our terminal
+    let sched_options = SchedulerOptions {
+        output: OutputMode::Capture,
+        max_runtime: None,
+        stop_signal: nix::sys::signal::Signal::SIGTERM,
+        shell: Shell::Bash,
+        env: Vec::new(),
+        clean_env: false,
+        retry: RetryPolicy::default(),
+        pre_cmd: None,
+        output_dir: None,
+        run_in_output_dir: false,
+        tunables: settings.tunables.clone(),
+        sshlogin: Vec::new(),
+        ssh: String::new(),
+        local_gpus: options.local_gpus,
+        agents: options.agents.is_some(),
+        history: options.history.clone(),
+        cuda_mps: options.cuda_mps,
+        yield_gpus: options.yield_gpus,
+        rescan_every: options.rescan_every,
+    };
+    let sched = Scheduler::new(app_state.clone(), sched_options).await?;
+    let daemon = Daemon {
+        sched: sched.clone(),
+        state: app_state,
+        token: token.clone(),
+        settings: Arc::new(std::sync::RwLock::new(settings)),
+        source: Arc::new(source),
+        stop: CancellationToken::new(),
+        persistent: store.is_some(),
+        user: user_name(nix::unistd::getuid().as_raw()),
+    };
+    let mut persisting = None;
+    if let Some(store) = &store {
+        // Subscribed first, so nothing the restored jobs do goes unsaved
+        let events = sched.subscribe();
+        daemon.restore(store, options.history.as_ref()).await?;
+        persisting = Some(tokio::spawn(daemon.clone().persist(store.clone(), events)));
+    }
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+    info!("Listening on {}", socket.display());
+    if let Some(listener) = http_listener {
+        tokio::spawn(http::serve(listener, daemon.clone()));
+    }
+    if let Some(listener) = tcp_listener {
+        info!("Listening on {}", listener.local_addr()?);
+        tokio::spawn(daemon.clone().serve_tcp(listener));
+    }
+    if let Some(listener) = agent_listener {
+        info!("Taking agents on {}", listener.local_addr()?);
+        tokio::spawn(cluster::serve(listener, sched.clone(), token));
+    }
+    systemd::notify("READY=1");
+    if let Some(interval) = systemd::watchdog_interval() {
+        // Pinged from the runtime that serves clients, so a wedged daemon
+        // gets restarted
+        tokio::spawn(async move {
+            loop {
+                systemd::notify("WATCHDOG=1");
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(daemon.clone().serve_client(stream));
+                }
+                Err(e) => warn!("Failed to accept connection: {}", e),
+            },
+            _ = tokio::signal::ctrl_c() => break,
+            _ = sigterm.recv() => break,
+            _ = daemon.stop.cancelled() => break,
+            _ = sighup.recv() => {
+                if let Err(e) = daemon.reload().await {
+                    warn!("Failed to reload settings: {}", e);
+                }
+            }
+        }
+    }
+
+    info!("Shutting down, terminating running jobs...");
+    systemd::notify("STOPPING=1");
+    drop(listener);
+    // An activated socket stays for systemd to start us again on
+    if !activated {
+        std::fs::remove_file(&socket).ok();
+    }
+    sched.shutdown().await;
+    // Events it has yet to save are older than what save_all writes
+    if let Some(persisting) = persisting {
+        persisting.abort();
+        persisting.await.ok();
+    }
+    if let Some(store) = &store {
+        if let Err(e) = daemon.save_all(store).await {
+            warn!("Failed to save job state: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Bind the socket, replacing a stale one left by a daemon that died, but
+/// never one a live daemon is still listening on.
+async fn bind(socket: &Path) -> Result<UnixListener> {
+    if socket.exists() {
+        if UnixStream::connect(socket).await.is_ok() {
+            anyhow::bail!("A daemon is already listening on {}", socket.display());
+        }
+        std::fs::remove_file(socket).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to remove stale socket '{}': {}",
+                socket.display(),
+                e
+            )
+        })?;
+    }
+    UnixListener::bind(socket)
+        .map_err(|e| anyhow::anyhow!("Failed to listen on '{}': {}", socket.display(), e))
+}
+
+/// Who may use the socket: its permission bits and group. Without either,
+/// the umask decides.
+fn set_socket_access(socket: &Path, mode: Option<u32>, group: Option<&str>) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if let Some(name) = group {
+        let group = nix::unistd::Group::from_name(name)?
+            .ok_or_else(|| anyhow::anyhow!("No group named '{}'", name))?;
+        nix::unistd::chown(socket, None, Some(group.gid)).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to give '{}' to group {}: {}",
+                socket.display(),
+                name,
+                e
+            )
+        })?;
+    }
+    if let Some(mode) = mode {
+        std::fs::set_permissions(socket, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// What every client connection gets to work with.
+#[derive(Clone)]
+pub struct Daemon {
+    pub sched: Scheduler,
+    pub state: Arc<RwLock<AppState>>,
+    /// What clients must present, if anything
+    pub token: Option<Arc<str>>,
+    /// What the config file last said, over the command line
+    pub settings: Arc<std::sync::RwLock<Settings>>,
+    source: Arc<SettingsSource>,
+    /// Cancelled once a drain has finished, to make the daemon exit
+    stop: CancellationToken,
+    /// Whether jobs are kept in a database, so queued ones outlive us
+    persistent: bool,
+    /// The user the daemon runs as, who like root may change anyone's jobs
+    /// and the daemon itself
+    user: Option<String>,
+}
+
+impl Daemon {
+    /// Read the config file again and apply it. On error the daemon keeps
+    /// its settings.
+    pub async fn reload(&self) -> Result<()> {
+        let Some(path) = &self.source.path else {
+            anyhow::bail!("the daemon has no --config file to reload");
+        };
+        let settings = self.source.load()?;
+        if *self.settings.read().unwrap() == settings {
+            info!("Settings in {} are unchanged", path.display());
+            return Ok(());
+        }
+        self.sched.reconfigure(settings.tunables.clone()).await;
+        *self.settings.write().unwrap() = settings;
+        info!("Reloaded settings from {}", path.display());
+        Ok(())
+    }
+
+    /// Take no more jobs and start no more queued ones, then exit once the
+    /// running jobs have finished, or stop them after `timeout`. Returns how
+    /// many jobs are running and queued.
+    pub async fn drain(&self, timeout: Option<Duration>) -> Result<(usize, usize)> {
+        if !self.sched.drain() {
+            anyhow::bail!("the daemon is already draining");
+        }
+        let (running, queued) = {
+            let state = self.state.read().await;
+            let count = |wanted: fn(&JobState) -> bool| {
+                state.jobs.iter().filter(|job| wanted(&job.state)).count()
+            };
+            (
+                count(|s| matches!(s, JobState::Running { .. })),
+                count(|s| matches!(s, JobState::Queued)),
+            )
+        };
+        info!(
+            "Draining: waiting for {} running jobs; {} queued jobs {}",
+            running,
+            queued,
+            match self.persistent {
+                true => "stay in the database",
+                false => "will be dropped",
+            }
+        );
+        let daemon = self.clone();
+        tokio::spawn(async move {
+            match timeout {
+                Some(timeout) => {
+                    if tokio::time::timeout(timeout, daemon.sched.drained())
+                        .await
+                        .is_err()
+                    {
+                        warn!(
+                            "Drain timed out after {}, stopping running jobs",
+                            humanize(timeout)
+                        );
+                    }
+                }
+                None => daemon.sched.drained().await,
+            }
+            daemon.stop.cancel();
+        });
+        Ok((running, queued))
+    }
+
+    /// Bring back the jobs a previous daemon saved: finished ones as
+    /// history, queued ones back in the queue. Jobs that were running when
+    /// it stopped are taken over if their process is still alive, and
+    /// marked failed otherwise. A job the `history` has as finished is never
+    /// run again, even if a damaged database lost that.
+    async fn restore(&self, store: &Store, history: Option<&History>) -> Result<()> {
+        let mut records = store.load()?;
+        let mut finished: HashMap<String, JobRecord> = match history {
+            Some(history) => history
+                .load()?
+                .into_iter()
+                .map(|job| (job.id.clone(), job))
+                .collect(),
+            None => HashMap::new(),
+        };
+        for (index, record) in records.iter_mut().enumerate() {
+            if !matches!(record.state.as_str(), "queued" | "running") {
+                continue;
+            }
+            if let Some(done) = finished.remove(&record.id) {
+                warn!(
+                    job = %record.id,
+                    "Job is {} in the database but finished in the history, keeping it finished",
+                    record.state
+                );
+                *record = done;
+                store.save(index, record).await?;
+            }
+        }
+        // The driver may have numbered the GPUs differently since
+        for record in records.iter_mut() {
+            if let Some(process) = &mut record.process {
+                self.sched.relocate(process);
+                record.gpu = process.gpus.first().copied();
+            }
+        }
+        // Jobs whose process outlived the last daemon keep their GPUs
+        let alive = |record: &JobRecord| {
+            record.state == "running" && record.process.as_ref().is_some_and(scheduler::is_running)
+        };
+        let held: Vec<u32> = records
+            .iter()
+            .filter(|record| alive(record))
+            .flat_map(|record| record.process.iter().flat_map(|p| p.gpus.clone()))
+            .collect();
+        self.sched.reserve_gpus(&held).await;
+
+        let (mut requeued, mut adopted) = (0, 0);
+        for (index, record) in records.iter().enumerate() {
+            if alive(record) {
+                match self.sched.adopt(record.to_job_info()?).await {
+                    Ok(()) => {
+                        adopted += 1;
+                        continue;
+                    }
+                    Err(e) => warn!(job = %record.id, "Cannot adopt job: {}", e),
+                }
+            }
+            let lost = match record.state.as_str() {
+                "queued" => match self.requeue(record).await {
+                    Ok(_) => {
+                        requeued += 1;
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!(job = %record.id, "Cannot requeue job: {}", e);
+                        true
+                    }
+                },
+                "running" => {
+                    warn!(
+                        job = %record.id,
+                        "Job was still running when the daemon stopped, marking it failed"
+                    );
+                    true
+                }
+                _ => false,
+            };
+            let mut record = record.clone();
+            if lost {
+                record.state = "failed".to_string();
+                store.save(index, &record).await?;
+            }
+            self.state.write().await.jobs.push(record.to_job_info()?);
+        }
+        if !records.is_empty() {
+            info!(
+                "Restored {} jobs ({} queued, {} still running)",
+                records.len(),
+                requeued,
+                adopted
+            );
+        }
+        Ok(())
+    }
+
+    /// Queue a saved job again, under its old id.
+    async fn requeue(&self, record: &JobRecord) -> Result<Uuid> {
+        let request = JobRequest {
+            cmd: record.cmd.clone(),
+            env: record.env.clone(),
+            gpus: Some(record.requested_gpus),
+            mem: record.mem.clone(),
+            power_limit: record.power_limit.clone(),
+            priority: Some(record.priority),
+            tag: record.tag.clone(),
+            source: record.source.clone(),
+            owner: record.owner.clone(),
+        };
+        self.sched.submit_as(record.id.parse()?, request).await
+    }
+
+    /// Save every job change, until the daemon exits.
+    async fn persist(self, store: Store, mut events: broadcast::Receiver<JobEvent>) {
+        loop {
+            let saved = match events.recv().await {
+                Ok(JobEvent::State { job_id, job }) => {
+                    let index = self
+                        .state
+                        .read()
+                        .await
+                        .jobs
+                        .iter()
+                        .position(|j| j.id == job_id);
+                    match index {
+                        Some(index) => store.save(index, &job).await,
+                        None => Ok(()),
+                    }
+                }
+                Ok(JobEvent::Log { .. }) => Ok(()),
+                Err(RecvError::Lagged(_)) => self.save_all(&store).await,
+                Err(RecvError::Closed) => break,
+            };
+            if let Err(e) = saved {
+                warn!("Failed to save job state: {}", e);
+            }
+        }
+    }
+
+    async fn save_all(&self, store: &Store) -> Result<()> {
+        let records = job_records(&*self.state.read().await);
+        for (index, record) in records.iter().enumerate() {
+            store.save(index, record).await?;
+        }
+        Ok(())
+    }
+
+    async fn serve_client(self, stream: UnixStream) {
+        let owner = peer_user(&stream);
+        let (reader, writer) = stream.into_split();
+        self.serve_connection(reader, writer, Peer::Local(owner))
+            .await;
+    }
+
+    /// Take clients from other machines on `listener`.
+    async fn serve_tcp(self, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    debug!("Client connected from {}", addr);
+                    let (reader, writer) = stream.into_split();
+                    let peer = Peer::Remote(None);
+                    tokio::spawn(self.clone().serve_connection(reader, writer, peer));
+                }
+                Err(e) => warn!("Failed to accept connection: {}", e),
+            }
+        }
+    }
+
+    async fn serve_connection(
+        self,
+        reader: impl AsyncRead + Unpin,
+        mut writer: impl AsyncWrite + Unpin,
+        mut peer: Peer,
+    ) {
+        let mut reader = BufReader::new(reader);
+        let mut authenticated = self.token.is_none();
+        loop {
+            let sent = match protocol::recv::<Request>(&mut reader).await {
+                Ok(None) => break,
+                Ok(Some(Request::Auth { token, user })) => {
+                    authenticated = self.authenticate(&token);
+                    if let (Peer::Remote(owner), true) = (&mut peer, authenticated) {
+                        *owner = user.map(|user| format!("{}@tcp", user));
+                    }
+                    let response = match authenticated {
+                        true => Response::Authenticated,
+                        false => error(anyhow::anyhow!("invalid token")),
+                    };
+                    protocol::send(&mut writer, &response).await
+                }
+                Ok(Some(_)) if !authenticated => {
+                    let message = "this daemon needs a token: pass --token-file";
+                    protocol::send(&mut writer, &error(anyhow::anyhow!(message)))
+                        .await
+                        .ok();
+                    break;
+                }
+                Ok(Some(request)) => self.handle(request, &peer, &mut writer).await,
+                Err(e) => protocol::send(&mut writer, &error(e)).await,
+            };
+            if let Err(e) = sent {
+                debug!("Client went away: {}", e);
+                break;
+            }
+        }
+    }
+
+    /// Whether `token` is the daemon's, or it needs none.
+    pub fn authenticate(&self, token: &str) -> bool {
+        self.token
+            .as_deref()
+            .is_none_or(|expected| auth::matches(expected, token))
+    }
+
+    /// Answer `request` from the client `peer`.
+    async fn handle(
+        &self,
+        request: Request,
+        peer: &Peer,
+        writer: &mut (impl AsyncWrite + Unpin),
+    ) -> Result<()> {
+        let owner = peer.owner();
+        let response = match request {
+            Request::Auth { .. } => Response::Authenticated,
+            Request::Logs { job, follow } => return self.stream_logs(&job, follow, writer).await,
+            Request::Watch => return self.watch(writer).await,
+            Request::Submit { .. } if self.sched.is_draining() => error(anyhow::anyhow!(
+                "the daemon is draining and takes no new jobs"
+            )),
+            Request::Submit { job } if job.cmd.trim().is_empty() => Response::Error {
+                message: "job has an empty \"cmd\"".to_string(),
+            },
+            Request::Submit { mut job } => match self.check_queue_limit(owner).await {
+                Ok(()) => {
+                    job.owner = owner.map(String::from);
+                    match self.sched.submit(job).await {
+                        Ok(id) => {
+                            debug!(job = %id, "Accepted job from client");
+                            Response::Submitted { id: id.to_string() }
+                        }
+                        Err(e) => error(e),
+                    }
+                }
+                Err(e) => error(e),
+            },
+            Request::Status => Response::Status {
+                jobs: job_records(&*self.state.read().await),
+            },
+            Request::Pause { .. } | Request::Reload | Request::Rescan | Request::Drain { .. }
+                if !self.is_admin(peer) =>
+            {
+                error(anyhow::anyhow!(
+                    "only {} and root can pause, reload, rescan or drain the daemon",
+                    self.user.as_deref().unwrap_or("the daemon's user")
+                ))
+            }
+            Request::Pause { paused } => {
+                self.sched.set_paused(paused).await;
+                Response::Paused { paused }
+            }
+            Request::Reload => match self.reload().await {
+                Ok(()) => Response::Reloaded,
+                Err(e) => error(e),
+            },
+            Request::Rescan => match self.sched.rescan().await {
+                Ok((added, removed)) => Response::Rescanned { added, removed },
+                Err(e) => error(e),
+            },
+            Request::Drain { timeout_secs } => {
+                let timeout = timeout_secs.map(Duration::from_secs_f64);
+                match self.drain(timeout).await {
+                    Ok((running, queued)) => Response::Draining { running, queued },
+                    Err(e) => error(e),
+                }
+            }
+            Request::Requeue { job, priority } => match self.queued_job(&job, peer).await {
+                Ok(id) => match self.sched.set_priority(id, priority).await {
+                    Ok(()) => Response::Requeued { id: id.to_string() },
+                    Err(e) => error(e),
+                },
+                Err(e) => error(e),
+            },
+            Request::Move { job, to } => match self.queued_job(&job, peer).await {
+                Ok(id) => match self.sched.move_job(id, matches!(to, QueueEnd::Front)).await {
+                    Ok(()) => Response::Requeued { id: id.to_string() },
+                    Err(e) => error(e),
+                },
+                Err(e) => error(e),
+            },
+            Request::Shift { job, up } => match self.queued_job(&job, peer).await {
+                Ok(id) => match self.sched.shift_job(id, up).await {
+                    Ok(()) => Response::Requeued { id: id.to_string() },
+                    Err(e) => error(e),
+                },
+                Err(e) => error(e),
+            },
+            Request::Cancel { target } => match self.cancel(target, peer).await {
+                Ok(ids) => Response::Cancelled { ids },
+                Err(e) => error(e),
+            },
+        };
+        protocol::send(writer, &response).await
+    }
+
+    /// Refuse another job from `owner` once `max_queued_per_user` of theirs
+    /// are waiting.
+    async fn check_queue_limit(&self, owner: Option<&str>) -> Result<()> {
+        let limit = self.settings.read().unwrap().max_queued_per_user;
+        let (Some(owner), Some(limit)) = (owner, limit) else {
+            return Ok(());
+        };
+        let queued = self
+            .state
+            .read()
+            .await
+            .jobs
+            .iter()
+            .filter(|job| job.owner.as_deref() == Some(owner))
+            .filter(|job| matches!(job.state, JobState::Queued))
+            .count();
+        if queued >= limit {
+            anyhow::bail!(
+                "{} already has {} queued jobs, the most allowed",
+                owner,
+                queued
+            );
+        }
+        Ok(())
+    }
+
+    /// Answer a `logs` request: the job's log buffer, then with `follow`
+    /// every new line until the job has finished.
+    async fn stream_logs(
+        &self,
+        prefix: &str,
+        follow: bool,
+        writer: &mut (impl AsyncWrite + Unpin),
+    ) -> Result<()> {
+        let (id, lines, mut finished, mut live) = {
+            let state = self.state.read().await;
+            let job = match find_job(&state, prefix) {
+                Ok(job) => job,
+                Err(e) => return protocol::send(writer, &error(e)).await,
+            };
+            let lines = job.log_lines.iter().cloned().collect();
+            let finished = !follow || job.state.is_finished();
+            (job.id, lines, finished, self.sched.subscribe())
+        };
+        protocol::send(writer, &Response::Log { lines }).await?;
+
+        // Output is drained before a job's final state is set, so its last
+        // line always comes before that event
+        while !finished {
+            let lines = match live.recv().await {
+                Ok(JobEvent::Log { job_id, line }) if job_id == id => vec![line],
+                Ok(JobEvent::State { job_id, job }) if job_id == id => {
+                    finished = !matches!(job.state.as_str(), "queued" | "running");
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    finished = self.is_finished(id).await;
+                    vec![format!("[gparallel] {} lines skipped", skipped)]
+                }
+                Err(RecvError::Closed) => break,
+            };
+            protocol::send(writer, &Response::Log { lines }).await?;
+        }
+        protocol::send(writer, &Response::LogEnd).await
+    }
+
+    async fn is_finished(&self, id: Uuid) -> bool {
+        self.state
+            .read()
+            .await
+            .jobs
+            .iter()
+            .any(|j| j.id == id && j.state.is_finished())
+    }
+
+    /// Answer a `watch` request: the current state and every job's log
+    /// buffer, then twice a second the state and the output captured since,
+    /// until the client goes away.
+    async fn watch(&self, writer: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+        let (snapshot, logs, mut live) = {
+            let state = self.state.read().await;
+            let logs: Vec<_> = state
+                .jobs
+                .iter()
+                .filter(|j| !j.log_lines.is_empty())
+                .map(|j| Response::JobLog {
+                    id: j.id.to_string(),
+                    lines: j.log_lines.iter().cloned().collect(),
+                })
+                .collect();
+            (state_response(&state), logs, self.sched.subscribe())
+        };
+        protocol::send(writer, &snapshot).await?;
+        for log in &logs {
+            protocol::send(writer, log).await?;
+        }
+
+        let period = Duration::from_millis(500);
+        let mut tick = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        let mut pending: BTreeMap<Uuid, Vec<String>> = BTreeMap::new();
+        loop {
+            tokio::select! {
+                received = live.recv() => match received {
+                    Ok(JobEvent::Log { job_id, line }) => pending.entry(job_id).or_default().push(line),
+                    Ok(JobEvent::State { .. }) => {}
+                    Err(RecvError::Lagged(skipped)) => debug!("Watcher skipped {} lines", skipped),
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                _ = tick.tick() => {
+                    // State first: a job's lines can only arrive once it is known
+                    let snapshot = state_response(&*self.state.read().await);
+                    protocol::send(writer, &snapshot).await?;
+                    for (id, lines) in std::mem::take(&mut pending) {
+                        let log = Response::JobLog { id: id.to_string(), lines };
+                        protocol::send(writer, &log).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether `peer` may change every job and the daemon itself: root or
+    /// the daemon's user, on this machine. A TCP client's user is only what
+    /// it says, so it never may.
+    fn is_admin(&self, peer: &Peer) -> bool {
+        match peer {
+            Peer::Local(Some(owner)) => owner == "root" || self.user.as_deref() == Some(owner),
+            Peer::Local(None) | Peer::Remote(_) => false,
+        }
+    }
+
+    /// Refuse to let `peer` change someone else's `job`.
+    fn check_owner(&self, peer: &Peer, job: &JobInfo, prefix: &str) -> Result<()> {
+        let owner = peer.owner();
+        if self.is_admin(peer) || (owner.is_some() && job.owner.as_deref() == owner) {
+            return Ok(());
+        }
+        anyhow::bail!(
+            "job {} belongs to {}",
+            prefix,
+            job.owner.as_deref().unwrap_or("the daemon's user")
+        )
+    }
+
+    /// The id of the queued job whose id starts with `prefix`, if `peer`
+    /// may change it.
+    async fn queued_job(&self, prefix: &str, peer: &Peer) -> Result<Uuid> {
+        let state = self.state.read().await;
+        let job = find_job(&state, prefix)?;
+        self.check_owner(peer, job, prefix)?;
+        if !matches!(job.state, JobState::Queued) {
+            anyhow::bail!("job {} is not queued", prefix);
+        }
+        Ok(job.id)
+    }
+
+    /// Cancel the jobs `target` names. `all` and `queued` only take the
+    /// jobs of `peer`, unless it may change every job.
+    async fn cancel(&self, target: CancelTarget, peer: &Peer) -> Result<Vec<String>> {
+        let ids: Vec<Uuid> = {
+            let state = self.state.read().await;
+            let owner = peer.owner();
+            let admin = self.is_admin(peer);
+            let pending = state
+                .jobs
+                .iter()
+                .filter(|j| !j.state.is_finished())
+                .filter(|j| admin || (owner.is_some() && j.owner.as_deref() == owner));
+            match &target {
+                CancelTarget::All => pending.map(|j| j.id).collect(),
+                CancelTarget::Queued => pending
+                    .filter(|j| matches!(j.state, JobState::Queued))
+                    .map(|j| j.id)
+                    .collect(),
+                CancelTarget::Job(prefix) => {
+                    let job = find_job(&state, prefix)?;
+                    self.check_owner(peer, job, prefix)?;
+                    if job.state.is_finished() {
+                        anyhow::bail!("job {} has already finished", prefix);
+                    }
+                    vec![job.id]
+                }
+            }
+        };
+
+        let mut cancelled = Vec::new();
+        for id in ids {
+            if self.sched.cancel(id).await {
+                cancelled.push(id.to_string());
+            }
+        }
+        Ok(cancelled)
+    }
+}
+
+/// The job whose id starts with `prefix` (the short ids `status` shows).
+pub fn find_job<'a>(state: &'a AppState, prefix: &str) -> Result<&'a JobInfo> {
+    let mut matches = state
+        .jobs
+        .iter()
+        .filter(|j| !prefix.is_empty() && j.id.to_string().starts_with(prefix));
+    match (matches.next(), matches.next()) {
+        (Some(job), None) => Ok(job),
+        (None, _) => anyhow::bail!("no job with id '{}'", prefix),
+        (Some(_), Some(_)) => anyhow::bail!("job id '{}' is ambiguous", prefix),
+    }
+}
+
+pub fn job_records(state: &AppState) -> Vec<JobRecord> {
+    state.jobs.iter().map(JobRecord::new).collect()
+}
+
+pub fn state_response(state: &AppState) -> Response {
+    Response::State {
+        gpus: state.gpus.clone(),
+        jobs: job_records(state),
+        paused: state.paused,
+        host: state.host,
+    }
+}
+
+/// Who is on the other end of a client connection, for job owners.
+enum Peer {
+    /// A user of this machine, known from the socket's credentials
+    Local(Option<String>),
+    /// A client over TCP, which names its user along with the token, as
+    /// nothing else can tell. It owns jobs as `user@tcp`, so it can never
+    /// pass for a user of this machine.
+    Remote(Option<String>),
+}
+
+impl Peer {
+    fn owner(&self) -> Option<&str> {
+        match self {
+            Peer::Local(owner) | Peer::Remote(owner) => owner.as_deref(),
+        }
+    }
+}
+
+/// The user running the process on the other end of the socket.
+fn peer_user(stream: &UnixStream) -> Option<String> {
+    user_name(stream.peer_cred().ok()?.uid())
+}
+
+/// The name of the user `uid`, or the uid when it has none.
+fn user_name(uid: u32) -> Option<String> {
+    let user = nix::unistd::User::from_uid(nix::unistd::Uid::from_raw(uid)).ok()?;
+    Some(user.map_or_else(|| uid.to_string(), |user| user.name))
+}
+
+fn error(e: anyhow::Error) -> Response {
+    Response::Error {
+        message: e.to_string(),
+    }
+}
@@ -0,0 +1,169 @@
+//! Daemon settings that change without a restart: `serve --config FILE` is
+//! read at startup and again on SIGHUP or `gparallel reload`, and running
+//! jobs carry on through it.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+use crate::alert::Alert;
+use crate::memory;
+use crate::scheduler::{GpuSelector, TempLimit, Tunables};
+
+/// What the daemon can change while it runs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub tunables: Tunables,
+    /// Jobs one user may have waiting in the queue
+    pub max_queued_per_user: Option<usize>,
+}
+
+/// Where the daemon's settings come from: its command line, with the
+/// config file on top.
+pub struct SettingsSource {
+    pub base: Settings,
+    pub path: Option<PathBuf>,
+}
+
+impl SettingsSource {
+    /// The settings as they are now; the file is read again every time.
+    pub fn load(&self) -> Result<Settings> {
+        match &self.path {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).map_err(|e| {
+                    anyhow::anyhow!("Failed to read config '{}': {}", path.display(), e)
+                })?;
+                apply(&self.base, &content)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+            }
+            None => Ok(self.base.clone()),
+        }
+    }
+}
+
+/// The keys of the config file, named like the command-line options:
+///
+/// ```toml
+/// gpus = [0, "GPU-5d5ba0d6-…"]  # only these GPUs of this machine
+/// jobs-per-gpu = 2
+/// memfree = "16G"
+/// max-temp = 85                 # resume-temp defaults to 10 below
+/// max-gpus-per-user = 2
+/// max-queued-per-user = 100
+/// alert-on-first-failure = "notify"
+/// ```
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct SettingsFile {
+    gpus: Option<Vec<GpuEntry>>,
+    jobs_per_gpu: Option<usize>,
+    memfree: Option<String>,
+    max_temp: Option<u32>,
+    resume_temp: Option<u32>,
+    max_gpus_per_user: Option<u32>,
+    max_queued_per_user: Option<usize>,
+    alert_on_first_failure: Option<String>,
+}
+
+/// A GPU in `gpus`: its index, or its UUID as a string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum GpuEntry {
+    Index(u32),
+    Uuid(String),
+}
+
+/// `base` with the settings of a config file's `content` on top.
+fn apply(base: &Settings, content: &str) -> Result<Settings> {
+    let file: SettingsFile = toml::from_str(content)?;
+    let mut settings = base.clone();
+    let tunables = &mut settings.tunables;
+    if let Some(gpus) = file.gpus {
+        let gpus = gpus.into_iter().map(|gpu| match gpu {
+            GpuEntry::Index(index) => Ok(GpuSelector::Index(index)),
+            GpuEntry::Uuid(uuid) => GpuSelector::parse(&uuid),
+        });
+        tunables.gpus = Some(gpus.collect::<Result<_>>()?);
+    }
+    if let Some(jobs) = file.jobs_per_gpu {
+        if jobs == 0 {
+            anyhow::bail!("jobs-per-gpu must be at least 1");
+        }
+        tunables.jobs_per_gpu = jobs;
+    }
+    if let Some(memfree) = file.memfree {
+        tunables.memfree = Some(memory::parse_size(&memfree)?);
+    }
+    match (file.max_temp, file.resume_temp) {
+        (Some(max), resume) => tunables.max_temp = Some(TempLimit::new(max, resume)?),
+        (None, Some(_)) => anyhow::bail!("resume-temp needs max-temp"),
+        (None, None) => {}
+    }
+    if let Some(alert) = file.alert_on_first_failure {
+        tunables.alert = Some(
+            Alert::from_str(&alert, true)
+                .map_err(|_| anyhow::anyhow!("unknown alert-on-first-failure '{}'", alert))?,
+        );
+    }
+    if let Some(limit) = file.max_gpus_per_user {
+        tunables.user_gpu_limit = Some(limit);
+    }
+    if let Some(limit) = file.max_queued_per_user {
+        settings.max_queued_per_user = Some(limit);
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_settings_override_the_command_line() {
+        let base = Settings {
+            tunables: Tunables {
+                user_gpu_limit: Some(4),
+                ..Default::default()
+            },
+            max_queued_per_user: Some(10),
+        };
+        let content = "gpus = [0, \"GPU-5d5ba0d6\"]\njobs-per-gpu = 2\nmemfree = \"1G\"\n\
+                       max-temp = 85\nmax-queued-per-user = 5\n\
+                       alert-on-first-failure = \"bell\"\n";
+
+        let settings = apply(&base, content).unwrap();
+
+        assert_eq!(
+            settings.tunables,
+            Tunables {
+                gpus: Some(vec![
+                    GpuSelector::Index(0),
+                    GpuSelector::Uuid("GPU-5d5ba0d6".to_string()),
+                ]),
+                jobs_per_gpu: 2,
+                memfree: Some(1 << 30),
+                max_temp: Some(TempLimit {
+                    max: 85,
+                    resume: 75
+                }),
+                alert: Some(Alert::Bell),
+                user_gpu_limit: Some(4),
+            }
+        );
+        assert_eq!(settings.max_queued_per_user, Some(5));
+        assert_eq!(apply(&base, "").unwrap(), base);
+    }
+
+    #[test]
+    fn bad_settings_are_errors() {
+        let base = Settings::default();
+        assert!(apply(&base, "jobs-per-gpu = 0").is_err());
+        assert!(apply(&base, "memfree = \"lots\"").is_err());
+        assert!(apply(&base, "alert-on-first-failure = \"siren\"").is_err());
+        assert!(apply(&base, "retries = 3").is_err());
+        assert!(apply(&base, "gpus = [\"first\"]").is_err());
+        assert!(apply(&base, "resume-temp = 70").is_err());
+        assert!(apply(&base, "max-temp = 80\nresume-temp = 90").is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+/// How job command lines are executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Sh,
+    /// Split the command into words and exec it directly, without a shell
+    None,
+}
+
+/// Marker that appends the lines of a file to the command as arguments.
+const ARGV_FILE: &str = "#argv-file=";
+
+impl Shell {
+    /// Build the process for a job's command line.
+    ///
+    /// A command ending in `#argv-file=PATH` bypasses the shell: the words
+    /// before the marker plus one argument per line of PATH are exec'd
+    /// directly, so huge generated argument lists never hit shell quoting or
+    /// command-line length limits.
+    pub fn command(self, cmd: &str) -> Result<Command> {
+        if let Some((head, path)) = split_argv_file(cmd) {
+            let mut words = split_words(head)?;
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read argv file '{}': {}", path, e))?;
+            words.extend(content.lines().map(str::to_string));
+            return exec(words);
+        }
+
+        let Some(program) = self.program() else {
+            return exec(split_words(cmd)?);
+        };
+        let mut command = Command::new(program);
+        command.arg("-c").arg(cmd);
+        Ok(command)
+    }
+
+    /// The shell to run commands with `-c`, or `None` to exec them directly.
+    pub fn program(self) -> Option<&'static str> {
+        match self {
+            Shell::Bash => Some("bash"),
+            Shell::Zsh => Some("zsh"),
+            Shell::Fish => Some("fish"),
+            Shell::Sh => Some("sh"),
+            Shell::None => None,
+        }
+    }
+}
+
+fn exec(words: Vec<String>) -> Result<Command> {
+    let (program, args) = words
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("empty command"))?;
+    let mut command = Command::new(program);
+    command.args(args);
+    Ok(command)
+}
+
+/// Split `prog args #argv-file=PATH` into the command words and PATH. The
+/// marker must start a word, so a literal `#argv-file=` inside an argument
+/// is left alone.
+fn split_argv_file(cmd: &str) -> Option<(&str, &str)> {
+    let start = cmd
+        .match_indices(ARGV_FILE)
+        .map(|(i, _)| i)
+        .find(|&i| i == 0 || cmd[..i].ends_with(char::is_whitespace))?;
+    let path = cmd[start + ARGV_FILE.len()..].trim();
+    (!path.is_empty()).then(|| (&cmd[..start], path))
+}
+
+/// Split a command line into words using POSIX shell quoting rules (single
+/// and double quotes, backslash escapes). No expansion is performed.
+pub fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => anyhow::bail!("unterminated single quote in: {}", line),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => anyhow::bail!("unterminated double quote in: {}", line),
+                        },
+                        Some(c) => word.push(c),
+                        None => anyhow::bail!("unterminated double quote in: {}", line),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some('\n') => {}
+                    Some(c) => word.push(c),
+                    None => word.push('\\'),
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Quote `word` for a POSIX shell, leaving it bare when that is safe.
+pub fn quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./:=,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_quoted_words() {
+        assert_eq!(
+            split_words(r#"python train.py --name 'run a' --tag "x \"y\"" a\ b"#).unwrap(),
+            vec!["python", "train.py", "--name", "run a", "--tag", "x \"y\"", "a b"]
+        );
+        assert_eq!(split_words("  ''  x ").unwrap(), vec!["", "x"]);
+    }
+
+    #[test]
+    fn finds_argv_file_marker() {
+        assert_eq!(
+            split_argv_file("python run.py --x 1 #argv-file=args.txt"),
+            Some(("python run.py --x 1 ", "args.txt"))
+        );
+        assert_eq!(split_argv_file("echo a#argv-file=b"), None);
+        assert_eq!(split_argv_file("echo #argv-file="), None);
+        assert_eq!(split_argv_file("echo plain"), None);
+    }
+
+    #[test]
+    fn rejects_unbalanced_quotes() {
+        assert!(split_words("echo 'oops").is_err());
+        assert!(split_words("echo \"oops").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Remote workers for `--sshlogin`: their GPUs are found with `nvidia-smi -L`
+//! over ssh, and jobs on them run through ssh too, with output coming back
+//! over its pipes like a local job's.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use tokio::process::Command;
+
+use crate::shell::{self, Shell};
+
+/// The `--sshlogin` entry that stands for this machine.
+pub const LOCAL: &str = ":";
+
+/// A GPU as `nvidia-smi -L` lists it, or as an agent offers it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListedGpu {
+    pub index: u32,
+    pub name: String,
+    pub uuid: Option<String>,
+}
+
+/// The GPUs of `host`, reached with the `ssh` command line.
+pub async fn discover(ssh: &str, host: &str) -> Result<Vec<ListedGpu>> {
+    let out = command(ssh, host, "nvidia-smi -L")?
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to run '{}': {}", ssh, e))?;
+    if !out.status.success() {
+        anyhow::bail!(
+            "Failed to list the GPUs of {}: {}",
+            host,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    let gpus = parse_gpu_list(&String::from_utf8_lossy(&out.stdout));
+    if gpus.is_empty() {
+        anyhow::bail!("{} has no GPUs", host);
+    }
+    Ok(gpus)
+}
+
+/// Lines look like "GPU 0: NVIDIA GeForce RTX 4090 (UUID: GPU-...)".
+fn parse_gpu_list(output: &str) -> Vec<ListedGpu> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (head, rest) = line.strip_prefix("GPU ")?.split_once(':')?;
+            let index = head.trim().parse().ok()?;
+            let (name, uuid) = match rest.split_once("(UUID:") {
+                Some((name, uuid)) => (name, Some(uuid.trim().trim_end_matches(')').to_string())),
+                None => (rest, None),
+            };
+            Some(ListedGpu {
+                index,
+                name: name.trim().to_string(),
+                uuid,
+            })
+        })
+        .collect()
+}
+
+/// `ssh host remote`, where `ssh` may carry its own options
+/// (e.g. "ssh -p 2222").
+pub fn command(ssh: &str, host: &str, remote: &str) -> Result<Command> {
+    let words = shell::split_words(ssh)?;
+    let (program, args) = words
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("empty --ssh command"))?;
+    let mut command = Command::new(program);
+    command.args(args).arg(host).arg(remote);
+    Ok(command)
+}
+
+/// The remote side of a job: `cmd` run by `shell` with `env` set. It runs in
+/// its own process group, which is killed once our end of the connection
+/// goes away (stdin closes), since sshd does not do that without a tty.
+pub fn remote_script(shell: Shell, env: &[(String, String)], cmd: &str) -> Result<String> {
+    let job = match shell.program() {
+        Some(program) => format!("{} -c {}", program, quote(cmd)),
+        None => {
+            let words = shell::split_words(cmd)?;
+            words.iter().map(|w| quote(w)).collect::<Vec<_>>().join(" ")
+        }
+    };
+    // Job control gives the job its own process group, and its "Done" and
+    // "Terminated" notices go to the old stderr, not the job's
+    let mut script = String::from("set -m\nexec 3>&2 2>/dev/null\n");
+    for (key, value) in env {
+        script.push_str(&format!("export {}={}\n", key, quote(value)));
+    }
+    script.push_str(&format!("{} 2>&3 3>&- & pid=$!\n", job));
+    script.push_str("{ cat >/dev/null; kill -TERM -$pid; } >/dev/null 3>&- &\n");
+    script.push_str("wait $pid\n");
+    Ok(format!("bash -c {}", quote(&script)))
+}
+
+/// Single-quote `s` for a POSIX shell.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nvidia_smi_list() {
+        let output = "GPU 0: NVIDIA A100-SXM4-80GB (UUID: GPU-1234)\n\
+                      GPU 1: Tesla V100\n\
+                      MIG 1g.10gb Device 0: (UUID: MIG-5678)\n";
+        assert_eq!(
+            parse_gpu_list(output),
+            vec![
+                ListedGpu {
+                    index: 0,
+                    name: "NVIDIA A100-SXM4-80GB".to_string(),
+                    uuid: Some("GPU-1234".to_string()),
+                },
+                ListedGpu {
+                    index: 1,
+                    name: "Tesla V100".to_string(),
+                    uuid: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn remote_script_runs_the_job_with_its_env() {
+        use std::io::Read;
+        let env = [("GREETING".to_string(), "it's".to_string())];
+        let script = remote_script(Shell::Bash, &env, "echo \"$GREETING 'quoted'\"").unwrap();
+        let mut child = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Held open like ssh's, or the job would be killed straight away
+        let stdin = child.stdin.take();
+        let mut output = String::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        drop(stdin);
+        assert!(child.wait().unwrap().success());
+        assert_eq!(output, "it's 'quoted'\n");
+    }
+
+    #[test]
+    fn ssh_command_keeps_its_options() {
+        let command = command("ssh -p 2222", "gpu1", "true").unwrap();
+        let command = command.as_std();
+        assert_eq!(command.get_program(), "ssh");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args, ["-p", "2222", "gpu1", "true"]);
+    }
+}
@@ -0,0 +1,126 @@
+//! The daemon's database (sled): one `JobRecord` per job, keyed by its
+//! position in submission order, so the queue and the job history survive a
+//! restart.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::report::JobRecord;
+
+/// Where gparallel keeps its files: `~/.local/share/gparallel`, or
+/// relative to the working directory without `$HOME`.
+pub fn data_dir() -> PathBuf {
+    let data = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => match std::env::var_os("HOME") {
+            Some(home) => Path::new(&home).join(".local/share"),
+            None => PathBuf::new(),
+        },
+    };
+    data.join("gparallel")
+}
+
+#[derive(Clone)]
+pub struct Store {
+    jobs: sled::Tree,
+}
+
+impl Store {
+    /// `~/.local/share/gparallel/daemon.db`
+    pub fn default_path() -> PathBuf {
+        data_dir().join("daemon.db")
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let db = sled::open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open database '{}': {}", path.display(), e))?;
+        Ok(Self {
+            jobs: db.open_tree("jobs")?,
+        })
+    }
+
+    /// Every saved job, in submission order. Records that cannot be read
+    /// are dropped with a warning, and the rest saved again in order, so
+    /// positions keep matching the daemon's job list. The rewrite is one
+    /// batch: a crash part way through leaves the database as it was.
+    pub fn load(&self) -> Result<Vec<JobRecord>> {
+        let mut jobs = Vec::new();
+        let mut keys = Vec::new();
+        for entry in self.jobs.iter() {
+            let (key, value) =
+                entry.map_err(|e| anyhow::anyhow!("Failed to read database: {}", e))?;
+            match serde_json::from_slice(&value) {
+                Ok(job) => jobs.push(job),
+                Err(e) => warn!("Dropping unreadable job record {}: {}", keys.len(), e),
+            }
+            keys.push(key);
+        }
+        if jobs.len() < keys.len() {
+            let mut batch = sled::Batch::default();
+            for key in keys {
+                batch.remove(key);
+            }
+            for (index, job) in jobs.iter().enumerate() {
+                batch.insert(&(index as u64).to_be_bytes()[..], serde_json::to_vec(job)?);
+            }
+            self.jobs.apply_batch(batch)?;
+            self.jobs.flush()?;
+        }
+        Ok(jobs)
+    }
+
+    /// Save the job submitted `index`-th (counting from 0).
+    pub async fn save(&self, index: usize, job: &JobRecord) -> Result<()> {
+        self.jobs
+            .insert((index as u64).to_be_bytes(), serde_json::to_vec(job)?)?;
+        self.jobs.flush_async().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::{JobInfo, JobState};
+    use uuid::Uuid;
+
+    #[test]
+    fn load_drops_damaged_records_and_keeps_the_rest_in_order() {
+        let path = std::env::temp_dir().join(format!("gparallel-store-{}", Uuid::new_v4()));
+        let jobs: Vec<JobRecord> = [JobState::Completed, JobState::Failed, JobState::Queued]
+            .into_iter()
+            .map(|state| {
+                JobRecord::new(&JobInfo {
+                    state,
+                    ..JobInfo::new(Uuid::new_v4(), "true")
+                })
+            })
+            .collect();
+        let damaged = serde_json::to_vec(&jobs[1]).unwrap();
+
+        // Cut the middle record short at every byte
+        for cut in 0..damaged.len() {
+            let store = Store::open(&path.join(cut.to_string())).unwrap();
+            for (index, job) in jobs.iter().enumerate() {
+                let value = match index {
+                    1 => damaged[..cut].to_vec(),
+                    _ => serde_json::to_vec(job).unwrap(),
+                };
+                store
+                    .jobs
+                    .insert((index as u64).to_be_bytes(), value)
+                    .unwrap();
+            }
+            let loaded = store.load().unwrap();
+            let ids: Vec<&str> = loaded.iter().map(|job| job.id.as_str()).collect();
+            assert_eq!(ids, [jobs[0].id.as_str(), jobs[2].id.as_str()]);
+            assert_eq!(loaded[0].state, jobs[0].state);
+            assert_eq!(loaded[1].state, jobs[2].state);
+            // Saved again where the daemon's job list has them
+            assert_eq!(store.jobs.len(), 2);
+            assert_eq!(store.load().unwrap().len(), 2);
+        }
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
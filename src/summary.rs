@@ -0,0 +1,155 @@
+//! End-of-run report for non-TUI runs.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::duration::humanize;
+use crate::ui::{AppState, JobExit, JobState};
+
+/// Group finished jobs by how they exited, with a count and the first
+/// command of each group, e.g.
+///
+/// ```text
+/// [gparallel] 10 jobs by exit status:
+///   exit 0               7  python train.py --lr 0.1
+///   exit 1               2  python train.py --lr 10
+///   signal 9 (SIGKILL)   1  python train.py --lr 1
+/// ```
+///
+/// Setup failures form their own groups, labelled with the failed phase.
+pub fn exit_summary(state: &AppState) -> Vec<String> {
+    let mut groups: BTreeMap<(Option<&str>, JobExit), (usize, &str)> = BTreeMap::new();
+    let mut not_run = 0;
+    for job in &state.jobs {
+        let phase = match job.state {
+            JobState::SetupFailed { phase } => Some(phase),
+            _ => None,
+        };
+        match job.exit {
+            Some(exit) => groups.entry((phase, exit)).or_insert((0, &job.cmd)).0 += 1,
+            None => not_run += 1,
+        }
+    }
+
+    let labels: Vec<(String, usize, &str)> = groups
+        .into_iter()
+        .map(|((phase, exit), (count, example))| {
+            let label = match phase {
+                Some(phase) => format!("{} failed: {}", phase, exit),
+                None => exit.to_string(),
+            };
+            (label, count, example)
+        })
+        .collect();
+    let label_width = labels.iter().map(|(l, _, _)| l.len()).max().unwrap_or(0);
+    let count_width = labels
+        .iter()
+        .map(|(_, c, _)| c.to_string().len())
+        .max()
+        .unwrap_or(1);
+
+    let mut lines = vec![format!(
+        "[gparallel] {} jobs by exit status:",
+        state.jobs.len()
+    )];
+    for (label, count, example) in labels {
+        lines.push(format!(
+            "  {:<lw$}  {:>cw$}  {}",
+            label,
+            count,
+            example,
+            lw = label_width,
+            cw = count_width
+        ));
+    }
+    if not_run > 0 {
+        lines.push(format!("  {} not run", not_run));
+    }
+    lines
+}
+
+/// Distribution of how long dispatched jobs waited for a GPU, e.g.
+/// `[gparallel] Queue wait: p50 0s, p95 4m 10s, max 6m 02s (40 jobs)`.
+/// `None` if no job has started.
+pub fn queue_wait_summary(state: &AppState) -> Option<String> {
+    let mut waits: Vec<Duration> = state
+        .jobs
+        .iter()
+        .filter(|j| j.timing.started.is_some())
+        .map(|j| j.timing.waited)
+        .collect();
+    waits.sort();
+    let max = *waits.last()?;
+    Some(format!(
+        "[gparallel] Queue wait: p50 {}, p95 {}, max {} ({} jobs)",
+        humanize(percentile(&waits, 50)),
+        humanize(percentile(&waits, 95)),
+        humanize(max),
+        waits.len()
+    ))
+}
+
+/// Nearest-rank percentile of a sorted, non-empty slice.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::JobInfo;
+    use uuid::Uuid;
+
+    #[test]
+    fn queue_wait_percentiles() {
+        let mut state = AppState::new();
+        assert_eq!(queue_wait_summary(&state), None);
+        let now = std::time::Instant::now();
+        state.jobs = (1..=20)
+            .map(|i| {
+                let mut job = job("x", Some(JobExit::Code(0)));
+                job.timing.started = Some(now);
+                job.timing.waited = Duration::from_secs(i * 10);
+                job
+            })
+            .collect();
+        state.jobs.push(job("queued", None));
+
+        assert_eq!(
+            queue_wait_summary(&state).unwrap(),
+            "[gparallel] Queue wait: p50 1m 40s, p95 3m 10s, max 3m 20s (20 jobs)"
+        );
+    }
+
+    fn job(cmd: &str, exit: Option<JobExit>) -> JobInfo {
+        JobInfo {
+            exit,
+            ..JobInfo::new(Uuid::new_v4(), cmd)
+        }
+    }
+
+    #[test]
+    fn groups_by_exit_with_first_example() {
+        let mut state = AppState::new();
+        state.jobs = vec![
+            job("ok 1", Some(JobExit::Code(0))),
+            job("bad 1", Some(JobExit::Code(2))),
+            job("ok 2", Some(JobExit::Code(0))),
+            job("killed", Some(JobExit::Signal(9))),
+            job("bad 2", Some(JobExit::Code(2))),
+            job("later", None),
+        ];
+
+        assert_eq!(
+            exit_summary(&state),
+            vec![
+                "[gparallel] 6 jobs by exit status:",
+                "  exit 0              2  ok 1",
+                "  exit 2              2  bad 1",
+                "  signal 9 (SIGKILL)  1  killed",
+                "  1 not run",
+            ]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Running `gparallel serve` as a systemd service: sockets systemd opened
+//! for us (socket activation), and `sd_notify` messages for readiness,
+//! shutdown and the watchdog. Outside systemd all of this does nothing.
+
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+use tracing::debug;
+
+/// The first file descriptor systemd passes (`SD_LISTEN_FDS_START`).
+const LISTEN_FDS_START: i32 = 3;
+
+/// Sockets passed by systemd socket activation, in the order of the
+/// `.socket` unit. Takes them out of the environment, so jobs do not think
+/// they were passed to them.
+pub fn listen_fds() -> Vec<OwnedFd> {
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<i32>().ok())
+        .unwrap_or(0);
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+    if !for_us {
+        return Vec::new();
+    }
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // Nor should jobs inherit the sockets themselves
+            use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).ok();
+            // SAFETY: systemd hands these descriptors to us, and nothing
+            // else in the process owns them
+            unsafe { OwnedFd::from_raw_fd(fd) }
+        })
+        .collect()
+}
+
+/// Tell systemd about our state, e.g. "READY=1", if it asked to be told.
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = notify_to(&path, state) {
+        debug!("Failed to notify systemd of {}: {}", state, e);
+    }
+}
+
+fn notify_to(path: &OsStr, state: &str) -> io::Result<()> {
+    let addr = match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// How often to send "WATCHDOG=1", if the unit has `WatchdogSec=`: twice
+/// per period, as systemd recommends.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    let for_us = env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_none_or(|pid| pid == std::process::id());
+    (for_us && usec > 0).then(|| Duration::from_micros(usec) / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notify_sends_the_state_to_the_socket() {
+        let path = env::temp_dir().join(format!("gparallel-notify-{}", uuid::Uuid::new_v4()));
+        let socket = UnixDatagram::bind(&path).unwrap();
+
+        notify_to(path.as_os_str(), "READY=1").unwrap();
+
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        std::fs::remove_file(path).unwrap();
+    }
+}
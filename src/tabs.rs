@@ -0,0 +1,399 @@
+//! The TUI's full-screen views besides the job queue: finished jobs, GPU
+//! detail, run statistics and per-GPU lanes.
+
+use ratatui::{
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Sparkline},
+    Frame,
+};
+use std::time::Duration;
+
+use crate::duration::humanize;
+use crate::history;
+use crate::report::JobRecord;
+use crate::summary;
+use crate::ui::{AppState, GpuStats, JobInfo, JobState};
+
+/// Which view fills the screen (`Tab`, or `1`-`5`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Tab {
+    #[default]
+    Queue,
+    History,
+    Gpus,
+    Stats,
+    Lanes,
+}
+
+impl Tab {
+    pub const ALL: [Tab; 5] = [Tab::Queue, Tab::History, Tab::Gpus, Tab::Stats, Tab::Lanes];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Tab::Queue => "Queue",
+            Tab::History => "History",
+            Tab::Gpus => "GPUs",
+            Tab::Stats => "Stats",
+            Tab::Lanes => "Lanes",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&t| t == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    pub fn previous(self) -> Self {
+        let i = Self::ALL.iter().position(|&t| t == self).unwrap_or(0);
+        Self::ALL[(i + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+}
+
+/// Finished jobs, in the order they finished, as `gparallel history` shows
+/// them; the newest that fit.
+pub fn draw_history(f: &mut Frame, area: Rect, state: &AppState) {
+    let mut finished: Vec<_> = state
+        .jobs
+        .iter()
+        .filter(|job| job.state.is_finished())
+        .collect();
+    finished.sort_by_key(|job| job.timing.finished);
+    let records: Vec<JobRecord> = finished.into_iter().map(JobRecord::new).collect();
+    let mut lines = history::history_table(&records);
+
+    // Keep the header and the footer, drop the oldest rows
+    let room = area.height.saturating_sub(2) as usize;
+    if lines.len() > room && room >= 2 {
+        let footer = lines.pop().unwrap_or_default();
+        let rows = lines.split_off(1);
+        lines.extend(rows[rows.len() - (room - 2)..].iter().cloned());
+        lines.push(footer);
+    }
+    let text: Vec<Line> = lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 {
+                Line::styled(line, Style::default().add_modifier(Modifier::BOLD))
+            } else {
+                Line::raw(line)
+            }
+        })
+        .collect();
+    let paragraph = Paragraph::new(text).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" Finished jobs "),
+    );
+    f.render_widget(paragraph, area);
+}
+
+/// One box per GPU: memory, utilization, what runs on it, its stats and
+/// sparklines as tall as the box allows.
+pub fn draw_gpus(f: &mut Frame, area: Rect, state: &AppState) {
+    if state.gpus.is_empty() {
+        let paragraph =
+            Paragraph::new("No GPUs").block(Block::default().borders(Borders::ALL).title(" GPUs "));
+        f.render_widget(paragraph, area);
+        return;
+    }
+    let stats = GpuStats::collect(&state.jobs);
+    let boxes = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![
+            Constraint::Ratio(1, state.gpus.len() as u32);
+            state.gpus.len()
+        ])
+        .split(area);
+    for (gpu, &area) in state.gpus.iter().zip(boxes.iter()) {
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" GPU {}: {} ", gpu.id, gpu.name));
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+
+        let used = gpu.total_memory_mb.saturating_sub(gpu.free_memory_mb);
+        let mut lines = vec![Line::from(format!(
+            "Memory {} / {} MB, utilization {}",
+            used,
+            gpu.total_memory_mb,
+            gpu.utilization
+                .map(|u| format!("{}%", u))
+                .unwrap_or_else(|| "unknown".to_string())
+        ))];
+        let running: Vec<String> = state
+            .jobs
+            .iter()
+            .filter(|job| matches!(job.state, JobState::Running { gpu_id } if gpu_id == gpu.id))
+            .map(|job| format!("{} {}", &job.id.to_string()[..8], job.cmd))
+            .collect();
+        lines.push(Line::from(match running.as_slice() {
+            [] => "Idle".to_string(),
+            jobs => format!("Running {}", jobs.join(", ")),
+        }));
+        lines.push(Line::from(match stats.get(&gpu.id) {
+            Some(stats) => format!(
+                "{} jobs done, busy {}{}",
+                stats.finished,
+                humanize(stats.busy),
+                stats
+                    .average()
+                    .map(|avg| format!(", avg {}", humanize(avg)))
+                    .unwrap_or_default()
+            ),
+            None => "No jobs yet".to_string(),
+        }));
+        let text_height = lines.len() as u16;
+        f.render_widget(
+            Paragraph::new(lines),
+            Rect {
+                height: text_height.min(inner.height),
+                ..inner
+            },
+        );
+
+        let charts = Rect {
+            y: inner.y + text_height.min(inner.height),
+            height: inner.height.saturating_sub(text_height),
+            ..inner
+        };
+        if charts.height == 0 {
+            continue;
+        }
+        let halves = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(charts);
+        for (area, title, history, color) in [
+            (halves[0], " memory % ", &gpu.memory_history, Color::Magenta),
+            (
+                halves[1],
+                " utilization % ",
+                &gpu.utilization_history,
+                Color::Green,
+            ),
+        ] {
+            let width = area.width.saturating_sub(2) as usize;
+            let data: Vec<u64> = history
+                .iter()
+                .skip(history.len().saturating_sub(width))
+                .copied()
+                .collect();
+            f.render_widget(
+                Sparkline::default()
+                    .block(Block::default().borders(Borders::TOP).title(title))
+                    .data(&data)
+                    .max(100)
+                    .style(Style::default().fg(color)),
+                area,
+            );
+        }
+    }
+}
+
+/// Counts by state, throughput and the end-of-run summaries, so far.
+pub fn draw_stats(f: &mut Frame, area: Rect, state: &AppState) {
+    let count =
+        |matches: fn(&JobState) -> bool| state.jobs.iter().filter(|j| matches(&j.state)).count();
+    let queued = count(|s| matches!(s, JobState::Queued));
+    let running = count(|s| matches!(s, JobState::Running { .. }));
+    let completed = count(|s| matches!(s, JobState::Completed));
+    let failed = count(JobState::is_failure);
+    let cancelled = count(|s| matches!(s, JobState::Cancelled));
+
+    let elapsed = state.run_started.elapsed();
+    let finished = completed + failed + cancelled;
+    let per_hour = finished as f64 / elapsed.as_secs_f64().max(1.0) * 3600.0;
+    let durations: Vec<_> = state
+        .jobs
+        .iter()
+        .filter(|j| j.state.is_finished())
+        .filter_map(|j| j.timing.elapsed())
+        .collect();
+    let mean = (!durations.is_empty())
+        .then(|| durations.iter().sum::<Duration>() / durations.len() as u32);
+
+    let label = |text: &'static str| Span::styled(text, Style::default().fg(Color::Cyan));
+    let mut lines = vec![
+        Line::from(vec![
+            label("Jobs      "),
+            Span::raw(format!(
+                "{} total: {} queued, {} running, {} done, {} failed, {} cancelled",
+                state.jobs.len(),
+                queued,
+                running,
+                completed,
+                failed,
+                cancelled
+            )),
+        ]),
+        Line::from(vec![
+            label("Running   "),
+            Span::raw(format!("for {}", humanize(elapsed))),
+        ]),
+        Line::from(vec![
+            label("Throughput"),
+            Span::raw(format!(" {:.1} jobs per hour", per_hour)),
+        ]),
+        Line::from(vec![
+            label("Duration  "),
+            Span::raw(
+                mean.map(|d| format!("{} on average", humanize(d)))
+                    .unwrap_or_else(|| "no job has finished".to_string()),
+            ),
+        ]),
+        Line::raw(""),
+    ];
+    let strip = |line: String| Line::raw(line.trim_start_matches("[gparallel] ").to_string());
+    if let Some(wait) = summary::queue_wait_summary(state) {
+        lines.push(strip(wait));
+    }
+    if finished > 0 {
+        lines.extend(summary::exit_summary(state).into_iter().map(strip));
+    }
+    let paragraph = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" Run statistics "),
+    );
+    f.render_widget(paragraph, area);
+}
+
+/// What a GPU runs now and the queued jobs it is expected to start next.
+pub struct Lane<'a> {
+    pub running: Vec<&'a JobInfo>,
+    /// With how long until each starts, once a job has finished to go by
+    pub next: Vec<(&'a JobInfo, Option<Duration>)>,
+}
+
+/// One lane per GPU, guessing that every job takes as long as the finished
+/// ones did on average: queued jobs go, in the order they start, to the
+/// GPUs that free up first. At most `depth` upcoming jobs per lane.
+pub fn lanes(state: &AppState, depth: usize) -> Vec<Lane<'_>> {
+    let durations: Vec<Duration> = state
+        .jobs
+        .iter()
+        .filter(|j| j.state.is_finished())
+        .filter_map(|j| j.timing.elapsed())
+        .collect();
+    let mean = (!durations.is_empty())
+        .then(|| durations.iter().sum::<Duration>() / durations.len() as u32);
+    // Without a duration to go by, hand out queued jobs in turn
+    let guess = mean.unwrap_or(Duration::from_secs(1));
+
+    let mut lanes: Vec<Lane> = Vec::new();
+    let mut free_in: Vec<Duration> = Vec::new();
+    for gpu in &state.gpus {
+        let running: Vec<&JobInfo> = state
+            .jobs
+            .iter()
+            .filter(|job| matches!(job.state, JobState::Running { gpu_id } if gpu_id == gpu.id))
+            .collect();
+        let left = running
+            .iter()
+            .map(|job| guess.saturating_sub(job.timing.elapsed().unwrap_or_default()))
+            .max()
+            .unwrap_or_default();
+        free_in.push(left);
+        lanes.push(Lane {
+            running,
+            next: Vec::new(),
+        });
+    }
+
+    let mut queued: Vec<&JobInfo> = state
+        .jobs
+        .iter()
+        .filter(|job| matches!(job.state, JobState::Queued))
+        .collect();
+    queued.sort_by_key(|job| std::cmp::Reverse(job.priority));
+    for job in queued {
+        let mut order: Vec<usize> = (0..lanes.len()).collect();
+        order.sort_by_key(|&i| free_in[i]);
+        let taken = &order[..(job.requested_gpus.max(1) as usize).min(order.len())];
+        let Some(start) = taken.iter().map(|&i| free_in[i]).max() else {
+            break;
+        };
+        for &i in taken {
+            free_in[i] = start + guess;
+            if lanes[i].next.len() < depth {
+                lanes[i].next.push((job, mean.map(|_| start)));
+            }
+        }
+        if lanes.iter().all(|lane| lane.next.len() >= depth) {
+            break;
+        }
+    }
+    lanes
+}
+
+/// A column per GPU, like a Gantt chart on its side: the running job on
+/// top, then the queued jobs expected to start there next.
+pub fn draw_lanes(f: &mut Frame, area: Rect, state: &AppState) {
+    let title = if state.paused {
+        " Lanes (paused) "
+    } else {
+        " Lanes "
+    };
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    if state.gpus.is_empty() {
+        f.render_widget(Paragraph::new("No GPUs"), inner);
+        return;
+    }
+
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![
+            Constraint::Ratio(1, state.gpus.len() as u32);
+            state.gpus.len()
+        ])
+        .split(inner);
+    // Two rows per job under the column's title
+    let depth = (inner.height.saturating_sub(2) as usize / 2).saturating_sub(1);
+    for ((gpu, lane), &column) in state
+        .gpus
+        .iter()
+        .zip(lanes(state, depth))
+        .zip(columns.iter())
+    {
+        let short_id = |job: &JobInfo| job.id.to_string()[..8].to_string();
+        let mut lines = Vec::new();
+        for job in &lane.running {
+            lines.push(Line::styled(
+                format!(
+                    "▶ {} {}",
+                    short_id(job),
+                    job.timing.elapsed().map(humanize).unwrap_or_default()
+                ),
+                Style::default()
+                    .fg(Color::Green)
+                    .add_modifier(Modifier::BOLD),
+            ));
+            lines.push(Line::raw(format!("  {}", job.cmd)));
+        }
+        if lane.running.is_empty() {
+            lines.push(Line::styled("Idle", Style::default().fg(Color::DarkGray)));
+            lines.push(Line::raw(""));
+        }
+        for (i, (job, starts_in)) in lane.next.iter().enumerate() {
+            let when = starts_in
+                .map(|d| format!(" in ~{}", humanize(d)))
+                .unwrap_or_default();
+            lines.push(Line::styled(
+                format!("{}. {}{}", i + 1, short_id(job), when),
+                Style::default().fg(Color::Yellow),
+            ));
+            lines.push(Line::raw(format!("  {}", job.cmd)));
+        }
+        let paragraph = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::TOP | Borders::RIGHT)
+                .title(format!(" GPU {}: {} ", gpu.id, gpu.name)),
+        );
+        f.render_widget(paragraph, column);
+    }
+}
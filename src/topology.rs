@@ -0,0 +1,206 @@
+//! How this machine's GPUs are wired to each other. A multi-GPU job
+//! all-reduces at the speed of the slowest link between its GPUs, so
+//! gangs are placed on GPUs joined by NVLink, else behind one PCIe switch,
+//! else on one NUMA node, as far as the free ones allow.
+
+use nvml_wrapper::Nvml;
+use std::collections::HashMap;
+
+/// How two GPUs reach each other, fastest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(clippy::enum_variant_names)]
+pub enum Link {
+    NvLink,
+    /// Through at most one PCIe switch, or on one board
+    PcieSwitch,
+    /// Through several PCIe switches, short of a host bridge
+    PcieSwitches,
+    HostBridge,
+    /// Across the host bridges of one NUMA node
+    NumaNode,
+    /// Across NUMA nodes, or not known
+    System,
+}
+
+/// Links between pairs of local GPUs, by NVML index. Pairs it holds
+/// nothing for, such as remote GPUs or without NVML, count as `System`.
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+    links: HashMap<(u32, u32), Link>,
+}
+
+impl Topology {
+    pub fn link(&self, a: u32, b: u32) -> Link {
+        self.links
+            .get(&(a.min(b), a.max(b)))
+            .copied()
+            .unwrap_or(Link::System)
+    }
+
+    fn insert(&mut self, a: u32, b: u32, link: Link) {
+        self.links.insert((a.min(b), a.max(b)), link);
+    }
+
+    /// The `count` of `gpus` (distinct, in the order the pool offers them)
+    /// whose slowest link is fastest, then whose links are fastest
+    /// overall. Grown greedily from each GPU in turn, which finds the
+    /// NVLink islands and PCIe switches real machines are built of without
+    /// trying every combination. With nothing known it is the first
+    /// `count`.
+    pub fn best_set(&self, gpus: &[u32], count: usize) -> Vec<u32> {
+        let mut best: Option<((Link, usize), Vec<u32>)> = None;
+        for &first in gpus {
+            let mut set = vec![first];
+            while set.len() < count {
+                let next = gpus
+                    .iter()
+                    .filter(|gpu| !set.contains(gpu))
+                    .min_by_key(|gpu| self.cost(&set, **gpu));
+                let Some(&next) = next else {
+                    break;
+                };
+                set.push(next);
+            }
+            if set.len() < count {
+                return gpus.iter().copied().take(count).collect();
+            }
+            let cost = self.set_cost(&set);
+            if best.as_ref().is_none_or(|(best, _)| cost < *best) {
+                best = Some((cost, set));
+            }
+        }
+        best.map(|(_, set)| set).unwrap_or_default()
+    }
+
+    /// The slowest link from `gpu` to any of `set`, and the sum of all.
+    fn cost(&self, set: &[u32], gpu: u32) -> (Link, usize) {
+        let links = set.iter().map(|other| self.link(*other, gpu));
+        let worst = links.clone().max().unwrap_or(Link::NvLink);
+        (worst, links.map(|link| link as usize).sum())
+    }
+
+    fn set_cost(&self, set: &[u32]) -> (Link, usize) {
+        let mut worst = Link::NvLink;
+        let mut total = 0;
+        for (i, a) in set.iter().enumerate() {
+            for b in &set[i + 1..] {
+                let link = self.link(*a, *b);
+                worst = worst.max(link);
+                total += link as usize;
+            }
+        }
+        (worst, total)
+    }
+}
+
+/// Ask `nvml` how the GPUs at `indices` are connected. NVLink peers are
+/// found by the PCI address at the far end of each active link; a link
+/// ending at an NVSwitch joins its GPU to every other GPU on one. Blocks on
+/// NVML.
+#[cfg(target_os = "linux")]
+pub fn detect(nvml: &Nvml, indices: &[u32]) -> Topology {
+    use nvml_wrapper::enum_wrappers::device::TopologyLevel;
+    use std::collections::HashSet;
+
+    /// NVML_NVLINK_MAX_LINKS
+    const MAX_LINKS: u32 = 18;
+
+    let mut topology = Topology::default();
+    let devices: Vec<_> = indices
+        .iter()
+        .filter_map(|index| Some((*index, nvml.device_by_index(*index).ok()?)))
+        .collect();
+    let by_bus_id: HashMap<String, u32> = devices
+        .iter()
+        .filter_map(|(index, device)| Some((bus_id(&device.pci_info().ok()?.bus_id), *index)))
+        .collect();
+    let mut nvlinked = HashSet::new();
+    let mut on_switch = HashSet::new();
+    for (index, device) in &devices {
+        for link in (0..MAX_LINKS).map(|link| device.link_wrapper_for(link)) {
+            if !link.is_active().unwrap_or(false) {
+                continue;
+            }
+            let Ok(remote) = link.remote_pci_info() else {
+                continue;
+            };
+            match by_bus_id.get(&bus_id(&remote.bus_id)) {
+                Some(peer) => {
+                    nvlinked.insert((*index.min(peer), *index.max(peer)));
+                }
+                None => {
+                    on_switch.insert(*index);
+                }
+            }
+        }
+    }
+    for (i, (a, device)) in devices.iter().enumerate() {
+        for (b, _) in &devices[i + 1..] {
+            if nvlinked.contains(&(*a.min(b), *a.max(b)))
+                || (on_switch.contains(a) && on_switch.contains(b))
+            {
+                topology.insert(*a, *b, Link::NvLink);
+                continue;
+            }
+            let Ok(other) = nvml.device_by_index(*b) else {
+                continue;
+            };
+            let link = match device.topology_common_ancestor(other) {
+                Ok(TopologyLevel::Internal | TopologyLevel::Single) => Link::PcieSwitch,
+                Ok(TopologyLevel::Multiple) => Link::PcieSwitches,
+                Ok(TopologyLevel::HostBridge) => Link::HostBridge,
+                Ok(TopologyLevel::Node) => Link::NumaNode,
+                _ => continue,
+            };
+            topology.insert(*a, *b, link);
+        }
+    }
+    topology
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn detect(_nvml: &Nvml, _indices: &[u32]) -> Topology {
+    Topology::default()
+}
+
+/// A PCI address as NVML writes it, with its domain in 4 or 8 digits
+/// depending on the call, reduced to one form.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn bus_id(id: &str) -> String {
+    let id = id.trim().to_ascii_lowercase();
+    match id.split_once(':') {
+        Some((domain, rest)) => format!("{}:{}", domain.trim_start_matches('0'), rest),
+        None => id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gangs_go_to_the_best_connected_gpus() {
+        // Two NVLink pairs, (0, 2) and (1, 3), on one NUMA node
+        let mut topology = Topology::default();
+        for a in 0..4 {
+            for b in a + 1..4 {
+                topology.insert(a, b, Link::NumaNode);
+            }
+        }
+        topology.insert(0, 2, Link::NvLink);
+        topology.insert(1, 3, Link::NvLink);
+        topology.insert(4, 5, Link::PcieSwitch);
+
+        assert_eq!(topology.best_set(&[0, 1, 2, 3], 2), vec![0, 2]);
+        assert_eq!(topology.best_set(&[0, 1, 3], 2), vec![1, 3]);
+        assert_eq!(topology.best_set(&[3, 4, 5], 2), vec![4, 5]);
+        // Nothing known: the pool's order
+        assert_eq!(topology.best_set(&[7, 6, 8], 2), vec![7, 6]);
+        assert_eq!(topology.link(2, 0), Link::NvLink);
+        assert_eq!(
+            bus_id("0000:07:00.0"),
+            bus_id("00000000:07:00.0"),
+            "domains are written in 4 or 8 digits"
+        );
+    }
+}
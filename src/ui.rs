@@ -1,6 +1,10 @@
 use anyhow::Result;
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -9,27 +13,336 @@ use ratatui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Paragraph, Wrap},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Sparkline, Tabs, Wrap},
     Frame, Terminal,
 };
-use std::{collections::VecDeque, io, sync::Arc, time::Duration};
-use tokio::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc::UnboundedSender, RwLock};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+use crate::alert::FailureFlash;
+use crate::duration::humanize;
+use crate::host::HostUsage;
+use crate::jobfile::{JobRequest, Priority};
+use crate::tabs::{self, Tab};
+
+/// Output lines kept per job; older ones are dropped.
+pub const LOG_LINES: usize = 1000;
+
+/// Lines PageUp / PageDown move the live log by.
+const LOG_PAGE: usize = 10;
+
+/// Log lines `Y` copies.
+const COPY_LINES: usize = 50;
+
+/// Columns ←/→ pan the unwrapped live log by.
+const LOG_PAN: u16 = 8;
+
+/// Screens narrower or shorter than this get the compact layout: no GPU
+/// panel or borders, and GPUs summed up in the status bar.
+const COMPACT_WIDTH: u16 = 100;
+const COMPACT_HEIGHT: u16 = 28;
+
+/// Samples kept per GPU for the sparklines of the GPU panel.
+pub const GPU_HISTORY: usize = 60;
+
+/// How often the TUI looks for changes to redraw, unless `--refresh-ms`
+/// says otherwise.
+pub const REFRESH: Duration = Duration::from_millis(100);
+
+/// Silence after which a running job gets an idle badge in the job list.
+const IDLE_BADGE: Duration = Duration::from_secs(60);
+
+/// Silence after which the idle badge turns red, unless `--hung-after`
+/// says otherwise.
+pub const HUNG_AFTER: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GpuInfo {
     pub id: u32,
     pub name: String,
     pub free_memory_mb: u64,
     pub total_memory_mb: u64,
+    /// Latest compute utilization in percent, where NVML reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub utilization: Option<u32>,
+    /// Core temperature in °C, where NVML reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<u32>,
+    /// Recent samples of the memory in use, in percent, oldest first
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub memory_history: VecDeque<u64>,
+    /// Recent samples of `utilization`, oldest first
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub utilization_history: VecDeque<u64>,
+    /// Why the GPU is quarantined, if it is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fault: Option<String>,
+    /// Who else runs processes on it ("PID 4242 (bob)"), while gparallel
+    /// keeps its jobs off it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_use_by: Option<String>,
+    /// Compute processes on it as of the latest sample
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub processes: Vec<GpuProcess>,
+}
+
+/// A compute process NVML sees on a GPU.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GpuProcess {
+    pub pid: u32,
+    /// GPU memory it holds, where the driver tells (not in most containers)
+    pub memory_mb: Option<u64>,
+    /// The job whose process tree it is in; none for anyone else's process
+    pub job: Option<Uuid>,
+}
+
+impl GpuInfo {
+    /// A GPU not sampled yet.
+    pub fn new(id: u32, name: impl Into<String>) -> Self {
+        Self {
+            id,
+            name: name.into(),
+            free_memory_mb: 0,
+            total_memory_mb: 0,
+            utilization: None,
+            temperature: None,
+            memory_history: VecDeque::new(),
+            utilization_history: VecDeque::new(),
+            fault: None,
+            in_use_by: None,
+            processes: Vec::new(),
+        }
+    }
+
+    /// Add the current memory and utilization to the history, dropping the
+    /// oldest samples past `GPU_HISTORY`.
+    pub fn record_sample(&mut self) {
+        if self.total_memory_mb > 0 {
+            let used = self.total_memory_mb.saturating_sub(self.free_memory_mb);
+            self.memory_history
+                .push_back(used * 100 / self.total_memory_mb);
+        }
+        if let Some(utilization) = self.utilization {
+            self.utilization_history.push_back(utilization.into());
+        }
+        for history in [&mut self.memory_history, &mut self.utilization_history] {
+            while history.len() > GPU_HISTORY {
+                history.pop_front();
+            }
+        }
+    }
+}
+
+/// What a GPU has got through so far, from the jobs that ran on it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GpuStats {
+    /// Jobs that finished on it, successfully or not
+    pub finished: u32,
+    /// Run time of those jobs and of the one running now
+    pub busy: Duration,
+    /// Run time of the finished jobs alone, for `average`
+    finished_time: Duration,
+}
+
+impl GpuStats {
+    /// Per GPU id, for the GPUs any job has run on.
+    pub fn collect(jobs: &[JobInfo]) -> std::collections::HashMap<u32, GpuStats> {
+        let mut stats = std::collections::HashMap::<u32, GpuStats>::new();
+        for job in jobs {
+            let (Some(gpu), Some(elapsed)) = (job.gpu, job.timing.elapsed()) else {
+                continue;
+            };
+            let entry = stats.entry(gpu).or_default();
+            entry.busy += elapsed;
+            if job.state.is_finished() {
+                entry.finished += 1;
+                entry.finished_time += elapsed;
+            }
+        }
+        stats
+    }
+
+    /// Mean run time of the finished jobs.
+    pub fn average(&self) -> Option<Duration> {
+        (self.finished > 0).then(|| self.finished_time / self.finished)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum JobState {
     Queued,
-    Running { gpu_id: u32 },
+    Running {
+        gpu_id: u32,
+    },
     Completed,
     Failed,
+    /// Stopped for running longer than `--max-runtime`
+    TimedOut,
+    /// A setup step (e.g. `--pre-cmd`) failed, so the command never ran
+    SetupFailed {
+        phase: &'static str,
+    },
+    /// Removed from the queue or stopped on request (`gparallel cancel`)
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Completed
+                | JobState::Failed
+                | JobState::TimedOut
+                | JobState::SetupFailed { .. }
+                | JobState::Cancelled
+        )
+    }
+
+    pub fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            JobState::Failed | JobState::TimedOut | JobState::SetupFailed { .. }
+        )
+    }
+}
+
+/// Which jobs the job queue panel lists (`s` cycles through them).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StateFilter {
+    #[default]
+    All,
+    Running,
+    Failed,
+    Queued,
+}
+
+impl StateFilter {
+    fn next(self) -> Self {
+        match self {
+            StateFilter::All => StateFilter::Running,
+            StateFilter::Running => StateFilter::Failed,
+            StateFilter::Failed => StateFilter::Queued,
+            StateFilter::Queued => StateFilter::All,
+        }
+    }
+
+    fn matches(self, state: &JobState) -> bool {
+        match self {
+            StateFilter::All => true,
+            StateFilter::Running => matches!(state, JobState::Running { .. }),
+            StateFilter::Failed => state.is_failure(),
+            StateFilter::Queued => matches!(state, JobState::Queued),
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            StateFilter::All => "all",
+            StateFilter::Running => "running",
+            StateFilter::Failed => "failed",
+            StateFilter::Queued => "queued",
+        }
+    }
+}
+
+/// When a job started and finished. All durations are computed
+/// from monotonic `Instant`s so clock adjustments during long runs cannot
+/// produce negative or absurd values; `started_at` is for display only.
+#[derive(Debug, Clone, Default)]
+pub struct JobTiming {
+    /// When the job (or its latest retry) became eligible for dispatch
+    pub queued: Option<Instant>,
+    /// Time spent in the queue before dispatch, summed over all attempts
+    pub waited: Duration,
+    pub started: Option<Instant>,
+    pub finished: Option<Instant>,
+    pub started_at: Option<DateTime<Local>>,
+    pub finished_at: Option<DateTime<Local>>,
+}
+
+impl JobTiming {
+    pub fn mark_queued(&mut self) {
+        self.queued = Some(Instant::now());
+    }
+
+    pub fn mark_started(&mut self) {
+        let now = Instant::now();
+        if let Some(queued) = self.queued.take() {
+            self.waited += now.duration_since(queued);
+        }
+        self.started = Some(now);
+        self.started_at = Some(Local::now());
+        self.finished = None;
+        self.finished_at = None;
+    }
+
+    pub fn mark_finished(&mut self) {
+        self.finished = Some(Instant::now());
+        self.finished_at = Some(Local::now());
+    }
+
+    /// Run time so far (or in total, once finished).
+    pub fn elapsed(&self) -> Option<Duration> {
+        let started = self.started?;
+        Some(
+            self.finished
+                .unwrap_or_else(Instant::now)
+                .duration_since(started),
+        )
+    }
+}
+
+/// How a finished job ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum JobExit {
+    Code(i32),
+    Signal(i32),
+    /// The process could not be started at all
+    SpawnFailed,
+    /// Re-adopted after a daemon restart: only a process's parent can see
+    /// its exit status
+    Unknown,
+    /// The agent running it disconnected before it finished
+    Lost,
+}
+
+impl JobExit {
+    pub fn from_status(status: std::process::ExitStatus) -> Self {
+        use std::os::unix::process::ExitStatusExt;
+        match (status.code(), status.signal()) {
+            (Some(code), _) => JobExit::Code(code),
+            (None, Some(sig)) => JobExit::Signal(sig),
+            (None, None) => JobExit::SpawnFailed,
+        }
+    }
+
+    pub fn success(self) -> bool {
+        self == JobExit::Code(0)
+    }
+}
+
+impl std::fmt::Display for JobExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobExit::Code(code) => write!(f, "exit {}", code),
+            JobExit::Signal(sig) => match nix::sys::signal::Signal::try_from(*sig) {
+                Ok(name) => write!(f, "signal {} ({})", sig, name),
+                Err(_) => write!(f, "signal {}", sig),
+            },
+            JobExit::SpawnFailed => write!(f, "spawn failed"),
+            JobExit::Unknown => write!(f, "exit status unknown"),
+            JobExit::Lost => write!(f, "agent lost"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -37,16 +350,456 @@ pub struct JobInfo {
     pub id: Uuid,
     pub cmd: String,
     pub state: JobState,
+    /// Command file the job came from, if any
+    pub source: Option<String>,
+    /// User who submitted the job to the daemon
+    pub owner: Option<String>,
+    /// Per-job environment from a `.jsonl` command file
+    pub env: std::collections::BTreeMap<String, String>,
+    /// GPUs the job runs on at once
+    pub requested_gpus: u32,
+    /// Free GPU memory the job asked for before starting, as given ("12G")
+    pub mem: Option<String>,
+    /// Power cap of its GPUs while it runs, as given ("250W")
+    pub power_limit: Option<String>,
+    pub priority: Priority,
+    /// Name shared by related jobs (`submit --tag`)
+    pub tag: Option<String>,
+    pub timing: JobTiming,
+    /// Set once the job has finished
+    pub exit: Option<JobExit>,
+    /// Times the job was put back in the queue after failing
+    pub retries: u32,
+    /// GPU of the latest attempt
+    pub gpu: Option<u32>,
+    /// Its UUID, which stays with the device if the driver renumbers it
+    pub gpu_uuid: Option<String>,
+    /// GPU memory its processes hold now, by GPU (sampled through NVML)
+    pub gpu_memory_mb: BTreeMap<u32, u64>,
+    /// Most GPU memory its processes held at once, over all its GPUs
+    pub peak_gpu_memory_mb: Option<u64>,
+    /// Directory holding the job's logs and `job.json` (`--output-dir`)
+    pub output_dir: Option<std::path::PathBuf>,
+    /// Set while a process of the job runs
+    pub process: Option<JobProcess>,
     pub log_lines: VecDeque<String>,
+    /// Lines dropped from the front of `log_lines` to stay under `LOG_LINES`
+    pub log_dropped: usize,
+    /// Fraction done, as last printed by the job (`45%`, `epoch 3/10`)
+    pub progress: Option<f64>,
+    /// When the job last printed a line
+    pub last_output: Option<Instant>,
+}
+
+/// A running job process, with what it takes to find it again after a
+/// daemon restart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobProcess {
+    pub pid: u32,
+    /// In clock ticks since boot, to tell a reused PID apart
+    pub start_time: u64,
+    pub gpus: Vec<u32>,
+    /// UUIDs of `gpus` in the same order, to find them again under other
+    /// ids after the driver renumbered them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gpu_uuids: Vec<String>,
+}
+
+impl JobInfo {
+    /// A freshly queued job.
+    pub fn new(id: Uuid, cmd: impl Into<String>) -> Self {
+        Self {
+            id,
+            cmd: cmd.into(),
+            state: JobState::Queued,
+            source: None,
+            owner: None,
+            env: Default::default(),
+            requested_gpus: 1,
+            mem: None,
+            power_limit: None,
+            priority: Priority::Normal,
+            tag: None,
+            timing: JobTiming {
+                queued: Some(Instant::now()),
+                ..Default::default()
+            },
+            exit: None,
+            retries: 0,
+            gpu: None,
+            gpu_uuid: None,
+            gpu_memory_mb: BTreeMap::new(),
+            peak_gpu_memory_mb: None,
+            output_dir: None,
+            process: None,
+            log_lines: VecDeque::new(),
+            log_dropped: 0,
+            progress: None,
+            last_output: None,
+        }
+    }
+
+    /// A request that queues the job again as a new one, with the same
+    /// command and options.
+    pub fn request(&self) -> JobRequest {
+        JobRequest {
+            cmd: self.cmd.clone(),
+            env: self.env.clone(),
+            gpus: Some(self.requested_gpus),
+            mem: self.mem.clone(),
+            power_limit: self.power_limit.clone(),
+            priority: Some(self.priority),
+            tag: self.tag.clone(),
+            source: self.source.clone(),
+            owner: None,
+        }
+    }
+
+    /// Append a captured output line, dropping the oldest past `LOG_LINES`.
+    pub fn push_log_line(&mut self, line: String) {
+        if let Some(progress) = parse_progress(&line) {
+            self.progress = Some(progress);
+        }
+        self.last_output = Some(Instant::now());
+        self.log_lines.push_back(line);
+        if self.log_lines.len() > LOG_LINES {
+            self.log_lines.pop_front();
+            self.log_dropped += 1;
+        }
+    }
+
+    /// Output lines so far, counting dropped ones.
+    pub fn log_line_count(&self) -> usize {
+        self.log_dropped + self.log_lines.len()
+    }
+
+    /// How long a running job has printed nothing, counting from the start
+    /// of its current attempt.
+    pub fn silent_for(&self) -> Option<Duration> {
+        if !matches!(self.state, JobState::Running { .. }) {
+            return None;
+        }
+        let since = self.last_output.max(self.timing.started)?;
+        Some(since.elapsed())
+    }
 }
 
+/// How far a job is by one line of its output, from a percentage (tqdm's
+/// ` 45%|████`) or an `epoch 3/10` / `step 300/1000` count.
+fn parse_progress(line: &str) -> Option<f64> {
+    // Progress bars redraw with \r; the last one is current
+    let line = line.rsplit('\r').find(|part| !part.trim().is_empty())?;
+    percentage(line)
+        .or_else(|| count(line))
+        .map(|p| p.clamp(0.0, 1.0))
+}
+
+/// The last number followed by `%` in `line`, as a fraction.
+fn percentage(line: &str) -> Option<f64> {
+    let mut rest = line;
+    while let Some(at) = rest.rfind('%') {
+        let before = &rest[..at];
+        let start = before
+            .rfind(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .map_or(0, |i| i + 1);
+        if let Ok(percent) = before[start..].parse::<f64>() {
+            if percent <= 100.0 {
+                return Some(percent / 100.0);
+            }
+        }
+        rest = before;
+    }
+    None
+}
+
+/// `i/N` after the word `epoch` or `step`, as a fraction.
+fn count(line: &str) -> Option<f64> {
+    let lower = line.to_ascii_lowercase();
+    ["epoch", "step"].iter().find_map(|word| {
+        let after = &lower[lower.rfind(word)? + word.len()..];
+        let after = after.trim_start_matches(|c: char| " :[(#".contains(c));
+        let (done, rest) = after.split_once('/')?;
+        let total: String = rest.chars().take_while(char::is_ascii_digit).collect();
+        let (done, total) = (done.parse::<u64>().ok()?, total.parse::<u64>().ok()?);
+        (total > 0 && done <= total).then(|| done as f64 / total as f64)
+    })
+}
+
+#[derive(Clone)]
 pub struct AppState {
     pub gpus: Vec<GpuInfo>,
     pub jobs: Vec<JobInfo>,
+    pub run_started: Instant,
     pub selected_job: Option<usize>,
     pub should_quit: bool,
     pub job_scroll_offset: usize,
     pub job_panel_visible_height: usize,
+    /// Set by `--alert-on-first-failure flash`
+    pub failure_flash: Option<FailureFlash>,
+    /// Showing a daemon's jobs (`gparallel attach`): stay open when they
+    /// have all finished, and quitting only detaches
+    pub attached: bool,
+    /// Outcome of the last key press that did something, e.g. a resubmit
+    pub notice: Option<Notice>,
+    /// Keep the live log on the newest output (`f` toggles)
+    pub log_follow: bool,
+    /// Otherwise the live log ends at this line, counting from the first
+    /// line of output, so new lines do not move it
+    pub log_end: usize,
+    /// Wrap long log lines, or else cut them and pan with ←/→ (`w`)
+    pub log_wrap: bool,
+    /// Columns the unwrapped live log is panned right by
+    pub log_pan: u16,
+    pub panels: PanelSizes,
+    pub keys: KeyMap,
+    /// First key of a two-key command (`gg`)
+    pub pending_key: Option<char>,
+    /// The view on screen
+    pub tab: Tab,
+    /// The live log fills the screen (`z`)
+    pub zoomed: bool,
+    /// Queued jobs are held back (`p`)
+    pub paused: bool,
+    /// Jobs the job queue panel lists
+    pub filter: StateFilter,
+    /// Only list jobs whose command contains this (`/`)
+    pub search: String,
+    /// Text being typed in the status bar, taking every key until Enter or Esc
+    pub prompt: Option<Prompt>,
+    /// Text for the terminal to put on the clipboard (`y`, `Y`)
+    pub clipboard: Option<String>,
+    /// The list of keys is shown over the screen (`?`)
+    pub show_help: bool,
+    /// Ctrl+C asks whether to stop every job
+    pub confirm_stop: bool,
+    /// Stopping every job was confirmed; whoever runs them does it once
+    /// the TUI has closed
+    pub stop_all: bool,
+    /// A job failed while another one was on screen
+    pub toast: Option<Notice>,
+    /// The job that failed last, for `F`
+    pub last_failed: Option<Uuid>,
+    /// Ring the terminal bell whenever a job fails (`--bell-on-failure`)
+    pub bell_on_failure: bool,
+    /// Stay open once every job has finished, until `q` (`--keep-open`, `o`)
+    pub keep_open: bool,
+    /// Every job has finished and the TUI stayed open
+    pub run_done: bool,
+    /// Silence that turns a running job's idle badge red (`--hung-after`)
+    pub hung_after: Duration,
+    /// Load and memory of the machine running the jobs
+    pub host: Option<HostUsage>,
+    /// Output lines of every job as they arrived, for the merged log
+    pub timeline: VecDeque<(Uuid, String)>,
+    /// The live log interleaves the output of all jobs (`m`)
+    pub merged_log: bool,
+}
+
+/// What the status bar prompt is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    /// Narrow the job list as the user types
+    Search,
+    /// A command to queue as a new job
+    Submit,
+    /// The command of this finished job, edited, to queue with its options
+    Edit(Uuid),
+}
+
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub text: String,
+    /// Where typed characters go, in characters from the start
+    pub cursor: usize,
+}
+
+impl Prompt {
+    /// A prompt holding `text`, with the cursor at its end.
+    pub fn new(kind: PromptKind, text: impl Into<String>) -> Self {
+        let text = text.into();
+        Self {
+            kind,
+            cursor: text.chars().count(),
+            text,
+        }
+    }
+
+    fn byte_offset(&self) -> usize {
+        self.text
+            .char_indices()
+            .nth(self.cursor)
+            .map_or(self.text.len(), |(i, _)| i)
+    }
+
+    /// Apply an editing key; false if `code` is not one.
+    fn edit(&mut self, code: KeyCode) -> bool {
+        let len = self.text.chars().count();
+        match code {
+            KeyCode::Char(c) => {
+                let at = self.byte_offset();
+                self.text.insert(at, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let at = self.byte_offset();
+                self.text.remove(at);
+            }
+            KeyCode::Delete if self.cursor < len => {
+                let at = self.byte_offset();
+                self.text.remove(at);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(len),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = len,
+            KeyCode::Backspace | KeyCode::Delete => {}
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// How long a notice replaces the status bar.
+const NOTICE_FOR: Duration = Duration::from_secs(4);
+
+/// Keys for moving around, besides the arrows and PgUp/PgDn (`--keys`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum KeyMap {
+    #[default]
+    Arrows,
+    /// Also `j`/`k`, `gg`/`G` and Ctrl-d/Ctrl-u
+    Vim,
+}
+
+/// A move through the job list, or through the log when it is zoomed.
+#[derive(Debug, Clone, Copy)]
+enum Motion {
+    By(isize),
+    HalfPageDown,
+    HalfPageUp,
+    First,
+    Last,
+}
+
+/// How the screen is split, changed with `+`/`-` (GPU panel and job list
+/// against the log) and `<`/`>` (GPU panel against the job list), and kept
+/// for the next run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PanelSizes {
+    /// Height of the GPU panel and job list, in percent of the screen
+    pub top: u16,
+    /// Width of the GPU panel, in percent of the screen
+    pub gpus: u16,
+}
+
+impl Default for PanelSizes {
+    fn default() -> Self {
+        Self { top: 40, gpus: 30 }
+    }
+}
+
+impl PanelSizes {
+    const STEP: u16 = 5;
+    const RANGE: std::ops::RangeInclusive<u16> = 10..=90;
+
+    fn path() -> std::path::PathBuf {
+        crate::store::data_dir().join("layout.json")
+    }
+
+    /// The sizes left by the last run, or the defaults.
+    pub fn load() -> Self {
+        std::fs::read(Self::path())
+            .ok()
+            .and_then(|data| serde_json::from_slice::<Self>(&data).ok())
+            .filter(|sizes| Self::RANGE.contains(&sizes.top) && Self::RANGE.contains(&sizes.gpus))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    fn resize(size: &mut u16, grow: bool) {
+        let resized = if grow {
+            *size + Self::STEP
+        } else {
+            size.saturating_sub(Self::STEP)
+        };
+        *size = resized.clamp(*Self::RANGE.start(), *Self::RANGE.end());
+    }
+
+    /// The GPU panel, job list, log panel and status bar. A `compact`
+    /// screen has no GPU panel: the job list takes the whole width.
+    fn split(&self, area: Rect, compact: bool) -> [Rect; 4] {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Percentage(self.top),
+                Constraint::Min(0),
+                Constraint::Length(1),
+            ])
+            .split(area);
+
+        let top_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([
+                Constraint::Percentage(self.gpus),
+                Constraint::Percentage(100 - self.gpus),
+            ])
+            .split(chunks[0]);
+        if compact {
+            return [Rect::default(), chunks[0], chunks[1], chunks[2]];
+        }
+        [top_chunks[0], top_chunks[1], chunks[1], chunks[2]]
+    }
+}
+
+/// A message shown in place of the status bar for a few seconds.
+#[derive(Debug, Clone)]
+pub struct Notice {
+    pub message: String,
+    pub until: Instant,
+}
+
+impl Notice {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            until: Instant::now() + NOTICE_FOR,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        Instant::now() < self.until
+    }
+}
+
+/// Something asked for in the TUI that whoever runs the jobs carries out:
+/// the scheduler of `gparallel run`, or the daemon behind `gparallel attach`.
+#[derive(Debug)]
+pub enum Action {
+    /// Queue a new job
+    Submit(JobRequest),
+    /// Hold queued jobs back, or let them start again
+    SetPaused(bool),
+    /// Swap a queued job with the one ahead of it (`up`) or behind it
+    Shift { job: Uuid, up: bool },
+}
+
+/// What the TUI says once the queue was paused or resumed.
+pub fn pause_notice(paused: bool) -> String {
+    if paused {
+        "Queue paused: no new jobs start, running ones carry on".to_string()
+    } else {
+        "Queue resumed".to_string()
+    }
 }
 
 impl AppState {
@@ -54,43 +807,162 @@ impl AppState {
         Self {
             gpus: vec![],
             jobs: vec![],
+            run_started: Instant::now(),
             selected_job: None,
             should_quit: false,
             job_scroll_offset: 0,
             job_panel_visible_height: 10, // Default fallback
+            failure_flash: None,
+            attached: false,
+            notice: None,
+            log_follow: true,
+            log_end: 0,
+            log_wrap: true,
+            log_pan: 0,
+            panels: PanelSizes::default(),
+            keys: KeyMap::Arrows,
+            pending_key: None,
+            tab: Tab::Queue,
+            zoomed: false,
+            paused: false,
+            filter: StateFilter::All,
+            search: String::new(),
+            prompt: None,
+            clipboard: None,
+            show_help: false,
+            confirm_stop: false,
+            stop_all: false,
+            toast: None,
+            last_failed: None,
+            bell_on_failure: false,
+            keep_open: false,
+            run_done: false,
+            hung_after: HUNG_AFTER,
+            host: None,
+            timeline: VecDeque::new(),
+            merged_log: false,
         }
     }
+
+    /// Append an output line of job `job_id` to its log and the timeline;
+    /// false if there is no such job.
+    pub fn push_log_line(&mut self, job_id: Uuid, line: String) -> bool {
+        let Some(job) = self.jobs.iter_mut().find(|j| j.id == job_id) else {
+            return false;
+        };
+        job.push_log_line(line.clone());
+        self.timeline.push_back((job_id, line));
+        if self.timeline.len() > LOG_LINES {
+            self.timeline.pop_front();
+        }
+        true
+    }
+
+    /// A copy to draw from, so that drawing does not hold the lock. Output
+    /// is only copied for the log on screen, the selected job's or the
+    /// merged one; other lines are set aside while the rest is cloned.
+    pub fn snapshot(&mut self) -> AppState {
+        let selected = self.selected_job;
+        let logs: Vec<Option<VecDeque<String>>> = self
+            .jobs
+            .iter_mut()
+            .enumerate()
+            .map(|(i, job)| (Some(i) != selected).then(|| std::mem::take(&mut job.log_lines)))
+            .collect();
+        let timeline = (!self.merged_log).then(|| std::mem::take(&mut self.timeline));
+        let snapshot = self.clone();
+        for (job, log) in self.jobs.iter_mut().zip(logs) {
+            if let Some(log) = log {
+                job.log_lines = log;
+            }
+        }
+        if let Some(timeline) = timeline {
+            self.timeline = timeline;
+        }
+        snapshot
+    }
+
+    /// Indexes into `jobs` of those the job queue panel lists, in order.
+    pub fn listed_jobs(&self) -> Vec<usize> {
+        (0..self.jobs.len())
+            .filter(|&i| self.filter.matches(&self.jobs[i].state))
+            .filter(|&i| self.jobs[i].cmd.contains(self.search.as_str()))
+            .collect()
+    }
 }
 
 pub struct UI {
-    terminal: Terminal<CrosstermBackend<io::Stdout>>,
+    terminal: Terminal<CrosstermBackend<Box<dyn Write + Send>>>,
     state: Arc<RwLock<AppState>>,
+    actions: Option<UnboundedSender<Action>>,
+    /// Failed jobs already toasted; those that had failed before the TUI
+    /// came up are taken as seen
+    failures_seen: Option<HashSet<Uuid>>,
+    /// The selected job, to stay on it when the job list is reordered
+    selected: Option<Uuid>,
+    /// How often to look for changes to redraw (`--refresh-ms`)
+    refresh: Duration,
 }
 
 impl UI {
-    pub async fn new(state: Arc<RwLock<AppState>>) -> Result<Self> {
+    /// Set up the terminal on stdout, or on stderr when `on_stderr` is set so
+    /// that stdout stays free for piping.
+    pub async fn new(state: Arc<RwLock<AppState>>, on_stderr: bool) -> Result<Self> {
         // Check if we can actually enable raw mode (requires a real TTY)
-        if !atty::is(atty::Stream::Stdout) {
-            return Err(anyhow::anyhow!("TUI requires stdout to be a terminal"));
+        let (stream, name) = if on_stderr {
+            (atty::Stream::Stderr, "stderr")
+        } else {
+            (atty::Stream::Stdout, "stdout")
+        };
+        if !atty::is(stream) {
+            return Err(anyhow::anyhow!("TUI requires {} to be a terminal", name));
         }
 
         enable_raw_mode().map_err(|e| anyhow::anyhow!("Failed to enable raw mode: {}", e))?;
 
-        let mut stdout = io::stdout();
-        execute!(stdout, EnterAlternateScreen, EnableMouseCapture)
+        let mut out: Box<dyn Write + Send> = if on_stderr {
+            Box::new(io::stderr())
+        } else {
+            Box::new(io::stdout())
+        };
+        execute!(out, EnterAlternateScreen, EnableMouseCapture)
             .map_err(|e| anyhow::anyhow!("Failed to setup terminal: {}", e))?;
-        let backend = CrosstermBackend::new(stdout);
+        let backend = CrosstermBackend::new(out);
         let terminal = Terminal::new(backend)
             .map_err(|e| anyhow::anyhow!("Failed to create terminal: {}", e))?;
 
-        Ok(Self { terminal, state })
+        state.write().await.panels = PanelSizes::load();
+        Ok(Self {
+            terminal,
+            state,
+            actions: None,
+            failures_seen: None,
+            selected: None,
+            refresh: REFRESH,
+        })
+    }
+
+    /// Let keys such as `r` act on jobs by sending to `actions`.
+    pub fn with_actions(mut self, actions: UnboundedSender<Action>) -> Self {
+        self.actions = Some(actions);
+        self
+    }
+
+    /// Look for changes to redraw every `refresh` rather than `REFRESH`.
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
     }
 
-    pub async fn run(mut self) -> Result<()> {
+    /// Draw and handle keys until the user quits, all jobs finish, or
+    /// `cancel` fires.
+    pub async fn run(mut self, cancel: CancellationToken) -> Result<()> {
+        // What the screen last showed; key presses and resizes clear it
+        let mut drawn = None;
         loop {
-            {
+            let snapshot = {
                 let mut state = self.state.write().await;
-                if state.should_quit {
+                if state.should_quit || cancel.is_cancelled() {
                     break;
                 }
 
@@ -98,192 +970,353 @@ impl UI {
                 if state.selected_job.is_none() && !state.jobs.is_empty() {
                     state.selected_job = Some(0);
                 }
+                keep_selected(&mut state, self.selected);
+                self.selected = selected_id(&state);
+
+                let seen = self
+                    .failures_seen
+                    .get_or_insert_with(|| failed_jobs(&state));
+                if toast_new_failures(&mut state, seen) && state.bell_on_failure {
+                    let out = self.terminal.backend_mut();
+                    out.write_all(b"\x07")?;
+                    out.flush()?;
+                }
 
-                // Auto-exit when all jobs are done
-                if !state.jobs.is_empty()
-                    && state
-                        .jobs
-                        .iter()
-                        .all(|j| matches!(j.state, JobState::Completed | JobState::Failed))
-                {
+                if finish_run(&mut state) {
                     break;
                 }
+                let fingerprint = screen_fingerprint(&state);
+                (drawn != Some(fingerprint)).then(|| {
+                    drawn = Some(fingerprint);
+                    state.snapshot()
+                })
+            };
+
+            // Jobs keep writing their output while the screen is drawn
+            if let Some(snapshot) = snapshot {
+                self.terminal.draw(|f| Self::draw_ui_static(f, &snapshot))?;
             }
 
-            self.terminal.draw(|f| {
-                let state = self.state.clone();
-                tokio::task::block_in_place(|| {
-                    let rt = tokio::runtime::Handle::current();
-                    rt.block_on(async {
-                        let state = state.read().await;
-                        Self::draw_ui_static(f, &*state);
-                    });
-                });
-            })?;
-
-            if event::poll(Duration::from_millis(100))? {
-                if let Event::Key(key) = event::read()? {
+            if event::poll(self.refresh)? {
+                let event = event::read()?;
+                if matches!(event, Event::Key(_) | Event::Resize(..)) {
+                    drawn = None;
+                }
+                if let Event::Key(key) = event {
                     let mut state = self.state.write().await;
-                    match key.code {
-                        KeyCode::Char('q') => state.should_quit = true,
-                        KeyCode::Up => {
-                            if !state.jobs.is_empty() {
-                                let new_selected = match state.selected_job {
-                                    Some(i) => i.saturating_sub(1),
-                                    None => 0,
-                                };
-                                state.selected_job = Some(new_selected);
-
-                                // Adjust scroll offset if selection goes above visible area
-                                if new_selected < state.job_scroll_offset {
-                                    state.job_scroll_offset = new_selected;
-                                }
-                            }
-                        }
-                        KeyCode::Down => {
-                            if !state.jobs.is_empty() {
-                                let new_selected = match state.selected_job {
-                                    Some(i) => (i + 1).min(state.jobs.len() - 1),
-                                    None => 0,
-                                };
-                                state.selected_job = Some(new_selected);
-
-                                let visible_height = state.job_panel_visible_height;
-
-                                // Adjust scroll offset if selection goes below visible area
-                                if new_selected >= state.job_scroll_offset + visible_height {
-                                    state.job_scroll_offset =
-                                        new_selected.saturating_sub(visible_height - 1);
-                                }
-                            }
-                        }
-                        _ => {}
+                    // For scrolling the job list by what the panel shows
+                    let screen = self.terminal.size()?;
+                    let (_, body) = Self::split_tab_bar(screen);
+                    let compact = is_compact(screen);
+                    let [_, job_area, _, _] = state.panels.split(body, compact);
+                    state.job_panel_visible_height = panel_block(String::new(), compact)
+                        .inner(job_area)
+                        .height
+                        .max(1) as usize;
+                    handle_key(&mut state, key, self.actions.as_ref());
+                    self.selected = selected_id(&state);
+                    if let Some(text) = state.clipboard.take() {
+                        let out = self.terminal.backend_mut();
+                        out.write_all(osc52(&text).as_bytes())?;
+                        out.flush()?;
                     }
                 }
             }
         }
 
+        let panels = self.state.read().await.panels;
+        if panels != PanelSizes::load() {
+            if let Err(e) = panels.save() {
+                tracing::warn!("Failed to remember the TUI layout: {}", e);
+            }
+        }
         Ok(())
     }
 
     fn draw_ui_static(f: &mut Frame, state: &AppState) {
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Percentage(40),
-                Constraint::Percentage(58),
-                Constraint::Length(1),
-            ])
-            .split(f.size());
+        Self::draw_screen(f, state);
+        if let Some(toast) = state.toast.as_ref().filter(|t| t.is_active()) {
+            Self::draw_toast(f, toast);
+        }
+        if state.show_help {
+            Self::draw_help_overlay(f, state);
+        }
+        if state.confirm_stop {
+            Self::draw_stop_dialog(f, state);
+        }
+    }
 
-        let top_chunks = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
-            .split(chunks[0]);
+    fn draw_screen(f: &mut Frame, state: &AppState) {
+        let compact = is_compact(f.size());
+        if state.zoomed {
+            let [log_area, status_area] = Self::split_status_bar(f.size());
+            Self::draw_log_panel(f, log_area, state, compact);
+            Self::draw_status_bar(f, status_area, state, compact);
+            return;
+        }
+        let (tab_bar, body) = Self::split_tab_bar(f.size());
+        Self::draw_tab_bar(f, tab_bar, state);
 
-        Self::draw_gpu_panel(f, top_chunks[0], &state.gpus, &state.jobs);
-        let job_panel_visible_height = top_chunks[1].height.saturating_sub(2) as usize;
+        if state.tab != Tab::Queue {
+            let [area, status_area] = Self::split_status_bar(body);
+            match state.tab {
+                Tab::History => tabs::draw_history(f, area, state),
+                Tab::Gpus => tabs::draw_gpus(f, area, state),
+                Tab::Lanes => tabs::draw_lanes(f, area, state),
+                _ => tabs::draw_stats(f, area, state),
+            }
+            Self::draw_status_bar(f, status_area, state, compact);
+            return;
+        }
+        let [gpu_area, job_area, log_area, status_area] = state.panels.split(body, compact);
 
-        Self::draw_job_queue_panel(
-            f,
-            top_chunks[1],
-            &state.jobs,
-            state.selected_job,
-            state.job_scroll_offset,
-            job_panel_visible_height,
-        );
-        Self::draw_log_panel(f, chunks[1], &state.jobs, state.selected_job);
-        Self::draw_help_line(f, chunks[2], state);
+        if !compact {
+            Self::draw_gpu_panel(f, gpu_area, state);
+        }
+        Self::draw_job_queue_panel(f, job_area, state, compact);
+        Self::draw_log_panel(f, log_area, state, compact);
+        Self::draw_status_bar(f, status_area, state, compact);
+    }
+
+    /// The row of tab titles, and the screen below it.
+    fn split_tab_bar(area: Rect) -> (Rect, Rect) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(1), Constraint::Min(0)])
+            .split(area);
+        (chunks[0], chunks[1])
     }
 
-    fn draw_gpu_panel(f: &mut Frame, area: Rect, gpus: &[GpuInfo], jobs: &[JobInfo]) {
-        let gpu_items: Vec<ListItem> = gpus
+    fn split_status_bar(area: Rect) -> [Rect; 2] {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(1)])
+            .split(area);
+        [chunks[0], chunks[1]]
+    }
+
+    fn draw_tab_bar(f: &mut Frame, area: Rect, state: &AppState) {
+        let current = state.tab;
+        let titles: Vec<String> = Tab::ALL
             .iter()
             .enumerate()
-            .map(|(i, gpu)| {
-                let free_mb = gpu.free_memory_mb;
-                let total_mb = gpu.total_memory_mb;
-                let usage_percent = if total_mb > 0 {
-                    ((total_mb - free_mb) as f32 / total_mb as f32 * 100.0) as u8
-                } else {
-                    0
-                };
+            .map(|(i, tab)| format!("{} {}", i + 1, tab.title()))
+            .collect();
+        let selected = Tab::ALL.iter().position(|&t| t == current).unwrap_or(0);
+        let tabs = Tabs::new(titles)
+            .select(selected)
+            .style(Style::default().fg(Color::DarkGray))
+            .highlight_style(
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD),
+            );
+        f.render_widget(tabs, area);
+        if state.paused {
+            let badge = " PAUSED ";
+            let width = (badge.len() as u16).min(area.width);
+            let badge_area = Rect {
+                x: area.right() - width,
+                width,
+                ..area
+            };
+            let badge = Paragraph::new(badge).style(
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            );
+            f.render_widget(badge, badge_area);
+        }
+    }
 
-                let color = if usage_percent > 80 {
-                    Color::Red
-                } else if usage_percent > 50 {
-                    Color::Yellow
-                } else {
-                    Color::Green
-                };
+    fn draw_gpu_panel(f: &mut Frame, area: Rect, state: &AppState) {
+        let (gpus, jobs) = (&state.gpus, &state.jobs);
+        let mut block = Block::default().borders(Borders::ALL).title(" GPUs ");
+        if let Some(host) = &state.host {
+            block = block.title_bottom(host_line(host, false));
+        }
+        let inner = block.inner(area);
+        f.render_widget(block, area);
 
-                // Check if any job is running on this GPU
-                let running_job = jobs.iter().find(
-                    |job| matches!(job.state, JobState::Running { gpu_id } if gpu_id == gpu.id),
+        // Under each GPU, given room: its stats, then sparklines of memory
+        // and utilization
+        let stats = GpuStats::collect(jobs);
+        let sampled = gpus.iter().any(|gpu| !gpu.memory_history.is_empty());
+        let most_rows = if sampled { 4 } else { 2 };
+        let rows_per_gpu = (inner.height as usize / gpus.len().max(1)).clamp(1, most_rows);
+        for (i, gpu) in gpus.iter().enumerate() {
+            let y = inner.y as usize + i * rows_per_gpu;
+            if y >= inner.bottom() as usize {
+                break;
+            }
+            let row = |offset: usize| Rect::new(inner.x, (y + offset) as u16, inner.width, 1);
+            f.render_widget(
+                Paragraph::new(Self::gpu_line(i, gpu, jobs))
+                    .style(Style::default().fg(Color::White)),
+                row(0),
+            );
+            if rows_per_gpu >= 2 {
+                let (line, color) = match (&gpu.fault, &gpu.in_use_by) {
+                    (Some(fault), _) => (format!("   quarantined: {}", fault), Color::Red),
+                    (None, Some(by)) => (format!("   in use by {}", by), Color::Yellow),
+                    (None, None) => (Self::gpu_stats_line(stats.get(&gpu.id)), Color::DarkGray),
+                };
+                f.render_widget(
+                    Paragraph::new(line).style(Style::default().fg(color)),
+                    row(1),
                 );
+            }
+            if rows_per_gpu >= 4 {
+                Self::draw_sparkline(f, row(2), "mem ", &gpu.memory_history, Color::Magenta);
+                Self::draw_sparkline(f, row(3), "util", &gpu.utilization_history, Color::Green);
+            }
+        }
+    }
 
-                let status_indicator = if running_job.is_some() {
-                    "●" // Filled circle for running
-                } else {
-                    "○" // Empty circle for idle
-                };
+    fn gpu_stats_line(stats: Option<&GpuStats>) -> String {
+        let Some(stats) = stats else {
+            return "   no jobs yet".to_string();
+        };
+        let average = stats
+            .average()
+            .map(|avg| format!(", avg {}", humanize(avg)))
+            .unwrap_or_default();
+        format!(
+            "   {} done, busy {}{}",
+            stats.finished,
+            humanize(stats.busy),
+            average
+        )
+    }
 
-                let status_color = if running_job.is_some() {
-                    Color::Green
-                } else {
-                    Color::DarkGray
-                };
+    fn gpu_line(i: usize, gpu: &GpuInfo, jobs: &[JobInfo]) -> Line<'static> {
+        let free_mb = gpu.free_memory_mb;
+        let total_mb = gpu.total_memory_mb;
+        let usage_percent = if total_mb > 0 {
+            ((total_mb - free_mb) as f32 / total_mb as f32 * 100.0) as u8
+        } else {
+            0
+        };
 
-                ListItem::new(Line::from(vec![
-                    Span::styled(format!("{:<2}", i), Style::default().fg(Color::Cyan)),
-                    Span::raw(" "),
-                    Span::styled(status_indicator, Style::default().fg(status_color)),
-                    Span::raw(" "),
-                    Span::styled(format!("{:<7}", gpu.name), Style::default()),
-                    Span::raw(" "),
-                    Span::styled(format!("{:>6} MB", free_mb), Style::default().fg(color)),
-                ]))
-            })
-            .collect();
+        let color = if usage_percent > 80 {
+            Color::Red
+        } else if usage_percent > 50 {
+            Color::Yellow
+        } else {
+            Color::Green
+        };
 
-        let gpu_list = List::new(gpu_items)
-            .block(Block::default().borders(Borders::ALL).title(" GPUs "))
-            .style(Style::default().fg(Color::White));
+        // Check if any job is running on this GPU
+        let running_job = jobs
+            .iter()
+            .find(|job| matches!(job.state, JobState::Running { gpu_id } if gpu_id == gpu.id));
+
+        let status_indicator = if gpu.fault.is_some() {
+            "✖" // Quarantined
+        } else if running_job.is_some() {
+            "●" // Filled circle for running
+        } else {
+            "○" // Empty circle for idle
+        };
 
-        f.render_widget(gpu_list, area);
+        let status_color = if gpu.fault.is_some() {
+            Color::Red
+        } else if running_job.is_some() {
+            Color::Green
+        } else {
+            Color::DarkGray
+        };
+
+        let mut spans = vec![
+            Span::styled(format!("{:<2}", i), Style::default().fg(Color::Cyan)),
+            Span::raw(" "),
+            Span::styled(status_indicator, Style::default().fg(status_color)),
+            Span::raw(" "),
+            Span::styled(format!("{:<7}", gpu.name), Style::default()),
+            Span::raw(" "),
+            Span::styled(format!("{:>6} MB", free_mb), Style::default().fg(color)),
+        ];
+        if let Some(utilization) = gpu.utilization {
+            spans.push(Span::raw(format!(" {:>3}%", utilization)));
+        }
+        if let Some(temperature) = gpu.temperature {
+            spans.push(Span::raw(format!(" {}°C", temperature)));
+        }
+        Line::from(spans)
     }
 
-    fn draw_job_queue_panel(
+    /// One row: `label`, then the newest `history` samples that fit, on a
+    /// 0-100 scale.
+    fn draw_sparkline(
         f: &mut Frame,
         area: Rect,
-        jobs: &[JobInfo],
-        selected: Option<usize>,
-        scroll_offset: usize,
-        visible_height: usize,
+        label: &str,
+        history: &VecDeque<u64>,
+        color: Color,
     ) {
+        let label_width = label.len() as u16 + 1;
+        f.render_widget(
+            Paragraph::new(label.to_string()).style(Style::default().fg(Color::DarkGray)),
+            area,
+        );
+        let chart = Rect {
+            x: area.x + label_width.min(area.width),
+            width: area.width.saturating_sub(label_width),
+            ..area
+        };
+        let skip = history.len().saturating_sub(chart.width as usize);
+        let data: Vec<u64> = history.iter().skip(skip).copied().collect();
+        f.render_widget(
+            Sparkline::default()
+                .data(&data)
+                .max(100)
+                .style(Style::default().fg(color)),
+            chart,
+        );
+    }
+
+    /// The listed jobs; a `compact` row drops the start time and progress
+    /// gauge, and gives the command what width is left.
+    fn draw_job_queue_panel(f: &mut Frame, area: Rect, state: &AppState, compact: bool) {
+        let visible_height = panel_block(String::new(), compact).inner(area).height as usize;
+        let selected = state.selected_job;
+        let listed = state.listed_jobs();
         // Get the visible slice of jobs
-        let visible_jobs: Vec<(usize, &JobInfo)> = jobs
+        let visible_jobs: Vec<(usize, &JobInfo)> = listed
             .iter()
-            .enumerate()
-            .skip(scroll_offset)
+            .skip(state.job_scroll_offset)
             .take(visible_height)
+            .map(|&i| (i, &state.jobs[i]))
             .collect();
 
         let job_items: Vec<ListItem> = visible_jobs
             .iter()
             .map(|(i, job)| {
                 let state_str = match &job.state {
+                    JobState::Queued if job.retries > 0 => format!("RETRY {} ", job.retries),
                     JobState::Queued => "QUEUE   ".to_string(),
                     JobState::Running { gpu_id } => format!("RUN  G{} ", gpu_id),
                     JobState::Completed => "DONE    ".to_string(),
-                    JobState::Failed => "FAIL    ".to_string(),
+                    // Killed by a signal reads as the shell would report it,
+                    // so an OOM kill shows as FAIL(137)
+                    JobState::Failed => match job.exit {
+                        Some(JobExit::Code(code)) => format!("{:<7} ", format!("FAIL({})", code)),
+                        Some(JobExit::Signal(sig)) => format!("FAIL({}) ", 128 + sig),
+                        _ => "FAIL    ".to_string(),
+                    },
+                    JobState::TimedOut => "TIMEOUT ".to_string(),
+                    JobState::SetupFailed { .. } => "SETUP!  ".to_string(),
+                    JobState::Cancelled => "CANCEL  ".to_string(),
                 };
 
                 let state_color = match &job.state {
                     JobState::Queued => Color::Yellow,
                     JobState::Running { .. } => Color::Green,
                     JobState::Completed => Color::Blue,
-                    JobState::Failed => Color::Red,
+                    JobState::Failed | JobState::TimedOut => Color::Red,
+                    JobState::SetupFailed { .. } => Color::Magenta,
+                    JobState::Cancelled => Color::DarkGray,
                 };
 
                 let id_str = job.id.to_string();
@@ -303,103 +1336,1149 @@ impl UI {
                     Style::default()
                 };
 
-                ListItem::new(Line::from(vec![
+                // Start time, then the live run time or the final duration
+                let started = job
+                    .timing
+                    .started_at
+                    .map(|at| at.format("%H:%M:%S").to_string())
+                    .unwrap_or_default();
+                let elapsed = job.timing.elapsed().map(humanize).unwrap_or_default();
+                let idle = idle_badge(job, state.hung_after)
+                    .map(|(badge, color)| Span::styled(badge, style.fg(color)));
+
+                if compact {
+                    let mut spans = vec![
+                        Span::styled(short_id, style.fg(Color::Cyan)),
+                        Span::styled(" ", style),
+                        Span::styled(state_str, style.fg(state_color)),
+                        Span::styled(format!("{:>8} ", elapsed), style),
+                    ];
+                    if let Some(badge) = idle {
+                        spans.extend([badge, Span::styled(" ", style)]);
+                    }
+                    spans.push(Span::styled(job.cmd.clone(), style));
+                    return ListItem::new(Line::from(spans)).style(style);
+                }
+                let mut spans = vec![
                     Span::styled(short_id, style.fg(Color::Cyan)),
                     Span::styled(" ", style),
                     Span::styled(format!("{:<30}", cmd_display), style),
                     Span::styled(" ", style),
                     Span::styled(state_str, style.fg(state_color)),
-                ]))
-                .style(style)
+                    Span::styled(format!(" {:<8} {:>11}", started, elapsed), style),
+                ];
+                // Ahead of the progress gauge, which narrow panels cut off
+                if let Some(badge) = idle {
+                    spans.extend([Span::styled(" ", style), badge]);
+                }
+                if let Some(usage) = gpu_memory_label(job) {
+                    spans.push(Span::styled(
+                        format!(" {}", usage),
+                        style.fg(Color::Magenta),
+                    ));
+                }
+                spans.push(Span::styled(
+                    job.progress.map(progress_bar).unwrap_or_default(),
+                    style.fg(Color::Green),
+                ));
+                ListItem::new(Line::from(spans)).style(style)
             })
             .collect();
 
+        let mut narrowed = Vec::new();
+        if state.filter != StateFilter::All {
+            narrowed.push(state.filter.label().to_string());
+        }
+        if !state.search.is_empty() {
+            narrowed.push(format!("/{}", state.search));
+        }
+        let title = if narrowed.is_empty() {
+            " Job queue ".to_string()
+        } else {
+            format!(
+                " Job queue : {} ({} of {}) ",
+                narrowed.join(" "),
+                listed.len(),
+                state.jobs.len()
+            )
+        };
         let job_list = List::new(job_items)
-            .block(Block::default().borders(Borders::ALL).title(" Job queue "))
+            .block(panel_block(title, compact))
             .style(Style::default().fg(Color::White));
 
         f.render_widget(job_list, area);
     }
 
-    fn draw_log_panel(f: &mut Frame, area: Rect, jobs: &[JobInfo], selected: Option<usize>) {
-        let title = if let Some(idx) = selected {
-            if let Some(job) = jobs.get(idx) {
-                let id_str = job.id.to_string();
-                let short_id = &id_str[..8];
-                format!(" Live log : job #{} (tail -f) ", short_id)
+    fn draw_log_panel(f: &mut Frame, area: Rect, state: &AppState, compact: bool) {
+        if state.merged_log {
+            return Self::draw_merged_log(f, area, state, compact);
+        }
+        let selected = state.selected_job.and_then(|idx| state.jobs.get(idx));
+        let title = if let Some(job) = selected {
+            let id_str = job.id.to_string();
+            let short_id = &id_str[..8];
+            let peak = job
+                .peak_gpu_memory_mb
+                .map(|mb| format!(", peak {} MiB", mb))
+                .unwrap_or_default();
+            let mode = if state.log_follow {
+                "tail -f".to_string()
             } else {
-                " Live log ".to_string()
+                let new = job.log_line_count().saturating_sub(state.log_end);
+                format!("paused, {} new lines", new)
+            };
+            let mode = match (state.log_wrap, state.log_pan) {
+                (true, _) => mode,
+                (false, 0) => format!("{}, no wrap", mode),
+                (false, pan) => format!("{}, no wrap, col {}", mode, pan + 1),
+            };
+            match &job.source {
+                Some(source) => format!(
+                    " Live log : job #{} from {}{} ({}) ",
+                    short_id, source, peak, mode
+                ),
+                None => format!(" Live log : job #{}{} ({}) ", short_id, peak, mode),
             }
         } else {
             " Live log ".to_string()
         };
 
-        let log_content = if let Some(idx) = selected {
-            if let Some(job) = jobs.get(idx) {
-                if job.log_lines.is_empty() {
-                    format!("No logs yet for job {} ({})", job.id, job.cmd)
-                } else {
-                    job.log_lines.iter().cloned().collect::<Vec<_>>().join("\n")
-                }
+        let log_content = if let Some(job) = selected {
+            if job.log_lines.is_empty() {
+                format!("No logs yet for job {} ({})", job.id, job.cmd)
             } else {
-                "Job not found".to_string()
+                let end = if state.log_follow {
+                    job.log_lines.len()
+                } else {
+                    state.log_end.saturating_sub(job.log_dropped)
+                };
+                let lines: Vec<&str> = job.log_lines.iter().take(end).map(String::as_str).collect();
+                lines.join("\n")
             }
+        } else if state.selected_job.is_some() {
+            "Job not found".to_string()
+        } else if state.jobs.is_empty() {
+            "No jobs available".to_string()
         } else {
-            if jobs.is_empty() {
-                "No jobs available".to_string()
-            } else {
-                "Select a job with ↑/↓ keys".to_string()
-            }
+            "Select a job with ↑/↓ keys".to_string()
         };
 
-        let log_paragraph = Paragraph::new(log_content)
-            .block(Block::default().borders(Borders::ALL).title(title))
-            .wrap(Wrap { trim: false })
-            .style(Style::default().fg(Color::White));
+        let mut log_paragraph =
+            Paragraph::new(log_content).style(Style::default().fg(Color::White));
+        if state.log_wrap {
+            log_paragraph = log_paragraph.wrap(Wrap { trim: false });
+        }
+        // Scroll so that the last of the shown lines sits at the bottom
+        let block = panel_block(title, compact);
+        let inner = block.inner(area);
+        let rows = log_paragraph.line_count(inner.width);
+        let scroll = rows
+            .saturating_sub(inner.height as usize)
+            .min(u16::MAX as usize) as u16;
+        let pan = if state.log_wrap { 0 } else { state.log_pan };
+        let log_paragraph = log_paragraph.block(block).scroll((scroll, pan));
 
         f.render_widget(log_paragraph, area);
     }
 
-    fn draw_help_line(f: &mut Frame, area: Rect, _state: &AppState) {
-        let help_text = Line::from(vec![
-            Span::styled(
-                "↑/↓",
-                Style::default()
-                    .fg(Color::Cyan)
-                    .add_modifier(Modifier::BOLD),
+    /// The newest output of all jobs, interleaved as it arrived, each line
+    /// tagged with its job and GPU in a colour of the job's own.
+    fn draw_merged_log(f: &mut Frame, area: Rect, state: &AppState, compact: bool) {
+        const COLORS: [Color; 6] = [
+            Color::Cyan,
+            Color::Magenta,
+            Color::Yellow,
+            Color::Green,
+            Color::LightBlue,
+            Color::LightRed,
+        ];
+        let lines: Vec<Line> = state
+            .timeline
+            .iter()
+            .map(|(id, line)| {
+                let index = state.jobs.iter().position(|job| job.id == *id);
+                let color = index.map_or(Color::Gray, |i| COLORS[i % COLORS.len()]);
+                let gpu = index
+                    .and_then(|i| match state.jobs[i].state {
+                        JobState::Running { gpu_id } => Some(gpu_id),
+                        _ => state.jobs[i].gpu,
+                    })
+                    .map_or(String::new(), |gpu| format!("G{}", gpu));
+                let tag = format!("{} {:<3}│ ", &id.to_string()[..8], gpu);
+                Line::from(vec![
+                    Span::styled(tag, Style::default().fg(color)),
+                    Span::raw(line.as_str()),
+                ])
+            })
+            .collect();
+        let jobs: HashSet<&Uuid> = state.timeline.iter().map(|(id, _)| id).collect();
+        let mode = if state.log_wrap { "" } else { ", no wrap" };
+        let title = format!(" Live log : {} jobs merged (m){} ", jobs.len(), mode);
+
+        let mut paragraph = if lines.is_empty() {
+            Paragraph::new("No output yet")
+        } else {
+            Paragraph::new(lines)
+        };
+        if state.log_wrap {
+            paragraph = paragraph.wrap(Wrap { trim: false });
+        }
+        let block = panel_block(title, compact);
+        let inner = block.inner(area);
+        let rows = paragraph.line_count(inner.width);
+        let scroll = rows
+            .saturating_sub(inner.height as usize)
+            .min(u16::MAX as usize) as u16;
+        let pan = if state.log_wrap { 0 } else { state.log_pan };
+        f.render_widget(paragraph.block(block).scroll((scroll, pan)), area);
+    }
+
+    /// Run totals and the ETA; on a `compact` screen also a dot per GPU,
+    /// green while it runs a job, and shorter labels.
+    fn draw_status_bar(f: &mut Frame, area: Rect, state: &AppState, compact: bool) {
+        if let Some(flash) = state.failure_flash.as_ref().filter(|f| f.is_active()) {
+            let banner = Paragraph::new(format!("✗ {}", flash.message))
+                .style(
+                    Style::default()
+                        .fg(Color::White)
+                        .bg(Color::Red)
+                        .add_modifier(Modifier::BOLD),
+                )
+                .alignment(Alignment::Center);
+            f.render_widget(banner, area);
+            return;
+        }
+        if let Some(prompt) = &state.prompt {
+            let (label, hint) = match prompt.kind {
+                PromptKind::Search => ("/", "  (Enter to keep, Esc to clear)"),
+                PromptKind::Submit => ("New job: ", "  (Enter to queue, Esc to cancel)"),
+                PromptKind::Edit(_) => ("Edit: ", "  (Enter to queue, Esc to cancel)"),
+            };
+            let (before, after) = prompt.text.split_at(prompt.byte_offset());
+            let mut after = after.chars();
+            let under_cursor = after.next().map_or(" ".to_string(), String::from);
+            let line = Paragraph::new(Line::from(vec![
+                Span::styled(label, Style::default().fg(Color::Cyan)),
+                Span::raw(before),
+                Span::styled(
+                    under_cursor,
+                    Style::default().add_modifier(Modifier::REVERSED),
+                ),
+                Span::raw(after.as_str()),
+                Span::styled(hint, Style::default().fg(Color::DarkGray)),
+            ]));
+            f.render_widget(line, area);
+            return;
+        }
+        if let Some(notice) = state.notice.as_ref().filter(|n| n.is_active()) {
+            let line = Paragraph::new(notice.message.as_str())
+                .style(
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                )
+                .alignment(Alignment::Center);
+            f.render_widget(line, area);
+            return;
+        }
+
+        let count = |matches: fn(&JobState) -> bool| {
+            state.jobs.iter().filter(|j| matches(&j.state)).count()
+        };
+        let total = |n: usize, label: &str, color: Color| {
+            Span::styled(format!("{} {}", n, label), Style::default().fg(color))
+        };
+        let separator = || Span::raw(if compact { " │ " } else { "  │  " });
+        let gap = || Span::raw(if compact { " " } else { "  " });
+        let eta = match run_eta(state) {
+            _ if state.run_done => "all jobs finished, q to quit".to_string(),
+            Some(eta) => format!("ETA {}", humanize(eta)),
+            None => "ETA -".to_string(),
+        };
+        let [queued, running, done, failed] = if compact {
+            ["queued", "run", "done", "fail"]
+        } else {
+            ["queued", "running", "done", "failed"]
+        };
+        let mut spans = Vec::new();
+        if compact {
+            spans.push(Span::raw("GPU "));
+            for gpu in &state.gpus {
+                let busy = state
+                    .jobs
+                    .iter()
+                    .any(|j| matches!(j.state, JobState::Running { gpu_id } if gpu_id == gpu.id));
+                let (dot, color) = if busy {
+                    ("●", Color::Green)
+                } else {
+                    ("○", Color::DarkGray)
+                };
+                spans.push(Span::styled(dot, Style::default().fg(color)));
+            }
+            spans.push(separator());
+        }
+        spans.extend([
+            total(
+                count(|s| matches!(s, JobState::Queued)),
+                queued,
+                Color::Yellow,
             ),
-            Span::raw(" Navigate jobs  "),
-            Span::styled(
-                "q",
-                Style::default()
-                    .fg(Color::Cyan)
-                    .add_modifier(Modifier::BOLD),
+            gap(),
+            total(
+                count(|s| matches!(s, JobState::Running { .. })),
+                running,
+                Color::Green,
             ),
-            Span::raw(" Quit (jobs continue)  "),
-            Span::styled(
-                "Ctrl+C",
-                Style::default()
-                    .fg(Color::Cyan)
-                    .add_modifier(Modifier::BOLD),
+            gap(),
+            total(
+                count(|s| matches!(s, JobState::Completed)),
+                done,
+                Color::Blue,
             ),
-            Span::raw(" Force quit & stop all jobs  "),
-            Span::styled(
-                "Auto-exit",
+            gap(),
+            total(count(JobState::is_failure), failed, Color::Red),
+        ]);
+        if !compact {
+            spans.push(separator());
+            spans.push(Span::raw(format!(
+                "{} elapsed",
+                humanize(state.run_started.elapsed())
+            )));
+        }
+        // Without the GPU panel, host usage has no other place
+        if let Some(host) = state.host.as_ref().filter(|_| compact) {
+            spans.push(separator());
+            spans.extend(host_line(host, true).spans);
+        }
+        spans.push(separator());
+        spans.push(Span::raw(eta));
+        if state.paused {
+            spans.push(separator());
+            spans.push(Span::styled(
+                "PAUSED",
                 Style::default()
                     .fg(Color::Yellow)
                     .add_modifier(Modifier::BOLD),
-            ),
-            Span::raw(" when all jobs complete"),
-        ]);
+            ));
+        }
+        spans.push(separator());
+        spans.push(Span::styled(
+            "?",
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        ));
+        spans.push(Span::raw(" Keys"));
 
-        let help_paragraph = Paragraph::new(help_text)
-            .style(Style::default().fg(Color::DarkGray))
+        let status = Paragraph::new(Line::from(spans))
+            .style(Style::default().fg(Color::Gray))
             .alignment(Alignment::Center);
+        f.render_widget(status, area);
+    }
+
+    /// The keys and what they do, in a box over the middle of the screen.
+    fn draw_help_overlay(f: &mut Frame, state: &AppState) {
+        let keys = help_keys(state.attached);
+        let key_width = keys
+            .iter()
+            .map(|(k, _)| k.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut lines: Vec<Line> = keys
+            .iter()
+            .map(|(key, what)| {
+                Line::from(vec![
+                    Span::styled(
+                        format!(" {:<w$}  ", key, w = key_width),
+                        Style::default()
+                            .fg(Color::Cyan)
+                            .add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(*what),
+                ])
+            })
+            .collect();
+        if !state.attached && !state.keep_open {
+            lines.push(Line::raw(""));
+            lines.push(Line::styled(
+                " gparallel exits by itself once all jobs have finished",
+                Style::default().fg(Color::Yellow),
+            ));
+        }
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(" Keys (any key closes) ");
+        Self::draw_dialog(f, lines, block);
+    }
+
+    /// Ask before Ctrl+C stops every job.
+    fn draw_stop_dialog(f: &mut Frame, state: &AppState) {
+        let count = |matches: fn(&JobState) -> bool| {
+            state.jobs.iter().filter(|j| matches(&j.state)).count()
+        };
+        let running = count(|s| matches!(s, JobState::Running { .. }));
+        let queued = count(|s| matches!(s, JobState::Queued));
+        let lines = vec![
+            Line::raw(format!(
+                " {} running jobs will be killed and {} queued jobs dropped. ",
+                running, queued
+            )),
+            Line::raw(""),
+            Line::from(vec![
+                Span::raw(" Stop everything and quit? "),
+                Span::styled(
+                    "y/N",
+                    Style::default()
+                        .fg(Color::Cyan)
+                        .add_modifier(Modifier::BOLD),
+                ),
+            ]),
+        ];
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Red))
+            .title(format!(" Kill {} running jobs? ", running));
+        Self::draw_dialog(f, lines, block);
+    }
+
+    /// A failure of a job other than the one on screen, in the top right
+    /// corner.
+    fn draw_toast(f: &mut Frame, toast: &Notice) {
+        let screen = f.size();
+        let text = format!(" {} ", toast.message);
+        let width = (text.chars().count() as u16 + 2).min(screen.width);
+        let area = Rect {
+            x: screen.right() - width,
+            y: screen.y + 1,
+            width,
+            height: 3.min(screen.height.saturating_sub(1)),
+        };
+        f.render_widget(Clear, area);
+        f.render_widget(
+            Paragraph::new(text)
+                .style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(Color::Red)),
+                ),
+            area,
+        );
+    }
+
+    /// Draw `lines` in `block` over the middle of the screen.
+    fn draw_dialog(f: &mut Frame, lines: Vec<Line>, block: Block) {
+        let screen = f.size();
+        let width = lines
+            .iter()
+            .map(Line::width)
+            .max()
+            .unwrap_or(0)
+            .saturating_add(3)
+            .min(screen.width as usize) as u16;
+        let height = (lines.len() + 2).min(screen.height as usize) as u16;
+        let area = Rect {
+            x: screen.x + (screen.width - width) / 2,
+            y: screen.y + (screen.height - height) / 2,
+            width,
+            height,
+        };
+        f.render_widget(Clear, area);
+        f.render_widget(Paragraph::new(lines).block(block), area);
+    }
+}
+
+/// Apply a key press to the state, sending anything beyond navigation to
+/// `actions`.
+fn handle_key(state: &mut AppState, key: KeyEvent, actions: Option<&UnboundedSender<Action>>) {
+    let code = key.code;
+    if state.confirm_stop {
+        state.confirm_stop = false;
+        if matches!(code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+            state.stop_all = true;
+            state.should_quit = true;
+        } else {
+            state.notice = Some(Notice::new("Jobs keep running"));
+        }
+        return;
+    }
+    if code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+        // A daemon's jobs are not ours to stop, so this only detaches
+        if state.attached {
+            state.should_quit = true;
+        } else {
+            state.confirm_stop = true;
+        }
+        return;
+    }
+    if state.prompt.is_some() {
+        return handle_prompt_key(state, code, actions);
+    }
+    if state.show_help {
+        state.show_help = false;
+        return;
+    }
+    let pending = state.pending_key.take();
+    if state.keys == KeyMap::Vim {
+        if let Some(motion) = vim_motion(key, pending, state) {
+            return apply_motion(state, motion);
+        }
+    }
+    match code {
+        KeyCode::Char('q') => state.should_quit = true,
+        KeyCode::Char('?') => state.show_help = true,
+        KeyCode::Char('F') => select_failed(state, false),
+        KeyCode::Char('f') if key.modifiers.contains(KeyModifiers::ALT) => {
+            select_failed(state, true)
+        }
+        KeyCode::Char('o') => {
+            state.keep_open = !state.keep_open;
+            state.notice = Some(Notice::new(if state.keep_open {
+                "Staying open when all jobs have finished"
+            } else {
+                "Exiting when all jobs have finished"
+            }));
+        }
+        KeyCode::Up | KeyCode::Down if key.modifiers.contains(KeyModifiers::SHIFT) => {
+            shift_selected(state, code == KeyCode::Up, actions)
+        }
+        KeyCode::Up => apply_motion(state, Motion::By(-1)),
+        KeyCode::Down => apply_motion(state, Motion::By(1)),
+        KeyCode::Char('s') => {
+            state.filter = state.filter.next();
+            relist(state);
+        }
+        KeyCode::Char('z') => state.zoomed = !state.zoomed,
+        KeyCode::Tab => state.tab = state.tab.next(),
+        KeyCode::BackTab => state.tab = state.tab.previous(),
+        KeyCode::Char(c @ '1'..='5') => state.tab = Tab::ALL[c as usize - '1' as usize],
+        KeyCode::Char('+') | KeyCode::Char('=') => PanelSizes::resize(&mut state.panels.top, true),
+        KeyCode::Char('-') => PanelSizes::resize(&mut state.panels.top, false),
+        KeyCode::Char('>') => PanelSizes::resize(&mut state.panels.gpus, true),
+        KeyCode::Char('<') => PanelSizes::resize(&mut state.panels.gpus, false),
+        KeyCode::Char('a') => {
+            state.prompt = Some(Prompt::new(PromptKind::Submit, ""));
+        }
+        KeyCode::Char('/') => {
+            state.prompt = Some(Prompt::new(PromptKind::Search, state.search.clone()));
+        }
+        KeyCode::Char('f') => {
+            state.log_follow = !state.log_follow;
+            pause_log_at_tail(state);
+        }
+        KeyCode::Char('w') => {
+            state.log_wrap = !state.log_wrap;
+            state.log_pan = 0;
+        }
+        KeyCode::Char('m') => {
+            state.merged_log = !state.merged_log;
+            state.log_pan = 0;
+        }
+        KeyCode::Left if !state.log_wrap => state.log_pan = state.log_pan.saturating_sub(LOG_PAN),
+        KeyCode::Right if !state.log_wrap => pan_log_right(state),
+        KeyCode::PageUp => scroll_log(state, -(LOG_PAGE as isize)),
+        KeyCode::PageDown => scroll_log(state, LOG_PAGE as isize),
+        KeyCode::Char('p') => {
+            if let Err(notice) = send_action(actions, Action::SetPaused(!state.paused)) {
+                state.notice = Some(Notice::new(notice));
+            }
+        }
+        KeyCode::Char('e') => {
+            let Some(job) = state.selected_job.and_then(|i| state.jobs.get(i)) else {
+                return;
+            };
+            if job.state.is_finished() {
+                state.prompt = Some(Prompt::new(PromptKind::Edit(job.id), job.cmd.clone()));
+            } else {
+                state.notice = Some(Notice::new("Only finished jobs can be edited"));
+            }
+        }
+        KeyCode::Char('y') => {
+            let Some(job) = state.selected_job.and_then(|i| state.jobs.get(i)) else {
+                return;
+            };
+            state.clipboard = Some(job.cmd.clone());
+            state.notice = Some(Notice::new("Copied the command"));
+        }
+        KeyCode::Char('Y') => {
+            let Some(job) = state.selected_job.and_then(|i| state.jobs.get(i)) else {
+                return;
+            };
+            let skip = job.log_lines.len().saturating_sub(COPY_LINES);
+            let tail: Vec<&str> = job
+                .log_lines
+                .iter()
+                .skip(skip)
+                .map(String::as_str)
+                .collect();
+            state.notice = Some(Notice::new(format!(
+                "Copied the last {} log lines",
+                tail.len()
+            )));
+            state.clipboard = Some(tail.join("\n"));
+        }
+        KeyCode::Char('r') => {
+            let Some(job) = state.selected_job.and_then(|i| state.jobs.get(i)) else {
+                return;
+            };
+            let notice = if !job.state.is_finished() {
+                "Only finished jobs can be resubmitted".to_string()
+            } else {
+                match send_action(actions, Action::Submit(job.request())) {
+                    Ok(()) => format!("Resubmitting {}", &job.id.to_string()[..8]),
+                    Err(notice) => notice.to_string(),
+                }
+            };
+            state.notice = Some(Notice::new(notice));
+        }
+        _ => {}
+    }
+}
+
+/// The keys of the `?` overlay, with what they do.
+fn help_keys(attached: bool) -> Vec<(&'static str, &'static str)> {
+    let mut keys = vec![
+        ("↑/↓", "Navigate jobs"),
+        ("a", "Add a job"),
+        ("r", "Resubmit the selected finished job"),
+        ("e", "Edit and resubmit the selected finished job"),
+        ("y / Y", "Copy the command / the log tail"),
+        ("p", "Pause or resume the queue"),
+        ("f", "Follow the live log or pause it"),
+        ("PgUp/PgDn", "Scroll the paused log"),
+        ("z", "Zoom the live log"),
+        ("w", "Wrap the live log, or cut it and pan with ←/→"),
+        ("m", "Merge the output of all jobs in the live log"),
+        ("F / Alt+F", "Select the next / previous failed job"),
+        ("Shift+↑/↓", "Move the selected queued job ahead or back"),
+        ("s", "Filter the job list by state"),
+        ("/", "Search the job list"),
+        ("Tab / 1-5", "Switch tabs"),
+        ("+/-  </>", "Resize the panels"),
+    ];
+    if attached {
+        keys.push(("q", "Detach (the daemon keeps running)"));
+    } else {
+        keys.push(("o", "Stay open or exit once all jobs have finished"));
+        keys.push(("q", "Quit (jobs continue)"));
+        keys.push(("Ctrl+C", "Stop all jobs and quit, after asking"));
+    }
+    keys
+}
+
+/// When the jobs not finished yet should be done: the average duration of
+/// finished jobs, spread over the GPUs. `None` until a job has finished.
+fn run_eta(state: &AppState) -> Option<Duration> {
+    let durations: Vec<Duration> = state
+        .jobs
+        .iter()
+        .filter(|j| j.state.is_finished())
+        .filter_map(|j| j.timing.elapsed())
+        .collect();
+    if durations.is_empty() {
+        return None;
+    }
+    let mean = durations.iter().sum::<Duration>() / durations.len() as u32;
+    let gpu_time: Duration = state
+        .jobs
+        .iter()
+        .filter(|j| !j.state.is_finished())
+        .map(|j| {
+            let left = match j.state {
+                JobState::Running { .. } => {
+                    mean.saturating_sub(j.timing.elapsed().unwrap_or_default())
+                }
+                _ => mean,
+            };
+            left * j.requested_gpus.max(1)
+        })
+        .sum();
+    Some(gpu_time / state.gpus.len().max(1) as u32)
+}
+
+/// "13.2 GB on GPU 3" for a job whose processes hold GPU memory now, the
+/// total over its GPUs for a multi-GPU job.
+fn gpu_memory_label(job: &JobInfo) -> Option<String> {
+    if job.gpu_memory_mb.is_empty() {
+        return None;
+    }
+    let gb = job.gpu_memory_mb.values().sum::<u64>() as f64 / 1024.0;
+    let gpus: Vec<String> = job.gpu_memory_mb.keys().map(u32::to_string).collect();
+    let on = if gpus.len() == 1 { "GPU" } else { "GPUs" };
+    Some(format!("{:.1} GB on {} {}", gb, on, gpus.join(",")))
+}
+
+/// "idle 12m" for a running job silent for `IDLE_BADGE` or more, in red
+/// once that is `hung_after`.
+fn idle_badge(job: &JobInfo, hung_after: Duration) -> Option<(String, Color)> {
+    let silent = job.silent_for().filter(|&d| d >= IDLE_BADGE)?;
+    let mins = silent.as_secs() / 60;
+    let badge = if mins < 60 {
+        format!("idle {}m", mins)
+    } else {
+        format!("idle {}h {:02}m", mins / 60, mins % 60)
+    };
+    let color = if silent >= hung_after {
+        Color::Red
+    } else {
+        Color::Yellow
+    };
+    Some((badge, color))
+}
+
+/// Host load, CPU and free memory, for under the GPU panel or, on a
+/// compact screen, the status bar. Red once less than a tenth of the
+/// memory is left.
+fn host_line(host: &HostUsage, compact: bool) -> Line<'static> {
+    let cpu = host
+        .cpu_percent
+        .map_or("-".to_string(), |p| format!("{:.0}%", p));
+    let gib = |bytes: u64| bytes as f64 / (1u64 << 30) as f64;
+    let ram_color = if host.mem_available < host.mem_total / 10 {
+        Color::Red
+    } else {
+        Color::Gray
+    };
+    let (load, ram) = if compact {
+        (
+            format!("cpu {} ", cpu),
+            format!("free {:.0}G", gib(host.mem_available)),
+        )
+    } else {
+        (
+            format!(" load {:.1} cpu {} ", host.load, cpu),
+            format!(
+                "free {:.1}/{:.0}G ",
+                gib(host.mem_available),
+                gib(host.mem_total)
+            ),
+        )
+    };
+    Line::from(vec![
+        Span::raw(load),
+        Span::styled(ram, Style::default().fg(ram_color)),
+    ])
+}
+
+/// A hash of what can change on screen without a key press: the jobs,
+/// their output and progress, GPU samples, host usage, messages, and the
+/// clocks, which tick once a second. The TUI redraws only when it changes.
+fn screen_fingerprint(state: &AppState) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    state.run_started.elapsed().as_secs().hash(&mut hasher);
+    for job in &state.jobs {
+        job.id.hash(&mut hasher);
+        std::mem::discriminant(&job.state).hash(&mut hasher);
+        (job.gpu, job.priority, job.retries, job.log_line_count()).hash(&mut hasher);
+        (job.progress.map(f64::to_bits), job.peak_gpu_memory_mb).hash(&mut hasher);
+        job.gpu_memory_mb.hash(&mut hasher);
+    }
+    for gpu in &state.gpus {
+        (
+            gpu.id,
+            gpu.free_memory_mb,
+            gpu.total_memory_mb,
+            gpu.utilization,
+            gpu.temperature,
+        )
+            .hash(&mut hasher);
+        (&gpu.memory_history, &gpu.utilization_history).hash(&mut hasher);
+        (&gpu.fault, &gpu.in_use_by).hash(&mut hasher);
+    }
+    if let Some(host) = &state.host {
+        (host.load.to_bits(), host.cpu_percent.map(f64::to_bits)).hash(&mut hasher);
+        host.mem_available.hash(&mut hasher);
+    }
+    let messages = [
+        state
+            .notice
+            .as_ref()
+            .filter(|n| n.is_active())
+            .map(|n| &n.message),
+        state
+            .toast
+            .as_ref()
+            .filter(|t| t.is_active())
+            .map(|t| &t.message),
+        state
+            .failure_flash
+            .as_ref()
+            .filter(|f| f.is_active())
+            .map(|f| &f.message),
+    ];
+    messages.hash(&mut hasher);
+    (state.selected_job, state.tab, state.paused).hash(&mut hasher);
+    (state.confirm_stop, state.run_done).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn selected_id(state: &AppState) -> Option<Uuid> {
+    state
+        .selected_job
+        .and_then(|i| state.jobs.get(i))
+        .map(|job| job.id)
+}
+
+/// Select job `id` again if the job list moved it to another row.
+fn keep_selected(state: &mut AppState, id: Option<Uuid>) {
+    let Some(id) = id.filter(|&id| selected_id(state) != Some(id)) else {
+        return;
+    };
+    if let Some(index) = state.jobs.iter().position(|job| job.id == id) {
+        state.selected_job = Some(index);
+        move_selection(state, 0);
+    }
+}
 
-        f.render_widget(help_paragraph, area);
+/// Move the selected queued job one place ahead in the queue (`up`) or
+/// back.
+fn shift_selected(state: &mut AppState, up: bool, actions: Option<&UnboundedSender<Action>>) {
+    let Some(job) = state.selected_job.and_then(|i| state.jobs.get(i)) else {
+        return;
+    };
+    if !matches!(job.state, JobState::Queued) {
+        state.notice = Some(Notice::new("Only queued jobs can be moved"));
+    } else if let Err(notice) = send_action(actions, Action::Shift { job: job.id, up }) {
+        state.notice = Some(Notice::new(notice));
     }
 }
 
+/// The escape sequence asking the terminal to put `text` on the system
+/// clipboard (OSC 52), which also works over SSH and inside tmux.
+fn osc52(text: &str) -> String {
+    use base64::Engine;
+    format!(
+        "\x1b]52;c;{}\x07",
+        base64::engine::general_purpose::STANDARD.encode(text)
+    )
+}
+
+/// Hand `action` to whoever runs the jobs, or say why it cannot be done.
+fn send_action(
+    actions: Option<&UnboundedSender<Action>>,
+    action: Action,
+) -> Result<(), &'static str> {
+    match actions.map(|actions| actions.send(action).is_ok()) {
+        Some(true) => Ok(()),
+        Some(false) => Err("The jobs can no longer be changed"),
+        None => Err("Not available here"),
+    }
+}
+
+/// The motion of a vim key, if `key` (after `pending`) is one. A lone `g`
+/// waits for the next key.
+fn vim_motion(key: KeyEvent, pending: Option<char>, state: &mut AppState) -> Option<Motion> {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+        KeyCode::Char('d') if ctrl => Some(Motion::HalfPageDown),
+        KeyCode::Char('u') if ctrl => Some(Motion::HalfPageUp),
+        KeyCode::Char('j') => Some(Motion::By(1)),
+        KeyCode::Char('k') => Some(Motion::By(-1)),
+        KeyCode::Char('G') => Some(Motion::Last),
+        KeyCode::Char('g') if pending == Some('g') => Some(Motion::First),
+        KeyCode::Char('g') => {
+            state.pending_key = Some('g');
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Move through the log when it is zoomed, or else through the job list.
+fn apply_motion(state: &mut AppState, motion: Motion) {
+    if state.zoomed {
+        let page = LOG_PAGE as isize;
+        match motion {
+            Motion::By(by) => scroll_log(state, by),
+            Motion::HalfPageDown => scroll_log(state, page),
+            Motion::HalfPageUp => scroll_log(state, -page),
+            Motion::First => scroll_log(state, isize::MIN),
+            Motion::Last => {
+                state.log_follow = true;
+            }
+        }
+        return;
+    }
+    let half_page = (state.job_panel_visible_height / 2).max(1) as isize;
+    let by = match motion {
+        Motion::By(by) => by,
+        Motion::HalfPageDown => half_page,
+        Motion::HalfPageUp => -half_page,
+        Motion::First => isize::MIN,
+        Motion::Last => isize::MAX,
+    };
+    move_selection(state, by);
+}
+
+/// Scroll the live log `by` lines, pausing it when scrolling back.
+fn scroll_log(state: &mut AppState, by: isize) {
+    if state.log_follow {
+        if by >= 0 {
+            return;
+        }
+        state.log_follow = false;
+        pause_log_at_tail(state);
+    }
+    let count = state
+        .selected_job
+        .and_then(|i| state.jobs.get(i))
+        .map_or(0, JobInfo::log_line_count);
+    // Keep at least one line in view
+    state.log_end = state
+        .log_end
+        .saturating_add_signed(by)
+        .clamp(1, count.max(1));
+}
+
+fn is_compact(screen: Rect) -> bool {
+    screen.width < COMPACT_WIDTH || screen.height < COMPACT_HEIGHT
+}
+
+/// The frame of a panel: a box, or on a `compact` screen just its title.
+fn panel_block(title: String, compact: bool) -> Block<'static> {
+    if compact {
+        Block::default().title(Span::styled(
+            title,
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        ))
+    } else {
+        Block::default().borders(Borders::ALL).title(title)
+    }
+}
+
+/// Whether the TUI should close because every job has finished. With
+/// `keep_open` it shows the run's statistics instead, once.
+fn finish_run(state: &mut AppState) -> bool {
+    let done = !state.attached
+        && !state.jobs.is_empty()
+        && state.jobs.iter().all(|j| j.state.is_finished());
+    if done && !state.keep_open {
+        return true;
+    }
+    if done && !state.run_done {
+        state.tab = Tab::Stats;
+        state.zoomed = false;
+    }
+    state.run_done = done;
+    false
+}
+
+/// The jobs that have failed so far.
+fn failed_jobs(state: &AppState) -> HashSet<Uuid> {
+    state
+        .jobs
+        .iter()
+        .filter(|job| job.state.is_failure())
+        .map(|job| job.id)
+        .collect()
+}
+
+/// Remember the jobs that failed since `seen` was last updated, and toast
+/// the latest unless it is the selected one. True if any job failed.
+fn toast_new_failures(state: &mut AppState, seen: &mut HashSet<Uuid>) -> bool {
+    let Some(job) = state
+        .jobs
+        .iter()
+        .filter(|job| job.state.is_failure() && seen.insert(job.id))
+        .max_by_key(|job| job.timing.finished)
+    else {
+        return false;
+    };
+    state.last_failed = Some(job.id);
+    let selected = state.selected_job.and_then(|i| state.jobs.get(i));
+    if selected.map(|s| s.id) != Some(job.id) {
+        let how = job
+            .exit
+            .map(|exit| format!(" ({})", exit))
+            .unwrap_or_default();
+        state.toast = Some(Notice::new(format!(
+            "✗ Job {} failed{}, F to show it",
+            &job.id.to_string()[..8],
+            how
+        )));
+    }
+    true
+}
+
+/// Select the next failed job after the selected one, or the one before
+/// it when `back`, wrapping around. While a failure is toasted, that job
+/// comes first. All jobs are listed again if the filter or search hides it.
+fn select_failed(state: &mut AppState, back: bool) {
+    let toasted = state
+        .toast
+        .as_ref()
+        .filter(|t| t.is_active())
+        .and(state.last_failed)
+        .and_then(|id| state.jobs.iter().position(|job| job.id == id));
+    let failed: Vec<usize> = (0..state.jobs.len())
+        .filter(|&i| state.jobs[i].state.is_failure())
+        .collect();
+    let current = state.selected_job;
+    let next = toasted.or_else(|| {
+        if back {
+            let before = failed.iter().rev().find(|&&i| Some(i) < current);
+            before.or(failed.last()).copied()
+        } else {
+            let after = failed.iter().find(|&&i| Some(i) > current);
+            after.or(failed.first()).copied()
+        }
+    });
+    let Some(index) = next else {
+        state.notice = Some(Notice::new("No job has failed"));
+        return;
+    };
+    if !state.listed_jobs().contains(&index) {
+        state.filter = StateFilter::All;
+        state.search.clear();
+        state.job_scroll_offset = 0;
+    }
+    state.selected_job = Some(index);
+    state.toast = None;
+    move_selection(state, 0);
+}
+
+/// Pan the unwrapped live log right, stopping at the end of its longest line.
+fn pan_log_right(state: &mut AppState) {
+    let widest = if state.merged_log {
+        // Each line comes after its job id and GPU
+        let tag = 14;
+        state
+            .timeline
+            .iter()
+            .map(|(_, l)| tag + l.chars().count())
+            .max()
+    } else {
+        state
+            .selected_job
+            .and_then(|i| state.jobs.get(i))
+            .and_then(|job| job.log_lines.iter().map(|l| l.chars().count()).max())
+    }
+    .unwrap_or(0);
+    let last = widest.saturating_sub(1).min(u16::MAX as usize) as u16;
+    state.log_pan = state.log_pan.saturating_add(LOG_PAN).min(last);
+}
+
+/// Edit the open prompt: Enter takes the text, Esc drops it.
+fn handle_prompt_key(
+    state: &mut AppState,
+    code: KeyCode,
+    actions: Option<&UnboundedSender<Action>>,
+) {
+    let Some(prompt) = state.prompt.as_mut() else {
+        return;
+    };
+    match code {
+        KeyCode::Enter => {
+            let Some(prompt) = state.prompt.take() else {
+                return;
+            };
+            let cmd = prompt.text.trim();
+            let request = match prompt.kind {
+                PromptKind::Search => return,
+                _ if cmd.is_empty() => return,
+                PromptKind::Submit => JobRequest::new(cmd),
+                PromptKind::Edit(id) => match state.jobs.iter().find(|job| job.id == id) {
+                    Some(job) => JobRequest {
+                        cmd: cmd.to_string(),
+                        ..job.request()
+                    },
+                    None => return,
+                },
+            };
+            let notice = match send_action(actions, Action::Submit(request)) {
+                Ok(()) => format!("Submitting {}", cmd),
+                Err(notice) => notice.to_string(),
+            };
+            state.notice = Some(Notice::new(notice));
+            return;
+        }
+        KeyCode::Esc => {
+            let kind = prompt.kind;
+            state.prompt = None;
+            // A dropped search lists every job again
+            if kind == PromptKind::Search {
+                state.search.clear();
+                relist(state);
+            }
+            return;
+        }
+        code => {
+            if !prompt.edit(code) {
+                return;
+            }
+        }
+    }
+    if prompt.kind == PromptKind::Search {
+        state.search = prompt.text.clone();
+        relist(state);
+    }
+}
+
+/// After the filter or search changed: keep the selection if it is still
+/// listed, or else select the first listed job.
+fn relist(state: &mut AppState) {
+    let listed = state.listed_jobs();
+    if !state.selected_job.is_some_and(|i| listed.contains(&i)) {
+        if let Some(&first) = listed.first() {
+            state.selected_job = Some(first);
+        }
+    }
+    state.job_scroll_offset = 0;
+    move_selection(state, 0);
+}
+
+/// Move the selection `by` rows of the job list, scrolling to keep it in
+/// view.
+fn move_selection(state: &mut AppState, by: isize) {
+    let listed = state.listed_jobs();
+    if listed.is_empty() {
+        return;
+    }
+    let new_selected = match state
+        .selected_job
+        .and_then(|s| listed.iter().position(|&i| i == s))
+    {
+        Some(row) => row.saturating_add_signed(by).min(listed.len() - 1),
+        None => 0,
+    };
+    state.selected_job = Some(listed[new_selected]);
+
+    // Adjust scroll offset if selection goes above or below visible area
+    let visible_height = state.job_panel_visible_height;
+    if new_selected < state.job_scroll_offset {
+        state.job_scroll_offset = new_selected;
+    } else if new_selected >= state.job_scroll_offset + visible_height {
+        state.job_scroll_offset = new_selected.saturating_sub(visible_height - 1);
+    }
+    pause_log_at_tail(state);
+}
+
+/// Without following, hold the live log at what the selected job has
+/// printed so far.
+fn pause_log_at_tail(state: &mut AppState) {
+    if !state.log_follow {
+        state.log_end = state
+            .selected_job
+            .and_then(|i| state.jobs.get(i))
+            .map_or(0, JobInfo::log_line_count);
+    }
+}
+
+/// ` ███████░░░  70%`: a job's progress as a gauge for the job list.
+fn progress_bar(progress: f64) -> String {
+    const WIDTH: usize = 10;
+    let filled = (progress * WIDTH as f64).round() as usize;
+    format!(
+        " {}{} {:>3}%",
+        "█".repeat(filled),
+        "░".repeat(WIDTH - filled),
+        (progress * 100.0).round()
+    )
+}
+
 impl Drop for UI {
     fn drop(&mut self) {
         let _ = disable_raw_mode();
@@ -411,3 +2490,862 @@ impl Drop for UI {
         let _ = self.terminal.show_cursor();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    fn job(cmd: &str, state: JobState, logs: &[&str]) -> JobInfo {
+        JobInfo {
+            state,
+            log_lines: logs.iter().map(|l| l.to_string()).collect(),
+            ..JobInfo::new(Uuid::new_v4(), cmd)
+        }
+    }
+
+    fn sample_state() -> AppState {
+        let mut state = AppState::new();
+        state.gpus = vec![
+            GpuInfo {
+                free_memory_mb: 20000,
+                total_memory_mb: 24000,
+                ..GpuInfo::new(0, "Mock0")
+            },
+            GpuInfo {
+                free_memory_mb: 24000,
+                total_memory_mb: 24000,
+                ..GpuInfo::new(1, "Mock1")
+            },
+        ];
+        state.jobs = vec![
+            job(
+                "python train.py --lr 0.1",
+                JobState::Running { gpu_id: 0 },
+                &["epoch 1/10"],
+            ),
+            job("python train.py --lr 0.01", JobState::Queued, &[]),
+            job("python eval.py", JobState::Completed, &[]),
+            job("false", JobState::Failed, &[]),
+        ];
+        state.selected_job = Some(0);
+        state
+    }
+
+    /// Render the full UI into a virtual terminal and return its rows.
+    fn render(state: &AppState, width: u16, height: u16) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| UI::draw_ui_static(f, state)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| buffer.get(x, y).symbol().to_string())
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    fn find_row<'a>(rows: &'a [String], needle: &str) -> &'a str {
+        rows.iter()
+            .find(|r| r.contains(needle))
+            .unwrap_or_else(|| panic!("{:?} not rendered:\n{}", needle, rows.join("\n")))
+    }
+
+    #[test]
+    fn renders_panels_and_job_states() {
+        let rows = render(&sample_state(), 120, 30);
+
+        assert!(find_row(&rows, "┌ GPUs ").contains(" Job queue "));
+        assert!(find_row(&rows, "Mock0").contains('●'));
+        assert!(find_row(&rows, "Mock1").contains('○'));
+        assert!(find_row(&rows, "--lr 0.1").contains("RUN  G0"));
+        assert!(find_row(&rows, "--lr 0.01").contains("QUEUE"));
+        assert!(find_row(&rows, "eval.py").contains("DONE"));
+        assert!(find_row(&rows, "false").contains("FAIL"));
+        find_row(&rows, "epoch 1/10");
+    }
+
+    #[test]
+    fn failed_jobs_show_how_they_ended() {
+        let mut state = sample_state();
+        state.jobs[3].exit = Some(JobExit::Code(2));
+        state.jobs.push(JobInfo {
+            exit: Some(JobExit::Signal(9)),
+            ..job("python oom.py", JobState::Failed, &[])
+        });
+        state.jobs.push(JobInfo {
+            exit: Some(JobExit::Signal(15)),
+            ..job("sleep 1000", JobState::TimedOut, &[])
+        });
+
+        let rows = render(&state, 120, 30);
+
+        assert!(find_row(&rows, "false").contains("FAIL(2)"));
+        assert!(find_row(&rows, "oom.py").contains("FAIL(137)"));
+        assert!(find_row(&rows, "sleep 1000").contains("TIMEOUT"));
+    }
+
+    #[test]
+    fn small_screens_get_the_compact_layout() {
+        let rows = render(&sample_state(), 80, 24);
+
+        assert!(!rows.iter().any(|r| r.contains("Mock0") || r.contains('┌')));
+        assert!(find_row(&rows, "Job queue").starts_with(" Job queue"));
+        let row = find_row(&rows, "--lr 0.1");
+        assert!(row.find("RUN  G0").unwrap() < row.find("python").unwrap());
+        let status = rows.last().unwrap();
+        assert!(status.contains("GPU ●○ │ 1 queued 1 run 1 done 1 fail"));
+        assert!(!status.contains("elapsed"));
+    }
+
+    #[test]
+    fn log_title_shows_source_file() {
+        let mut state = sample_state();
+        state.jobs[0].source = Some("sweep.txt".to_string());
+
+        let rows = render(&state, 120, 30);
+
+        find_row(&rows, "from sweep.txt");
+    }
+
+    #[test]
+    fn job_list_respects_scroll_offset() {
+        let mut state = sample_state();
+        state.job_scroll_offset = 2;
+
+        let rows = render(&state, 120, 30);
+
+        assert!(!rows.iter().any(|r| r.contains("--lr 0.1 ")));
+        find_row(&rows, "eval.py");
+    }
+
+    #[test]
+    fn r_resubmits_the_selected_finished_job() {
+        let mut state = sample_state();
+        state.jobs[3].tag = Some("sweep".to_string());
+        state.jobs[3].requested_gpus = 2;
+        let (actions, mut asked) = tokio::sync::mpsc::unbounded_channel();
+
+        handle_key(&mut state, KeyCode::Char('r').into(), Some(&actions));
+        assert!(asked.try_recv().is_err(), "a running job was resubmitted");
+        state.selected_job = Some(3);
+        handle_key(&mut state, KeyCode::Char('r').into(), Some(&actions));
+
+        let Ok(Action::Submit(req)) = asked.try_recv() else {
+            panic!("nothing was submitted");
+        };
+        assert_eq!(req.cmd, "false");
+        assert_eq!((req.gpus, req.tag.as_deref()), (Some(2), Some("sweep")));
+        assert!(state.notice.unwrap().message.starts_with("Resubmitting"));
+    }
+
+    #[test]
+    fn f_pauses_the_live_log_where_it_is() {
+        let mut state = sample_state();
+        for i in 0..50 {
+            state.jobs[0].push_log_line(format!("line {}", i));
+        }
+        assert!(find_row(&render(&state, 120, 30), "line 49").starts_with('│'));
+
+        handle_key(&mut state, KeyCode::Char('f').into(), None);
+        state.jobs[0].push_log_line("line 50".to_string());
+        let rows = render(&state, 120, 30);
+        find_row(&rows, "line 49");
+        find_row(&rows, "paused, 1 new lines");
+        assert!(!rows.iter().any(|r| r.contains("line 50")));
+
+        handle_key(&mut state, KeyCode::PageUp.into(), None);
+        let rows = render(&state, 120, 30);
+        find_row(&rows, "line 39");
+        assert!(!rows.iter().any(|r| r.contains("line 40")));
+
+        handle_key(&mut state, KeyCode::Char('f').into(), None);
+        find_row(&render(&state, 120, 30), "line 50");
+    }
+
+    #[test]
+    fn w_cuts_long_log_lines_and_arrows_pan_them() {
+        let mut state = sample_state();
+        state.jobs[0].push_log_line(format!("loss 0.25 {}| acc 0.91", "-".repeat(200)));
+        find_row(&render(&state, 120, 30), "acc 0.91");
+
+        handle_key(&mut state, KeyCode::Char('w').into(), None);
+        let rows = render(&state, 120, 30);
+        find_row(&rows, "tail -f, no wrap");
+        find_row(&rows, "loss 0.25");
+        assert!(!rows.iter().any(|r| r.contains("acc 0.91")));
+
+        for _ in 0..30 {
+            handle_key(&mut state, KeyCode::Right.into(), None);
+        }
+        assert_eq!(state.log_pan, 219);
+        let rows = render(&state, 120, 30);
+        assert!(!rows.iter().any(|r| r.contains("loss 0.25")));
+        handle_key(&mut state, KeyCode::Left.into(), None);
+        assert_eq!(state.log_pan, 211);
+
+        handle_key(&mut state, KeyCode::Char('w').into(), None);
+        assert_eq!(state.log_pan, 0);
+    }
+
+    #[test]
+    fn s_cycles_the_state_filter_of_the_job_list() {
+        let mut state = sample_state();
+        state
+            .jobs
+            .push(job("python crash.py", JobState::Failed, &[]));
+
+        handle_key(&mut state, KeyCode::Char('s').into(), None);
+        assert_eq!(state.filter, StateFilter::Running);
+        handle_key(&mut state, KeyCode::Char('s').into(), None);
+        let rows = render(&state, 120, 30);
+
+        find_row(&rows, "Job queue : failed (2 of 5)");
+        assert!(!rows.iter().any(|r| r.contains("eval.py")));
+        assert_eq!(state.selected_job, Some(3));
+        handle_key(&mut state, KeyCode::Down.into(), None);
+        assert_eq!(state.selected_job, Some(4));
+        handle_key(&mut state, KeyCode::Down.into(), None);
+        assert_eq!(state.selected_job, Some(4));
+    }
+
+    #[test]
+    fn slash_narrows_the_job_list_to_matching_commands() {
+        let mut state = sample_state();
+
+        for code in [KeyCode::Char('/'), KeyCode::Char('q'), KeyCode::Char('l')] {
+            handle_key(&mut state, code.into(), None);
+        }
+        assert!(!state.should_quit, "q was not typed into the prompt");
+        handle_key(&mut state, KeyCode::Backspace.into(), None);
+        for c in "eval".chars() {
+            handle_key(&mut state, KeyCode::Char(c).into(), None);
+        }
+        let rows = render(&state, 120, 30);
+        find_row(&rows, "/qeval");
+        assert!(!rows.iter().any(|r| r.contains("eval.py")));
+
+        for _ in 0.."qeval".len() {
+            handle_key(&mut state, KeyCode::Backspace.into(), None);
+        }
+        for c in "lr 0.0".chars() {
+            handle_key(&mut state, KeyCode::Char(c).into(), None);
+        }
+        handle_key(&mut state, KeyCode::Enter.into(), None);
+        let rows = render(&state, 120, 30);
+        find_row(&rows, "Job queue : /lr 0.0 (1 of 4)");
+        assert_eq!(state.selected_job, Some(1));
+
+        handle_key(&mut state, KeyCode::Char('/').into(), None);
+        handle_key(&mut state, KeyCode::Esc.into(), None);
+        assert_eq!(state.listed_jobs().len(), 4);
+    }
+
+    #[test]
+    fn job_list_shows_start_time_and_duration() {
+        let mut state = sample_state();
+        let now = Instant::now();
+        let started_at = Local::now();
+        state.jobs[2].timing = JobTiming {
+            started: Some(now - Duration::from_secs(187)),
+            finished: Some(now),
+            started_at: Some(started_at),
+            ..Default::default()
+        };
+
+        let rows = render(&state, 120, 30);
+
+        let row = find_row(&rows, "eval.py");
+        assert!(row.contains(&started_at.format("%H:%M:%S").to_string()));
+        assert!(row.contains("3m 07s"));
+        assert!(!find_row(&rows, "--lr 0.01").contains(':'));
+    }
+
+    #[test]
+    fn status_bar_totals_the_run_and_estimates_the_rest() {
+        let mut state = sample_state();
+        let now = Instant::now();
+        state.jobs[0].timing.started = Some(now - Duration::from_secs(1000));
+        state.jobs[2].timing = JobTiming {
+            started: Some(now - Duration::from_secs(600)),
+            finished: Some(now),
+            ..Default::default()
+        };
+        state.paused = true;
+
+        let rows = render(&state, 120, 30);
+
+        let status = rows.last().unwrap();
+        assert!(status.contains("1 queued  1 running  1 done  1 failed"));
+        // The queued job's 10 minutes over two GPUs; the running one is overdue
+        assert!(status.contains("ETA 5m 00s"));
+        assert!(status.contains("PAUSED"));
+        assert!(status.contains("? Keys"));
+    }
+
+    #[test]
+    fn question_mark_lists_the_keys_until_any_key() {
+        let mut state = sample_state();
+
+        handle_key(&mut state, KeyCode::Char('?').into(), None);
+        let rows = render(&state, 120, 30);
+        find_row(&rows, "Keys (any key closes)");
+        find_row(&rows, "Stop all jobs and quit, after asking");
+
+        handle_key(&mut state, KeyCode::Char('q').into(), None);
+        assert!(!state.should_quit);
+        let rows = render(&state, 120, 30);
+        assert!(!rows.iter().any(|r| r.contains("any key closes")));
+    }
+
+    #[test]
+    fn ctrl_c_asks_before_stopping_every_job() {
+        let mut state = sample_state();
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+
+        handle_key(&mut state, ctrl_c, None);
+        find_row(&render(&state, 120, 30), "Kill 1 running jobs?");
+        handle_key(&mut state, KeyCode::Char('n').into(), None);
+        assert!(!state.confirm_stop && !state.stop_all && !state.should_quit);
+
+        handle_key(&mut state, ctrl_c, None);
+        handle_key(&mut state, KeyCode::Char('y').into(), None);
+        assert!(state.stop_all && state.should_quit);
+
+        let mut attached = AppState {
+            attached: true,
+            ..sample_state()
+        };
+        handle_key(&mut attached, ctrl_c, None);
+        assert!(!attached.confirm_stop && !attached.stop_all && attached.should_quit);
+    }
+
+    #[test]
+    fn gpu_panel_draws_sparklines_of_recent_samples() {
+        let mut state = sample_state();
+        for used in [0, 12000, 24000] {
+            let gpu = &mut state.gpus[0];
+            gpu.free_memory_mb = 24000 - used;
+            gpu.utilization = Some((used / 240) as u32);
+            gpu.record_sample();
+        }
+        assert_eq!(state.gpus[0].memory_history, [0, 50, 100]);
+
+        let rows = render(&state, 120, 30);
+
+        assert!(find_row(&rows, "Mock0").contains("100%"));
+        let mem = find_row(&rows, "mem");
+        assert!(mem.contains('█'), "no full bar: {}", mem);
+        find_row(&rows, "util");
+
+        for _ in 0..GPU_HISTORY {
+            state.gpus[0].record_sample();
+        }
+        assert_eq!(state.gpus[0].memory_history.len(), GPU_HISTORY);
+    }
+
+    #[test]
+    fn gpu_panel_shows_jobs_done_busy_time_and_average() {
+        let mut state = sample_state();
+        let now = Instant::now();
+        for (i, secs) in [(0, 30), (2, 60), (3, 120)] {
+            let job = &mut state.jobs[i];
+            job.gpu = Some(0);
+            job.timing.started = Some(now - Duration::from_secs(secs));
+            if job.state.is_finished() {
+                job.timing.finished = Some(now);
+            }
+        }
+
+        let stats = GpuStats::collect(&state.jobs);
+        assert_eq!(stats[&0].finished, 2);
+        assert_eq!(stats[&0].average(), Some(Duration::from_secs(90)));
+        let rows = render(&state, 120, 30);
+        find_row(&rows, "2 done, busy 3m 30s, avg 1m 30s");
+        find_row(&rows, "no jobs yet");
+    }
+
+    #[test]
+    fn quarantined_gpus_show_why() {
+        let mut state = sample_state();
+        state.gpus[1].fault = Some("Xid 79".to_string());
+
+        let rows = render(&state, 120, 30);
+        assert!(find_row(&rows, "Mock1").contains("✖"));
+        find_row(&rows, "quarantined: Xid 79");
+        assert!(find_row(&rows, "Mock0").contains("●"));
+
+        state.gpus[1].fault = None;
+        state.gpus[1].in_use_by = Some("PID 4242 (bob)".to_string());
+        find_row(&render(&state, 120, 30), "in use by PID 4242 (bob)");
+    }
+
+    #[test]
+    fn silent_running_jobs_get_an_idle_badge_that_turns_red() {
+        let mut state = sample_state();
+        let now = Instant::now();
+        state.jobs[0].timing.started = Some(now - Duration::from_secs(3600));
+        state.jobs[0].last_output = Some(now - Duration::from_secs(12 * 60 + 5));
+
+        let row = find_row(&render(&state, 120, 30), "--lr 0.1").to_string();
+        assert!(row.contains(" idle 12m"));
+        let color = |hung_after| idle_badge(&state.jobs[0], hung_after).map(|(_, c)| c);
+        assert_eq!(color(HUNG_AFTER), Some(Color::Red));
+        assert_eq!(color(Duration::from_secs(30 * 60)), Some(Color::Yellow));
+
+        state.jobs[0].push_log_line("epoch 2/10".to_string());
+        assert!(idle_badge(&state.jobs[0], HUNG_AFTER).is_none());
+        // Finished jobs are not idle, however long ago they printed
+        assert!(idle_badge(&state.jobs[2], HUNG_AFTER).is_none());
+    }
+
+    #[test]
+    fn m_interleaves_the_output_of_all_jobs() {
+        let mut state = sample_state();
+        state.jobs[1].state = JobState::Running { gpu_id: 1 };
+        let (first, second) = (state.jobs[0].id, state.jobs[1].id);
+        assert!(state.push_log_line(first, "loss 0.91".to_string()));
+        assert!(state.push_log_line(second, "loss 0.87".to_string()));
+        assert!(state.push_log_line(first, "loss 0.52".to_string()));
+        assert!(!state.push_log_line(Uuid::new_v4(), "lost".to_string()));
+
+        handle_key(&mut state, KeyCode::Char('m').into(), None);
+        let rows = render(&state.snapshot(), 120, 30);
+
+        find_row(&rows, "2 jobs merged (m)");
+        let tagged = |id: Uuid, gpu: &str, line: &str| {
+            let tag = format!("{} {:<3}│ {}", &id.to_string()[..8], gpu, line);
+            rows.iter().position(|r| r.contains(&tag)).unwrap()
+        };
+        let order = [
+            tagged(first, "G0", "loss 0.91"),
+            tagged(second, "G1", "loss 0.87"),
+            tagged(first, "G0", "loss 0.52"),
+        ];
+        assert!(order.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn snapshots_copy_only_the_output_on_screen() {
+        let mut state = sample_state();
+        state.jobs[3].push_log_line("error: no such file".to_string());
+
+        let snapshot = state.snapshot();
+
+        assert_eq!(snapshot.jobs[0].log_lines, ["epoch 1/10"]);
+        assert!(snapshot.jobs[3].log_lines.is_empty());
+        assert_eq!(state.jobs[3].log_lines, ["error: no such file"]);
+        find_row(&render(&snapshot, 120, 30), "epoch 1/10");
+    }
+
+    #[test]
+    fn only_changes_to_what_is_shown_call_for_a_redraw() {
+        let mut state = sample_state();
+        let drawn = screen_fingerprint(&state);
+        state.log_end = 7;
+        assert_eq!(screen_fingerprint(&state), drawn);
+
+        state.jobs[0].push_log_line("epoch 2/10".to_string());
+        assert_ne!(screen_fingerprint(&state), drawn);
+        let drawn = screen_fingerprint(&state);
+        state.notice = Some(Notice::new("Queued as 1234abcd"));
+        assert_ne!(screen_fingerprint(&state), drawn);
+    }
+
+    #[test]
+    fn host_load_and_free_memory_are_shown_under_the_gpus() {
+        let mut state = sample_state();
+        state.host = Some(HostUsage {
+            load: 3.21,
+            cpu_percent: Some(45.4),
+            mem_available: 12 << 30,
+            mem_total: 64 << 30,
+        });
+
+        find_row(&render(&state, 120, 30), "load 3.2 cpu 45% free 12.0/64G");
+        let status = render(&state, 80, 24).pop().unwrap();
+        assert!(status.contains("│ cpu 45% free 12G"));
+    }
+
+    #[test]
+    fn progress_is_parsed_from_tqdm_bars_and_epoch_counts() {
+        let cases = [
+            (" 45%|████▌     | 45/100 [00:01<00:01]", Some(0.45)),
+            ("  0%|   | 0/9\r 33%|███ | 3/9\r", Some(0.33)),
+            ("Epoch [3/10] loss=0.25", Some(0.3)),
+            ("step: 250/1000, lr 1e-4", Some(0.25)),
+            ("saved to runs/2024/10", None),
+            ("accuracy 140%?", None),
+            ("epoch 12/10", None),
+        ];
+        for (line, expected) in cases {
+            let parsed = parse_progress(line);
+            assert!(
+                match (parsed, expected) {
+                    (Some(a), Some(b)) => (a - b).abs() < 1e-9,
+                    (a, b) => a == b,
+                },
+                "{:?}: {:?}",
+                line,
+                parsed
+            );
+        }
+    }
+
+    #[test]
+    fn job_list_shows_progress_gauges() {
+        let mut state = sample_state();
+        state.jobs[0].push_log_line("epoch 7/10".to_string());
+        state.jobs[0].push_log_line("loss 0.1".to_string());
+
+        let rows = render(&state, 140, 30);
+
+        assert!(find_row(&rows, "--lr 0.1 ").contains("███████░░░  70%"));
+    }
+
+    #[test]
+    fn job_list_shows_live_gpu_memory() {
+        let mut state = sample_state();
+        state.jobs[0].gpu_memory_mb = BTreeMap::from([(0, 13517)]);
+
+        let rows = render(&state, 140, 30);
+
+        assert!(find_row(&rows, "--lr 0.1 ").contains("13.2 GB on GPU 0"));
+        state.jobs[0].gpu_memory_mb.insert(1, 2048);
+        assert_eq!(
+            gpu_memory_label(&state.jobs[0]).as_deref(),
+            Some("15.2 GB on GPUs 0,1")
+        );
+    }
+
+    #[test]
+    fn panels_resize_within_bounds() {
+        let mut state = sample_state();
+        for _ in 0..20 {
+            handle_key(&mut state, KeyCode::Char('+').into(), None);
+        }
+        handle_key(&mut state, KeyCode::Char('<').into(), None);
+        assert_eq!(state.panels, PanelSizes { top: 90, gpus: 25 });
+
+        let [gpus, jobs, log, help] = state.panels.split(Rect::new(0, 0, 100, 41), false);
+        assert_eq!((gpus.width, jobs.width, gpus.height), (25, 75, 37));
+        assert_eq!((log.height, help.height), (3, 1));
+    }
+
+    #[test]
+    fn z_zooms_the_live_log_to_the_full_screen() {
+        let mut state = sample_state();
+        for i in 0..25 {
+            state.jobs[0].push_log_line(format!("line {}", i));
+        }
+
+        handle_key(&mut state, KeyCode::Char('z').into(), None);
+        let rows = render(&state, 120, 30);
+
+        assert!(rows[0].contains("Live log"));
+        assert!(!rows.iter().any(|r| r.contains("┌ GPUs ")));
+        find_row(&rows, "line 0");
+        handle_key(&mut state, KeyCode::Char('z').into(), None);
+        find_row(&render(&state, 120, 30), "┌ GPUs ");
+    }
+
+    #[test]
+    fn tabs_switch_between_full_screen_views() {
+        let mut state = sample_state();
+        state.jobs[3].gpu = Some(1);
+        state.jobs[3].exit = Some(JobExit::Code(2));
+
+        assert!(render(&state, 120, 30)[0].contains("1 Queue"));
+        handle_key(&mut state, KeyCode::Char('2').into(), None);
+        let rows = render(&state, 120, 30);
+        assert!(find_row(&rows, "ID ").contains("COMMAND"));
+        assert!(find_row(&rows, "false").contains("failed"));
+        find_row(&rows, "2 jobs, 1 failed");
+
+        handle_key(&mut state, KeyCode::Tab.into(), None);
+        assert_eq!(state.tab, Tab::Gpus);
+        let rows = render(&state, 120, 30);
+        assert!(find_row(&rows, "GPU 0: Mock0").contains("GPU 0"));
+        find_row(&rows, "Running ");
+
+        handle_key(&mut state, KeyCode::Tab.into(), None);
+        let rows = render(&state, 120, 30);
+        find_row(
+            &rows,
+            "4 total: 1 queued, 1 running, 1 done, 1 failed, 0 cancelled",
+        );
+        handle_key(&mut state, KeyCode::Tab.into(), None);
+        assert_eq!(state.tab, Tab::Lanes);
+        handle_key(&mut state, KeyCode::Tab.into(), None);
+        assert_eq!(state.tab, Tab::Queue);
+    }
+
+    #[test]
+    fn lanes_put_queued_jobs_on_the_gpu_that_frees_up_first() {
+        let mut state = sample_state();
+        let now = Instant::now();
+        state.jobs[0].timing.started = Some(now - Duration::from_secs(90));
+        state.jobs[2].timing = JobTiming {
+            started: Some(now - Duration::from_secs(600)),
+            finished: Some(now),
+            ..Default::default()
+        };
+        state
+            .jobs
+            .push(job("python train.py --lr 1", JobState::Queued, &[]));
+
+        let lanes = tabs::lanes(&state, 5);
+        assert_eq!(lanes[0].running[0].id, state.jobs[0].id);
+        // GPU 1 is idle, GPU 0 busy for another 8m 30s
+        let next = |lane: usize| -> Vec<_> {
+            lanes[lane]
+                .next
+                .iter()
+                .map(|(job, starts_in)| (job.id, starts_in.map(|d| d.as_secs() / 60)))
+                .collect()
+        };
+        assert_eq!(next(1)[0], (state.jobs[1].id, Some(0)));
+        assert_eq!(next(0), [(state.jobs[4].id, Some(8))]);
+        assert_eq!(next(1).len(), 1);
+
+        handle_key(&mut state, KeyCode::Char('5').into(), None);
+        let rows = render(&state, 120, 30);
+        let titles = find_row(&rows, "GPU 0: Mock0");
+        assert!(titles.find("GPU 0").unwrap() < titles.find("GPU 1: Mock1").unwrap());
+        assert!(find_row(&rows, "▶ ").contains("1m 30s"));
+        find_row(&rows, "python train.py --lr 1");
+    }
+
+    #[test]
+    fn vim_keys_move_through_the_job_list_and_zoomed_log() {
+        let mut state = sample_state();
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+
+        handle_key(&mut state, KeyCode::Char('j').into(), None);
+        assert_eq!(state.selected_job, Some(0), "vim keys are off by default");
+        state.keys = KeyMap::Vim;
+        handle_key(&mut state, KeyCode::Char('j').into(), None);
+        assert_eq!(state.selected_job, Some(1));
+        handle_key(&mut state, KeyCode::Char('G').into(), None);
+        assert_eq!(state.selected_job, Some(3));
+        handle_key(&mut state, KeyCode::Char('g').into(), None);
+        handle_key(&mut state, KeyCode::Char('k').into(), None);
+        assert_eq!(state.selected_job, Some(2), "a lone g is not a motion");
+        handle_key(&mut state, KeyCode::Char('g').into(), None);
+        handle_key(&mut state, KeyCode::Char('g').into(), None);
+        assert_eq!(state.selected_job, Some(0));
+        handle_key(&mut state, ctrl('d'), None);
+        assert_eq!(state.selected_job, Some(3));
+
+        state.selected_job = Some(0);
+        for i in 0..50 {
+            state.jobs[0].push_log_line(format!("line {}", i));
+        }
+        handle_key(&mut state, KeyCode::Char('z').into(), None);
+        handle_key(&mut state, ctrl('u'), None);
+        assert!(!state.log_follow);
+        assert_eq!(state.log_end, 41);
+        handle_key(&mut state, KeyCode::Char('G').into(), None);
+        assert!(state.log_follow);
+    }
+
+    #[test]
+    fn failures_of_other_jobs_are_toasted_and_f_shows_the_last() {
+        let mut state = sample_state();
+        let mut seen = failed_jobs(&state);
+        assert!(!toast_new_failures(&mut state, &mut seen));
+
+        state.jobs[1].state = JobState::Failed;
+        state.jobs[1].exit = Some(JobExit::Code(1));
+        state.filter = StateFilter::Running;
+        assert!(toast_new_failures(&mut state, &mut seen));
+        let id = state.jobs[1].id.to_string();
+        let toast = format!("Job {} failed (exit 1), F to show it", &id[..8]);
+        find_row(&render(&state, 120, 30), &toast);
+        assert!(!toast_new_failures(&mut state, &mut seen));
+
+        handle_key(&mut state, KeyCode::Char('F').into(), None);
+        assert_eq!(state.selected_job, Some(1));
+        assert_eq!(state.filter, StateFilter::All);
+        assert!(state.toast.is_none());
+
+        // The job on screen fails: nothing to point at
+        state.selected_job = Some(0);
+        state.jobs[0].state = JobState::Failed;
+        assert!(toast_new_failures(&mut state, &mut seen));
+        assert!(state.toast.is_none());
+        assert_eq!(state.last_failed, Some(state.jobs[0].id));
+    }
+
+    #[test]
+    fn f_and_alt_f_cycle_through_the_failed_jobs() {
+        let mut state = sample_state();
+        state.jobs[1].state = JobState::TimedOut;
+        let alt_f = KeyEvent::new(KeyCode::Char('f'), KeyModifiers::ALT);
+
+        let mut visited = Vec::new();
+        for _ in 0..3 {
+            handle_key(&mut state, KeyCode::Char('F').into(), None);
+            visited.push(state.selected_job.unwrap());
+        }
+        assert_eq!(visited, [1, 3, 1]);
+        handle_key(&mut state, alt_f, None);
+        assert_eq!(state.selected_job, Some(3));
+
+        for job in &mut state.jobs {
+            job.state = JobState::Completed;
+        }
+        handle_key(&mut state, KeyCode::Char('F').into(), None);
+        assert_eq!(state.selected_job, Some(3));
+        assert_eq!(state.notice.as_ref().unwrap().message, "No job has failed");
+    }
+
+    #[test]
+    fn keep_open_shows_the_stats_once_every_job_has_finished() {
+        let mut state = sample_state();
+        for job in &mut state.jobs {
+            job.state = JobState::Completed;
+        }
+        assert!(finish_run(&mut state));
+
+        handle_key(&mut state, KeyCode::Char('o').into(), None);
+        assert!(state.keep_open);
+        assert!(!finish_run(&mut state));
+        assert_eq!(state.tab, Tab::Stats);
+        // Once the notice about o has made way for the status bar
+        state.notice = None;
+        assert!(render(&state, 120, 30)
+            .last()
+            .unwrap()
+            .contains("all jobs finished, q to quit"));
+
+        // Looking elsewhere is not undone, and a new job resumes the run
+        state.tab = Tab::Queue;
+        assert!(!finish_run(&mut state));
+        assert_eq!(state.tab, Tab::Queue);
+        state.jobs.push(job("echo again", JobState::Queued, &[]));
+        assert!(!finish_run(&mut state));
+        assert!(!state.run_done);
+    }
+
+    #[test]
+    fn shift_arrows_move_queued_jobs_and_the_selection_stays_on_them() {
+        let mut state = sample_state();
+        let (actions, mut asked) = tokio::sync::mpsc::unbounded_channel();
+        let shift = |code| KeyEvent::new(code, KeyModifiers::SHIFT);
+
+        handle_key(&mut state, shift(KeyCode::Up), Some(&actions));
+        assert!(asked.try_recv().is_err());
+        assert_eq!(
+            state.notice.as_ref().unwrap().message,
+            "Only queued jobs can be moved"
+        );
+
+        state.selected_job = Some(1);
+        let queued = state.jobs[1].id;
+        handle_key(&mut state, shift(KeyCode::Up), Some(&actions));
+        assert!(matches!(
+            asked.try_recv(),
+            Ok(Action::Shift { job, up: true }) if job == queued
+        ));
+
+        // What the scheduler does with the job list
+        state.jobs.swap(0, 1);
+        keep_selected(&mut state, Some(queued));
+        assert_eq!(state.selected_job, Some(0));
+    }
+
+    #[test]
+    fn p_asks_to_pause_and_resume_and_the_tab_bar_says_so() {
+        let mut state = sample_state();
+        let (actions, mut asked) = tokio::sync::mpsc::unbounded_channel();
+
+        handle_key(&mut state, KeyCode::Char('p').into(), Some(&actions));
+        assert!(matches!(asked.try_recv(), Ok(Action::SetPaused(true))));
+        state.paused = true;
+        assert!(render(&state, 120, 30)[0].ends_with("PAUSED"));
+        handle_key(&mut state, KeyCode::Char('p').into(), Some(&actions));
+        assert!(matches!(asked.try_recv(), Ok(Action::SetPaused(false))));
+    }
+
+    #[test]
+    fn a_prompts_for_a_command_and_submits_it() {
+        let mut state = sample_state();
+        let (actions, mut asked) = tokio::sync::mpsc::unbounded_channel();
+
+        handle_key(&mut state, KeyCode::Char('a').into(), Some(&actions));
+        for c in "python train.py --lr 1e-4".chars() {
+            handle_key(&mut state, KeyCode::Char(c).into(), Some(&actions));
+        }
+        find_row(
+            &render(&state, 120, 30),
+            "New job: python train.py --lr 1e-4",
+        );
+        handle_key(&mut state, KeyCode::Enter.into(), Some(&actions));
+
+        let Ok(Action::Submit(req)) = asked.try_recv() else {
+            panic!("nothing was submitted");
+        };
+        assert_eq!(req.cmd, "python train.py --lr 1e-4");
+        assert!(state.prompt.is_none());
+
+        handle_key(&mut state, KeyCode::Char('a').into(), Some(&actions));
+        handle_key(&mut state, KeyCode::Char('x').into(), Some(&actions));
+        handle_key(&mut state, KeyCode::Esc.into(), Some(&actions));
+        assert!(asked.try_recv().is_err());
+    }
+
+    #[test]
+    fn e_edits_a_finished_job_and_queues_the_result_with_its_options() {
+        let mut state = sample_state();
+        state.jobs[3].cmd = "python train.py --lr=1e-4 --epohcs 3".to_string();
+        state.jobs[3].tag = Some("sweep".to_string());
+        state.selected_job = Some(3);
+        let (actions, mut asked) = tokio::sync::mpsc::unbounded_channel();
+        let mut press = |code: KeyCode| handle_key(&mut state, code.into(), Some(&actions));
+
+        press(KeyCode::Char('e'));
+        press(KeyCode::Left);
+        press(KeyCode::Left);
+        for _ in 0.."ohcs".len() {
+            press(KeyCode::Backspace);
+        }
+        for c in "ochs".chars() {
+            press(KeyCode::Char(c));
+        }
+        press(KeyCode::Home);
+        press(KeyCode::Delete);
+        press(KeyCode::Char('P'));
+        press(KeyCode::Enter);
+
+        let Ok(Action::Submit(req)) = asked.try_recv() else {
+            panic!("nothing was submitted");
+        };
+        assert_eq!(req.cmd, "Python train.py --lr=1e-4 --epochs 3");
+        assert_eq!(req.tag.as_deref(), Some("sweep"));
+    }
+
+    #[test]
+    fn y_copies_the_command_and_shift_y_the_log_tail() {
+        let mut state = sample_state();
+        state.selected_job = Some(0);
+        for i in 0..COPY_LINES + 5 {
+            state.jobs[0].push_log_line(format!("step {}", i));
+        }
+
+        handle_key(&mut state, KeyCode::Char('y').into(), None);
+        assert_eq!(
+            state.clipboard.take().as_deref(),
+            Some(state.jobs[0].cmd.as_str())
+        );
+        handle_key(&mut state, KeyCode::Char('Y').into(), None);
+        let tail = state.clipboard.take().unwrap();
+        assert_eq!(tail.lines().count(), COPY_LINES);
+        assert!(tail.ends_with(&format!("step {}", COPY_LINES + 4)));
+
+        assert_eq!(osc52("echo hi"), "\x1b]52;c;ZWNobyBoaQ==\x07");
+    }
+}
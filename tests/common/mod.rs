@@ -0,0 +1,98 @@
+//! Shared helpers for the end-to-end tests: a scratch directory per test and
+//! a runner for the real `gparallel` binary backed by mock GPUs.
+
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+};
+
+pub struct Sandbox {
+    pub dir: PathBuf,
+    gpus: u32,
+}
+
+impl Sandbox {
+    pub fn new(gpus: u32) -> Self {
+        let dir = std::env::temp_dir().join(format!("gparallel-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        Self { dir, gpus }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    pub fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    pub fn read(&self, name: &str) -> String {
+        fs::read_to_string(self.path(name)).unwrap_or_default()
+    }
+
+    /// A `gparallel` command running in the sandbox with mock GPUs and no TUI.
+    pub fn command(&self) -> Command {
+        let mut cmd = self.subcommand(None);
+        cmd.arg("--no-tui");
+        cmd
+    }
+
+    /// Like `command`, but starting with an explicit subcommand (and no
+    /// other arguments, since options cannot precede it).
+    pub fn subcommand(&self, name: Option<&str>) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_gparallel"));
+        cmd.current_dir(&self.dir)
+            .env("GPARALLEL_MOCK_GPUS", self.gpus.to_string())
+            .env("HOME", &self.dir)
+            .env_remove("XDG_DATA_HOME")
+            .env_remove("CUDA_VISIBLE_DEVICES")
+            .env_remove("RUST_BACKTRACE")
+            .args(name);
+        cmd
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command()
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    }
+
+    pub fn run_with_stdin(&self, args: &[&str], input: &str) -> Output {
+        let mut child = self
+            .command()
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn stdout_lines(output: &Output) -> Vec<String> {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::{stdout_lines, Sandbox};
+
+fn two_files(sb: &Sandbox) {
+    sb.write("a.txt", "echo a1\necho a2\n");
+    sb.write("b.txt", "echo b1\necho b2\n");
+}
+
+#[test]
+fn local_config_sets_defaults() {
+    let sb = Sandbox::new(1);
+    two_files(&sb);
+    sb.write("gparallel.toml", "interleave = true\n");
+
+    let out = sb.run(&["a.txt", "b.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["a1", "b1", "a2", "b2"]);
+}
+
+#[test]
+fn profile_overrides_defaults() {
+    let sb = Sandbox::new(1);
+    two_files(&sb);
+    sb.write(
+        "gparallel.toml",
+        "interleave = false\n\n[profile.mixed]\ninterleave = true\n",
+    );
+
+    let plain = sb.run(&["a.txt", "b.txt"]);
+    let mixed = sb.run(&["--profile", "mixed", "a.txt", "b.txt"]);
+
+    assert_eq!(stdout_lines(&plain), vec!["a1", "a2", "b1", "b2"]);
+    assert_eq!(stdout_lines(&mixed), vec!["a1", "b1", "a2", "b2"]);
+}
+
+#[test]
+fn local_config_overrides_user_config() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "echo hi\n");
+    std::fs::create_dir_all(sb.path(".config/gparallel")).unwrap();
+    sb.write(".config/gparallel/config.toml", "format = \"jsonl\"\n");
+    sb.write("gparallel.toml", "format = \"text\"\n");
+
+    let out = sb.run(&["jobs.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["hi"]);
+}
+
+#[test]
+fn command_line_wins_over_config() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "echo hi\n");
+    sb.write("gparallel.toml", "format = \"jsonl\"\n");
+
+    let out = sb.run(&["--format", "text", "jobs.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["hi"]);
+}
+
+#[test]
+fn unknown_keys_and_profiles_are_errors() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "true\n");
+    sb.write("gparallel.toml", "no-such-flag = 1\n");
+
+    let bad_key = sb.run(&["jobs.txt"]);
+    let ignored = sb.run(&["--no-config", "jobs.txt"]);
+
+    assert!(!bad_key.status.success());
+    assert!(String::from_utf8_lossy(&bad_key.stderr).contains("no-such-flag"));
+    assert!(ignored.status.success());
+
+    sb.write("gparallel.toml", "");
+    let bad_profile = sb.run(&["--profile", "nope", "jobs.txt"]);
+    assert!(!bad_profile.status.success());
+    assert!(String::from_utf8_lossy(&bad_profile.stderr).contains("nope"));
+}
@@ -0,0 +1,1295 @@
+mod common;
+
+use common::Sandbox;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    os::unix::net::UnixStream,
+    path::Path,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// A `gparallel serve` process on a socket inside the sandbox.
+struct Daemon {
+    child: Child,
+}
+
+impl Daemon {
+    fn start(sb: &Sandbox) -> Self {
+        Self::start_with(sb, &[])
+    }
+
+    fn start_with(sb: &Sandbox, args: &[&str]) -> Self {
+        let child = sb
+            .subcommand(Some("serve"))
+            .args(["--socket", "d.sock"])
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        assert!(
+            wait_for(|| sb.path("d.sock").exists()),
+            "daemon never created its socket"
+        );
+        Self { child }
+    }
+
+    /// Start with `--http` on a free local port, returning its address.
+    fn start_with_http(sb: &Sandbox, args: &[&str]) -> (Self, String) {
+        let addr = {
+            let probe = TcpListener::bind("127.0.0.1:0").unwrap();
+            probe.local_addr().unwrap().to_string()
+        };
+        let daemon = Self::start_with(sb, &[&["--http", addr.as_str()], args].concat());
+        assert!(wait_for(|| TcpStream::connect(&addr).is_ok()));
+        (daemon, addr)
+    }
+
+    fn stop(&mut self) {
+        Command::new("kill")
+            .args(["-TERM", &self.child.id().to_string()])
+            .status()
+            .unwrap();
+        self.child.wait().unwrap();
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn wait_for(mut ready: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if ready() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+/// `GET path` over HTTP/1.0, returning the response head and body.
+fn http_get(addr: &str, path: &str) -> (String, String) {
+    let mut http = TcpStream::connect(addr).unwrap();
+    write!(http, "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, addr).unwrap();
+    let mut response = String::new();
+    http.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_string(), body.to_string())
+}
+
+/// Send raw protocol lines and collect one response line for each.
+fn exchange(socket: &Path, requests: &[&str]) -> Vec<serde_json::Value> {
+    let stream = UnixStream::connect(socket).unwrap();
+    talk(stream.try_clone().unwrap(), stream, requests)
+}
+
+/// `exchange` with a daemon's `--listen` address.
+fn exchange_tcp(addr: &str, requests: &[&str]) -> Vec<serde_json::Value> {
+    let stream = TcpStream::connect(addr).unwrap();
+    talk(stream.try_clone().unwrap(), stream, requests)
+}
+
+fn talk(mut writer: impl Write, reader: impl Read, requests: &[&str]) -> Vec<serde_json::Value> {
+    let mut reader = BufReader::new(reader);
+    requests
+        .iter()
+        .map(|request| {
+            writeln!(writer, "{}", request).unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn serve_runs_submitted_jobs_until_terminated() {
+    let sb = Sandbox::new(1);
+    let mut daemon = Daemon::start(&sb);
+
+    let responses = exchange(
+        &sb.path("d.sock"),
+        &[
+            r#"{"type": "submit", "job": {"cmd": "echo $N > first.txt", "env": {"N": "1"}}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo second > second.txt"}}"#,
+            r#"{"type": "launch"}"#,
+        ],
+    );
+
+    assert_eq!(responses[0]["type"], "submitted");
+    assert!(responses[0]["id"].is_string());
+    assert_eq!(responses[1]["type"], "submitted");
+    assert_eq!(responses[2]["type"], "error");
+    assert!(wait_for(
+        || sb.read("first.txt") == "1\n" && sb.read("second.txt") == "second\n"
+    ));
+
+    daemon.stop();
+    assert!(!sb.path("d.sock").exists());
+}
+
+#[test]
+fn second_daemon_refuses_a_live_socket() {
+    let sb = Sandbox::new(1);
+    let _daemon = Daemon::start(&sb);
+
+    let out = sb
+        .subcommand(Some("serve"))
+        .args(["--socket", "d.sock"])
+        .output()
+        .unwrap();
+
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("already listening"));
+}
+
+#[test]
+fn submit_sends_jobs_with_their_options() {
+    let sb = Sandbox::new(2);
+    let _daemon = Daemon::start(&sb);
+    let submit = |args: &[&str], stdin: &str| {
+        let mut child = sb
+            .subcommand(Some("submit"))
+            .args(["--socket", "d.sock"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    };
+
+    let out = submit(
+        &[
+            "--gpus",
+            "2",
+            "--env",
+            "TAG=pair",
+            "echo $TAG $CUDA_VISIBLE_DEVICES > pair.txt",
+        ],
+        "",
+    );
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&out.stdout).lines().count(), 1);
+
+    let out = submit(&[], "echo a > a.txt\n# comment\necho b > b.txt\n");
+    assert_eq!(String::from_utf8_lossy(&out.stdout).lines().count(), 2);
+
+    let out = submit(&["--gpus", "3", "true"], "");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("asks for 3 GPUs, but 2 are available"));
+
+    assert!(wait_for(|| sb.read("pair.txt") == "pair 0,1\n"
+        && sb.read("a.txt") == "a\n"
+        && sb.read("b.txt") == "b\n"));
+}
+
+#[test]
+fn status_lists_jobs_as_table_and_json() {
+    let sb = Sandbox::new(1);
+    let _daemon = Daemon::start(&sb);
+    exchange(
+        &sb.path("d.sock"),
+        &[
+            r#"{"type": "submit", "job": {"cmd": "exit 3"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "sleep 30"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "true"}}"#,
+        ],
+    );
+    let status = |args: &[&str]| {
+        let out = sb
+            .subcommand(Some("status"))
+            .args(["--socket", "d.sock"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        String::from_utf8_lossy(&out.stdout).into_owned()
+    };
+
+    assert!(wait_for(
+        || status(&[]).contains("1 queued, 1 running, 0 completed, 1 failed")
+    ));
+    let table = status(&[]);
+    assert!(table.starts_with("ID "));
+    assert!(table
+        .lines()
+        .any(|l| l.contains("failed") && l.contains(" 3 ") && l.ends_with("exit 3")));
+
+    let jobs: serde_json::Value = serde_json::from_str(&status(&["--json"])).unwrap();
+    let states: Vec<_> = jobs
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|j| (j["cmd"].as_str().unwrap(), j["state"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        states,
+        [
+            ("exit 3", "failed"),
+            ("sleep 30", "running"),
+            ("true", "queued")
+        ]
+    );
+}
+
+#[test]
+fn cancel_drops_queued_jobs_and_stops_running_ones() {
+    let sb = Sandbox::new(1);
+    let _daemon = Daemon::start(&sb);
+    let ids: Vec<String> = exchange(
+        &sb.path("d.sock"),
+        &[
+            r#"{"type": "submit", "job": {"cmd": "trap 'touch stopped.txt; exit 1' TERM; sleep 30 & wait"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "touch b.txt"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "touch c.txt"}}"#,
+        ],
+    )
+    .iter()
+    .map(|r| r["id"].as_str().unwrap().to_string())
+    .collect();
+    let cancel = |args: &[&str]| {
+        sb.subcommand(Some("cancel"))
+            .args(["--socket", "d.sock"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let stdout = |out: &std::process::Output| String::from_utf8_lossy(&out.stdout).into_owned();
+
+    let out = cancel(&[&ids[1][..8]]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), format!("{}\n", ids[1]));
+    let out = cancel(&[&ids[1][..8]]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("has already finished"));
+
+    assert_eq!(stdout(&cancel(&["--queued"])), format!("{}\n", ids[2]));
+    assert_eq!(stdout(&cancel(&["--all"])), format!("{}\n", ids[0]));
+    assert!(wait_for(|| sb.path("stopped.txt").exists()));
+
+    assert!(wait_for(|| {
+        let out = sb
+            .subcommand(Some("status"))
+            .args(["--socket", "d.sock", "--json"])
+            .output()
+            .unwrap();
+        let jobs: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+        jobs.as_array()
+            .unwrap()
+            .iter()
+            .all(|j| j["state"] == "cancelled")
+    }));
+    assert!(!sb.path("b.txt").exists() && !sb.path("c.txt").exists());
+
+    let out = cancel(&[]);
+    assert!(!out.status.success());
+}
+
+#[test]
+fn requeue_and_move_reorder_the_queue() {
+    let sb = Sandbox::new(1);
+    let _daemon = Daemon::start(&sb);
+    let ids: Vec<String> = exchange(
+        &sb.path("d.sock"),
+        &[
+            r#"{"type": "submit", "job": {"cmd": "while [ ! -e go ]; do sleep 0.1; done"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo a >> order.txt"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo b >> order.txt"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo c >> order.txt", "priority": "low"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo d >> order.txt", "priority": "high"}}"#,
+        ],
+    )
+    .iter()
+    .map(|r| r["id"].as_str().unwrap().to_string())
+    .collect();
+    let client = |args: &[&str]| {
+        sb.subcommand(Some(args[0]))
+            .args(["--socket", "d.sock"])
+            .args(&args[1..])
+            .output()
+            .unwrap()
+    };
+
+    // Queued as d, a, b, c; then c goes behind d, and b ahead of everything
+    let out = client(&["requeue", &ids[3][..8], "--priority", "high"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(String::from_utf8_lossy(&out.stderr).contains("now waits with high priority"));
+    let out = client(&["move", &ids[2], "--front"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let out = client(&["move", &ids[0], "--back"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("is not queued"));
+
+    let status = exchange(&sb.path("d.sock"), &[r#"{"type": "status"}"#]);
+    assert_eq!(status[0]["jobs"][3]["priority"], "high");
+    assert!(status[0]["jobs"][1].get("priority").is_none());
+
+    sb.write("go", "");
+    assert!(wait_for(|| sb.read("order.txt") == "b\nd\nc\na\n"));
+}
+
+#[test]
+fn wait_blocks_until_jobs_finish_and_fails_if_any_did() {
+    let sb = Sandbox::new(2);
+    let _daemon = Daemon::start(&sb);
+    let client = |args: &[&str]| {
+        sb.subcommand(Some(args[0]))
+            .args(["--socket", "d.sock"])
+            .args(&args[1..])
+            .output()
+            .unwrap()
+    };
+    let ok = client(&["submit", "--tag", "sweep", "sleep 0.5; touch ok.txt"]);
+    let ok_id = String::from_utf8_lossy(&ok.stdout).trim().to_string();
+    client(&["submit", "--tag", "sweep", "sleep 0.5; exit 3"]);
+
+    let out = client(&["wait", "--tag", "sweep"]);
+    assert!(!out.status.success());
+    assert!(sb.path("ok.txt").exists());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("1 of 2 jobs failed"));
+
+    let out = client(&["wait", &ok_id[..8]]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(String::from_utf8_lossy(&out.stderr).contains("1 jobs finished, none failed"));
+
+    // Jobs that had finished before do not count without ids or a tag
+    client(&["submit", "sleep 30"]);
+    let out = client(&["wait", "--timeout", "1s"]);
+    assert!(!out.status.success());
+    assert!(
+        String::from_utf8_lossy(&out.stderr).contains("timed out with 1 of 1 jobs not finished")
+    );
+
+    let out = client(&["wait", "--tag", "missing"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("no job has the tag 'missing'"));
+}
+
+#[test]
+fn logs_prints_and_follows_job_output() {
+    let sb = Sandbox::new(1);
+    let _daemon = Daemon::start(&sb);
+    let responses = exchange(
+        &sb.path("d.sock"),
+        &[
+            r#"{"type": "submit", "job": {"cmd": "echo one; sleep 1; echo two >&2; sleep 0.2; echo three"}}"#,
+        ],
+    );
+    let id = responses[0]["id"].as_str().unwrap();
+    let logs = |args: &[&str]| {
+        sb.subcommand(Some("logs"))
+            .args(["--socket", "d.sock"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let out = logs(&["-f", &id[..8]]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "one\n[stderr] two\nthree\n"
+    );
+    let out = logs(&[id]);
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "one\n[stderr] two\nthree\n"
+    );
+
+    let out = logs(&["ffffffff"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("no job with id 'ffffffff'"));
+}
+
+#[test]
+fn watch_streams_state_and_output() {
+    let sb = Sandbox::new(2);
+    let _daemon = Daemon::start(&sb);
+    exchange(
+        &sb.path("d.sock"),
+        &[r#"{"type": "submit", "job": {"cmd": "echo before; sleep 1; echo after; sleep 30"}}"#],
+    );
+    std::thread::sleep(Duration::from_millis(500));
+
+    let stream = UnixStream::connect(sb.path("d.sock")).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut messages = BufReader::new(stream)
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(&line.unwrap()).unwrap());
+    writeln!(writer, r#"{{"type": "watch"}}"#).unwrap();
+
+    let state = messages.next().unwrap();
+    assert_eq!(state["type"], "state");
+    assert_eq!(state["gpus"].as_array().unwrap().len(), 2);
+    assert_eq!(state["jobs"][0]["state"], "running");
+    let buffered = messages.next().unwrap();
+    assert_eq!(buffered["type"], "job_log");
+    assert_eq!(buffered["lines"], serde_json::json!(["before"]));
+    let live = messages.find(|m| m["type"] == "job_log").unwrap();
+    assert_eq!(live["lines"], serde_json::json!(["after"]));
+    assert_eq!(live["id"], state["jobs"][0]["id"]);
+}
+
+#[test]
+fn attach_needs_a_terminal() {
+    let sb = Sandbox::new(1);
+    let _daemon = Daemon::start(&sb);
+
+    let out = sb
+        .subcommand(Some("attach"))
+        .args(["--socket", "d.sock"])
+        .output()
+        .unwrap();
+
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("TUI requires stdout to be a terminal"));
+}
+
+#[test]
+fn http_serves_status_and_pushes_events() {
+    let sb = Sandbox::new(1);
+    let (_daemon, addr) = Daemon::start_with_http(&sb, &[]);
+
+    let (mut events, _) = tungstenite::connect(format!("ws://{}/events", addr)).unwrap();
+    let mut next = || loop {
+        if let tungstenite::Message::Text(text) = events.read().unwrap() {
+            return serde_json::from_str::<serde_json::Value>(&text).unwrap();
+        }
+    };
+    let snapshot = next();
+    assert_eq!(snapshot["type"], "state");
+    assert_eq!(snapshot["jobs"], serde_json::json!([]));
+
+    exchange(
+        &sb.path("d.sock"),
+        &[r#"{"type": "submit", "job": {"cmd": "echo hi"}}"#],
+    );
+    let mut seen = Vec::new();
+    loop {
+        let event = next();
+        match event["type"].as_str().unwrap() {
+            // A running job is sent again once its process has started
+            "job" => seen.push(event["job"]["state"].as_str().unwrap().to_string()),
+            "job_log" => seen.push(event["lines"][0].as_str().unwrap().to_string()),
+            other => panic!("unexpected event {}", other),
+        }
+        seen.dedup();
+        if seen.last().unwrap() == "completed" {
+            break;
+        }
+    }
+    assert_eq!(seen, ["queued", "running", "hi", "completed"]);
+
+    let (head, body) = http_get(&addr, "/status");
+    assert!(head.starts_with("HTTP/1.0 200"), "{}", head);
+    let jobs: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(jobs[0]["cmd"], "echo hi");
+    assert_eq!(jobs[0]["state"], "completed");
+}
+
+#[test]
+fn http_serves_dashboard_gpus_and_job_logs() {
+    let sb = Sandbox::new(2);
+    let (_daemon, addr) = Daemon::start_with_http(&sb, &[]);
+    let id = exchange(
+        &sb.path("d.sock"),
+        &[r#"{"type": "submit", "job": {"cmd": "echo one; echo two >&2"}}"#],
+    )[0]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let (head, page) = http_get(&addr, "/");
+    assert!(head.contains("text/html"), "{}", head);
+    assert!(page.contains("<title>gparallel</title>"));
+    assert!(page.contains(r#"api("events")"#));
+
+    let (_, gpus) = http_get(&addr, "/gpus");
+    let gpus: serde_json::Value = serde_json::from_str(&gpus).unwrap();
+    assert_eq!(gpus.as_array().unwrap().len(), 2);
+
+    let logs = || {
+        let (_, body) = http_get(&addr, &format!("/jobs/{}/logs", &id[..8]));
+        serde_json::from_str::<Vec<String>>(&body).unwrap()
+    };
+    assert!(wait_for(|| logs().len() == 2));
+    assert!(logs().contains(&"[stderr] two".to_string()));
+
+    let (head, _) = http_get(&addr, "/jobs/ffffffff/logs");
+    assert!(head.starts_with("HTTP/1.0 404"), "{}", head);
+}
+
+#[test]
+fn restart_keeps_history_and_queue() {
+    let sb = Sandbox::new(1);
+    let mut daemon = Daemon::start(&sb);
+    let ids: Vec<String> = exchange(
+        &sb.path("d.sock"),
+        &[
+            r#"{"type": "submit", "job": {"cmd": "echo done > a.txt"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "sleep 30"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo later > c.txt"}}"#,
+        ],
+    )
+    .iter()
+    .map(|r| r["id"].as_str().unwrap().to_string())
+    .collect();
+    assert!(wait_for(|| {
+        let status = exchange(&sb.path("d.sock"), &[r#"{"type": "status"}"#]);
+        status[0]["jobs"][1]["state"] == "running"
+    }));
+    daemon.stop();
+    assert_eq!(sb.read("a.txt"), "done\n");
+    assert_eq!(sb.read("c.txt"), "");
+
+    let _daemon = Daemon::start(&sb);
+    assert!(wait_for(|| sb.read("c.txt") == "later\n"));
+    let status = exchange(&sb.path("d.sock"), &[r#"{"type": "status"}"#]);
+    let jobs: Vec<_> = status[0]["jobs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|j| (j["id"].as_str().unwrap(), j["state"].as_str().unwrap()))
+        .collect();
+    assert_eq!(jobs[0], (ids[0].as_str(), "completed"));
+    assert_eq!(jobs[1], (ids[1].as_str(), "failed"));
+    assert_eq!(jobs[2].0, ids[2]);
+}
+
+#[test]
+fn shifted_jobs_run_and_restart_in_queue_order() {
+    let sb = Sandbox::new(1);
+    let mut daemon = Daemon::start(&sb);
+    let socket = sb.path("d.sock");
+    let submitted = exchange(
+        &socket,
+        &[
+            r#"{"type": "pause", "paused": true}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo a >> ran.txt"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo b >> ran.txt"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo c >> ran.txt", "priority": "high"}}"#,
+        ],
+    );
+    let ids: Vec<&str> = submitted[1..]
+        .iter()
+        .map(|r| r["id"].as_str().unwrap())
+        .collect();
+    let order = |status: &serde_json::Value| -> Vec<String> {
+        let jobs = status["jobs"].as_array().unwrap();
+        let name = |id: &str| ["a", "b", "c"][ids.iter().position(|i| *i == id).unwrap()];
+        jobs.iter()
+            .map(|j| name(j["id"].as_str().unwrap()).to_string())
+            .collect()
+    };
+
+    // c's priority puts it first in the queue; b then passes a
+    let shift = format!(r#"{{"type": "shift", "job": "{}", "up": true}}"#, ids[1]);
+    let responses = exchange(&socket, &[&shift, r#"{"type": "status"}"#]);
+    assert_eq!(responses[0]["type"], "requeued");
+    assert_eq!(order(&responses[1]), ["c", "b", "a"]);
+    exchange(&socket, &[r#"{"type": "pause", "paused": false}"#]);
+    assert!(wait_for(|| sb.read("ran.txt") == "c\nb\na\n"));
+    std::thread::sleep(Duration::from_millis(300));
+    daemon.stop();
+
+    let _daemon = Daemon::start(&sb);
+    let status = &exchange(&socket, &[r#"{"type": "status"}"#])[0];
+    assert_eq!(order(status), ["c", "b", "a"]);
+    for job in status["jobs"].as_array().unwrap() {
+        assert_eq!(job["state"], "completed");
+    }
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(sb.read("ran.txt"), "c\nb\na\n");
+}
+
+#[test]
+fn restart_adopts_jobs_that_are_still_running() {
+    let sb = Sandbox::new(1);
+    let mut daemon = Daemon::start(&sb);
+    let submitted = exchange(
+        &sb.path("d.sock"),
+        &[r#"{"type": "submit", "job": {"cmd": "sleep 2; touch done.txt"}}"#],
+    );
+    let id = submitted[0]["id"].as_str().unwrap().to_string();
+    let state = |sb: &Sandbox| {
+        let status = exchange(&sb.path("d.sock"), &[r#"{"type": "status"}"#]);
+        status[0]["jobs"][0]["state"].as_str().unwrap().to_string()
+    };
+    assert!(wait_for(|| state(&sb) == "running"));
+    // Let the daemon save the job's process before it dies
+    std::thread::sleep(Duration::from_millis(300));
+    daemon.child.kill().unwrap();
+    daemon.child.wait().unwrap();
+    std::fs::remove_file(sb.path("d.sock")).unwrap();
+
+    let _daemon = Daemon::start(&sb);
+    let status = exchange(&sb.path("d.sock"), &[r#"{"type": "status"}"#]);
+    assert_eq!(status[0]["jobs"][0]["id"], id.as_str());
+    assert_eq!(status[0]["jobs"][0]["state"], "running");
+    assert!(wait_for(|| state(&sb) == "completed"));
+    assert!(sb.path("done.txt").exists());
+}
+
+/// Copy the directory `from` to `to`, as sled's database is one.
+fn copy_dir(from: &Path, to: &Path) {
+    std::fs::create_dir_all(to).unwrap();
+    for entry in std::fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()));
+        } else {
+            std::fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+        }
+    }
+}
+
+/// Bytes no file gparallel or sled writes would hold.
+fn garbage(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 131 % 251) as u8).collect()
+}
+
+#[test]
+fn restart_never_reruns_jobs_the_history_has_finished() {
+    let sb = Sandbox::new(1);
+    let data = sb.path(".local/share/gparallel");
+    let mut daemon = Daemon::start(&sb);
+    let socket = sb.path("d.sock");
+    exchange(
+        &socket,
+        &[
+            r#"{"type": "pause", "paused": true}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo ran >> ran.txt"}}"#,
+        ],
+    );
+    // The database as a crash could leave it: saved before the job ran
+    std::thread::sleep(Duration::from_millis(300));
+    copy_dir(&data.join("daemon.db"), &sb.path("old.db"));
+    exchange(&socket, &[r#"{"type": "pause", "paused": false}"#]);
+    assert!(wait_for(|| {
+        let status = exchange(&socket, &[r#"{"type": "status"}"#]);
+        status[0]["jobs"][0]["state"] == "completed"
+    }));
+    daemon.stop();
+    std::fs::remove_dir_all(data.join("daemon.db")).unwrap();
+    copy_dir(&sb.path("old.db"), &data.join("daemon.db"));
+    let mut history = std::fs::read(data.join("history.jsonl")).unwrap();
+    history.extend_from_slice(b"{\"id\": \"cut sh\xff");
+    std::fs::write(data.join("history.jsonl"), history).unwrap();
+
+    let _daemon = Daemon::start(&sb);
+    let status = exchange(&socket, &[r#"{"type": "status"}"#]);
+    assert_eq!(status[0]["jobs"][0]["state"], "completed");
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(sb.read("ran.txt"), "ran\n");
+}
+
+#[test]
+fn restart_with_an_unreadable_database_fails_clearly() {
+    let sb = Sandbox::new(1);
+    let mut daemon = Daemon::start(&sb);
+    exchange(
+        &sb.path("d.sock"),
+        &[r#"{"type": "submit", "job": {"cmd": "echo ran >> ran.txt"}}"#],
+    );
+    assert!(wait_for(|| sb.read("ran.txt") == "ran\n"));
+    std::thread::sleep(Duration::from_millis(300));
+    daemon.stop();
+    let conf = sb.path(".local/share/gparallel/daemon.db/conf");
+    let len = std::fs::metadata(&conf).unwrap().len() as usize;
+    std::fs::write(&conf, garbage(len)).unwrap();
+
+    let out = sb
+        .subcommand(Some("serve"))
+        .args(["--socket", "d.sock"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(
+        stderr.contains("Failed to open database"),
+        "unclear error: {}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert_eq!(sb.read("ran.txt"), "ran\n");
+}
+
+#[test]
+fn restart_after_a_torn_database_write_keeps_the_jobs_before_it() {
+    let sb = Sandbox::new(1);
+    let mut daemon = Daemon::start(&sb);
+    exchange(
+        &sb.path("d.sock"),
+        &[
+            r#"{"type": "pause", "paused": true}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo a >> ran.txt"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo b >> ran.txt"}}"#,
+        ],
+    );
+    std::thread::sleep(Duration::from_millis(300));
+    daemon.stop();
+    // A write cut short by a crash leaves a partial record at the end
+    let mut log = std::fs::OpenOptions::new()
+        .append(true)
+        .open(sb.path(".local/share/gparallel/daemon.db/db"))
+        .unwrap();
+    log.write_all(&garbage(4096)).unwrap();
+    drop(log);
+
+    let _daemon = Daemon::start(&sb);
+    assert!(wait_for(|| sb.read("ran.txt") == "a\nb\n"));
+    let status = &exchange(&sb.path("d.sock"), &[r#"{"type": "status"}"#])[0];
+    assert_eq!(status["jobs"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn token_file_guards_socket_and_http() {
+    use std::os::unix::fs::PermissionsExt;
+    let sb = Sandbox::new(1);
+    let (_daemon, addr) =
+        Daemon::start_with_http(&sb, &["--token-file", "token", "--socket-mode", "600"]);
+    let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(&sb.path("token")), 0o600);
+    assert_eq!(mode(&sb.path("d.sock")), 0o600);
+    let token = sb.read("token").trim().to_string();
+
+    let status = |args: &[&str]| {
+        sb.subcommand(Some("status"))
+            .args(["--socket", "d.sock"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let out = status(&[]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("needs a token"));
+    std::fs::write(sb.path("wrong"), "nope\n").unwrap();
+    let out = status(&["--token-file", "wrong"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("invalid token"));
+    assert!(status(&["--token-file", "token"]).status.success());
+
+    assert!(http_get(&addr, "/status").0.starts_with("HTTP/1.0 401"));
+    let (head, _) = http_get(&addr, &format!("/status?token={}", token));
+    assert!(head.starts_with("HTTP/1.0 200"), "{}", head);
+}
+
+#[test]
+fn listen_takes_clients_over_tcp_with_the_token() {
+    let sb = Sandbox::new(1);
+    let out = sb
+        .subcommand(Some("serve"))
+        .args(["--socket", "d.sock", "--listen", "127.0.0.1:0"])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("--token-file"));
+
+    let addr = {
+        let probe = TcpListener::bind("127.0.0.1:0").unwrap();
+        probe.local_addr().unwrap().to_string()
+    };
+    let _daemon = Daemon::start_with(&sb, &["--token-file", "token", "--listen", &addr]);
+    assert!(wait_for(|| TcpStream::connect(&addr).is_ok()));
+    let client = |name: &str, args: &[&str]| {
+        sb.subcommand(Some(name))
+            .args(["--connect", &addr])
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    std::fs::write(sb.path("wrong"), "nope\n").unwrap();
+    let out = client("status", &["--token-file", "wrong"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("invalid token"));
+
+    let out = client("submit", &["--token-file", "token", "echo remote > r.txt"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(wait_for(|| sb.read("r.txt") == "remote\n"));
+    let out = client("status", &["--token-file", "token", "--json"]);
+    let jobs: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let user = Command::new("id").arg("-un").output().unwrap().stdout;
+    let user = String::from_utf8(user).unwrap();
+    assert_eq!(jobs[0]["owner"], format!("{}@tcp", user.trim()));
+}
+
+#[test]
+fn clients_only_change_their_own_jobs() {
+    let sb = Sandbox::new(1);
+    let addr = {
+        let probe = TcpListener::bind("127.0.0.1:0").unwrap();
+        probe.local_addr().unwrap().to_string()
+    };
+    let _daemon = Daemon::start_with(&sb, &["--token-file", "token", "--listen", &addr]);
+    assert!(wait_for(|| TcpStream::connect(&addr).is_ok()));
+    let token = sb.read("token").trim().to_string();
+    let as_user = |user: &str, requests: &[&str]| {
+        let auth = format!(
+            r#"{{"type": "auth", "token": "{}", "user": "{}"}}"#,
+            token, user
+        );
+        let mut responses = exchange_tcp(&addr, &[&[auth.as_str()], requests].concat());
+        responses.remove(0);
+        responses
+    };
+    let id = |response: &serde_json::Value| response["id"].as_str().unwrap().to_string();
+
+    // alice's first job holds the only GPU; her second and bob's wait
+    let alice = as_user(
+        "alice",
+        &[
+            r#"{"type": "submit", "job": {"cmd": "sleep 30"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "touch a.txt"}}"#,
+        ],
+    );
+    let (running, queued) = (id(&alice[0]), id(&alice[1]));
+    let bob = id(&as_user(
+        "bob",
+        &[r#"{"type": "submit", "job": {"cmd": "touch b.txt"}}"#],
+    )[0]);
+
+    let cancel_job = |prefix: &str| {
+        format!(
+            r#"{{"type": "cancel", "target": {{"job": "{}"}}}}"#,
+            &prefix[..8]
+        )
+    };
+    let move_front = format!(r#"{{"type": "move", "job": "{}", "to": "front"}}"#, queued);
+    let responses = as_user(
+        "bob",
+        &[
+            &cancel_job(&running),
+            &move_front,
+            r#"{"type": "pause", "paused": true}"#,
+            r#"{"type": "cancel", "target": "all"}"#,
+        ],
+    );
+    assert_eq!(responses[0]["type"], "error");
+    assert!(responses[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("belongs to alice"));
+    assert_eq!(responses[1]["type"], "error");
+    assert_eq!(responses[2]["type"], "error");
+    assert_eq!(responses[3]["ids"], serde_json::json!([bob]));
+
+    let status = as_user("alice", &[&cancel_job(&queued), r#"{"type": "status"}"#]);
+    assert_eq!(status[0]["ids"], serde_json::json!([queued]));
+    assert_eq!(status[1]["jobs"][0]["state"], "running");
+
+    // The daemon's own user may cancel anyone's jobs
+    let auth = format!(r#"{{"type": "auth", "token": "{}"}}"#, token);
+    let responses = exchange(
+        &sb.path("d.sock"),
+        &[&auth, r#"{"type": "cancel", "target": "all"}"#],
+    );
+    assert_eq!(responses[1]["ids"], serde_json::json!([running]));
+    assert!(!sb.path("a.txt").exists() && !sb.path("b.txt").exists());
+}
+
+#[test]
+fn tcp_clients_cannot_claim_to_be_root_or_the_daemons_user() {
+    let sb = Sandbox::new(1);
+    let addr = {
+        let probe = TcpListener::bind("127.0.0.1:0").unwrap();
+        probe.local_addr().unwrap().to_string()
+    };
+    let _daemon = Daemon::start_with(&sb, &["--token-file", "token", "--listen", &addr]);
+    assert!(wait_for(|| TcpStream::connect(&addr).is_ok()));
+    let token = sb.read("token").trim().to_string();
+    let auth = format!(r#"{{"type": "auth", "token": "{}"}}"#, token);
+    let local = exchange(
+        &sb.path("d.sock"),
+        &[&auth, r#"{"type": "submit", "job": {"cmd": "sleep 30"}}"#],
+    );
+    let id = local[1]["id"].as_str().unwrap().to_string();
+    let me = Command::new("id").arg("-un").output().unwrap();
+    let me = String::from_utf8_lossy(&me.stdout).trim().to_string();
+
+    for user in ["root", me.as_str()] {
+        let auth = format!(
+            r#"{{"type": "auth", "token": "{}", "user": "{}"}}"#,
+            token, user
+        );
+        let responses = exchange_tcp(
+            &addr,
+            &[
+                &auth,
+                r#"{"type": "pause", "paused": true}"#,
+                &format!(r#"{{"type": "cancel", "target": {{"job": "{}"}}}}"#, id),
+                r#"{"type": "cancel", "target": "all"}"#,
+                r#"{"type": "submit", "job": {"cmd": "true"}}"#,
+                r#"{"type": "status"}"#,
+            ],
+        );
+        assert_eq!(responses[1]["type"], "error", "{}", user);
+        assert_eq!(responses[2]["type"], "error", "{}", user);
+        let cancelled = responses[3]["ids"].as_array().unwrap();
+        assert!(!cancelled.contains(&serde_json::json!(id)), "{}", user);
+        let submitted = responses[4]["id"].as_str().unwrap();
+        let jobs = responses[5]["jobs"].as_array().unwrap();
+        let job = jobs.iter().find(|job| job["id"] == submitted).unwrap();
+        assert_eq!(job["owner"], format!("{}@tcp", user));
+        assert_eq!(jobs[0]["state"], "running");
+    }
+}
+
+#[test]
+fn per_user_limits_hold_back_and_refuse_jobs() {
+    let sb = Sandbox::new(2);
+    let _daemon = Daemon::start_with(
+        &sb,
+        &["--max-gpus-per-user", "1", "--max-queued-per-user", "1"],
+    );
+    let responses = exchange(
+        &sb.path("d.sock"),
+        &[
+            r#"{"type": "submit", "job": {"cmd": "sleep 1; echo a > a.txt"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo b > b.txt"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo c > c.txt"}}"#,
+        ],
+    );
+    assert_eq!(responses[0]["type"], "submitted");
+    assert_eq!(responses[1]["type"], "submitted");
+    assert_eq!(responses[2]["type"], "error");
+    assert!(responses[2]["message"]
+        .as_str()
+        .unwrap()
+        .contains("queued jobs"));
+
+    // A GPU is free, but the second job waits for the first
+    let status = exchange(&sb.path("d.sock"), &[r#"{"type": "status"}"#]);
+    assert_eq!(status[0]["jobs"][1]["state"], "queued");
+    let user = Command::new("id").arg("-un").output().unwrap().stdout;
+    let user = String::from_utf8(user).unwrap();
+    assert_eq!(status[0]["jobs"][0]["owner"], user.trim());
+
+    assert!(wait_for(|| sb.read("b.txt") == "b\n"));
+    assert_eq!(sb.read("a.txt"), "a\n");
+}
+
+#[test]
+fn agents_run_the_coordinators_jobs() {
+    let sb = Sandbox::new(2);
+    let addr = {
+        let probe = TcpListener::bind("127.0.0.1:0").unwrap();
+        probe.local_addr().unwrap().to_string()
+    };
+    let out = sb
+        .subcommand(Some("serve"))
+        .args(["--socket", "d.sock", "--agents", &addr])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("--token-file"));
+
+    let _daemon = Daemon::start_with(
+        &sb,
+        &[
+            "--token-file",
+            "token",
+            "--agents",
+            &addr,
+            "--no-local-gpus",
+        ],
+    );
+    let auth = format!(
+        r#"{{"type": "auth", "token": "{}"}}"#,
+        sb.read("token").trim()
+    );
+    let ask = |requests: &[&str]| {
+        let mut responses = exchange(&sb.path("d.sock"), &[&[auth.as_str()], requests].concat());
+        responses.remove(0);
+        responses
+    };
+    // Queued until an agent brings GPUs, even one bigger than any yet
+    let ids: Vec<String> = ask(&[
+        r#"{"type": "submit", "job": {"cmd": "echo one $CUDA_VISIBLE_DEVICES"}}"#,
+        r#"{"type": "submit", "job": {"cmd": "echo two $CUDA_VISIBLE_DEVICES >&2", "gpus": 2}}"#,
+    ])
+    .iter()
+    .map(|r| r["id"].as_str().unwrap().to_string())
+    .collect();
+    let status = ask(&[r#"{"type": "status"}"#]);
+    assert_eq!(status[0]["jobs"][0]["state"], "queued");
+
+    let mut agent = sb
+        .subcommand(Some("agent"))
+        .args(["--coordinator", &addr, "--name", "node1"])
+        .args(["--token-file", "token"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let done = || {
+        let status = ask(&[r#"{"type": "status"}"#]);
+        status[0]["jobs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|j| j["state"] == "completed")
+    };
+    let finished = wait_for(done);
+    agent.kill().unwrap();
+    agent.wait().unwrap();
+    assert!(finished);
+
+    let logs = |id: &str| {
+        let request = format!(r#"{{"type": "logs", "job": "{}"}}"#, id);
+        ask(&[&request])[0]["lines"].clone()
+    };
+    assert_eq!(logs(&ids[0]), serde_json::json!(["one 0"]));
+    assert_eq!(logs(&ids[1]), serde_json::json!(["[stderr] two 0,1"]));
+}
+
+#[test]
+fn serve_takes_its_socket_from_systemd_and_notifies_it() {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::{
+        net::{UnixDatagram, UnixListener},
+        process::CommandExt,
+    };
+    let sb = Sandbox::new(1);
+    let listener = UnixListener::bind(sb.path("activated.sock")).unwrap();
+    let notify = UnixDatagram::bind(sb.path("notify.sock")).unwrap();
+    notify
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let next_state = || {
+        let mut buf = [0; 64];
+        let len = notify.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).to_string()
+    };
+
+    // LISTEN_PID has to be the daemon's own pid, so a shell execs it
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(r#"LISTEN_PID=$$ LISTEN_FDS=1 exec "$0" serve --socket unused.sock --no-db"#)
+        .arg(env!("CARGO_BIN_EXE_gparallel"))
+        .current_dir(&sb.dir)
+        .env("GPARALLEL_MOCK_GPUS", "1")
+        .env("NOTIFY_SOCKET", sb.path("notify.sock"))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+    let fd = listener.as_raw_fd();
+    // SAFETY: dup2 and fcntl are async-signal-safe
+    unsafe {
+        command.pre_exec(move || {
+            use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+            match fd {
+                3 => fcntl(3, FcntlArg::F_SETFD(FdFlag::empty())).map(drop)?,
+                _ => nix::unistd::dup2(fd, 3).map(drop)?,
+            }
+            Ok(())
+        });
+    }
+    let mut daemon = Daemon {
+        child: command.spawn().unwrap(),
+    };
+    drop(listener);
+    assert_eq!(next_state(), "READY=1");
+
+    let responses = exchange(&sb.path("activated.sock"), &[r#"{"type": "status"}"#]);
+    assert_eq!(responses[0]["type"], "status");
+    assert!(!sb.path("unused.sock").exists());
+
+    daemon.stop();
+    assert_eq!(next_state(), "STOPPING=1");
+    assert!(sb.path("activated.sock").exists());
+}
+
+#[test]
+fn reload_applies_the_config_file_to_a_running_daemon() {
+    let sb = Sandbox::new(1);
+    sb.write("serve.toml", "jobs-per-gpu = 1\n");
+    let daemon = Daemon::start_with(&sb, &["--config", "serve.toml"]);
+    let submit = |cmd: &str| {
+        let request = format!(r#"{{"type": "submit", "job": {{"cmd": "{}"}}}}"#, cmd);
+        exchange(&sb.path("d.sock"), &[&request]).remove(0)
+    };
+    let state = |index: usize| {
+        let status = exchange(&sb.path("d.sock"), &[r#"{"type": "status"}"#]);
+        status[0]["jobs"][index]["state"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    };
+    let reload = || {
+        sb.subcommand(Some("reload"))
+            .args(["--socket", "d.sock"])
+            .output()
+            .unwrap()
+    };
+    submit("sleep 5");
+    submit("touch b.txt");
+    assert!(wait_for(|| state(0) == "running"));
+    assert_eq!(state(1), "queued");
+
+    // A second place on the GPU starts the waiting job beside the first
+    sb.write("serve.toml", "jobs-per-gpu = 2\n");
+    let out = reload();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(wait_for(|| sb.path("b.txt").exists()));
+    assert_eq!(state(0), "running");
+
+    sb.write("serve.toml", "jobs-per-gpu = 0\n");
+    let out = reload();
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("jobs-per-gpu must be at least 1"));
+
+    // SIGHUP does the same; without GPUs no job can be taken
+    sb.write("serve.toml", "gpus = []\n");
+    Command::new("kill")
+        .args(["-HUP", &daemon.child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(wait_for(|| submit("true")["type"] == "error"));
+    assert_eq!(state(0), "running");
+}
+
+#[test]
+fn drain_finishes_running_jobs_keeps_the_queue_and_exits() {
+    let sb = Sandbox::new(1);
+    let mut daemon = Daemon::start(&sb);
+    let status = || exchange(&sb.path("d.sock"), &[r#"{"type": "status"}"#]).remove(0);
+    exchange(
+        &sb.path("d.sock"),
+        &[
+            r#"{"type": "submit", "job": {"cmd": "sleep 1; echo done > a.txt"}}"#,
+            r#"{"type": "submit", "job": {"cmd": "echo later > b.txt"}}"#,
+        ],
+    );
+    assert!(wait_for(|| status()["jobs"][0]["state"] == "running"));
+
+    let mut drain = sb
+        .subcommand(Some("drain"))
+        .args(["--socket", "d.sock"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(drain.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    assert!(
+        line.contains("waiting for 1 running jobs, 1 queued jobs left"),
+        "{}",
+        line
+    );
+    let submitted = exchange(
+        &sb.path("d.sock"),
+        &[r#"{"type": "submit", "job": {"cmd": "true"}}"#],
+    );
+    assert_eq!(
+        submitted[0]["message"],
+        "the daemon is draining and takes no new jobs"
+    );
+    stderr.read_line(&mut line).unwrap();
+    assert!(line.contains("Daemon stopped"), "{}", line);
+    assert!(drain.wait().unwrap().success());
+    assert!(daemon.child.wait().unwrap().success());
+    assert_eq!(sb.read("a.txt"), "done\n");
+    assert!(!sb.path("b.txt").exists());
+
+    // The queued job runs once the daemon is back
+    let _daemon = Daemon::start(&sb);
+    assert!(wait_for(|| sb.read("b.txt") == "later\n"));
+}
+
+#[test]
+fn drain_stops_jobs_still_running_after_its_timeout() {
+    let sb = Sandbox::new(1);
+    let mut daemon = Daemon::start(&sb);
+    exchange(
+        &sb.path("d.sock"),
+        &[r#"{"type": "submit", "job": {"cmd": "sleep 30"}}"#],
+    );
+
+    let started = Instant::now();
+    let out = sb
+        .subcommand(Some("drain"))
+        .args(["--socket", "d.sock", "--timeout", "1s"])
+        .output()
+        .unwrap();
+
+    assert!(out.status.success());
+    assert!(started.elapsed() < Duration::from_secs(20));
+    assert!(daemon.child.wait().unwrap().success());
+}
+
+#[test]
+fn submit_without_daemon_explains_itself() {
+    let sb = Sandbox::new(1);
+
+    let out = sb
+        .subcommand(Some("submit"))
+        .args(["--socket", "missing.sock", "true"])
+        .output()
+        .unwrap();
+
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("is `gparallel serve` running?"));
+}
+
+#[test]
+fn paused_queue_holds_jobs_until_resumed() {
+    let sb = Sandbox::new(1);
+    let _daemon = Daemon::start(&sb);
+    let socket = sb.path("d.sock");
+    let replies = exchange(
+        &socket,
+        &[
+            r#"{"type": "pause", "paused": true}"#,
+            r#"{"type": "submit", "job": {"cmd": "touch ran"}}"#,
+        ],
+    );
+    assert_eq!(replies[0]["type"], "paused");
+
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    let status = exchange(&socket, &[r#"{"type": "status"}"#]);
+    assert_eq!(status[0]["jobs"][0]["state"], "queued");
+    assert!(!sb.path("ran").exists());
+
+    exchange(&socket, &[r#"{"type": "pause", "paused": false}"#]);
+    assert!(wait_for(|| sb.path("ran").exists()));
+}
@@ -0,0 +1,692 @@
+mod common;
+
+use common::{stdout_lines, Sandbox};
+
+#[test]
+fn runs_every_job_and_exits_zero() {
+    let sb = Sandbox::new(2);
+    sb.write(
+        "jobs.txt",
+        "touch a.done\ntouch b.done\n\ntouch c.done\ntouch d.done\n",
+    );
+
+    let out = sb.run(&["jobs.txt"]);
+
+    assert!(out.status.success(), "{:?}", out);
+    for name in ["a.done", "b.done", "c.done", "d.done"] {
+        assert!(sb.path(name).exists(), "{} was not created", name);
+    }
+}
+
+#[test]
+fn run_subcommand_matches_default() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "echo one\necho two\n");
+    sb.write("gparallel.toml", "shell = \"sh\"\n");
+
+    let out = sb
+        .subcommand(Some("run"))
+        .args(["--no-tui", "jobs.txt"])
+        .output()
+        .unwrap();
+
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(stdout_lines(&out), vec!["one", "two"]);
+}
+
+#[test]
+fn options_cannot_precede_a_subcommand() {
+    let sb = Sandbox::new(1);
+
+    let out = sb.run(&["run", "jobs.txt"]);
+
+    assert!(!out.status.success());
+}
+
+#[test]
+fn doctor_reports_scheduled_gpus() {
+    let sb = Sandbox::new(2);
+
+    let out = sb.subcommand(Some("doctor")).output().unwrap();
+
+    assert!(out.status.success(), "{:?}", out);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        stdout.contains("0 (Mock0), 1 (Mock1) [from GPARALLEL_MOCK_GPUS]"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn failing_job_does_not_stop_the_rest() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "exit 3\necho after\n");
+
+    let out = sb.run(&["jobs.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["after"]);
+}
+
+#[test]
+fn prints_exit_status_summary() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "exit 3\ntrue\nexit 3 # again\nkill -9 $$\n");
+
+    let out = sb.run(&["jobs.txt"]);
+
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("4 jobs by exit status"), "{}", stderr);
+    assert!(stderr.contains("Queue wait: p50 "), "{}", stderr);
+    assert!(stderr.contains("exit 0              1  true"), "{}", stderr);
+    assert!(
+        stderr.contains("exit 3              2  exit 3\n"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("signal 9 (SIGKILL)  1  kill -9 $$"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn bell_rings_once_on_first_failure() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "true\nexit 1\nexit 2\n");
+
+    let out = sb.run(&["--alert-on-first-failure", "bell", "jobs.txt"]);
+
+    let bells = out.stderr.iter().filter(|&&b| b == 0x07).count();
+    assert_eq!(bells, 1);
+}
+
+#[test]
+fn retries_only_matching_exit_codes() {
+    let sb = Sandbox::new(1);
+    sb.write(
+        "jobs.txt",
+        "echo t >> transient.log; [ $(wc -l < transient.log) -ge 3 ]\n\
+         echo f >> fatal.log; exit 2\n",
+    );
+
+    let out = sb.run(&["--retries", "3", "--retry-on", "1,137", "jobs.txt"]);
+
+    assert_eq!(sb.read("transient.log").lines().count(), 3);
+    assert_eq!(sb.read("fatal.log").lines().count(), 1);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("retrying (2/3)"), "{}", stderr);
+    assert!(stderr.contains("exit 0  1"), "{}", stderr);
+}
+
+#[test]
+fn retry_waits_for_its_delay() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "date +%s.%N >> attempts.log; exit 1\n");
+
+    let out = sb.run(&["--retries", "1", "--retry-delay", "1s", "jobs.txt"]);
+
+    let times: Vec<f64> = sb
+        .read("attempts.log")
+        .lines()
+        .map(|l| l.parse().unwrap())
+        .collect();
+    assert_eq!(times.len(), 2);
+    assert!(times[1] - times[0] >= 1.0, "{:?}", times);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("retrying in 1s (1/1)"));
+}
+
+#[test]
+fn junit_report_lists_every_job() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "echo fine\necho 'bad <input>' >&2; exit 4\n");
+
+    let out = sb.run(&["--junit", "report.xml", "jobs.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["fine"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("bad <input>"));
+    let xml = sb.read("report.xml");
+    assert!(xml.contains("tests=\"2\" failures=\"1\""), "{}", xml);
+    assert!(xml.contains("<testcase name=\"echo fine\" classname=\"jobs.txt\""));
+    assert!(xml.contains("<failure message=\"exit 4\">bad &lt;input&gt;</failure>"));
+}
+
+#[test]
+fn pre_cmd_failure_is_a_setup_failure() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "echo ok-$TAG\necho bad-$TAG\n");
+
+    let out = sb.run(&[
+        "--pre-cmd",
+        "echo setup >> setup.log; [ $(wc -l < setup.log) -ne 2 ]",
+        "--retries",
+        "2",
+        "--env",
+        "TAG=x",
+        "jobs.txt",
+    ]);
+
+    assert_eq!(stdout_lines(&out), vec!["ok-x"]);
+    // the failed setup is not retried
+    assert_eq!(sb.read("setup.log").lines().count(), 2);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("pre-cmd failed: exit 1  1  echo bad-$TAG"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn results_export_json_and_csv() {
+    let sb = Sandbox::new(2);
+    sb.write("jobs.txt", "true\nexit 5\n");
+
+    sb.run(&["--results", "results.json", "jobs.txt"]);
+    sb.run(&["--results", "results.csv", "jobs.txt"]);
+
+    let json: serde_json::Value = serde_json::from_str(&sb.read("results.json")).unwrap();
+    let jobs = json.as_array().unwrap();
+    assert_eq!(jobs.len(), 2);
+    assert_eq!(jobs[0]["cmd"], "true");
+    assert_eq!(jobs[0]["state"], "completed");
+    assert_eq!(jobs[1]["exit_code"], 5);
+    assert!(jobs[1]["gpu"].is_u64());
+    assert!(jobs[1]["duration_secs"].is_f64());
+    assert!(jobs[1]["finished_at"].is_string());
+
+    let csv = sb.read("results.csv");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("id,cmd,"));
+    assert!(
+        lines[2].contains(",exit 5,jobs.txt,failed,"),
+        "{}",
+        lines[2]
+    );
+}
+
+#[test]
+fn history_keeps_finished_jobs_across_runs() {
+    let sb = Sandbox::new(2);
+    sb.write(
+        "jobs.txt",
+        "echo resnet-a\nexit 3 # resnet-b\nexit 4 # vit\n",
+    );
+    sb.run(&["jobs.txt"]);
+    sb.run(&["--no-history", "jobs.txt"]);
+    let history = |args: &[&str]| sb.subcommand(Some("history")).args(args).output().unwrap();
+
+    let out = history(&["--failed", "--grep", "resnet", "--since", "1h"]);
+    assert!(out.status.success());
+    let lines = stdout_lines(&out);
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    assert!(lines[0].starts_with("ID        FINISHED"));
+    assert!(lines[1].contains(" failed ") && lines[1].ends_with("exit 3 # resnet-b"));
+    assert_eq!(lines[2], "1 jobs, 1 failed");
+
+    let out = history(&["--json"]);
+    let jobs: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let mut cmds: Vec<&str> = jobs
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|job| job["cmd"].as_str().unwrap())
+        .collect();
+    cmds.sort_unstable();
+    assert_eq!(cmds, ["echo resnet-a", "exit 3 # resnet-b", "exit 4 # vit"]);
+    assert!(jobs[0]["finished_at"].is_string());
+}
+
+#[test]
+fn retry_failed_reruns_only_failures_with_their_env() {
+    let sb = Sandbox::new(2);
+    sb.write(
+        "jobs.jsonl",
+        "{\"cmd\": \"echo ok\"}\n{\"cmd\": \"echo $TAG; exit 1\", \"env\": {\"TAG\": \"lr-0.1\"}}\n",
+    );
+    sb.run(&["--results", "results.json", "jobs.jsonl"]);
+
+    let out = sb.run(&["--retry-failed", "results.json", "--results", "again.json"]);
+
+    assert_eq!(stdout_lines(&out), vec!["lr-0.1"]);
+    let again: serde_json::Value = serde_json::from_str(&sb.read("again.json")).unwrap();
+    assert_eq!(again.as_array().unwrap().len(), 1);
+    assert_eq!(again[0]["source"], "jobs.jsonl");
+}
+
+#[test]
+fn output_dir_holds_logs_and_metadata() {
+    let sb = Sandbox::new(1);
+    sb.write(
+        "jobs.txt",
+        "echo out; echo err >&2; pwd > where.txt\nexit 3\n",
+    );
+
+    let out = sb.run(&["--output-dir", "runs", "--run-in-output-dir", "jobs.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["out"]);
+    let mut dirs: Vec<_> = std::fs::read_dir(sb.path("runs"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    dirs.sort();
+    assert_eq!(dirs.len(), 2);
+    assert!(dirs[0].starts_with("0001-"), "{:?}", dirs);
+
+    let first = format!("runs/{}", dirs[0]);
+    assert_eq!(sb.read(&format!("{}/stdout.log", first)), "out\n");
+    assert_eq!(sb.read(&format!("{}/stderr.log", first)), "err\n");
+    assert!(sb
+        .read(&format!("{}/where.txt", first))
+        .trim()
+        .ends_with(&first));
+    let meta: serde_json::Value =
+        serde_json::from_str(&sb.read(&format!("runs/{}/job.json", dirs[1]))).unwrap();
+    assert_eq!(meta["exit_code"], 3);
+    assert_eq!(meta["cmd"], "exit 3");
+}
+
+#[test]
+fn log_verbosity_and_json_format() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "exit 2\n");
+
+    let quiet = sb.run(&["-q", "--retries", "1", "jobs.txt"]);
+    assert!(!String::from_utf8_lossy(&quiet.stderr).contains("retrying"));
+
+    let verbose = sb.run(&["-vv", "jobs.txt"]);
+    let stderr = String::from_utf8_lossy(&verbose.stderr);
+    assert!(
+        stderr.contains("[gparallel] DEBUG: Dispatching job: exit 2"),
+        "{}",
+        stderr
+    );
+
+    let json = sb.run(&["--log-format", "json", "--retries", "1", "jobs.txt"]);
+    let stderr = String::from_utf8_lossy(&json.stderr);
+    let line = stderr.lines().find(|l| l.starts_with('{')).unwrap();
+    let event: serde_json::Value = serde_json::from_str(line).unwrap();
+    assert_eq!(event["level"], "INFO");
+    assert_eq!(event["message"], "Job failed (exit 2), retrying (1/1)");
+    assert!(event["job"].is_string());
+}
+
+#[test]
+fn memfree_within_reach_runs_jobs() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "echo ran\n");
+
+    let out = sb.run(&["--memfree", "1M", "jobs.txt"]);
+    assert_eq!(stdout_lines(&out), vec!["ran"]);
+
+    let out = sb.run(&["--memfree", "8X", "jobs.txt"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("unknown size unit"));
+}
+
+#[test]
+fn memfree_starts_jobs_one_at_a_time() {
+    let sb = Sandbox::new(2);
+    sb.write(
+        "jobs.txt",
+        "date +%s%N >> starts.txt\ndate +%s%N >> starts.txt\n",
+    );
+
+    let out = sb.run(&["--memfree", "1M", "jobs.txt"]);
+    assert!(out.status.success());
+    let starts: Vec<u64> = sb
+        .read("starts.txt")
+        .lines()
+        .map(|line| line.parse().unwrap())
+        .collect();
+    assert_eq!(starts.len(), 2);
+    // Free GPUs for both, but the second waits for the first's memory to show
+    assert!(starts[0].abs_diff(starts[1]) >= 900_000_000);
+}
+
+#[test]
+fn jobs_from_csv_template() {
+    let sb = Sandbox::new(1);
+    sb.write("params.csv", "lr,bs\n0.1,32\n0.01,64\n");
+
+    let out = sb.run(&[
+        "--from-csv",
+        "params.csv",
+        "--template",
+        "echo lr={lr} bs={bs}",
+    ]);
+
+    assert_eq!(stdout_lines(&out), vec!["lr=0.1 bs=32", "lr=0.01 bs=64"]);
+}
+
+#[test]
+fn multi_gpu_jobs_wait_for_a_full_set() {
+    let sb = Sandbox::new(2);
+    sb.write(
+        "jobs.jsonl",
+        concat!(
+            "{\"cmd\": \"sleep 0.3; echo single $CUDA_VISIBLE_DEVICES\"}\n",
+            "{\"cmd\": \"echo pair $CUDA_VISIBLE_DEVICES $GPARALLEL_GPU_LOGICAL\", \"gpus\": 2}\n",
+            "{\"cmd\": \"sleep 0.3; echo after\"}\n",
+        ),
+    );
+
+    let out = sb.run(&["jobs.jsonl"]);
+
+    let lines = stdout_lines(&out);
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    assert!(lines.contains(&"pair 0,1 0,1".to_string()), "{:?}", lines);
+    // The pair needs both GPUs, so it cannot overlap either single job
+    let pair = lines.iter().position(|l| l.starts_with("pair")).unwrap();
+    assert!(
+        lines[..pair].iter().any(|l| l.starts_with("single")),
+        "{:?}",
+        lines
+    );
+}
+
+#[test]
+fn missing_command_file_is_an_error() {
+    let sb = Sandbox::new(1);
+
+    let out = sb.run(&["nope.txt"]);
+
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("nope.txt"));
+}
+
+#[test]
+fn each_job_gets_its_own_gpu() {
+    let sb = Sandbox::new(2);
+    sb.write(
+        "jobs.txt",
+        "echo $CUDA_VISIBLE_DEVICES > first.gpu; sleep 1\necho $CUDA_VISIBLE_DEVICES > second.gpu; sleep 1\n",
+    );
+
+    let out = sb.run(&["jobs.txt"]);
+
+    assert!(out.status.success());
+    let mut gpus = vec![sb.read("first.gpu"), sb.read("second.gpu")];
+    gpus.sort();
+    assert_eq!(gpus, vec!["0\n", "1\n"]);
+}
+
+#[test]
+fn single_gpu_runs_jobs_in_order() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "echo one\necho two\necho three\n");
+
+    let out = sb.run(&["jobs.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["one", "two", "three"]);
+}
+
+#[test]
+fn exports_gpu_identity_variables() {
+    let sb = Sandbox::new(1);
+    sb.write(
+        "jobs.txt",
+        "echo $GPARALLEL_GPU_LOGICAL $GPARALLEL_GPU_PHYSICAL $GPARALLEL_GPU_UUID\n\
+         echo $GPARALLEL_GPU $NVIDIA_VISIBLE_DEVICES ${#GPARALLEL_JOB_ID}\n",
+    );
+
+    let out = sb.run(&["jobs.txt"]);
+
+    assert_eq!(
+        stdout_lines(&out),
+        vec!["0 0 GPU-mock-0", "0 GPU-mock-0 36"]
+    );
+}
+
+#[test]
+fn reads_json_lines_with_env() {
+    let sb = Sandbox::new(1);
+    sb.write(
+        "jobs.jsonl",
+        "{\"cmd\": \"echo $LR\", \"env\": {\"LR\": \"0.01\"}}\n{\"cmd\": \"echo plain\"}\n",
+    );
+
+    let out = sb.run(&["jobs.jsonl"]);
+
+    assert_eq!(stdout_lines(&out), vec!["0.01", "plain"]);
+}
+
+#[test]
+fn rejects_unknown_json_fields() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.jsonl", "{\"cmd\": \"true\", \"bogus\": 1}\n");
+
+    let out = sb.run(&["jobs.jsonl"]);
+
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("line 1"));
+}
+
+#[test]
+fn reads_commands_from_stdin() {
+    let sb = Sandbox::new(1);
+
+    let out = sb.run_with_stdin(&["-"], "echo from-stdin\n");
+
+    assert_eq!(stdout_lines(&out), vec!["from-stdin"]);
+}
+
+#[test]
+fn stdin_cannot_be_given_twice() {
+    let sb = Sandbox::new(1);
+    sb.write("a.txt", "echo a1\n");
+
+    let out = sb.run_with_stdin(&["-", "a.txt", "-"], "echo from-stdin\n");
+
+    assert!(!out.status.success());
+    assert!(stdout_lines(&out).is_empty());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("only be read once"), "{}", stderr);
+}
+
+#[test]
+fn interleaves_multiple_files() {
+    let sb = Sandbox::new(1);
+    sb.write("a.txt", "echo a1\necho a2\n");
+    sb.write("b.txt", "echo b1\necho b2\n");
+
+    let sequential = sb.run(&["a.txt", "b.txt"]);
+    let interleaved = sb.run(&["--interleave", "a.txt", "b.txt"]);
+
+    assert_eq!(stdout_lines(&sequential), vec!["a1", "a2", "b1", "b2"]);
+    assert_eq!(stdout_lines(&interleaved), vec!["a1", "b1", "a2", "b2"]);
+}
+
+#[test]
+fn progress_bars_forward_job_output() {
+    let sb = Sandbox::new(2);
+    sb.write("jobs.txt", "echo out\necho err >&2\n");
+
+    let out = sb.run(&["--progress", "bars", "jobs.txt"]);
+
+    assert!(out.status.success());
+    assert_eq!(stdout_lines(&out), vec!["out"]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("err"));
+    assert!(stderr.contains("2/2 done"));
+}
+
+#[test]
+fn single_line_bar_stays_on_one_row() {
+    let sb = Sandbox::new(2);
+    sb.write("jobs.txt", "echo out\necho err >&2\n");
+
+    let out = sb.run(&["--progress", "bar", "jobs.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["out"]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("err\n"));
+    assert!(
+        stderr.contains("2/2 done, 0 failed, 0/2 GPUs busy"),
+        "{}",
+        stderr
+    );
+    // Redrawn with carriage returns, never moving the cursor up
+    assert!(!stderr.contains("\x1b[1A"), "{}", stderr);
+}
+
+#[test]
+fn skips_comments_and_joins_continuations() {
+    let sb = Sandbox::new(1);
+    sb.write(
+        "jobs.txt",
+        "# sweep over learning rates\necho one \\\n    two \\\n    three\n  # indented comment\necho four\n",
+    );
+
+    let out = sb.run(&["jobs.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["one two three", "four"]);
+}
+
+#[test]
+fn max_runtime_terminates_long_jobs() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "sleep 30\necho next\n");
+
+    let started = std::time::Instant::now();
+    let out = sb.run(&["--max-runtime", "1s", "jobs.txt"]);
+
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    assert_eq!(stdout_lines(&out), vec!["next"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("exceeded max runtime of 1s"));
+}
+
+#[test]
+fn stop_signal_reaches_timed_out_jobs() {
+    let sb = Sandbox::new(1);
+    sb.write(
+        "jobs.txt",
+        "trap 'kill $!; echo checkpoint; exit 0' INT; sleep 30 >/dev/null 2>&1 & wait\n",
+    );
+
+    let out = sb.run(&["--max-runtime", "1s", "--stop-signal", "int", "jobs.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["checkpoint"]);
+}
+
+#[test]
+fn invalid_duration_is_rejected() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "true\n");
+
+    let out = sb.run(&["--max-runtime", "10 parsecs", "jobs.txt"]);
+
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("unknown unit 'parsecs'"));
+}
+
+#[test]
+fn shell_none_execs_without_expansion() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "printf '%s|' \"a b\" $HOME c\n");
+
+    let out = sb.run(&["--shell", "none", "jobs.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["a b|$HOME|c|"]);
+}
+
+#[test]
+fn shell_sh_runs_commands() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "echo $((1 + 2))\n");
+
+    let out = sb.run(&["--shell", "sh", "jobs.txt"]);
+
+    assert_eq!(stdout_lines(&out), vec!["3"]);
+}
+
+#[test]
+fn argv_file_passes_lines_as_arguments() {
+    let sb = Sandbox::new(1);
+    let long = "x".repeat(100_000);
+    sb.write("args.txt", &format!("it's\n$HOME\n\n{}\n", long));
+    sb.write(
+        "jobs.txt",
+        "sh -c 'for a; do echo \"${#a}:$a\" | cut -c1-12; done' sh #argv-file=args.txt\n",
+    );
+
+    let out = sb.run(&["jobs.txt"]);
+
+    assert_eq!(
+        stdout_lines(&out),
+        vec!["4:it's", "5:$HOME", "0:", "100000:xxxxx"]
+    );
+}
+
+#[test]
+fn env_flags_inject_and_pass_through() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "echo $SEED/$FROM_PARENT/$NOISE\n");
+
+    let out = sb
+        .command()
+        .env("FROM_PARENT", "kept")
+        .env("NOISE", "loud")
+        .args(["--env", "SEED=42", "--env", "FROM_PARENT", "jobs.txt"])
+        .output()
+        .unwrap();
+
+    assert_eq!(stdout_lines(&out), vec!["42/kept/loud"]);
+}
+
+#[test]
+fn clean_env_drops_unlisted_variables() {
+    let sb = Sandbox::new(1);
+    sb.write("jobs.txt", "echo $SEED/$FROM_PARENT/$NOISE/${PATH:+path}\n");
+
+    let out = sb
+        .command()
+        .env("FROM_PARENT", "kept")
+        .env("NOISE", "loud")
+        .args([
+            "--clean-env",
+            "--env",
+            "SEED=42",
+            "--env",
+            "FROM_PARENT",
+            "jobs.txt",
+        ])
+        .output()
+        .unwrap();
+
+    assert_eq!(stdout_lines(&out), vec!["42/kept//path"]);
+}
+
+#[test]
+fn sshlogin_runs_jobs_on_remote_gpus() {
+    use std::os::unix::fs::PermissionsExt;
+    let sb = Sandbox::new(1);
+    // Stands in for ssh: every host is this machine with two GPUs
+    let ssh = sb.write(
+        "fake-ssh",
+        "#!/bin/sh\n\
+         host=$1; shift\n\
+         if [ \"$1\" = 'nvidia-smi -L' ]; then\n\
+         echo \"GPU 0: Fake (UUID: GPU-$host-0)\"\n\
+         echo \"GPU 1: Fake (UUID: GPU-$host-1)\"\n\
+         else exec sh -c \"$1\"; fi\n",
+    );
+    std::fs::set_permissions(&ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let job =
+        "sleep 0.5; echo $CUDA_VISIBLE_DEVICES $NVIDIA_VISIBLE_DEVICES > $GPARALLEL_JOB_ID.gpu";
+    sb.write("jobs.txt", &format!("{}\n{}\n{}\n", job, job, job));
+
+    let out = sb.run(&["--sshlogin", ":,h1", "--ssh", "./fake-ssh", "jobs.txt"]);
+
+    assert!(out.status.success(), "{:?}", out);
+    let mut seen: Vec<String> = std::fs::read_dir(&sb.dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "gpu"))
+        .map(|path| std::fs::read_to_string(path).unwrap().trim().to_string())
+        .collect();
+    seen.sort();
+    assert_eq!(seen, ["0 GPU-h1-0", "0 GPU-mock-0", "1 GPU-h1-1"]);
+}